@@ -0,0 +1,64 @@
+// Enables a `libffi_X_Y` cfg flag for each libffi version that the
+// linked libffi is at least, as reported by libffi-sys, so that wrappers
+// of newer entry points are only compiled when the library has them,
+// `libffi_complex` when libffi-sys reports complex type support, and
+// sets `libffi_long_double` to the format of C `long double` on the target,
+// with `libffi_f128` when that is IEEE binary128.
+
+use std::env;
+
+fn main() {
+    // libffi-sys decides which of these to set from the libffi version,
+    // setting none when it cannot tell the version.
+    let cfgs = |name| env::var(name).unwrap_or_default();
+    for cfg in cfgs("DEP_FFI_VERSION_CFGS")
+        .split(',')
+        .filter(|cfg| !cfg.is_empty())
+    {
+        println!("cargo:rustc-check-cfg=cfg({})", cfg);
+    }
+    for cfg in cfgs("DEP_FFI_ENABLED_VERSION_CFGS")
+        .split(',')
+        .filter(|cfg| !cfg.is_empty())
+    {
+        println!("cargo:rustc-cfg={}", cfg);
+    }
+
+    println!("cargo:rustc-check-cfg=cfg(libffi_complex)");
+    if env::var_os("DEP_FFI_COMPLEX").is_some() {
+        println!("cargo:rustc-cfg=libffi_complex");
+    }
+
+    println!(
+        "cargo:rustc-check-cfg=cfg(libffi_long_double, values(none(), \"double\", \"x87\", \"binary128\"))"
+    );
+    println!("cargo:rustc-check-cfg=cfg(libffi_f128)");
+    if let Some(format) = long_double_format() {
+        println!("cargo:rustc-cfg=libffi_long_double");
+        println!("cargo:rustc-cfg=libffi_long_double=\"{}\"", format);
+        if format == "binary128" {
+            println!("cargo:rustc-cfg=libffi_f128");
+        }
+    }
+}
+
+// The format of C `long double` on the target, where this crate knows it
+// and libffi passes it correctly. 32-bit ARM is left out because libffi
+// has no `ffi_type_longdouble` there, PowerPC because it may be either
+// IBM double-double or binary128, and x86-64 Android because libffi
+// passes it as the x87 format rather than binary128.
+fn long_double_format() -> Option<&'static str> {
+    let var = |name| env::var(name).unwrap_or_default();
+    let os = var("CARGO_CFG_TARGET_OS");
+    match var("CARGO_CFG_TARGET_ARCH").as_str() {
+        "x86" | "x86_64" if var("CARGO_CFG_TARGET_ENV") == "msvc" => Some("double"),
+        "x86" if os == "android" => Some("double"),
+        "x86_64" if os == "android" => None,
+        "x86" | "x86_64" => Some("x87"),
+        "aarch64" if var("CARGO_CFG_TARGET_VENDOR") == "apple" || os == "windows" => Some("double"),
+        "aarch64" | "riscv32" | "riscv64" | "s390x" | "sparc64" | "loongarch64" | "mips64"
+        | "mips64r6" => Some("binary128"),
+        "mips" | "mips32r6" => Some("double"),
+        _ => None,
+    }
+}
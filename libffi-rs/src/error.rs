@@ -0,0 +1,107 @@
+//! Errors reported by the checked operations of the higher layers.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
+
+use crate::low;
+
+/// An error from a checked libffi operation, such as
+/// [`Cif::call_checked`](../middle/struct.Cif.html#method.call_checked).
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Error {
+    /// libffi itself reported an error.
+    Ffi(low::Error),
+    /// The number of arguments did not match the CIF.
+    ArgCount {
+        /// The number of arguments the CIF takes.
+        expected: usize,
+        /// The number of arguments passed.
+        actual: usize,
+    },
+    /// The function pointer was null.
+    NullFunction,
+    /// An argument pointer was not aligned for its type.
+    MisalignedArg {
+        /// The index of the argument.
+        index: usize,
+        /// The alignment its type requires.
+        align: usize,
+    },
+    /// An argument value did not match its declared type.
+    ArgValue {
+        /// The index of the argument.
+        index: usize,
+    },
+    /// An argument type was `void`.
+    VoidArg {
+        /// The index of the argument.
+        index: usize,
+    },
+    /// A complex type was used on a target where libffi does not
+    /// support them.
+    UnsupportedComplex,
+    /// There were more arguments than a C `unsigned int` can count.
+    TooManyArgs {
+        /// The number of arguments.
+        count: usize,
+    },
+    /// The calling convention was out of range for the target.
+    BadAbi {
+        /// The calling convention.
+        abi: low::ffi_abi,
+    },
+    /// The type, such as `longdouble`, has no
+    /// [`Value`](../middle/enum.Value.html) representation.
+    UnsupportedType,
+    /// No function is registered under the name, in a
+    /// [`Registry`](../high/registry/struct.Registry.html).
+    UnknownSymbol,
+    /// A function is already registered under the name, in a
+    /// [`Registry`](../high/registry/struct.Registry.html).
+    DuplicateSymbol,
+}
+
+/// The `Result` type specialized for libffi [`Error`](enum.Error.html)s.
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Ffi(low::Error::Typedef) => {
+                f.write_str("bad or unsupported type representation")
+            }
+            Error::Ffi(low::Error::Abi) => f.write_str("bad or unsupported ABI"),
+            Error::ArgCount { expected, actual } => {
+                write!(f, "expected {} arguments but got {}", expected, actual)
+            }
+            Error::NullFunction => f.write_str("function pointer is null"),
+            Error::MisalignedArg { index, align } => {
+                write!(f, "argument {} is not aligned to {} bytes", index, align)
+            }
+            Error::ArgValue { index } => {
+                write!(f, "argument {} does not match its type", index)
+            }
+            Error::VoidArg { index } => write!(f, "argument {} has type void", index),
+            Error::UnsupportedComplex => {
+                f.write_str("complex types are not supported on this target")
+            }
+            Error::TooManyArgs { count } => write!(f, "too many arguments: {}", count),
+            Error::BadAbi { abi } => write!(f, "calling convention {} is out of range", abi),
+            Error::UnsupportedType => f.write_str("type has no value representation"),
+            Error::UnknownSymbol => f.write_str("no function is registered under the name"),
+            Error::DuplicateSymbol => {
+                f.write_str("a function is already registered under the name")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for Error {}
+
+impl From<low::Error> for Error {
+    fn from(error: low::Error) -> Self {
+        Error::Ffi(error)
+    }
+}
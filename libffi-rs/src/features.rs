@@ -0,0 +1,181 @@
+//! Run-time queries of what the linked libffi supports.
+//!
+//! With the `system` feature, this crate links whatever libffi the
+//! system provides, which may be older than the headers it was written
+//! against. Code that must run against a libffi of unknown vintage can
+//! use these functions to decide which facilities to rely on.
+//!
+//! Support that libffi fixes per target is reported from this crate’s
+//! knowledge of the target. Facilities that only newer versions of
+//! libffi provide are detected by looking up their entry points in the
+//! running process, which requires libffi to be dynamically linked: when
+//! it is statically linked, as it is without the `system` feature, they
+//! are reported as unavailable. Whether closures can be allocated at
+//! all is found by trying.
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::features;
+//!
+//! if features::closures_available() {
+//!     // ... use libffi::high::Closure1 and friends ...
+//! }
+//!
+//! match features::version() {
+//!     Some(version) => println!("libffi {}", version),
+//!     None => println!("libffi older than 3.4.5, or linked statically"),
+//! }
+//! ```
+
+use core::{slice, str};
+
+use libc::{c_char, c_int, c_ulong};
+
+#[cfg(feature = "closures")]
+use crate::low;
+use crate::raw;
+
+/// Whether libffi supports closures on this target, and this crate was
+/// built with them (the `closures` feature).
+pub fn closures() -> bool {
+    cfg!(feature = "closures") && raw::FFI_CLOSURES != 0
+}
+
+/// Whether closures can be allocated in this process.
+///
+/// Even where libffi supports closures on the target, the platform may
+/// refuse the executable memory they need. On AArch64 Apple platforms,
+/// libffi builds closures from a table of preassembled trampolines, so
+/// they work under the hardened runtime and on iOS without `MAP_JIT` or
+/// `pthread_jit_write_protect_np`. Elsewhere, libffi maps memory both
+/// writable and executable, which the macOS hardened runtime forbids
+/// without the `com.apple.security.cs.allow-unsigned-executable-memory`
+/// entitlement, as do some SELinux and PaX policies. Closures then fail
+/// to allocate and this returns `false`, unless an allocator that the
+/// platform allows has been installed with
+/// [`low::set_closure_allocator`](../low/fn.set_closure_allocator.html),
+/// such as `low::MAP_JIT_ALLOCATOR` on x86-64 macOS.
+pub fn closures_available() -> bool {
+    #[cfg(feature = "closures")]
+    {
+        if !closures() {
+            return false;
+        }
+
+        let (closure, _) = low::closure_alloc();
+        if closure.is_null() {
+            return false;
+        }
+
+        unsafe { low::closure_free(closure) };
+        true
+    }
+
+    #[cfg(not(feature = "closures"))]
+    false
+}
+
+/// Whether libffi supports complex types on this target, following its
+/// `FFI_TARGET_HAS_COMPLEX_TYPE`.
+///
+/// This includes x86 and x86-64 Windows targets other than MSVC, but not
+/// ARM Windows targets. Where it is false, the `complex` feature does not
+/// provide `Type::c32` and the other complex types.
+pub fn complex_types() -> bool {
+    cfg!(libffi_complex)
+}
+
+/// Whether libffi supports Go closures (`ffi_prep_go_closure`) on this
+/// target.
+pub fn go_closures() -> bool {
+    cfg!(any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "arm",
+        all(
+            target_arch = "aarch64",
+            not(any(target_os = "windows", target_vendor = "apple"))
+        ),
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "sparc",
+        target_arch = "sparc64",
+    ))
+}
+
+/// Whether the linked libffi builds closure trampolines from static
+/// code, instead of writing them into executable memory, as libffi 3.4
+/// and later can on some targets.
+pub fn static_trampolines() -> bool {
+    match unsafe { lookup::<unsafe extern "C" fn() -> c_int>("ffi_tramp_is_supported\0") } {
+        Some(is_supported) => unsafe { is_supported() != 0 },
+        None => false,
+    }
+}
+
+/// The version of the linked libffi, such as `"3.4.6"`.
+///
+/// Returns `None` if libffi is older than 3.4.5, which introduced
+/// `ffi_get_version`, or is linked statically.
+pub fn version() -> Option<&'static str> {
+    let get_version =
+        unsafe { lookup::<unsafe extern "C" fn() -> *const c_char>("ffi_get_version\0") }?;
+    let version = unsafe {
+        let version = get_version();
+        slice::from_raw_parts(version as *const u8, libc::strlen(version))
+    };
+    str::from_utf8(version).ok()
+}
+
+/// The version of the linked libffi as a number, such as `30406` for
+/// 3.4.6.
+///
+/// Returns `None` under the same conditions as [`version`](fn.version.html).
+pub fn version_number() -> Option<u64> {
+    let get_version_number =
+        unsafe { lookup::<unsafe extern "C" fn() -> c_ulong>("ffi_get_version_number\0") }?;
+    // `c_ulong` is only 32 bits on some targets.
+    #[allow(clippy::useless_conversion)]
+    Some(u64::from(unsafe { get_version_number() }))
+}
+
+// Looks up an optional libffi function by its nul-terminated name.
+// `F` must be the function pointer type of the function.
+#[cfg(unix)]
+unsafe fn lookup<F: Copy>(name: &str) -> Option<F> {
+    let symbol = libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr() as *const c_char);
+    if symbol.is_null() {
+        None
+    } else {
+        Some(core::mem::transmute_copy(&symbol))
+    }
+}
+
+// Without `dlsym`, optional functions are reported as missing.
+#[cfg(not(unix))]
+unsafe fn lookup<F: Copy>(_name: &str) -> Option<F> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn consistent() {
+        assert_eq!(cfg!(feature = "closures"), closures());
+        assert_eq!(closures(), closures_available());
+        assert_eq!(version().is_some(), version_number().is_some());
+    }
+
+    #[cfg(all(feature = "complex", libffi_complex))]
+    #[test]
+    fn complex_cif() {
+        use crate::middle::{Builder, Type};
+
+        assert!(complex_types());
+        let cif = Builder::new().arg(Type::c64()).res(Type::c32());
+        assert!(cif.try_into_cif().is_ok());
+    }
+}
@@ -0,0 +1,55 @@
+//! Aborting the process from FFI callbacks, with or without `std`.
+
+use core::fmt;
+
+/// Prints a message to standard error, if there is one, and aborts the
+/// process.
+pub(crate) fn abort(message: fmt::Arguments) -> ! {
+    #[cfg(feature = "std")]
+    {
+        use std::io::{self, Write};
+
+        let _ = writeln!(io::stderr(), "{}", message);
+        std::process::abort()
+    }
+
+    // Without `std` there is no way to abort directly, but panicking
+    // while unwinding from another panic aborts.
+    #[cfg(not(feature = "std"))]
+    {
+        struct Abort;
+
+        impl Drop for Abort {
+            fn drop(&mut self) {
+                panic!("aborting");
+            }
+        }
+
+        let _abort = Abort;
+        panic!("{}", message)
+    }
+}
+
+/// Aborts if dropped, which the `abort_on_panic!` stand-in below
+/// prevents unless its body unwinds.
+#[cfg(not(feature = "std"))]
+pub(crate) struct PanicGuard(pub &'static str);
+
+#[cfg(not(feature = "std"))]
+impl Drop for PanicGuard {
+    fn drop(&mut self) {
+        abort(format_args!("{}", self.0));
+    }
+}
+
+// Without `std`, stands in for the macro of the `abort_on_panic` crate,
+// which needs `std`.
+#[cfg(not(feature = "std"))]
+macro_rules! abort_on_panic {
+    ($message:expr, $body:block) => {{
+        let guard = $crate::high::abort::PanicGuard($message);
+        let result = $body;
+        core::mem::forget(guard);
+        result
+    }};
+}
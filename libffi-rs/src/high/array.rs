@@ -0,0 +1,60 @@
+// Const generics need Rust 1.51, so this module is only compiled, and
+// only parsed, with the `arrays` feature.
+
+use super::types::{CType, Type};
+use crate::middle;
+
+/// Arrays are passed as structs of `N` elements, since C has no way to
+/// pass arrays by value. This is how an array field of a C struct is
+/// laid out, too.
+///
+/// This impl is enabled by `#[cfg(feature = "arrays")]`, which needs
+/// Rust 1.51 or later.
+///
+/// # Panics
+///
+/// `reify` panics when `N` is zero, since libffi has no zero-sized
+/// types.
+unsafe impl<T: CType, const N: usize> CType for [T; N] {
+    fn reify() -> Type<Self> {
+        assert!(N > 0, "CType::reify: zero-length arrays have no C type");
+        let element = T::reify().into_middle();
+        unsafe { Type::from_middle(middle::Type::structure(vec![element; N])) }
+    }
+
+    unsafe fn check_received(ptr: *const Self) {
+        for i in 0..N {
+            T::check_received((ptr as *const T).add(i));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "closures"))]
+mod test {
+    use super::*;
+    use crate::high::Closure1;
+
+    #[test]
+    fn array() {
+        let f = |xs: [u16; 3]| [xs[2], xs[1], xs[0]];
+        let closure = Closure1::new(&f);
+
+        assert_eq!([3, 2, 1], closure.code_ptr()([1, 2, 3]));
+        assert_eq!(
+            "struct { u16, u16, u16 }",
+            <[u16; 3]>::reify().into_middle().describe()
+        );
+    }
+
+    #[cfg(all(feature = "complex", libffi_complex))]
+    #[test]
+    fn array_with_complex() {
+        use crate::high::types::c_c32;
+
+        assert_eq!(
+            "struct { f32, f32 }",
+            <[f32; 2]>::reify().into_middle().describe()
+        );
+        assert_eq!("complex", c_c32::reify().into_middle().describe());
+    }
+}
@@ -17,11 +17,28 @@
 //! assert!((result - 5f32).abs() < 0.0001);
 //! ```
 
-use std::marker::PhantomData;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::ffi::c_void;
+use core::mem::MaybeUninit;
+use core::{mem, slice};
+// `alloc::ffi::CString` needs Rust 1.64, like `no_std` itself.
+#[cfg(not(feature = "std"))]
+use alloc::ffi::CString;
+#[cfg(feature = "std")]
+use std::ffi::CString;
+
+use libc::c_char;
 
 use crate::middle;
 pub use middle::CodePtr;
 
+// The number of arguments that `call` gathers on the stack; calls with
+// more collect them in a `Vec`. Either way, `call` prepares a new `Cif`
+// for each call.
+const STACK_ARGS: usize = 16;
+
 /// Encapsulates an argument with its type information.
 ///
 /// In order to set up calls using [`call`](index.html#method.call), we
@@ -32,8 +49,17 @@ pub struct Arg<'a> {
     // There should be some type T such that type_ is the middle-layer
     // value of Type<T> and value is T::reify().
     type_: middle::Type,
-    value: middle::Arg,
-    _marker: PhantomData<&'a ()>,
+    value: middle::Arg<'a>,
+    // Owns the argument value when the `Arg` was made from a Rust value
+    // that must first be converted, as by `arg_str` and `arg_slice`;
+    // `value` points into it.
+    _storage: Option<Rc<dyn Any>>,
+}
+
+#[derive(Debug)]
+struct StrStorage {
+    _string: CString,
+    ptr: *const c_char,
 }
 
 impl<'a> Arg<'a> {
@@ -45,21 +71,205 @@ impl<'a> Arg<'a> {
         Arg {
             type_: T::reify().into_middle(),
             value: middle::Arg::new(arg),
-            _marker: PhantomData,
+            _storage: None,
+        }
+    }
+
+    /// Converts a string to a NUL-terminated C string owned by the
+    /// resulting `Arg`, which passes it as a `*const c_char`.
+    ///
+    /// For a shorter alias of the same, see
+    /// [`high::call::arg_str`](fn.arg_str.html).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `s` contains a NUL byte.
+    pub fn new_str(s: &str) -> Arg<'static> {
+        let string = CString::new(s).expect("Arg::new_str: string contains a NUL byte");
+        let storage = Rc::new(StrStorage {
+            ptr: string.as_ptr(),
+            _string: string,
+        });
+
+        Arg {
+            type_: middle::Type::pointer(),
+            // The `Arg` owns `storage`, so the pointer stays valid.
+            value: unsafe { middle::Arg::from_ptr(&storage.ptr) },
+            _storage: Some(storage),
+        }
+    }
+}
+
+impl<'a> Arg<'a> {
+    /// Converts a slice to a pointer to its data and its length, as
+    /// `Arg`s passing a pointer and a `usize`.
+    ///
+    /// For a shorter alias of the same, see
+    /// [`high::call::arg_slice`](fn.arg_slice.html).
+    pub fn new_slice<T>(slice: &'a [T]) -> [Self; 2] {
+        [
+            Arg::owned(slice.as_ptr() as *const c_void),
+            Arg::owned(slice.len()),
+        ]
+    }
+
+    // Makes an `Arg` that owns its value.
+    fn owned<T: super::CType + 'static>(value: T) -> Self {
+        let storage = Rc::new(value);
+
+        Arg {
+            type_: T::reify().into_middle(),
+            value: unsafe { middle::Arg::from_ptr(&*storage) },
+            _storage: Some(storage),
         }
     }
 }
 
 /// Constructs an [`Arg`](struct.Arg.html) for passing to
 /// [`call`](fn.call.html).
-pub fn arg<T: super::CType>(arg: &T) -> Arg {
+pub fn arg<T: super::CType>(arg: &T) -> Arg<'_> {
     Arg::new(arg)
 }
 
+/// Constructs an [`Arg`](struct.Arg.html) that passes a string as a
+/// NUL-terminated `*const c_char`.
+///
+/// The C string is owned by the `Arg`, so it lives for the duration of
+/// the call. The callee must not hold on to it after returning.
+///
+/// # Panics
+///
+/// Panics if `s` contains a NUL byte.
+///
+/// # Examples
+///
+/// ```
+/// use std::ffi::CStr;
+/// use std::os::raw::c_char;
+///
+/// use libffi::high::call::*;
+///
+/// extern "C" fn length(s: *const c_char) -> usize {
+///     unsafe { CStr::from_ptr(s) }.to_bytes().len()
+/// }
+///
+/// let result = unsafe {
+///     call::<usize>(CodePtr(length as *mut _), &[arg_str("hello")])
+/// };
+///
+/// assert_eq!(5, result);
+/// ```
+pub fn arg_str(s: &str) -> Arg<'static> {
+    Arg::new_str(s)
+}
+
+/// Constructs the two [`Arg`](struct.Arg.html)s that pass a slice as a
+/// pointer to its data followed by its length as a `usize`, which is
+/// how C functions most commonly accept buffers.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::high::call::*;
+///
+/// extern "C" fn sum(data: *const u32, len: usize) -> u32 {
+///     unsafe { std::slice::from_raw_parts(data, len) }.iter().sum()
+/// }
+///
+/// let data = [1, 2, 3, 4];
+/// let result = unsafe {
+///     call::<u32>(CodePtr(sum as *mut _), &arg_slice(&data))
+/// };
+///
+/// assert_eq!(10, result);
+/// ```
+pub fn arg_slice<T>(slice: &[T]) -> [Arg<'_>; 2] {
+    Arg::new_slice(slice)
+}
+
+/// Storage for a value that a C function returns through a pointer
+/// argument.
+///
+/// [`arg`](#method.arg) passes a pointer to the storage, and once the
+/// call has written it, [`assume_init`](#method.assume_init) takes the
+/// value out. The storage starts uninitialized, so it is never read
+/// unless the callee writes it; the `Arg` borrows it mutably, so it
+/// cannot be read while the call may still write it.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::high::call::*;
+///
+/// extern "C" fn divide(x: i32, y: i32, remainder: *mut i32) -> i32 {
+///     unsafe { *remainder = x % y };
+///     x / y
+/// }
+///
+/// let mut remainder = OutParam::<i32>::new();
+/// let quotient = unsafe {
+///     call::<i32>(
+///         CodePtr(divide as *mut _),
+///         &[arg(&17), arg(&5), remainder.arg()],
+///     )
+/// };
+///
+/// assert_eq!((3, 2), (quotient, unsafe { remainder.assume_init() }));
+/// ```
+#[derive(Debug)]
+pub struct OutParam<T> {
+    value: MaybeUninit<T>,
+}
+
+impl<T> OutParam<T> {
+    /// Creates uninitialized storage.
+    pub fn new() -> Self {
+        OutParam {
+            value: MaybeUninit::uninit(),
+        }
+    }
+
+    /// A pointer to the storage, for passing in other ways.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.value.as_mut_ptr()
+    }
+
+    /// Makes an [`Arg`](struct.Arg.html) that passes a pointer to the
+    /// storage.
+    pub fn arg(&mut self) -> Arg<'_> {
+        Arg::owned(self.as_mut_ptr() as *mut c_void)
+    }
+
+    /// Takes the value that the call wrote.
+    ///
+    /// # Safety
+    ///
+    /// The callee must have written a valid `T` through the pointer.
+    pub unsafe fn assume_init(self) -> T {
+        self.value.assume_init()
+    }
+}
+
+impl<T> Default for OutParam<T> {
+    fn default() -> Self {
+        OutParam::new()
+    }
+}
+
 /// Performs a dynamic call to a C function.
 ///
 /// To reduce boilerplate, see [`ffi_call!`](../../macro.ffi_call!.html).
 ///
+/// The result is checked with
+/// [`CType::check_received`](../trait.CType.html#method.check_received),
+/// so a result type such as `bool` or `char`, for which not every bit
+/// pattern is valid, aborts the process if the callee returns an invalid
+/// value.
+///
+/// With the `tracing` feature enabled, each call is made inside a
+/// debug-level `high_call` span recording the result type, the number of
+/// arguments, and the address of `fun`.
+///
 /// # Examples
 ///
 /// ```
@@ -76,11 +286,169 @@ pub fn arg<T: super::CType>(arg: &T) -> Arg {
 /// assert!((result - 5f32).abs() < 0.0001);
 /// ```
 pub unsafe fn call<R: super::CType>(fun: CodePtr, args: &[Arg]) -> R {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!(
+        "high_call",
+        result = core::any::type_name::<R>(),
+        nargs = args.len(),
+        fun = ?fun.0,
+    )
+    .entered();
+
     let types = args.iter().map(|arg| arg.type_.clone());
     let cif = middle::Cif::new(types, R::reify().into_middle());
 
-    let values = args.iter().map(|arg| arg.value.clone()).collect::<Vec<_>>();
-    cif.call(fun, &values)
+    let result: MaybeUninit<R> = if args.len() <= STACK_ARGS {
+        let mut values = [middle::Arg::new(&()); STACK_ARGS];
+        for (value, arg) in values.iter_mut().zip(args) {
+            *value = arg.value;
+        }
+        cif.call_unbounded(fun, &values[..args.len()])
+    } else {
+        let values = args.iter().map(|arg| arg.value).collect::<Vec<_>>();
+        cif.call_unbounded(fun, &values)
+    };
+
+    R::check_received(result.as_ptr());
+    result.assume_init()
+}
+
+/// Builds a dynamic call whose types are only known at run time.
+///
+/// Each argument is pushed as a [`middle::Type`](../../middle/struct.Type.html)
+/// together with a [`middle::Value`](../../middle/enum.Value.html), and
+/// the result type is declared once. Before calling,
+/// [`call`](#method.call) checks that every value matches its type, so
+/// that an interpreter cannot pass a mismatched argument by mistake, and
+/// it returns the result as a `Value` too.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::high::call::*;
+/// use libffi::middle::{Type, Value};
+///
+/// #[repr(C)]
+/// struct Pair { a: u8, b: f64 }
+///
+/// extern "C" fn weigh(scale: i32, pair: Pair) -> f64 {
+///     scale as f64 * pair.a as f64 * pair.b
+/// }
+///
+/// let pair = Type::structure(vec![Type::u8(), Type::f64()]);
+/// let result = unsafe {
+///     CallBuilder::new(Type::f64())
+///         .arg(Type::i32(), Value::I32(2))
+///         .arg(pair, Value::Struct(vec![Value::U8(3), Value::F64(0.5)]))
+///         .call(CodePtr(weigh as *mut _))
+/// };
+///
+/// assert_eq!(Ok(Value::F64(3.0)), result);
+///
+/// let mismatched = unsafe {
+///     CallBuilder::new(Type::f64())
+///         .arg(Type::i32(), Value::U8(2))
+///         .call(CodePtr(weigh as *mut _))
+/// };
+///
+/// assert_eq!(Err(libffi::Error::ArgValue { index: 0 }), mismatched);
+/// ```
+#[derive(Clone, Debug)]
+pub struct CallBuilder {
+    args: Vec<(middle::Type, middle::Value)>,
+    result: middle::Type,
+}
+
+impl CallBuilder {
+    /// Starts building a call to a function with the given result type.
+    pub fn new(result: middle::Type) -> Self {
+        CallBuilder {
+            args: vec![],
+            result,
+        }
+    }
+
+    /// Adds an argument of the given type.
+    pub fn arg(mut self, type_: middle::Type, value: middle::Value) -> Self {
+        self.args.push((type_, value));
+        self
+    }
+
+    /// Calls `fun` with the arguments, returning its result.
+    ///
+    /// Returns an error without calling if `fun` is null, an argument
+    /// type has size zero, an argument value does not match its type,
+    /// or a type has no `Value` representation.
+    ///
+    /// # Safety
+    ///
+    /// The declared types must match those of `fun`.
+    pub unsafe fn call(&self, fun: CodePtr) -> crate::Result<middle::Value> {
+        if fun.is_null() {
+            return Err(crate::Error::NullFunction);
+        }
+        for (type_, _) in &self.args {
+            if type_.layout().size() == 0 {
+                return Err(crate::Error::Ffi(crate::low::Error::Typedef));
+            }
+        }
+
+        let types = self.args.iter().map(|(type_, _)| type_.clone());
+        let cif = middle::Cif::prepare(
+            types.collect::<Vec<_>>(),
+            self.result.clone(),
+            middle::ffi_abi_FFI_DEFAULT_ABI,
+        )?;
+        let values = self.args.iter().map(|(_, value)| value.clone());
+        call_values(&cif, fun, &values.collect::<Vec<_>>())
+    }
+}
+
+// Calls `fun` through `cif` with arguments given as `Value`s, checking
+// each against its type, and reads the result as a `Value`.
+pub(crate) unsafe fn call_values(
+    cif: &middle::Cif,
+    fun: CodePtr,
+    args: &[middle::Value],
+) -> crate::Result<middle::Value> {
+    if fun.is_null() {
+        return Err(crate::Error::NullFunction);
+    }
+
+    let raw = &*cif.as_raw_ptr();
+    if !middle::is_representable(raw.rtype) {
+        return Err(crate::Error::UnsupportedType);
+    }
+    if args.len() != raw.nargs as usize {
+        return Err(crate::Error::ArgCount {
+            expected: raw.nargs as usize,
+            actual: args.len(),
+        });
+    }
+
+    let mut frame = middle::CallFrame::new();
+    for (index, value) in args.iter().enumerate() {
+        let type_ = *raw.arg_types.add(index);
+        let size = (*type_).size;
+
+        let mut scratch = vec![0u64; size / 8 + 1];
+        let ptr = scratch.as_mut_ptr() as *mut c_void;
+        if !middle::write_ffi_type(ptr, type_, value) {
+            return Err(crate::Error::ArgValue { index });
+        }
+        let bytes = slice::from_raw_parts(ptr as *const u8, size);
+        frame.push_bytes(bytes, usize::from((*type_).alignment));
+    }
+
+    let size = (*raw.rtype).size.max(mem::size_of::<crate::raw::ffi_arg>());
+    let mut buffer = vec![0u64; size / 8 + 1];
+    let bytes = slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut u8, size);
+    cif.call_with_ret_buffer(fun, frame.args(), bytes);
+
+    Ok(middle::read_result(
+        buffer.as_ptr() as *const c_void,
+        raw.rtype,
+    ))
 }
 
 /// Performs a dynamic call to a C function.
@@ -0,0 +1,165 @@
+//! Closures whose arity is only known at run time.
+//!
+//! The <code>Closure<em>N</em></code> types fix the number and types of
+//! their arguments statically, while
+//! [`middle::Closure`](../../middle/struct.Closure.html) leaves the
+//! callback to interpret a raw argument array and manage the CIF and
+//! userdata itself. A [`ClosureAny`](struct.ClosureAny.html) sits in
+//! between: its argument types are given at run time, but it owns its
+//! CIF, borrows a Rust closure like `Closure1` does, and passes that
+//! closure the arguments as a slice of the right length.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::os::raw::c_void;
+//!
+//! use libffi::high::ClosureAny;
+//! use libffi::middle::Type;
+//!
+//! let sum = |args: &[*const c_void]| {
+//!     args.iter().map(|&arg| unsafe { *(arg as *const u32) }).sum::<u32>()
+//! };
+//!
+//! let closure = ClosureAny::new(vec![Type::u32(); 3], &sum);
+//! let fun: &extern "C" fn(u32, u32, u32) -> u32 = unsafe {
+//!     closure.instantiate_code_ptr()
+//! };
+//!
+//! assert_eq!(6, fun(1, 2, 3));
+//! ```
+
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::marker::PhantomData;
+use core::slice;
+
+#[cfg(feature = "std")]
+use abort_on_panic::abort_on_panic;
+
+use super::{cast_callback, AsCFnPtr, CType};
+use crate::{low, middle};
+
+/// A closure with a run-time list of argument types, whose Rust
+/// callback receives pointers to the arguments.
+///
+/// The result type `R` is static, so that results are written as they
+/// are for the other high-layer closures.
+#[derive(Debug)]
+pub struct ClosureAny<'a, R> {
+    untyped: middle::Closure<'a>,
+    _marker: PhantomData<fn() -> R>,
+}
+
+impl<'a, R: CType> ClosureAny<'a, R> {
+    /// Constructs a closure taking arguments of the given types, which
+    /// calls `callback` with a pointer to each argument.
+    pub fn new<Callback>(args: Vec<middle::Type>, callback: &'a Callback) -> Self
+    where
+        Callback: Fn(&[*const c_void]) -> R + 'a,
+    {
+        let cif = middle::Cif::new(args, R::reify().into_middle());
+        // The CIF's result type is that of `R`.
+        unsafe { Self::new_with_cif(cif, callback) }
+    }
+
+    /// Constructs a closure from a CIF, such as one using a different
+    /// calling convention, which calls `callback` with a pointer to each
+    /// argument.
+    ///
+    /// # Safety
+    ///
+    /// The CIF’s result type must be that of `R`, since results are
+    /// written to libffi’s buffer for it as an `R`.
+    pub unsafe fn new_with_cif<Callback>(cif: middle::Cif, callback: &'a Callback) -> Self
+    where
+        Callback: Fn(&[*const c_void]) -> R + 'a,
+    {
+        let callback_fn = Self::static_callback::<Callback> as extern "C" fn(_, _, _, _);
+        ClosureAny {
+            untyped: middle::Closure::new::<_, R>(
+                cif,
+                unsafe { cast_callback(callback_fn) },
+                callback,
+            ),
+            _marker: PhantomData,
+        }
+    }
+
+    extern "C" fn static_callback<Callback>(
+        cif: &low::ffi_cif,
+        result: *mut c_void,
+        args: *const *const c_void,
+        userdata: &Callback,
+    ) where
+        Callback: Fn(&[*const c_void]) -> R + 'a,
+    {
+        let args = match cif.nargs as usize {
+            0 => &[],
+            nargs => unsafe { slice::from_raw_parts(args, nargs) },
+        };
+        abort_on_panic!("Cannot panic inside FFI callback", {
+            unsafe {
+                middle::write_result(cif, result, userdata(args));
+            }
+        });
+    }
+}
+
+impl<'a, R> ClosureAny<'a, R> {
+    /// Gets the C code pointer that is used to invoke the closure.
+    ///
+    /// It is valid only as long as the closure is alive.
+    pub fn code_ptr(&self) -> low::CodePtr {
+        self.untyped.as_code_ptr()
+    }
+
+    /// Transmutes the C code pointer that is used to invoke the closure
+    /// to a reference to a function pointer type, in order to call it.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be a function pointer type matching the closure’s
+    /// argument and result types.
+    pub unsafe fn instantiate_code_ptr<T>(&self) -> &T {
+        self.untyped.instantiate_code_ptr()
+    }
+}
+
+impl<'a, R> AsCFnPtr for ClosureAny<'a, R> {
+    fn as_code_ptr(&self) -> low::CodePtr {
+        self.untyped.as_code_ptr()
+    }
+
+    fn cif(&self) -> &middle::Cif {
+        self.untyped.cif()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::Type;
+
+    #[test]
+    fn mixed_arguments() {
+        let weigh = |args: &[*const c_void]| unsafe {
+            f64::from(*(args[0] as *const u8)) * *(args[1] as *const f64)
+        };
+
+        let closure = ClosureAny::new(vec![Type::u8(), Type::f64()], &weigh);
+        let fun: &extern "C" fn(u8, f64) -> f64 = unsafe { closure.instantiate_code_ptr() };
+
+        assert_eq!(7.5, fun(3, 2.5));
+    }
+
+    #[test]
+    fn no_arguments() {
+        let count = |args: &[*const c_void]| args.len() as u16;
+
+        let closure = ClosureAny::new(vec![], &count);
+        let fun: &extern "C" fn() -> u16 = unsafe { closure.instantiate_code_ptr() };
+
+        assert_eq!(0, fun());
+    }
+}
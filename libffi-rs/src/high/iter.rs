@@ -0,0 +1,122 @@
+//! Iterators over items that C APIs enumerate through callbacks.
+//!
+//! Many C libraries only offer enumeration as a function that calls a
+//! callback once per item, passing along a `void*` of user data, such as
+//! `for_each(callback, userdata)`. The functions here make the callback
+//! from a [`ClosureMut2`](../struct.ClosureMut2.html), drive the
+//! enumeration, and return the items as an ordinary Rust iterator.
+//!
+//! Since the C function holds control until it has passed every item,
+//! the items are collected before the iterator is returned.
+
+use alloc::vec::{self, Vec};
+use core::ffi::c_void;
+
+use super::{CType, ClosureMut2};
+
+/// The callback type that [`from_callback`](fn.from_callback.html) and
+/// [`from_callback_map`](fn.from_callback_map.html) pass to C.
+pub type Callback<T> = extern "C" fn(T, *mut c_void);
+
+/// Collects the items that a C function passes to a callback.
+///
+/// `drive` receives the callback and must call the C enumeration function
+/// with it. The callback ignores its user data, so `drive` can pass null.
+///
+/// # Safety
+///
+/// The callback is a closure that is freed when `drive` returns, and the
+/// function pointer carries no lifetime, so neither `drive` nor the C
+/// function may keep it and call it afterward.
+///
+/// # Examples
+///
+/// ```
+/// use std::os::raw::c_void;
+/// use std::ptr;
+///
+/// use libffi::high::iter::{self, Callback};
+///
+/// // A C API in the usual shape.
+/// extern "C" fn for_each_square(n: u32, callback: Callback<u32>, data: *mut c_void) {
+///     for i in 1..=n {
+///         callback(i * i, data);
+///     }
+/// }
+///
+/// let squares = unsafe {
+///     iter::from_callback(|callback| for_each_square(4, callback, ptr::null_mut()))
+/// };
+///
+/// assert_eq!(vec![1, 4, 9, 16], squares.collect::<Vec<u32>>());
+/// ```
+pub unsafe fn from_callback<T, F>(drive: F) -> vec::IntoIter<T>
+where
+    T: CType,
+    F: FnOnce(Callback<T>),
+{
+    from_callback_map(drive, |item| item)
+}
+
+/// Collects the items that a C function passes to a callback, converting
+/// each with `map` during the callback.
+///
+/// This is for items, such as pointers into the C library’s own
+/// storage, that are only valid until the callback returns; `map` can
+/// copy out what it needs while they are.
+///
+/// If `map` panics, the process aborts, since the panic cannot unwind
+/// through C.
+///
+/// # Safety
+///
+/// As for [`from_callback`](fn.from_callback.html): the callback must not
+/// be called after `drive` returns.
+pub unsafe fn from_callback_map<T, U, F, M>(drive: F, mut map: M) -> vec::IntoIter<U>
+where
+    T: CType,
+    F: FnOnce(Callback<T>),
+    M: FnMut(T) -> U,
+{
+    let mut items = Vec::new();
+    {
+        let mut push = |item: T, _data: *mut c_void| items.push(map(item));
+        let closure = ClosureMut2::new(&mut push);
+        drive(*closure.code_ptr());
+    }
+    items.into_iter()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CStr;
+    use std::os::raw::c_char;
+    use std::ptr;
+
+    extern "C" fn for_each_name(callback: Callback<*const c_char>, data: *mut c_void) {
+        for name in &[b"alpha\0", b"gamma\0"] {
+            // The strings only live until the callback returns.
+            let owned = name.to_vec();
+            callback(owned.as_ptr() as *const c_char, data);
+        }
+    }
+
+    #[test]
+    fn map_borrowed_items() {
+        let names = unsafe {
+            from_callback_map(
+                |callback| for_each_name(callback, ptr::null_mut()),
+                |name| CStr::from_ptr(name).to_string_lossy().into_owned(),
+            )
+        };
+
+        assert_eq!(vec!["alpha", "gamma"], names.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn no_items() {
+        let items = unsafe { from_callback::<u8, _>(|_| {}) };
+        assert_eq!(0, items.len());
+    }
+}
@@ -0,0 +1,357 @@
+//! A Rust representation of C `long double`.
+
+// The associated constants such as `f64::INFINITY` need Rust 1.43.
+#![allow(clippy::legacy_numeric_constants)]
+
+use core::fmt;
+
+use super::types::{CType, Type};
+use crate::middle;
+
+/// A C `long double` value.
+///
+/// Rust has no type for `long double`, whose format differs between
+/// targets: it is the same as `double` on MSVC, Apple AArch64, and 32-bit
+/// MIPS, the x87 80-bit extended format on other x86 and x86-64 targets,
+/// and IEEE binary128 on AArch64 Linux, RISC-V, s390x, and others. This
+/// type has the size and alignment of the target’s `long double` and
+/// holds its bits opaquely, converting to and from `f64` for arithmetic.
+///
+/// Converting from `f64` is exact. Converting to `f64` rounds to nearest,
+/// ties to even, and so loses precision where `long double` has more.
+///
+/// This item is only available on targets whose `long double` format is
+/// known and where libffi has `ffi_type_longdouble`. That leaves out
+/// x86-64 Android, whose `long double` is binary128 but which libffi
+/// passes in the x87 format.
+///
+/// # Warning
+///
+/// Rust has no `long double`, and passes this type by value as it would
+/// a struct, which is not how C passes `long double`. So a function
+/// pointer typed with a `LongDouble` argument or result, such as the
+/// `extern "C" fn(LongDouble)` that a closure’s `code_ptr` returns, has
+/// undefined behavior when called from Rust. Pass it only through libffi,
+/// as [`call`](call/fn.call.html) does, or to closures called from C.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::high::{call::*, LongDouble};
+///
+/// // Rust cannot call `sqrtl` itself, so it is declared without its
+/// // signature for libffi to call.
+/// extern "C" {
+///     fn sqrtl();
+/// }
+///
+/// let two = LongDouble::from(2.0);
+/// let root: LongDouble = unsafe {
+///     call(CodePtr(sqrtl as *mut _), &[arg(&two)])
+/// };
+///
+/// assert_eq!(core::f64::consts::SQRT_2, root.to_f64());
+/// ```
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct LongDouble(Repr);
+
+#[cfg(libffi_long_double = "double")]
+type Repr = f64;
+
+// The 64-bit significand, with its explicit integer bit, then the sign
+// and 15-bit exponent, padded to 12 bytes on x86 and 16 on x86-64.
+#[cfg(all(libffi_long_double = "x87", target_arch = "x86"))]
+#[derive(Clone, Copy)]
+#[repr(C, align(4))]
+struct Repr([u16; 6]);
+
+#[cfg(all(libffi_long_double = "x87", target_arch = "x86_64"))]
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct Repr([u16; 8]);
+
+// A native-endian 128-bit integer, which is how binary128 is laid out in
+// memory. Only s390x aligns it to less than its size.
+#[cfg(libffi_long_double = "binary128")]
+#[derive(Clone, Copy)]
+#[cfg_attr(not(target_arch = "s390x"), repr(C, align(16)))]
+#[cfg_attr(target_arch = "s390x", repr(C, align(8)))]
+struct Repr([u8; 16]);
+
+impl LongDouble {
+    /// Converts an `f64` to a `long double`, exactly.
+    pub fn from_f64(x: f64) -> Self {
+        LongDouble(repr_from_f64(x))
+    }
+
+    /// Converts to the nearest `f64`, rounding ties to even.
+    pub fn to_f64(self) -> f64 {
+        repr_to_f64(self.0)
+    }
+}
+
+impl From<f64> for LongDouble {
+    fn from(x: f64) -> Self {
+        LongDouble::from_f64(x)
+    }
+}
+
+impl From<LongDouble> for f64 {
+    fn from(x: LongDouble) -> Self {
+        x.to_f64()
+    }
+}
+
+impl Default for LongDouble {
+    fn default() -> Self {
+        LongDouble::from_f64(0.0)
+    }
+}
+
+impl fmt::Debug for LongDouble {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("LongDouble").field(&self.to_f64()).finish()
+    }
+}
+
+unsafe impl CType for LongDouble {
+    fn reify() -> Type<Self> {
+        unsafe { Type::from_middle(middle::Type::longdouble()) }
+    }
+}
+
+impl middle::ArgTarget for LongDouble {}
+
+#[cfg(libffi_long_double = "double")]
+fn repr_from_f64(x: f64) -> Repr {
+    x
+}
+
+#[cfg(libffi_long_double = "double")]
+fn repr_to_f64(repr: Repr) -> f64 {
+    repr
+}
+
+#[cfg(libffi_long_double = "x87")]
+fn repr_from_f64(x: f64) -> Repr {
+    let (exponent, significand) = match decompose(x) {
+        Decomposed::Zero => (0, 0),
+        Decomposed::Infinite => (0x7fff, 1 << 63),
+        // The quiet bit moves from bit 51 to bit 62 with the payload.
+        Decomposed::Nan(payload) => (0x7fff, 1 << 63 | payload << 11),
+        Decomposed::Finite(exponent, significand) => ((exponent + 16383) as u16, significand),
+    };
+    let sign = if x.is_sign_negative() { 0x8000 } else { 0 };
+
+    let mut repr = Repr(Default::default());
+    for (i, word) in repr.0.iter_mut().take(4).enumerate() {
+        *word = (significand >> (16 * i)) as u16;
+    }
+    repr.0[4] = sign | exponent;
+    repr
+}
+
+#[cfg(libffi_long_double = "x87")]
+fn repr_to_f64(repr: Repr) -> f64 {
+    let significand = (0..4).fold(0u64, |acc, i| acc | u64::from(repr.0[i]) << (16 * i));
+    let negative = repr.0[4] & 0x8000 != 0;
+    let exponent = i32::from(repr.0[4] & 0x7fff);
+
+    let magnitude = if exponent == 0x7fff {
+        if significand << 1 == 0 {
+            core::f64::INFINITY
+        } else {
+            f64::from_bits(0x7ff8 << 48 | (significand << 1) >> 12)
+        }
+    } else {
+        // Denormals have the exponent of the smallest normal number.
+        let exponent = exponent.max(1) - 16383 - 63;
+        round_to_f64(exponent, u128::from(significand))
+    };
+
+    if negative {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+#[cfg(libffi_long_double = "binary128")]
+fn repr_from_f64(x: f64) -> Repr {
+    const MANTISSA: u128 = (1 << 112) - 1;
+
+    let bits: u128 = match decompose(x) {
+        Decomposed::Zero => 0,
+        Decomposed::Infinite => 0x7fff << 112,
+        // The quiet bit moves from bit 51 to bit 111 with the payload.
+        Decomposed::Nan(payload) => 0x7fff << 112 | u128::from(payload) << 60,
+        Decomposed::Finite(exponent, significand) => {
+            ((exponent + 16383) as u128) << 112 | (u128::from(significand) << 49) & MANTISSA
+        }
+    };
+    let bits = bits | if x.is_sign_negative() { 1 << 127 } else { 0 };
+
+    Repr(bits.to_ne_bytes())
+}
+
+#[cfg(libffi_long_double = "binary128")]
+fn repr_to_f64(repr: Repr) -> f64 {
+    const MANTISSA: u128 = (1 << 112) - 1;
+
+    let bits = u128::from_ne_bytes(repr.0);
+    let negative = bits >> 127 != 0;
+    let exponent = (bits >> 112) as i32 & 0x7fff;
+    let mantissa = bits & MANTISSA;
+
+    let magnitude = if exponent == 0x7fff {
+        if mantissa == 0 {
+            core::f64::INFINITY
+        } else {
+            f64::from_bits(0x7ff8 << 48 | (mantissa >> 60) as u64)
+        }
+    } else if exponent == 0 {
+        round_to_f64(1 - 16383 - 112, mantissa)
+    } else {
+        round_to_f64(exponent - 16383 - 112, mantissa | 1 << 112)
+    };
+
+    if negative {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+// An `f64` taken apart, ignoring its sign.
+#[cfg(not(libffi_long_double = "double"))]
+enum Decomposed {
+    Zero,
+    Infinite,
+    // The mantissa bits, including the quiet bit.
+    Nan(u64),
+    // The unbiased exponent of the leading bit, and the significand with
+    // its leading bit at bit 63.
+    Finite(i32, u64),
+}
+
+#[cfg(not(libffi_long_double = "double"))]
+fn decompose(x: f64) -> Decomposed {
+    const MANTISSA: u64 = (1 << 52) - 1;
+
+    let bits = x.to_bits();
+    let exponent = (bits >> 52) as i32 & 0x7ff;
+    let mantissa = bits & MANTISSA;
+
+    match (exponent, mantissa) {
+        (0, 0) => Decomposed::Zero,
+        (0x7ff, 0) => Decomposed::Infinite,
+        (0x7ff, _) => Decomposed::Nan(mantissa),
+        (0, _) => {
+            let shift = mantissa.leading_zeros();
+            Decomposed::Finite(-1011 - shift as i32, mantissa << shift)
+        }
+        _ => Decomposed::Finite(exponent - 1023, (mantissa | 1 << 52) << 11),
+    }
+}
+
+// Rounds `significand * 2^exponent` to the nearest `f64`, ties to even.
+#[cfg(not(libffi_long_double = "double"))]
+fn round_to_f64(exponent: i32, significand: u128) -> f64 {
+    if significand == 0 {
+        return 0.0;
+    }
+
+    // Put the leading bit at bit 127, so that the value is
+    // 1.f * 2^leading.
+    let shift = significand.leading_zeros();
+    let significand = significand << shift;
+    let leading = exponent + 127 - shift as i32;
+
+    if leading > 1023 {
+        return core::f64::INFINITY;
+    }
+
+    // Keep 53 bits, or fewer for subnormal results.
+    let subnormal = (-1022 - leading).max(0) as u32;
+    let dropped = 75 + subnormal;
+    if dropped > 128 {
+        return 0.0;
+    }
+
+    let (mut kept, rest, half) = if dropped == 128 {
+        (0, significand, 1 << 127)
+    } else {
+        (
+            (significand >> dropped) as u64,
+            significand & ((1 << dropped) - 1),
+            1 << (dropped - 1),
+        )
+    };
+    if rest > half || (rest == half && kept & 1 == 1) {
+        kept += 1;
+    }
+
+    // The leading bit, or a carry out of a subnormal, increments the
+    // exponent field, and a carry out of the largest finite value gives
+    // infinity.
+    let bits = if subnormal > 0 {
+        kept
+    } else {
+        (((leading + 1022) as u64) << 52) + kept
+    };
+    f64::from_bits(bits)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::high::call::{arg, call, CodePtr};
+    use std::mem;
+
+    #[test]
+    fn layout() {
+        let ty = LongDouble::reify().into_middle();
+        let raw = unsafe { &*ty.as_raw_ptr() };
+        assert_eq!(raw.size, mem::size_of::<LongDouble>());
+        assert_eq!(usize::from(raw.alignment), mem::align_of::<LongDouble>());
+    }
+
+    #[test]
+    fn round_trip() {
+        for &x in &[
+            0.0,
+            -0.0,
+            1.0,
+            -2.5,
+            std::f64::consts::PI,
+            std::f64::MAX,
+            std::f64::MIN_POSITIVE,
+            std::f64::MIN_POSITIVE / 3.0,
+            5e-324,
+            std::f64::INFINITY,
+            std::f64::NEG_INFINITY,
+        ] {
+            let y = LongDouble::from(x).to_f64();
+            assert_eq!(x.to_bits(), y.to_bits(), "{} round-tripped to {}", x, y);
+        }
+
+        assert!(LongDouble::from(std::f64::NAN).to_f64().is_nan());
+    }
+
+    #[test]
+    fn arithmetic() {
+        extern "C" {
+            fn fmal();
+        }
+
+        let (x, y, z) = (
+            LongDouble::from(1.5),
+            LongDouble::from(4.0),
+            LongDouble::from(-0.25),
+        );
+        let result: LongDouble =
+            unsafe { call(CodePtr(fmal as *mut _), &[arg(&x), arg(&y), arg(&z)]) };
+        assert_eq!(5.75, result.to_f64());
+    }
+}
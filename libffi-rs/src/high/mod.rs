@@ -6,8 +6,8 @@
 //! <code>Closure<span></span>Mut<em>N</em></code>,
 //! and <code>Closure<span></span>Once<em>N</em></code>,
 //! for natural numbers *`N`*
-//! from `0` to `12` (as of
-//! now). These represent C closures of *`N`* arguments, which can be
+//! from `0` to `12`, or to `16` or `32` with the `arity16` or `arity32`
+//! feature. These represent C closures of *`N`* arguments, which can be
 //! used to turn Rust lambdas (or in generally, anything that implements
 //! `Fn` or `FnMut`) into ordinary C function pointers. For example, a
 //! Rust value of type `Fn(u32, u32) -> u64` can be turned into a
@@ -33,6 +33,7 @@
 //! Rust lambda into a C function pointer.
 //!
 //! ```
+//! # #[cfg(feature = "closures")] {
 //! use libffi::high::ClosureMut1;
 //!
 //! let mut x = 0u64;
@@ -44,6 +45,7 @@
 //! assert_eq!(5, counter(5));
 //! assert_eq!(6, counter(1));
 //! assert_eq!(8, counter(2));
+//! # }
 //! ```
 //!
 //! Note that in the above example, `counter` is an ordinary C function
@@ -53,6 +55,7 @@
 //! a vector:
 //!
 //! ```
+//! # #[cfg(feature = "closures")] {
 //! use libffi::high::ClosureOnce3;
 //!
 //! let v = vec![1, 2, 3, 4, 5];
@@ -64,25 +67,146 @@
 //! let call = closure.code_ptr();
 //!
 //! assert_eq!(12, call(2, 3, 4));
+//! # }
 //! ```
 //!
-//! Invoking the closure a second time will panic.
+//! Invoking the closure a second time will abort the process, unless
+//! the closure is constructed with a [`RecallPolicy`](enum.RecallPolicy.html)
+//! that says otherwise.
 
+#[macro_use]
+mod abort;
+
+#[cfg(feature = "closures")]
+use alloc::boxed::Box;
+#[cfg(feature = "closures")]
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
 use abort_on_panic::abort_on_panic;
 
-pub use crate::middle::{ffi_abi_FFI_DEFAULT_ABI, FfiAbi};
+pub use crate::middle::{ffi_abi_FFI_DEFAULT_ABI, Abi, FfiAbi};
 
 pub mod types;
-pub use types::{CType, Type};
+#[cfg(libffi_f128)]
+pub use types::F128;
+pub use types::{CStrPtr, CType, Type};
+
+#[cfg(feature = "arrays")]
+mod array;
+
+#[cfg(libffi_long_double)]
+mod long_double;
+#[cfg(libffi_long_double)]
+pub use long_double::LongDouble;
 
 pub mod call;
 pub use call::*;
 
+#[cfg(feature = "closures")]
+pub mod threadsafe;
+#[cfg(feature = "closures")]
+pub use threadsafe::{SendClosure, SyncClosure};
+
+#[cfg(feature = "closures")]
+pub mod panic;
+#[cfg(all(feature = "closures", feature = "std"))]
+pub use panic::{take_last_panic, PanicPolicy, RecallPolicy};
+
+#[cfg(feature = "closures")]
+pub mod dynamic;
+#[cfg(feature = "closures")]
+pub use dynamic::ClosureAny;
+
+#[cfg(feature = "closures")]
+pub mod tuple;
+#[cfg(feature = "closures")]
+pub use tuple::{ArgTuple, Closure};
+
+#[cfg(feature = "closures")]
+pub mod iter;
+
+#[cfg(feature = "closures")]
+pub mod vtable;
+
+pub mod split;
+
+#[cfg(feature = "std")]
+pub mod registry;
+
+/// Closures of any type, seen as untyped C code pointers.
+///
+/// This is implemented by every closure type in the high layer, so
+/// that registries and other generic plumbing can hold closures of
+/// different types together, for example as `Box<dyn AsCFnPtr>`.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::high::{AsCFnPtr, Closure0, Closure2};
+///
+/// let zero = || 0u8;
+/// let add = |x: u32, y: u32| x + y;
+///
+/// let closures: Vec<Box<dyn AsCFnPtr>> = vec![
+///     Box::new(Closure0::new(&zero)),
+///     Box::new(Closure2::new(&add)),
+/// ];
+///
+/// for closure in &closures {
+///     assert!(!closure.as_code_ptr().is_null());
+/// }
+/// assert_eq!(2, unsafe { (*closures[1].cif().as_raw_ptr()).nargs });
+/// ```
+#[cfg(feature = "closures")]
+pub trait AsCFnPtr {
+    /// The untyped C code pointer that invokes the closure. It is valid
+    /// only as long as the closure is alive.
+    fn as_code_ptr(&self) -> crate::low::CodePtr;
+
+    /// The CIF describing the closure’s signature.
+    fn cif(&self) -> &crate::middle::Cif;
+}
+
+#[cfg(feature = "closures")]
+impl<C: AsCFnPtr> AsCFnPtr for SendClosure<C> {
+    fn as_code_ptr(&self) -> crate::low::CodePtr {
+        (**self).as_code_ptr()
+    }
+
+    fn cif(&self) -> &crate::middle::Cif {
+        (**self).cif()
+    }
+}
+
+#[cfg(feature = "closures")]
+impl<C: AsCFnPtr> AsCFnPtr for SyncClosure<C> {
+    fn as_code_ptr(&self) -> crate::low::CodePtr {
+        (**self).as_code_ptr()
+    }
+
+    fn cif(&self) -> &crate::middle::Cif {
+        (**self).cif()
+    }
+}
+
+// The callbacks of the typed closures take libffi’s result buffer as a
+// `*mut c_void` rather than the `&mut R` of their public callback types,
+// because `middle::write_result` may write a whole `ffi_arg` to it, which
+// is more than a reference to a small `R` covers. The two are
+// ABI-compatible, and this converts between them.
+#[cfg(feature = "closures")]
+pub(crate) unsafe fn cast_callback<F: Copy, G: Copy>(callback: F) -> G {
+    debug_assert_eq!(core::mem::size_of::<F>(), core::mem::size_of::<G>());
+    core::mem::transmute_copy(&callback)
+}
+
 macro_rules! define_closure_mod {
     (
         $module:ident $cif:ident
           $callback:ident $callback_mut:ident $callback_once:ident
-          $closure:ident $closure_mut:ident $closure_once:ident;
+          $closure:ident $closure_mut:ident $closure_once:ident
+          $fn_ptr:ident;
         $( $T:ident )*
     )
         =>
@@ -90,13 +214,15 @@ macro_rules! define_closure_mod {
         /// CIF and closure types organized by function arity.
         #[allow(clippy::too_many_arguments)]
         pub mod $module {
-            use std::any::Any;
-            use std::marker::PhantomData;
-            use std::{mem, process, ptr};
-            use std::io::{self, Write};
+            use core::marker::PhantomData;
+            #[cfg(feature = "closures")]
+            use core::{any::Any, ffi::c_void, fmt, mem, ops::Deref,
+                       sync::atomic::Ordering};
 
             use super::*;
-            use crate::{low, middle};
+            use crate::middle;
+            #[cfg(feature = "closures")]
+            use crate::low;
 
             /// A typed CIF, which statically tracks argument and result types.
             pub struct $cif<$( $T, )* R> {
@@ -115,7 +241,26 @@ macro_rules! define_closure_mod {
                     $cif { untyped: cif, _marker: PhantomData }
                 }
 
+                /// Creates a new statically-typed CIF with the given argument
+                /// and result types, prepared for the given calling
+                /// convention.
+                #[allow(non_snake_case)]
+                pub fn new_with_abi($( $T: Type<$T>, )*
+                                    result: Type<R>,
+                                    abi: FfiAbi) -> Self {
+                    let cif = middle::Cif::new_with_abi(
+                        vec![$( $T.into_middle() ),*].into_iter(),
+                        result.into_middle(),
+                        abi);
+                    $cif { untyped: cif, _marker: PhantomData }
+                }
+
                 /// Sets the CIF to use the given calling convention.
+                ///
+                /// The CIF was prepared for the convention it was
+                /// created with, which some targets depend on, so prefer
+                /// [`new_with_abi`](#method.new_with_abi) or
+                /// [`reify_with_abi`](#method.reify_with_abi).
                 pub fn set_abi(&mut self, abi: FfiAbi) {
                     self.untyped.set_abi(abi);
                 }
@@ -127,6 +272,13 @@ macro_rules! define_closure_mod {
                 pub fn reify() -> Self {
                     Self::new($( $T::reify(), )* R::reify())
                 }
+
+                /// Creates a new statically-typed CIF by reifying the
+                /// argument types, prepared for the given calling
+                /// convention.
+                pub fn reify_with_abi(abi: FfiAbi) -> Self {
+                    Self::new_with_abi($( $T::reify(), )* R::reify(), abi)
+                }
             }
 
             // We use tuples of pointers to describe the arguments, and we
@@ -137,6 +289,7 @@ macro_rules! define_closure_mod {
             // behavior.
 
             /// The type of function called from an immutable, typed closure.
+            #[cfg(feature = "closures")]
             pub type $callback<U, $( $T, )* R>
                 = extern "C" fn(cif:      &low::ffi_cif,
                                 result:   &mut R,
@@ -145,11 +298,16 @@ macro_rules! define_closure_mod {
 
             /// An immutable, typed closure with the given argument and result
             /// types.
+            #[cfg(feature = "closures")]
             pub struct $closure<'a, $( $T, )* R> {
                 untyped: middle::Closure<'a>,
+                // Owns the userdata of closures with a panic policy;
+                // declared after `untyped` so that it is dropped after.
+                _policy: Option<Box<dyn panic::Owned + 'a>>,
                 _marker: PhantomData<fn($( $T, )*) -> R>,
             }
 
+            #[cfg(feature = "closures")]
             impl<'a, $($T: CType,)* R: CType> $closure<'a, $($T,)* R> {
                 /// Constructs a typed closure callable from C from a
                 /// Rust closure.
@@ -158,8 +316,39 @@ macro_rules! define_closure_mod {
                 {
                     Self::new_with_cif($cif::reify(), callback)
                 }
+
+                /// Constructs a typed closure callable from C from a
+                /// `Sync` Rust closure. The result may be moved to or
+                /// shared with other threads.
+                pub fn new_sync<Callback>(callback: &'a Callback)
+                                          -> SyncClosure<Self>
+                    where Callback: Fn($( $T, )*) -> R + Sync + 'a
+                {
+                    SyncClosure::new(Self::new(callback))
+                }
+
+                /// Constructs a typed closure callable from C from a
+                /// Rust closure that receives the arguments by
+                /// reference.
+                pub fn new_by_ref<Callback>(callback: &'a Callback) -> Self
+                    where Callback: Fn($( &$T, )*) -> R + 'a
+                {
+                    Self::new_with_cif_by_ref($cif::reify(), callback)
+                }
+
+                /// Constructs a typed closure callable from C from a
+                /// Rust closure, using the calling convention of
+                /// `extern "system"` functions, for Win32 callbacks.
+                ///
+                /// Call it through [`system_code_ptr`](#method.system_code_ptr).
+                pub fn new_system<Callback>(callback: &'a Callback) -> Self
+                    where Callback: Fn($( $T, )*) -> R + 'a
+                {
+                    Self::new_with_cif($cif::reify_with_abi(Abi::system().as_raw()), callback)
+                }
             }
 
+            #[cfg(feature = "closures")]
             impl<'a, $( $T, )* R> $closure<'a, $( $T, )* R> {
                 /// Gets the C code pointer that is used to invoke the
                 /// closure.
@@ -169,6 +358,26 @@ macro_rules! define_closure_mod {
                     }
                 }
 
+                /// Gets the C function pointer that is used to invoke
+                /// the closure, by value. It cannot outlive the closure.
+                pub fn fn_ptr(&self) -> $fn_ptr<'_, $( $T, )* R> {
+                    $fn_ptr::new(self.code_ptr())
+                }
+
+                /// Gets the C code pointer of a closure constructed with
+                /// `new_system`, typed as an `extern "system"` function.
+                ///
+                /// Returns `None` unless the closure’s CIF uses
+                /// [`Abi::system`](../middle/enum.Abi.html#method.system).
+                pub fn system_code_ptr(&self) -> Option<&extern "system" fn($( $T, )*) -> R> {
+                    let abi = unsafe { (*self.untyped.cif().as_raw_ptr()).abi };
+                    if abi == Abi::system().as_raw() {
+                        Some(unsafe { self.untyped.instantiate_code_ptr() })
+                    } else {
+                        None
+                    }
+                }
+
                 /// Constructs a typed closure callable from C from a CIF
                 /// describing the calling convention for the resulting
                 /// function, a callback for the function to call, and
@@ -185,12 +394,28 @@ macro_rules! define_closure_mod {
                                                userdata);
                     $closure {
                         untyped: closure,
+                        _policy: None,
                         _marker: PhantomData,
                     }
                 }
             }
 
-            impl<'a, $( $T: Copy, )* R> $closure<'a, $( $T, )* R> {
+            #[cfg(feature = "closures")]
+            impl<$( $T, )* R> $closure<'static, $( $T, )* R> {
+                /// Leaks the closure, returning a C function pointer that
+                /// stays valid for the rest of the program.
+                ///
+                /// This is for callbacks that are registered once and
+                /// never unregistered, such as signal handlers.
+                pub fn leak(self) -> extern "C" fn($( $T, )*) -> R {
+                    let fun = *self.code_ptr();
+                    mem::forget(self);
+                    fun
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T: CType, )* R> $closure<'a, $( $T, )* R> {
                 /// Constructs a typed closure callable from C from a CIF
                 /// describing the calling convention for the resulting
                 /// function and the Rust closure to call.
@@ -198,29 +423,120 @@ macro_rules! define_closure_mod {
                                               callback: &'a Callback) -> Self
                     where Callback: Fn($( $T, )*) -> R + 'a
                 {
+                    let callback_fn = Self::static_callback::<Callback>
+                        as extern "C" fn(_, _, _, _);
                     Self::from_parts(cif,
-                                     Self::static_callback,
+                                     unsafe { cast_callback(callback_fn) },
                                      callback)
                 }
 
                 #[allow(non_snake_case)]
                 extern "C" fn static_callback<Callback>
-                    (_cif:     &low::ffi_cif,
-                     result:   &mut R,
-                     &($( &$T, )*):
-                               &($( &$T, )*),
+                    (cif:      &low::ffi_cif,
+                     result:   *mut c_void,
+                     args:     &($( &$T, )*),
                      userdata: &Callback)
                   where Callback: Fn($( $T, )*) -> R + 'a
                 {
+                    let &($( $T, )*) = args;
+                    $( let $T = types::receive($T); )*
                     abort_on_panic!("Cannot panic inside FFI callback", {
                         unsafe {
-                            ptr::write(result, userdata($( $T, )*));
+                            middle::write_result(cif, result, userdata($( $T, )*));
+                        }
+                    });
+                }
+            }
+
+            #[cfg(all(feature = "closures", feature = "std"))]
+            impl<'a, $( $T: CType, )* R: CType + 'a> $closure<'a, $( $T, )* R> {
+                /// Constructs a typed closure callable from C from a
+                /// Rust closure, with the given policy for when the Rust
+                /// closure panics.
+                pub fn new_with_policy<Callback>(callback: &'a Callback,
+                                                 policy: PanicPolicy<R>)
+                                                 -> Self
+                    where Callback: Fn($( $T, )*) -> R + 'a
+                {
+                    let userdata = Box::new(panic::WithPolicy {
+                        callback,
+                        policy,
+                    });
+                    // The box is owned by the result, and outlives the
+                    // untyped closure borrowing it.
+                    let borrow = unsafe {
+                        &*(&*userdata as *const panic::WithPolicy<_, R>)
+                    };
+                    let callback_fn = Self::static_callback_with_policy::<Callback>
+                        as extern "C" fn(_, _, _, _);
+                    let mut closure =
+                        Self::from_parts($cif::reify(),
+                                         unsafe { cast_callback(callback_fn) },
+                                         borrow);
+                    closure._policy = Some(userdata);
+                    closure
+                }
+
+                #[allow(non_snake_case)]
+                extern "C" fn static_callback_with_policy<Callback>
+                    (cif:      &low::ffi_cif,
+                     result:   *mut c_void,
+                     args:     &($( &$T, )*),
+                     userdata: &panic::WithPolicy<&'a Callback, R>)
+                  where Callback: Fn($( $T, )*) -> R + 'a
+                {
+                    let &($( $T, )*) = args;
+                    $( let $T = types::receive($T); )*
+                    let callback = userdata.callback;
+                    userdata.policy.invoke(cif, result, || callback($( $T, )*));
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T: CType, )* R> $closure<'a, $( $T, )* R> {
+                /// Constructs a typed closure callable from C from a CIF
+                /// describing the calling convention for the resulting
+                /// function and a Rust closure that receives the
+                /// arguments by reference.
+                ///
+                /// Unlike [`new_with_cif`](#method.new_with_cif), this
+                /// does not copy the arguments, so large structs are
+                /// passed to the Rust closure in place. Each argument is
+                /// still checked with
+                /// [`CType::check_received`](trait.CType.html#method.check_received)
+                /// first.
+                pub fn new_with_cif_by_ref<Callback>(cif: $cif<$( $T, )* R>,
+                                                     callback: &'a Callback)
+                                                     -> Self
+                    where Callback: Fn($( &$T, )*) -> R + 'a
+                {
+                    let callback_fn = Self::static_callback_by_ref::<Callback>
+                        as extern "C" fn(_, _, _, _);
+                    Self::from_parts(cif,
+                                     unsafe { cast_callback(callback_fn) },
+                                     callback)
+                }
+
+                #[allow(non_snake_case)]
+                extern "C" fn static_callback_by_ref<Callback>
+                    (cif:      &low::ffi_cif,
+                     result:   *mut c_void,
+                     &($( $T, )*):
+                               &($( &$T, )*),
+                     userdata: &Callback)
+                  where Callback: Fn($( &$T, )*) -> R + 'a
+                {
+                    $( unsafe { <$T as CType>::check_received($T) }; )*
+                    abort_on_panic!("Cannot panic inside FFI callback", {
+                        unsafe {
+                            middle::write_result(cif, result, userdata($( $T, )*));
                         }
                     });
                 }
             }
 
             /// The type of function called from a mutable, typed closure.
+            #[cfg(feature = "closures")]
             pub type $callback_mut<U, $( $T, )* R>
                 = extern "C" fn(cif:      &low::ffi_cif,
                                 result:   &mut R,
@@ -229,11 +545,16 @@ macro_rules! define_closure_mod {
 
             /// A mutable, typed closure with the given argument and
             /// result types.
+            #[cfg(feature = "closures")]
             pub struct $closure_mut<'a, $( $T, )* R> {
                 untyped: middle::Closure<'a>,
+                // Owns the userdata of closures with a panic policy;
+                // declared after `untyped` so that it is dropped after.
+                _policy: Option<Box<dyn panic::Owned + 'a>>,
                 _marker: PhantomData<fn($( $T, )*) -> R>,
             }
 
+            #[cfg(feature = "closures")]
             impl<'a, $($T: CType,)* R: CType>
                 $closure_mut<'a, $($T,)* R>
             {
@@ -244,8 +565,39 @@ macro_rules! define_closure_mod {
                 {
                     Self::new_with_cif($cif::reify(), callback)
                 }
+
+                /// Constructs a typed closure callable from C from a
+                /// `Send` Rust closure. The result may be moved to
+                /// another thread.
+                pub fn new_send<Callback>(callback: &'a mut Callback)
+                                          -> SendClosure<Self>
+                    where Callback: FnMut($( $T, )*) -> R + Send + 'a
+                {
+                    SendClosure::new(Self::new(callback))
+                }
+
+                /// Constructs a typed closure callable from C from a
+                /// Rust closure that receives the arguments by
+                /// reference.
+                pub fn new_by_ref<Callback>(callback: &'a mut Callback) -> Self
+                    where Callback: FnMut($( &$T, )*) -> R + 'a
+                {
+                    Self::new_with_cif_by_ref($cif::reify(), callback)
+                }
+
+                /// Constructs a typed closure callable from C from a
+                /// Rust closure, using the calling convention of
+                /// `extern "system"` functions, for Win32 callbacks.
+                ///
+                /// Call it through [`system_code_ptr`](#method.system_code_ptr).
+                pub fn new_system<Callback>(callback: &'a mut Callback) -> Self
+                    where Callback: FnMut($( $T, )*) -> R + 'a
+                {
+                    Self::new_with_cif($cif::reify_with_abi(Abi::system().as_raw()), callback)
+                }
             }
 
+            #[cfg(feature = "closures")]
             impl<'a, $( $T, )* R> $closure_mut<'a, $( $T, )* R> {
                 /// Gets the C code pointer that is used to invoke the
                 /// closure.
@@ -255,6 +607,26 @@ macro_rules! define_closure_mod {
                     }
                 }
 
+                /// Gets the C function pointer that is used to invoke
+                /// the closure, by value. It cannot outlive the closure.
+                pub fn fn_ptr(&self) -> $fn_ptr<'_, $( $T, )* R> {
+                    $fn_ptr::new(self.code_ptr())
+                }
+
+                /// Gets the C code pointer of a closure constructed with
+                /// `new_system`, typed as an `extern "system"` function.
+                ///
+                /// Returns `None` unless the closure’s CIF uses
+                /// [`Abi::system`](../middle/enum.Abi.html#method.system).
+                pub fn system_code_ptr(&self) -> Option<&extern "system" fn($( $T, )*) -> R> {
+                    let abi = unsafe { (*self.untyped.cif().as_raw_ptr()).abi };
+                    if abi == Abi::system().as_raw() {
+                        Some(unsafe { self.untyped.instantiate_code_ptr() })
+                    } else {
+                        None
+                    }
+                }
+
                 /// Constructs a typed closure callable from C from a CIF
                 /// describing the calling convention for the resulting
                 /// function, a callback for the function to call, and
@@ -271,12 +643,39 @@ macro_rules! define_closure_mod {
                                                    userdata);
                     $closure_mut {
                         untyped: closure,
+                        _policy: None,
                         _marker: PhantomData,
                     }
                 }
             }
 
-            impl<'a, $( $T: Copy, )* R> $closure_mut<'a, $( $T, )* R> {
+            #[cfg(feature = "closures")]
+            impl<$( $T, )* R> $closure_mut<'static, $( $T, )* R> {
+                /// Leaks the closure, returning a C function pointer that
+                /// stays valid for the rest of the program.
+                ///
+                /// This is for callbacks that are registered once and
+                /// never unregistered, such as signal handlers.
+                ///
+                /// # Safety
+                ///
+                /// The closure does not record whether its callback is
+                /// `Send`, but the function pointer can be called from any
+                /// thread. It must only be called from threads that the
+                /// callback could be sent to, and never re-entrantly or
+                /// from two threads at once, since each call borrows the
+                /// callback mutably. A signal handler may interrupt a
+                /// call in progress, so it must not share a closure with
+                /// the code it interrupts.
+                pub unsafe fn leak(self) -> extern "C" fn($( $T, )*) -> R {
+                    let fun = *self.code_ptr();
+                    mem::forget(self);
+                    fun
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T: CType, )* R> $closure_mut<'a, $( $T, )* R> {
                 /// Constructs a typed closure callable from C from a CIF
                 /// describing the calling convention for the resulting
                 /// function and the Rust closure to call.
@@ -285,86 +684,278 @@ macro_rules! define_closure_mod {
                                               -> Self
                     where Callback: FnMut($( $T, )*) -> R + 'a
                 {
+                    let callback_fn = Self::static_callback::<Callback>
+                        as extern "C" fn(_, _, _, _);
                     Self::from_parts(cif,
-                                     Self::static_callback,
+                                     unsafe { cast_callback(callback_fn) },
                                      callback)
                 }
 
                 #[allow(non_snake_case)]
                 extern "C" fn static_callback<Callback>
-                    (_cif:     &low::ffi_cif,
-                     result:   &mut R,
-                     &($( &$T, )*):
-                               &($( &$T, )*),
+                    (cif:      &low::ffi_cif,
+                     result:   *mut c_void,
+                     args:     &($( &$T, )*),
                      userdata: &mut Callback)
                   where Callback: FnMut($( $T, )*) -> R + 'a
                 {
+                    let &($( $T, )*) = args;
+                    $( let $T = types::receive($T); )*
                     abort_on_panic!("Cannot panic inside FFI callback", {
                         unsafe {
-                            ptr::write(result, userdata($( $T, )*));
+                            middle::write_result(cif, result, userdata($( $T, )*));
+                        }
+                    });
+                }
+            }
+
+            #[cfg(all(feature = "closures", feature = "std"))]
+            impl<'a, $( $T: CType, )* R: CType + 'a>
+                $closure_mut<'a, $( $T, )* R>
+            {
+                /// Constructs a typed closure callable from C from a
+                /// Rust closure, with the given policy for when the Rust
+                /// closure panics.
+                pub fn new_with_policy<Callback>(callback: &'a mut Callback,
+                                                 policy: PanicPolicy<R>)
+                                                 -> Self
+                    where Callback: FnMut($( $T, )*) -> R + 'a
+                {
+                    let mut userdata = Box::new(panic::WithPolicy {
+                        callback,
+                        policy,
+                    });
+                    // The box is owned by the result, and outlives the
+                    // untyped closure borrowing it.
+                    let borrow = unsafe {
+                        &mut *(&mut *userdata as *mut panic::WithPolicy<_, R>)
+                    };
+                    let callback_fn = Self::static_callback_with_policy::<Callback>
+                        as extern "C" fn(_, _, _, _);
+                    let mut closure =
+                        Self::from_parts($cif::reify(),
+                                         unsafe { cast_callback(callback_fn) },
+                                         borrow);
+                    closure._policy = Some(userdata);
+                    closure
+                }
+
+                #[allow(non_snake_case)]
+                extern "C" fn static_callback_with_policy<Callback>
+                    (cif:      &low::ffi_cif,
+                     result:   *mut c_void,
+                     args:     &($( &$T, )*),
+                     userdata: &mut panic::WithPolicy<&'a mut Callback, R>)
+                  where Callback: FnMut($( $T, )*) -> R + 'a
+                {
+                    let &($( $T, )*) = args;
+                    $( let $T = types::receive($T); )*
+                    let callback = &mut userdata.callback;
+                    userdata.policy.invoke(cif, result, || callback($( $T, )*));
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T: CType, )* R> $closure_mut<'a, $( $T, )* R> {
+                /// Constructs a typed closure callable from C from a CIF
+                /// describing the calling convention for the resulting
+                /// function and a Rust closure that receives the
+                /// arguments by reference.
+                ///
+                /// Unlike [`new_with_cif`](#method.new_with_cif), this
+                /// does not copy the arguments, so large structs are
+                /// passed to the Rust closure in place. Each argument is
+                /// still checked with
+                /// [`CType::check_received`](trait.CType.html#method.check_received)
+                /// first.
+                pub fn new_with_cif_by_ref<Callback>(cif: $cif<$( $T, )* R>,
+                                                     callback: &'a mut Callback)
+                                                     -> Self
+                    where Callback: FnMut($( &$T, )*) -> R + 'a
+                {
+                    let callback_fn = Self::static_callback_by_ref::<Callback>
+                        as extern "C" fn(_, _, _, _);
+                    Self::from_parts(cif,
+                                     unsafe { cast_callback(callback_fn) },
+                                     callback)
+                }
+
+                #[allow(non_snake_case)]
+                extern "C" fn static_callback_by_ref<Callback>
+                    (cif:      &low::ffi_cif,
+                     result:   *mut c_void,
+                     &($( $T, )*):
+                               &($( &$T, )*),
+                     userdata: &mut Callback)
+                  where Callback: FnMut($( &$T, )*) -> R + 'a
+                {
+                    $( unsafe { <$T as CType>::check_received($T) }; )*
+                    abort_on_panic!("Cannot panic inside FFI callback", {
+                        unsafe {
+                            middle::write_result(cif, result, userdata($( $T, )*));
                         }
                     });
                 }
             }
 
             /// The type of function called from a one-shot, typed closure.
+            #[cfg(feature = "closures")]
             pub type $callback_once<U, $( $T, )* R>
                 = $callback_mut<Option<U>, $( $T, )* R>;
 
             /// A one-shot, typed closure with the given argument and
             /// result types.
+            #[cfg(feature = "closures")]
             pub struct $closure_once<$( $T, )* R> {
                 untyped: middle::ClosureOnce,
+                // Tracks calls and allows taking back the callback, for
+                // closures constructed from a Rust closure rather than
+                // with `from_parts`.
+                handle: Option<panic::OnceHandle>,
                 _marker: PhantomData<fn($( $T, )*) -> R>,
             }
 
+            #[cfg(feature = "closures")]
             impl<$($T: CType,)* R: CType> $closure_once<$($T,)* R> {
                 /// Constructs a typed closure callable from C from a
                 /// Rust closure.
+                ///
+                /// If the Rust closure panics or the closure is called a
+                /// second time, the process aborts.
                 pub fn new<Callback>(callback: Callback) -> Self
                     where Callback: FnOnce($( $T, )*) -> R + Any
                 {
                     Self::new_with_cif($cif::reify(), callback)
                 }
+
+                /// Constructs a typed closure callable from C from a
+                /// `Send` Rust closure. The result may be moved to
+                /// another thread.
+                pub fn new_send<Callback>(callback: Callback)
+                                          -> SendClosure<Self>
+                    where Callback: FnOnce($( $T, )*) -> R + Send + Any
+                {
+                    SendClosure::new(Self::new(callback))
+                }
             }
 
-            impl<$( $T: Copy, )* R> $closure_once<$( $T, )* R> {
+            #[cfg(feature = "closures")]
+            impl<$( $T: CType, )* R> $closure_once<$( $T, )* R> {
                 /// Constructs a one-shot closure callable from C from a CIF
                 /// describing the calling convention for the resulting
                 /// function and the Rust closure to call.
+                ///
+                /// If the Rust closure panics or the closure is called a
+                /// second time, the process aborts.
                 pub fn new_with_cif<Callback>(cif: $cif<$( $T, )* R>,
                                               callback: Callback) -> Self
                     where Callback: FnOnce($( $T, )*) -> R + Any
                 {
-                    Self::from_parts(cif,
-                                     Self::static_callback,
-                                     callback)
+                    let state = panic::OnceState::new(callback);
+                    let handle = panic::OnceHandle {
+                        status: state.status.clone(),
+                        take: panic::OnceState::<Callback>::take_from,
+                    };
+                    let callback_fn = Self::static_callback::<Callback>
+                        as extern "C" fn(_, _, _, _);
+                    let mut closure = Self::from_parts(cif,
+                                                       unsafe { cast_callback(callback_fn) },
+                                                       state);
+                    closure.handle = Some(handle);
+                    closure
                 }
 
                 #[allow(non_snake_case)]
                 extern "C" fn static_callback<Callback>
-                    (_cif:     &low::ffi_cif,
-                     result:   &mut R,
-                     &($( &$T, )*):
-                               &($( &$T, )*),
-                     userdata: &mut Option<Callback>)
+                    (cif:      &low::ffi_cif,
+                     result:   *mut c_void,
+                     args:     &($( &$T, )*),
+                     userdata: &mut Option<panic::OnceState<Callback>>)
                   where Callback: FnOnce($( $T, )*) -> R
                 {
-                    if let Some(userdata) = userdata.take() {
-                        abort_on_panic!("Cannot panic inside FFI callback", {
-                            unsafe {
-                                ptr::write(result, userdata($( $T, )*));
-                            }
-                        });
-                    } else {
-                        // There is probably a better way to abort here.
-                        let _ =
-                            io::stderr().write(b"FnOnce closure already used");
-                        process::exit(2);
+                    let &($( $T, )*) = args;
+                    $( let $T = types::receive($T); )*
+                    match userdata.as_mut().and_then(|state| state.take()) {
+                        Some(callback) => {
+                            abort_on_panic!("Cannot panic inside FFI callback", {
+                                unsafe {
+                                    middle::write_result(cif, result, callback($( $T, )*));
+                                }
+                            });
+                        }
+                        None => panic::abort_recalled(),
+                    }
+                }
+            }
+
+            #[cfg(all(feature = "closures", feature = "std"))]
+            impl<$( $T: CType, )* R: CType + 'static> $closure_once<$( $T, )* R> {
+                /// Constructs a one-shot closure callable from C from a
+                /// Rust closure, with the given policy for when the Rust
+                /// closure panics.
+                ///
+                /// Calling the closure a second time is treated as a
+                /// panic, and handled according to `policy`.
+                pub fn new_with_policy<Callback>(callback: Callback,
+                                                 policy: PanicPolicy<R>)
+                                                 -> Self
+                    where Callback: FnOnce($( $T, )*) -> R + Any
+                {
+                    Self::new_with_policies(callback,
+                                            policy,
+                                            RecallPolicy::Panic)
+                }
+
+                /// Constructs a one-shot closure callable from C from a
+                /// Rust closure, with the given policies for when the
+                /// Rust closure panics and for when the closure is called
+                /// a second time.
+                pub fn new_with_policies<Callback>(callback: Callback,
+                                                   panic: PanicPolicy<R>,
+                                                   recall: RecallPolicy<R>)
+                                                   -> Self
+                    where Callback: FnOnce($( $T, )*) -> R + Any
+                {
+                    let state = panic::OnceState::new(callback);
+                    let handle = panic::OnceHandle {
+                        status: state.status.clone(),
+                        take: panic::OnceWithPolicy::<Callback, R>::take_from,
+                    };
+                    let userdata = panic::OnceWithPolicy {
+                        state,
+                        panic,
+                        recall,
+                    };
+                    let callback_fn = Self::static_callback_with_policy::<Callback>
+                        as extern "C" fn(_, _, _, _);
+                    let mut closure =
+                        Self::from_parts($cif::reify(),
+                                         unsafe { cast_callback(callback_fn) },
+                                         userdata);
+                    closure.handle = Some(handle);
+                    closure
+                }
+
+                #[allow(non_snake_case)]
+                extern "C" fn static_callback_with_policy<Callback>
+                    (cif:      &low::ffi_cif,
+                     result:   *mut c_void,
+                     args:     &($( &$T, )*),
+                     userdata: &mut Option<panic::OnceWithPolicy<Callback, R>>)
+                  where Callback: FnOnce($( $T, )*) -> R
+                {
+                    let &($( $T, )*) = args;
+                    $( let $T = types::receive($T); )*
+                    match userdata.as_mut() {
+                        Some(userdata) => userdata.invoke(cif, result, |callback| {
+                            callback($( $T, )*)
+                        }),
+                        None => panic::abort_recalled(),
                     }
                 }
             }
 
+            #[cfg(feature = "closures")]
             impl<$( $T, )* R> $closure_once<$( $T, )* R> {
                 /// Gets the C code pointer that is used to invoke the
                 /// closure.
@@ -374,6 +965,12 @@ macro_rules! define_closure_mod {
                     }
                 }
 
+                /// Gets the C function pointer that is used to invoke
+                /// the closure, by value. It cannot outlive the closure.
+                pub fn fn_ptr(&self) -> $fn_ptr<'_, $( $T, )* R> {
+                    $fn_ptr::new(self.code_ptr())
+                }
+
                 /// Constructs a one-shot closure callable from C from a CIF
                 /// describing the calling convention for the resulting
                 /// function, a callback for the function to call, and
@@ -392,9 +989,256 @@ macro_rules! define_closure_mod {
                                                    userdata);
                     $closure_once {
                         untyped: closure,
+                        handle: None,
                         _marker: PhantomData,
                     }
                 }
+
+                /// Returns whether the closure has been called.
+                ///
+                /// This is only tracked for closures constructed from a
+                /// Rust closure, not with `from_parts`.
+                pub fn was_called(&self) -> bool {
+                    self.handle.as_ref().map_or(false, |handle| {
+                        handle.status.called.load(Ordering::SeqCst)
+                    })
+                }
+
+                /// Returns whether the closure has been called again after
+                /// its Rust closure was consumed (or taken back with
+                /// [`take_callback`](#method.take_callback)).
+                ///
+                /// This is only tracked for closures constructed from a
+                /// Rust closure, not with `from_parts`. For closures with
+                /// [`RecallPolicy::Return`](enum.RecallPolicy.html#variant.Return),
+                /// this is how the extra call can be detected.
+                pub fn was_recalled(&self) -> bool {
+                    self.handle.as_ref().map_or(false, |handle| {
+                        handle.status.recalled.load(Ordering::SeqCst)
+                    })
+                }
+
+                /// Takes back the Rust closure if it has not been called,
+                /// so that whatever it owns isn’t stranded when C never
+                /// calls it.
+                ///
+                /// The result may be downcast to the Rust closure’s type,
+                /// or simply dropped. Calling the closure from C afterward
+                /// is treated as a second call. Returns `None` if the
+                /// closure was already called or taken back, or if it was
+                /// constructed with `from_parts`, in which case
+                /// [`take_userdata`](#method.take_userdata) may be used.
+                pub fn take_callback(&mut self) -> Option<Box<dyn Any>> {
+                    let take = self.handle.as_ref()?.take;
+                    take(&mut self.untyped)
+                }
+
+                /// Takes back the userdata of a closure constructed with
+                /// `from_parts`, if it hasn’t been taken by the callback.
+                ///
+                /// Returns `None` if the userdata has been taken or is not
+                /// of type `U`. The callback receives `None` if it is
+                /// called afterward.
+                pub fn take_userdata<U: Any>(&mut self) -> Option<U> {
+                    self.untyped.take_userdata()
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<$( $T, )* R> $closure_once<$( $T, )* R> {
+                /// Leaks the closure, returning a C function pointer that
+                /// stays valid for the rest of the program.
+                ///
+                /// This is for callbacks that are registered once and
+                /// never unregistered, and that C calls at most once.
+                /// Leaking does not change what happens on a second call:
+                /// the process aborts, or with
+                /// `new_with_policies`, the closure follows its
+                /// `RecallPolicy`. Beware that a signal handler may run
+                /// more than once, so a signal handler that should
+                /// survive that is better made from a `ClosureN`.
+                ///
+                /// # Safety
+                ///
+                /// The closure does not record whether its callback is
+                /// `Send`, but the function pointer can be called from any
+                /// thread. It must only be called from threads that the
+                /// callback could be sent to, and never re-entrantly or
+                /// from two threads at once, since each call borrows the
+                /// callback’s slot mutably to take it, even when it has
+                /// already been taken.
+                pub unsafe fn leak(self) -> extern "C" fn($( $T, )*) -> R {
+                    let fun = *self.code_ptr();
+                    mem::forget(self);
+                    fun
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T, )* R> AsCFnPtr for $closure<'a, $( $T, )* R> {
+                fn as_code_ptr(&self) -> low::CodePtr {
+                    self.untyped.as_code_ptr()
+                }
+
+                fn cif(&self) -> &middle::Cif {
+                    self.untyped.cif()
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T, )* R> AsCFnPtr for $closure_mut<'a, $( $T, )* R> {
+                fn as_code_ptr(&self) -> low::CodePtr {
+                    self.untyped.as_code_ptr()
+                }
+
+                fn cif(&self) -> &middle::Cif {
+                    self.untyped.cif()
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<$( $T, )* R> AsCFnPtr for $closure_once<$( $T, )* R> {
+                fn as_code_ptr(&self) -> low::CodePtr {
+                    self.untyped.as_code_ptr()
+                }
+
+                fn cif(&self) -> &middle::Cif {
+                    self.untyped.cif()
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            unsafe impl<$( $T: CType, )*> ArgTuple for ($( $T, )*) {
+                fn reify() -> Vec<middle::Type> {
+                    vec![$( $T::reify().into_middle() ),*]
+                }
+
+                #[allow(non_snake_case, unused_assignments, unused_mut, unused_variables)]
+                #[allow(clippy::unused_unit)]
+                unsafe fn read(args: *const *const c_void) -> Self {
+                    let mut next = args;
+                    $(
+                        let $T = types::receive(&*(*next as *const $T));
+                        next = next.add(1);
+                    )*
+                    ($( $T, )*)
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T, )* R> Closure<'a, ($( $T, )*), R> {
+                /// Gets the C code pointer that is used to invoke the
+                /// closure.
+                pub fn code_ptr(&self) -> &extern "C" fn($( $T, )*) -> R {
+                    unsafe {
+                        self.untyped.instantiate_code_ptr()
+                    }
+                }
+            }
+
+            /// A C function pointer borrowed from a closure.
+            ///
+            /// This is the function pointer itself, by value, but it
+            /// cannot outlive the closure it was borrowed from. It
+            /// dereferences to the `extern "C" fn`, so it can be called
+            /// directly.
+            #[cfg(feature = "closures")]
+            pub struct $fn_ptr<'a, $( $T, )* R> {
+                fun: extern "C" fn($( $T, )*) -> R,
+                _marker: PhantomData<&'a ()>,
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T, )* R> $fn_ptr<'a, $( $T, )* R> {
+                fn new(fun: &'a extern "C" fn($( $T, )*) -> R) -> Self {
+                    $fn_ptr { fun: *fun, _marker: PhantomData }
+                }
+
+                /// Gets the underlying `extern "C" fn`.
+                ///
+                /// # Safety
+                ///
+                /// The result is no longer tied to the closure’s
+                /// lifetime, so it must not be called after the closure
+                /// is dropped.
+                pub unsafe fn into_extern(self) -> extern "C" fn($( $T, )*) -> R {
+                    self.fun
+                }
+
+                /// Gets the address of the function as a raw pointer.
+                pub fn as_raw(&self) -> *const c_void {
+                    self.fun as *const c_void
+                }
+
+                /// Wraps the function pointer as a Rust closure, for
+                /// passing to generic code that expects an `Fn`.
+                ///
+                /// (The `Fn` traits themselves cannot be implemented
+                /// outside the standard library.)
+                #[allow(non_snake_case)]
+                pub fn to_fn(self) -> impl Fn($( $T, )*) -> R + 'a
+                    where $( $T: 'a, )* R: 'a
+                {
+                    move |$( $T, )*| (self.fun)($( $T, )*)
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T, )* R> From<$fn_ptr<'a, $( $T, )* R>> for low::CodePtr {
+                fn from(fun: $fn_ptr<'a, $( $T, )* R>) -> Self {
+                    low::CodePtr::from_typed_fn(fun.fun)
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T, )* R> Clone for $fn_ptr<'a, $( $T, )* R> {
+                fn clone(&self) -> Self {
+                    *self
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T, )* R> Copy for $fn_ptr<'a, $( $T, )* R> {}
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T, )* R> Deref for $fn_ptr<'a, $( $T, )* R> {
+                type Target = extern "C" fn($( $T, )*) -> R;
+
+                fn deref(&self) -> &Self::Target {
+                    &self.fun
+                }
+            }
+
+            #[cfg(feature = "closures")]
+            impl<'a, $( $T, )* R> fmt::Debug for $fn_ptr<'a, $( $T, )* R> {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.debug_tuple(stringify!($fn_ptr))
+                        .field(&(self.fun as *const ()))
+                        .finish()
+                }
+            }
+
+            impl<Fun, $( $T, )* R> split::SplitFn<($( $T, )*)> for Fun
+                where Fun: FnMut($( $T, )*) -> R
+            {
+                type Shim = unsafe extern "C" fn($( $T, )* *mut core::ffi::c_void) -> R;
+
+                fn shim() -> Self::Shim {
+                    #[allow(non_snake_case)]
+                    unsafe extern "C" fn shim<Fun, $( $T, )* R>(
+                        $( $T: $T, )*
+                        userdata: *mut core::ffi::c_void,
+                    ) -> R
+                        where Fun: FnMut($( $T, )*) -> R
+                    {
+                        let closure = &mut *(userdata as *mut Fun);
+                        abort_on_panic!("Cannot panic inside FFI callback", {
+                            closure($( $T, )*)
+                        })
+                    }
+
+                    shim::<Fun, $( $T, )* R>
+                }
             }
         }
 
@@ -404,60 +1248,374 @@ macro_rules! define_closure_mod {
 
 define_closure_mod!(arity0 Cif0
 Callback0 CallbackMut0 CallbackOnce0
-Closure0 ClosureMut0 ClosureOnce0;
+Closure0 ClosureMut0 ClosureOnce0 FnPtr0;
 );
 define_closure_mod!(arity1 Cif1
                     Callback1 CallbackMut1 CallbackOnce1
-                    Closure1 ClosureMut1 ClosureOnce1;
+                    Closure1 ClosureMut1 ClosureOnce1 FnPtr1;
                     A);
 define_closure_mod!(arity2 Cif2
                     Callback2 CallbackMut2 CallbackOnce2
-                    Closure2 ClosureMut2 ClosureOnce2;
+                    Closure2 ClosureMut2 ClosureOnce2 FnPtr2;
                     A B);
 define_closure_mod!(arity3 Cif3
                     Callback3 CallbackMut3 CallbackOnce3
-                    Closure3 ClosureMut3 ClosureOnce3;
+                    Closure3 ClosureMut3 ClosureOnce3 FnPtr3;
                     A B C);
 define_closure_mod!(arity4 Cif4
                     Callback4 CallbackMut4 CallbackOnce4
-                    Closure4 ClosureMut4 ClosureOnce4;
+                    Closure4 ClosureMut4 ClosureOnce4 FnPtr4;
                     A B C D);
 define_closure_mod!(arity5 Cif5
                     Callback5 CallbackMut5 CallbackOnce5
-                    Closure5 ClosureMut5 ClosureOnce5;
+                    Closure5 ClosureMut5 ClosureOnce5 FnPtr5;
                     A B C D E);
 define_closure_mod!(arity6 Cif6
                     Callback6 CallbackMut6 CallbackOnce6
-                    Closure6 ClosureMut6 ClosureOnce6;
+                    Closure6 ClosureMut6 ClosureOnce6 FnPtr6;
                     A B C D E F);
 define_closure_mod!(arity7 Cif7
                     Callback7 CallbackMut7 CallbackOnce7
-                    Closure7 ClosureMut7 ClosureOnce7;
+                    Closure7 ClosureMut7 ClosureOnce7 FnPtr7;
                     A B C D E F G);
 define_closure_mod!(arity8 Cif8
                     Callback8 CallbackMut8 CallbackOnce8
-                    Closure8 ClosureMut8 ClosureOnce8;
+                    Closure8 ClosureMut8 ClosureOnce8 FnPtr8;
                     A B C D E F G H);
 define_closure_mod!(arity9 Cif9
                     Callback9 CallbackMut9 CallbackOnce9
-                    Closure9 ClosureMut9 ClosureOnce9;
+                    Closure9 ClosureMut9 ClosureOnce9 FnPtr9;
                     A B C D E F G H I);
 define_closure_mod!(arity10 Cif10
                     Callback10 CallbackMut10 CallbackOnce10
-                    Closure10 ClosureMut10 ClosureOnce10;
+                    Closure10 ClosureMut10 ClosureOnce10 FnPtr10;
                     A B C D E F G H I J);
 define_closure_mod!(arity11 Cif11
                     Callback11 CallbackMut11 CallbackOnce11
-                    Closure11 ClosureMut11 ClosureOnce11;
+                    Closure11 ClosureMut11 ClosureOnce11 FnPtr11;
                     A B C D E F G H I J K);
 define_closure_mod!(arity12 Cif12
                     Callback12 CallbackMut12 CallbackOnce12
-                    Closure12 ClosureMut12 ClosureOnce12;
+                    Closure12 ClosureMut12 ClosureOnce12 FnPtr12;
                     A B C D E F G H I J K L);
 
-#[cfg(test)]
+#[cfg(feature = "arity16")]
+define_closure_mod!(arity13 Cif13
+                    Callback13 CallbackMut13 CallbackOnce13
+                    Closure13 ClosureMut13 ClosureOnce13 FnPtr13;
+                    A B C D E F G H I J K L M);
+#[cfg(feature = "arity16")]
+define_closure_mod!(arity14 Cif14
+                    Callback14 CallbackMut14 CallbackOnce14
+                    Closure14 ClosureMut14 ClosureOnce14 FnPtr14;
+                    A B C D E F G H I J K L M N);
+#[cfg(feature = "arity16")]
+define_closure_mod!(arity15 Cif15
+                    Callback15 CallbackMut15 CallbackOnce15
+                    Closure15 ClosureMut15 ClosureOnce15 FnPtr15;
+                    A B C D E F G H I J K L M N O);
+#[cfg(feature = "arity16")]
+define_closure_mod!(arity16 Cif16
+                    Callback16 CallbackMut16 CallbackOnce16
+                    Closure16 ClosureMut16 ClosureOnce16 FnPtr16;
+                    A B C D E F G H I J K L M N O P);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity17 Cif17
+                    Callback17 CallbackMut17 CallbackOnce17
+                    Closure17 ClosureMut17 ClosureOnce17 FnPtr17;
+                    A B C D E F G H I J K L M N O P Q);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity18 Cif18
+                    Callback18 CallbackMut18 CallbackOnce18
+                    Closure18 ClosureMut18 ClosureOnce18 FnPtr18;
+                    A B C D E F G H I J K L M N O P Q S);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity19 Cif19
+                    Callback19 CallbackMut19 CallbackOnce19
+                    Closure19 ClosureMut19 ClosureOnce19 FnPtr19;
+                    A B C D E F G H I J K L M N O P Q S T);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity20 Cif20
+                    Callback20 CallbackMut20 CallbackOnce20
+                    Closure20 ClosureMut20 ClosureOnce20 FnPtr20;
+                    A B C D E F G H I J K L M N O P Q S T V);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity21 Cif21
+                    Callback21 CallbackMut21 CallbackOnce21
+                    Closure21 ClosureMut21 ClosureOnce21 FnPtr21;
+                    A B C D E F G H I J K L M N O P Q S T V W);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity22 Cif22
+                    Callback22 CallbackMut22 CallbackOnce22
+                    Closure22 ClosureMut22 ClosureOnce22 FnPtr22;
+                    A B C D E F G H I J K L M N O P Q S T V W X);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity23 Cif23
+                    Callback23 CallbackMut23 CallbackOnce23
+                    Closure23 ClosureMut23 ClosureOnce23 FnPtr23;
+                    A B C D E F G H I J K L M N O P Q S T V W X Y);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity24 Cif24
+                    Callback24 CallbackMut24 CallbackOnce24
+                    Closure24 ClosureMut24 ClosureOnce24 FnPtr24;
+                    A B C D E F G H I J K L M N O P Q S T V W X Y Z);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity25 Cif25
+                    Callback25 CallbackMut25 CallbackOnce25
+                    Closure25 ClosureMut25 ClosureOnce25 FnPtr25;
+                    A B C D E F G H I J K L M N O P Q S T V W X Y Z A1);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity26 Cif26
+                    Callback26 CallbackMut26 CallbackOnce26
+                    Closure26 ClosureMut26 ClosureOnce26 FnPtr26;
+                    A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity27 Cif27
+                    Callback27 CallbackMut27 CallbackOnce27
+                    Closure27 ClosureMut27 ClosureOnce27 FnPtr27;
+                    A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity28 Cif28
+                    Callback28 CallbackMut28 CallbackOnce28
+                    Closure28 ClosureMut28 ClosureOnce28 FnPtr28;
+                    A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity29 Cif29
+                    Callback29 CallbackMut29 CallbackOnce29
+                    Closure29 ClosureMut29 ClosureOnce29 FnPtr29;
+                    A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity30 Cif30
+                    Callback30 CallbackMut30 CallbackOnce30
+                    Closure30 ClosureMut30 ClosureOnce30 FnPtr30;
+                    A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1 F1);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity31 Cif31
+                    Callback31 CallbackMut31 CallbackOnce31
+                    Closure31 ClosureMut31 ClosureOnce31 FnPtr31;
+                    A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1 F1 G1);
+#[cfg(feature = "arity32")]
+define_closure_mod!(arity32 Cif32
+                    Callback32 CallbackMut32 CallbackOnce32
+                    Closure32 ClosureMut32 ClosureOnce32 FnPtr32;
+                    A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1 F1 G1 H1);
+
+#[cfg(all(test, feature = "closures"))]
 mod test {
     use super::*;
+    use crate::middle;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Big {
+        words: [u64; 4],
+    }
+
+    unsafe impl CType for Big {
+        fn reify() -> Type<Self> {
+            let words = vec![middle::Type::u64(); 4];
+            unsafe { Type::from_middle(middle::Type::structure(words)) }
+        }
+    }
+
+    #[test]
+    fn new_with_cif_by_ref() {
+        let f = |big: &Big, scale: &u64| big.words.iter().sum::<u64>() * scale;
+
+        let cif = Cif2::new(Big::reify(), u64::reify(), u64::reify());
+        let closure = Closure2::new_with_cif_by_ref(cif, &f);
+        let big = Big {
+            words: [1, 2, 3, 4],
+        };
+
+        assert_eq!(20, closure.code_ptr()(big, 2));
+    }
+
+    #[test]
+    fn leak() {
+        static OFFSET: u32 = 10;
+        let add: &'static _ = Box::leak(Box::new(|x: u32| x + OFFSET));
+        let add = Closure1::new(add).leak();
+
+        let name = String::from("libffi");
+        let len = unsafe { ClosureOnce0::new(move || name.len()).leak() };
+
+        let mut calls = 0;
+        let count: &'static mut _ = Box::leak(Box::new(move || {
+            calls += 1;
+            calls
+        }));
+        let count = unsafe { ClosureMut0::new(count).leak() };
+
+        assert_eq!(15, add(5));
+        assert_eq!(6, len());
+        assert_eq!(1, count());
+        assert_eq!(2, count());
+    }
+
+    #[test]
+    fn new_with_abi() {
+        let f = |x: u64, y: u64| x - y;
+        let cif = Cif2::reify_with_abi(Abi::Default.as_raw());
+        let closure = Closure2::new_with_cif(cif, &f);
+
+        assert_eq!(3, closure.code_ptr()(5, 2));
+    }
+
+    #[test]
+    fn new_system() {
+        let f = |x: u32, y: u32| x * 10 + y;
+        let closure = Closure2::new_system(&f);
+        assert_eq!(42, closure.system_code_ptr().unwrap()(4, 2));
+
+        let mut total = 0u32;
+        let mut g = |x: u32| {
+            total += x;
+            total
+        };
+        let closure = ClosureMut1::new_system(&mut g);
+        assert_eq!(3, closure.system_code_ptr().unwrap()(3));
+        assert_eq!(7, closure.system_code_ptr().unwrap()(4));
+    }
+
+    #[test]
+    fn fn_ptr_by_value() {
+        let f = |x: u32, y: u32| x * y;
+        let closure = Closure2::new(&f);
+
+        let fun = closure.fn_ptr();
+        let copy = fun;
+        assert_eq!(12, fun(3, 4));
+        assert_eq!(30, copy(5, 6));
+        assert_eq!(*closure.code_ptr() as *const (), *fun as *const ());
+    }
+
+    #[test]
+    #[cfg(feature = "arity16")]
+    fn sixteen_arguments() {
+        let f = |a: u8,
+                 b: u16,
+                 c: u32,
+                 d: u64,
+                 e: i8,
+                 f: i16,
+                 g: i32,
+                 h: i64,
+                 i: u8,
+                 j: u16,
+                 k: u32,
+                 l: u64,
+                 m: i8,
+                 n: i16,
+                 o: i32,
+                 p: i64| {
+            [
+                a as i64, b as i64, c as i64, d as i64, e as i64, f as i64, g as i64, h, i as i64,
+                j as i64, k as i64, l as i64, m as i64, n as i64, o as i64, p,
+            ]
+            .iter()
+            .sum::<i64>()
+        };
+        let closure = Closure16::new(&f);
+
+        assert_eq!(
+            136,
+            closure.code_ptr()(1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16)
+        );
+    }
+
+    #[test]
+    fn fn_ptr_conversions() {
+        fn apply<F: Fn(u32, u32) -> u32>(f: F) -> u32 {
+            f(6, 7)
+        }
+
+        let f = |x: u32, y: u32| x + y;
+        let closure = Closure2::new(&f);
+        let fun = closure.fn_ptr();
+
+        assert_eq!(13, apply(fun.to_fn()));
+        assert_eq!(3, unsafe { fun.into_extern() }(1, 2));
+        assert_eq!(fun.as_raw(), crate::low::CodePtr::from(fun).as_ptr());
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[repr(C)]
+    struct Small {
+        tag: u8,
+        value: i16,
+    }
+
+    unsafe impl CType for Small {
+        fn reify() -> Type<Self> {
+            let fields = vec![middle::Type::u8(), middle::Type::i16()];
+            unsafe { Type::from_middle(middle::Type::structure(fields)) }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[repr(C)]
+    struct Large {
+        words: [u64; 5],
+    }
+
+    #[cfg(feature = "std")]
+    unsafe impl CType for Large {
+        fn reify() -> Type<Self> {
+            let fields = vec![middle::Type::u64(); 5];
+            unsafe { Type::from_middle(middle::Type::structure(fields)) }
+        }
+    }
+
+    #[test]
+    fn return_small_struct() {
+        let f = |tag: u8| Small { tag, value: -7 };
+        let closure = Closure1::new(&f);
+
+        assert_eq!(Small { tag: 3, value: -7 }, closure.code_ptr()(3));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn return_large_struct() {
+        let f = |x: u64| Large {
+            words: [x, x + 1, x + 2, x + 3, x + 4],
+        };
+        let closure = Closure1::new_with_policy(&f, PanicPolicy::Return(Large { words: [0; 5] }));
+
+        assert_eq!(
+            Large {
+                words: [1, 2, 3, 4, 5]
+            },
+            closure.code_ptr()(1)
+        );
+    }
+
+    #[test]
+    fn return_small_integers() {
+        let f = |x: i8| x;
+        let closure = Closure1::new(&f);
+        assert_eq!(-5, closure.code_ptr()(-5));
+
+        let g = |x: u16| x;
+        let closure = Closure1::new(&g);
+        assert_eq!(65535, closure.code_ptr()(65535));
+    }
+
+    #[test]
+    fn new_mut_by_ref() {
+        let mut total = 0u64;
+        let mut f = |x: &u64| {
+            total += x;
+            total
+        };
+
+        let closure = ClosureMut1::new_by_ref(&mut f);
+
+        assert_eq!(3, closure.code_ptr()(3));
+        assert_eq!(7, closure.code_ptr()(4));
+    }
 
     #[test]
     fn new_with_cif() {
@@ -515,4 +1673,155 @@ mod test {
         assert_eq!(6, counter(1));
         assert_eq!(8, counter(2));
     }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn new_with_policy() {
+        let f = |x: i32, y: i32| {
+            assert!(y != 0, "division by zero");
+            x / y
+        };
+
+        let closure = Closure2::new_with_policy(&f, PanicPolicy::Return(0));
+        let divide = closure.code_ptr();
+
+        assert_eq!(3, divide(7, 2));
+        assert!(take_last_panic().is_none());
+        assert_eq!(0, divide(7, 0));
+
+        let payload = take_last_panic().unwrap();
+        assert_eq!(Some(&"division by zero"), payload.downcast_ref::<&str>());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    // `u32::MAX` needs Rust 1.43.
+    #[allow(clippy::legacy_numeric_constants)]
+    fn new_with_policy_handler() {
+        let mut calls = 0u32;
+        let mut f = |x: u32| {
+            calls += 1;
+            if x == 0 {
+                panic!("zero");
+            }
+            calls
+        };
+
+        let policy = PanicPolicy::handler(|payload| {
+            assert_eq!(Some(&"zero"), payload.downcast_ref::<&str>());
+            u32::max_value()
+        });
+        let closure = ClosureMut1::new_with_policy(&mut f, policy);
+        let counter = closure.code_ptr();
+
+        assert_eq!(1, counter(1));
+        assert_eq!(u32::max_value(), counter(0));
+        assert_eq!(3, counter(1));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn new_once_with_policy() {
+        let v = [1u8, 2, 3];
+        let closure = ClosureOnce1::new_with_policy(move |i: usize| v[i], PanicPolicy::Return(0));
+
+        assert_eq!(0, closure.code_ptr()(10));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn once_recalled_with_policy() {
+        let closure = ClosureOnce1::new_with_policy(|x: u32| x + 1, PanicPolicy::Return(0));
+
+        assert!(!closure.was_recalled());
+        assert_eq!(6, closure.code_ptr()(5));
+        assert_eq!(0, closure.code_ptr()(5));
+        assert!(closure.was_recalled());
+        assert!(take_last_panic().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn once_recalled_returns() {
+        let closure =
+            ClosureOnce0::new_with_policies(|| 3u8, PanicPolicy::Abort, RecallPolicy::Return(9));
+
+        assert_eq!(3, closure.code_ptr()());
+        assert!(!closure.was_recalled());
+        assert_eq!(9, closure.code_ptr()());
+        assert!(closure.was_recalled());
+    }
+
+    #[test]
+    fn once_was_called() {
+        let closure = ClosureOnce1::new(|x: u64| x * 2);
+
+        assert!(!closure.was_called());
+        assert_eq!(10, closure.code_ptr()(5));
+        assert!(closure.was_called());
+    }
+
+    #[test]
+    fn once_take_callback() {
+        use std::rc::Rc;
+
+        let resource = Rc::new(());
+        let owned = resource.clone();
+        let mut closure = ClosureOnce0::new(move || drop(owned));
+
+        assert_eq!(2, Rc::strong_count(&resource));
+        assert!(closure.take_callback().is_some());
+        assert_eq!(1, Rc::strong_count(&resource));
+        assert!(closure.take_callback().is_none());
+        assert!(!closure.was_called());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn once_take_callback_with_policy() {
+        let mut closure =
+            ClosureOnce0::new_with_policies(|| 1i32, PanicPolicy::Abort, RecallPolicy::Return(-1));
+
+        assert!(closure.take_callback().is_some());
+        assert_eq!(-1, closure.code_ptr()());
+        assert!(closure.was_recalled());
+        assert!(!closure.was_called());
+    }
+
+    #[test]
+    fn new_sync() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::thread;
+
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        static F: fn(u64) -> u64 = |y| COUNT.fetch_add(y, Ordering::SeqCst) + y;
+
+        let closure = Closure1::new_sync(&F);
+        let closure = thread::spawn(move || {
+            closure.code_ptr()(5);
+            closure
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(7, closure.code_ptr()(2));
+    }
+
+    #[test]
+    fn new_send_mut() {
+        use std::thread;
+
+        let mut x = 0u64;
+        let f = Box::leak(Box::new(move |y: u64| {
+            x += y;
+            x
+        }));
+
+        let closure = ClosureMut1::new_send(f);
+        let result = thread::spawn(move || closure.code_ptr()(3) + closure.code_ptr()(4))
+            .join()
+            .unwrap();
+
+        assert_eq!(10, result);
+    }
 }
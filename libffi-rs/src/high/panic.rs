@@ -0,0 +1,292 @@
+//! Panic policies for high-layer closures.
+//!
+//! Unwinding out of an FFI callback into C is undefined behavior, so by
+//! default a panic in the Rust callback of a
+//! <code>Closure<em>N</em></code> aborts the process. That is
+//! unacceptable in some settings, such as plugin hosts, so closures
+//! constructed with `new_with_policy` instead catch the panic and
+//! follow a [`PanicPolicy`](enum.PanicPolicy.html).
+//!
+//! Catching a panic needs `std`, so the policies are only available with
+//! the `std` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "std")] {
+//! use libffi::high::{Closure1, PanicPolicy};
+//!
+//! let f = |x: i32| {
+//!     if x < 0 { panic!("negative argument") }
+//!     x * 2
+//! };
+//!
+//! let closure = Closure1::new_with_policy(&f, PanicPolicy::Return(-1));
+//! let double = closure.code_ptr();
+//!
+//! assert_eq!(8, double(4));
+//! assert_eq!(-1, double(-4));
+//! # }
+//! ```
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::any::Any;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use core::{cell::RefCell, ffi::c_void, fmt};
+#[cfg(feature = "std")]
+use std::panic::{self, AssertUnwindSafe};
+
+#[cfg(feature = "std")]
+use abort_on_panic::abort_on_panic;
+
+use super::abort::abort;
+#[cfg(feature = "std")]
+use crate::low;
+use crate::middle;
+
+#[cfg(feature = "std")]
+thread_local! {
+    static LAST_PANIC: RefCell<Option<Box<dyn Any + Send>>> = RefCell::new(None);
+}
+
+/// Takes the payload of the most recent panic swallowed by a closure
+/// with a [`Return`](enum.PanicPolicy.html#variant.Return) policy on the
+/// current thread.
+///
+/// This lets the host re-raise the panic, *e.g.,* with
+/// [`std::panic::resume_unwind`](https://doc.rust-lang.org/std/panic/fn.resume_unwind.html),
+/// once the C function that invoked the closure has returned. Only the
+/// latest payload is kept, and payloads are stashed on the thread that
+/// invoked the closure.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::high::{take_last_panic, Closure0, PanicPolicy};
+///
+/// let f = || -> u32 { panic!("oops") };
+/// let closure = Closure0::new_with_policy(&f, PanicPolicy::Return(0));
+///
+/// assert_eq!(0, closure.code_ptr()());
+///
+/// let payload = take_last_panic().unwrap();
+/// assert_eq!(Some(&"oops"), payload.downcast_ref::<&str>());
+/// assert!(take_last_panic().is_none());
+/// ```
+#[cfg(feature = "std")]
+pub fn take_last_panic() -> Option<Box<dyn Any + Send>> {
+    LAST_PANIC.with(|last| last.borrow_mut().take())
+}
+
+/// What a high-layer closure does when its Rust callback panics.
+#[cfg(feature = "std")]
+pub enum PanicPolicy<R> {
+    /// Abort the process, as closures without a policy do.
+    Abort,
+    /// Return the given value to the C caller. The panic payload is
+    /// stashed so that it can be retrieved with
+    /// [`take_last_panic`](fn.take_last_panic.html) once control returns
+    /// from C.
+    Return(R),
+    /// Pass the panic payload to a handler and return its result to the
+    /// C caller. If the handler itself panics, the process aborts.
+    Handler(Box<dyn Fn(Box<dyn Any + Send>) -> R>),
+}
+
+#[cfg(feature = "std")]
+impl<R> PanicPolicy<R> {
+    /// Constructs a [`Handler`](#variant.Handler) policy.
+    pub fn handler<F>(handler: F) -> Self
+    where
+        F: Fn(Box<dyn Any + Send>) -> R + 'static,
+    {
+        PanicPolicy::Handler(Box::new(handler))
+    }
+}
+
+// Deriving this needs `#[default]`, which needs Rust 1.62.
+#[cfg(feature = "std")]
+#[allow(clippy::derivable_impls)]
+impl<R> Default for PanicPolicy<R> {
+    fn default() -> Self {
+        PanicPolicy::Abort
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: fmt::Debug> fmt::Debug for PanicPolicy<R> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PanicPolicy::Abort => formatter.write_str("Abort"),
+            PanicPolicy::Return(value) => formatter.debug_tuple("Return").field(value).finish(),
+            PanicPolicy::Handler(_) => formatter.write_str("Handler(..)"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Copy> PanicPolicy<R> {
+    /// Calls `f`, writing its result, or the result prescribed by the
+    /// policy if it panics, to `result`.
+    pub(crate) fn invoke<F>(&self, cif: &low::ffi_cif, result: *mut c_void, f: F)
+    where
+        F: FnOnce() -> R,
+    {
+        let value = match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(value) => value,
+            Err(payload) => self.recover(payload),
+        };
+
+        unsafe {
+            middle::write_result(cif, result, value);
+        }
+    }
+
+    fn recover(&self, payload: Box<dyn Any + Send>) -> R {
+        match self {
+            PanicPolicy::Abort => abort(format_args!("Cannot panic inside FFI callback")),
+            PanicPolicy::Return(value) => {
+                LAST_PANIC.with(|last| *last.borrow_mut() = Some(payload));
+                *value
+            }
+            PanicPolicy::Handler(handler) => {
+                abort_on_panic!("Cannot panic inside FFI panic handler", {
+                    handler(payload)
+                })
+            }
+        }
+    }
+}
+
+/// What a one-shot closure does when it is called again after its Rust
+/// closure has been consumed.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug)]
+pub enum RecallPolicy<R> {
+    /// Print a message and abort the process.
+    Abort,
+    /// Panic, which is then handled according to the closure’s
+    /// [`PanicPolicy`](enum.PanicPolicy.html).
+    Panic,
+    /// Return the given value to the C caller. The extra call can be
+    /// detected from Rust with the closure’s `was_recalled` method.
+    Return(R),
+}
+
+// Deriving this needs `#[default]`, which needs Rust 1.62.
+#[cfg(feature = "std")]
+#[allow(clippy::derivable_impls)]
+impl<R> Default for RecallPolicy<R> {
+    fn default() -> Self {
+        RecallPolicy::Abort
+    }
+}
+
+/// Aborts because a one-shot closure was called again.
+pub(crate) fn abort_recalled() -> ! {
+    abort(format_args!("FnOnce closure already used"))
+}
+
+/// Records what has happened to a one-shot closure, shared between the
+/// closure’s userdata and its Rust handle.
+#[derive(Debug, Default)]
+pub(crate) struct OnceStatus {
+    pub called: AtomicBool,
+    pub recalled: AtomicBool,
+}
+
+/// The userdata of a one-shot closure constructed from a Rust closure.
+pub(crate) struct OnceState<C> {
+    pub callback: Option<C>,
+    pub status: Arc<OnceStatus>,
+}
+
+impl<C> OnceState<C> {
+    pub fn new(callback: C) -> Self {
+        OnceState {
+            callback: Some(callback),
+            status: Arc::default(),
+        }
+    }
+
+    /// Takes the callback for a call, recording whether it had already
+    /// been taken.
+    pub fn take(&mut self) -> Option<C> {
+        let callback = self.callback.take();
+        let flag = match callback {
+            Some(_) => &self.status.called,
+            None => &self.status.recalled,
+        };
+        flag.store(true, Ordering::SeqCst);
+        callback
+    }
+}
+
+impl<C: Any> OnceState<C> {
+    /// Takes the callback back out of a closure whose userdata is a
+    /// `OnceState<C>`, if it hasn’t been taken.
+    pub fn take_from(closure: &mut middle::ClosureOnce) -> Option<Box<dyn Any>> {
+        let state = closure.userdata_mut::<Self>()?;
+        state.callback.take().map(|c| Box::new(c) as Box<dyn Any>)
+    }
+}
+
+/// The Rust side of a one-shot closure constructed from a Rust closure.
+pub(crate) struct OnceHandle {
+    pub status: Arc<OnceStatus>,
+    pub take: fn(&mut middle::ClosureOnce) -> Option<Box<dyn Any>>,
+}
+
+/// The userdata of a one-shot closure constructed with policies.
+#[cfg(feature = "std")]
+pub(crate) struct OnceWithPolicy<C, R> {
+    pub state: OnceState<C>,
+    pub panic: PanicPolicy<R>,
+    pub recall: RecallPolicy<R>,
+}
+
+#[cfg(feature = "std")]
+impl<C: Any, R: 'static> OnceWithPolicy<C, R> {
+    /// Like [`OnceState::take_from`], for closures with policies.
+    pub fn take_from(closure: &mut middle::ClosureOnce) -> Option<Box<dyn Any>> {
+        let userdata = closure.userdata_mut::<Self>()?;
+        userdata
+            .state
+            .callback
+            .take()
+            .map(|c| Box::new(c) as Box<dyn Any>)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C, R: Copy> OnceWithPolicy<C, R> {
+    pub fn invoke<F>(&mut self, cif: &low::ffi_cif, result: *mut c_void, f: F)
+    where
+        F: FnOnce(C) -> R,
+    {
+        match self.state.take() {
+            Some(callback) => self.panic.invoke(cif, result, || f(callback)),
+            None => match self.recall {
+                RecallPolicy::Abort => abort_recalled(),
+                RecallPolicy::Panic => self
+                    .panic
+                    .invoke(cif, result, || panic!("FnOnce closure already used")),
+                RecallPolicy::Return(value) => unsafe { middle::write_result(cif, result, value) },
+            },
+        }
+    }
+}
+
+/// The userdata of a closure constructed with a panic policy.
+#[cfg(feature = "std")]
+pub(crate) struct WithPolicy<C, R> {
+    pub callback: C,
+    pub policy: PanicPolicy<R>,
+}
+
+/// Anything; used to own a closure’s `WithPolicy` userdata without
+/// naming its type.
+pub(crate) trait Owned {}
+impl<T> Owned for T {}
@@ -0,0 +1,251 @@
+//! A registry of C functions by name, for calling with dynamic values.
+//!
+//! Interpreters and other embedders that expose C functions to a
+//! dynamic language typically keep a table from each function’s name to
+//! its signature and address, and call through it with values whose
+//! types are only known at run time. A [`Registry`](struct.Registry.html)
+//! is that table. It can be shared between threads, and each call
+//! checks its arguments against the registered signature, as
+//! [`CallBuilder`](../call/struct.CallBuilder.html) does.
+//!
+//! (This is unrelated to
+//! [`middle::registry`](../../middle/registry/index.html), which tracks
+//! live closures for diagnostics.)
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::high::registry::Registry;
+//! use libffi::middle::{Cif, CodePtr, Type, Value};
+//!
+//! extern "C" fn scale(x: f64, by: i32) -> f64 {
+//!     x * by as f64
+//! }
+//!
+//! let registry = Registry::new();
+//! unsafe {
+//!     registry
+//!         .register(
+//!             "scale",
+//!             Cif::new(vec![Type::f64(), Type::i32()], Type::f64()),
+//!             CodePtr(scale as *mut _),
+//!         )
+//!         .unwrap();
+//! }
+//!
+//! let result = unsafe { registry.call("scale", &[Value::F64(1.5), Value::I32(4)]) };
+//! assert_eq!(Ok(Value::F64(6.0)), result);
+//!
+//! let unknown = unsafe { registry.call("shift", &[]) };
+//! assert_eq!(Err(libffi::Error::UnknownSymbol), unknown);
+//! ```
+
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt;
+use std::sync::RwLock;
+
+use super::call::call_values;
+use crate::middle::{Cif, CodePtr, Value};
+
+/// A registered C function: its signature and address.
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    cif: Cif,
+    fun: CodePtr,
+}
+
+// Registering a function is unsafe, and its contract includes that the
+// function may be called from any thread.
+unsafe impl Send for Symbol {}
+unsafe impl Sync for Symbol {}
+
+impl Symbol {
+    /// The function’s signature.
+    pub fn cif(&self) -> &Cif {
+        &self.cif
+    }
+
+    /// The function’s address.
+    pub fn code_ptr(&self) -> CodePtr {
+        self.fun
+    }
+}
+
+/// A thread-safe table of C functions by name.
+///
+/// See the [module documentation](index.html) for an example.
+#[derive(Default)]
+pub struct Registry {
+    symbols: RwLock<HashMap<String, Symbol>>,
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbols = self.symbols.read().unwrap();
+        f.debug_map().entries(symbols.iter()).finish()
+    }
+}
+
+impl Registry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a function under `name`.
+    ///
+    /// Returns [`Error::DuplicateSymbol`](../../enum.Error.html#variant.DuplicateSymbol),
+    /// leaving the existing registration in place, if `name` is taken.
+    ///
+    /// # Safety
+    ///
+    /// `cif` must match the calling convention and types of `fun`, and
+    /// `fun` must remain callable, from any thread, for as long as it is
+    /// registered.
+    pub unsafe fn register(
+        &self,
+        name: impl Into<String>,
+        cif: Cif,
+        fun: CodePtr,
+    ) -> crate::Result<()> {
+        match self.symbols.write().unwrap().entry(name.into()) {
+            Entry::Occupied(_) => Err(crate::Error::DuplicateSymbol),
+            Entry::Vacant(entry) => {
+                entry.insert(Symbol { cif, fun });
+                Ok(())
+            }
+        }
+    }
+
+    /// Removes the function registered under `name`, returning it.
+    pub fn unregister(&self, name: &str) -> Option<Symbol> {
+        self.symbols.write().unwrap().remove(name)
+    }
+
+    /// Looks up the function registered under `name`.
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.read().unwrap().get(name).cloned()
+    }
+
+    /// Whether a function is registered under `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.symbols.read().unwrap().contains_key(name)
+    }
+
+    /// The names of the registered functions, in no particular order.
+    pub fn names(&self) -> Vec<String> {
+        self.symbols.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Calls the function registered under `name` with the given
+    /// arguments, returning its result.
+    ///
+    /// Returns an error without calling if no function is registered
+    /// under `name`, the number of arguments is wrong, an argument value
+    /// does not match its type, or the result type has no `Value`
+    /// representation.
+    ///
+    /// The registry is not locked during the call, so the function may
+    /// itself use the registry.
+    ///
+    /// # Safety
+    ///
+    /// Pointer arguments must be valid for however the function uses
+    /// them.
+    pub unsafe fn call(&self, name: &str, args: &[Value]) -> crate::Result<Value> {
+        let symbol = self.get(name).ok_or(crate::Error::UnknownSymbol)?;
+        call_values(&symbol.cif, symbol.fun, args)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::Type;
+    use std::sync::Arc;
+    use std::thread;
+
+    extern "C" fn double(x: u32) -> u32 {
+        x * 2
+    }
+
+    extern "C" fn negate(x: i8) -> i8 {
+        -x
+    }
+
+    fn unary(ty: Type) -> Cif {
+        Cif::new(vec![ty.clone()], ty)
+    }
+
+    #[test]
+    fn duplicates() {
+        let registry = Registry::new();
+        unsafe {
+            registry
+                .register("f", unary(Type::u32()), CodePtr(double as *mut _))
+                .unwrap();
+            assert_eq!(
+                Err(crate::Error::DuplicateSymbol),
+                registry.register("f", unary(Type::i8()), CodePtr(negate as *mut _))
+            );
+            assert_eq!(Ok(Value::U32(6)), registry.call("f", &[Value::U32(3)]));
+        }
+
+        assert!(registry.unregister("f").is_some());
+        assert!(!registry.contains("f"));
+        unsafe {
+            registry
+                .register("f", unary(Type::i8()), CodePtr(negate as *mut _))
+                .unwrap();
+            assert_eq!(Ok(Value::I8(-3)), registry.call("f", &[Value::I8(3)]));
+        }
+    }
+
+    #[test]
+    fn checked_arguments() {
+        let registry = Registry::new();
+        unsafe {
+            registry
+                .register("double", unary(Type::u32()), CodePtr(double as *mut _))
+                .unwrap();
+            assert_eq!(
+                Err(crate::Error::ArgCount {
+                    expected: 1,
+                    actual: 0
+                }),
+                registry.call("double", &[])
+            );
+            assert_eq!(
+                Err(crate::Error::ArgValue { index: 0 }),
+                registry.call("double", &[Value::I8(1)])
+            );
+        }
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let registry = Arc::new(Registry::new());
+        unsafe {
+            registry
+                .register("double", unary(Type::u32()), CodePtr(double as *mut _))
+                .unwrap();
+        }
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let registry = registry.clone();
+                thread::spawn(
+                    move || match unsafe { registry.call("double", &[Value::U32(i)]) } {
+                        Ok(Value::U32(result)) => result,
+                        other => panic!("unexpected result {:?}", other),
+                    },
+                )
+            })
+            .collect();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(2 * i as u32, handle.join().unwrap());
+        }
+        assert_eq!(vec!["double".to_owned()], registry.names());
+    }
+}
@@ -0,0 +1,140 @@
+//! Rust closures as a C function pointer plus user data, without libffi
+//! closures.
+//!
+//! When a C API takes a callback together with a `void*` that it passes
+//! back to the callback, no trampoline is needed to carry the closure’s
+//! environment: the `void*` can point to the closure, and the callback
+//! can be an ordinary `extern "C"` function, monomorphized for the
+//! closure’s type, that casts it back and calls it.
+//! [`split_closure`](fn.split_closure.html) produces that pair. Unlike
+//! the <code>Closure<em>N</em></code> types, this allocates nothing and
+//! works without the `closures` feature.
+//!
+//! The shim takes the closure’s arguments followed by the user data, the
+//! order that most C callbacks use.
+
+use core::ffi::c_void;
+use core::marker::PhantomData;
+
+/// Closures that [`split_closure`](fn.split_closure.html) can split,
+/// taking the tuple of argument types `Args`.
+///
+/// This is implemented for every `FnMut` of up to twelve arguments (or
+/// more with the `arity16` or `arity32` feature).
+pub trait SplitFn<Args>: Sized {
+    /// The C function type of the shim, which is the closure’s
+    /// signature with a trailing `*mut c_void`, as an `unsafe extern "C"
+    /// fn`, which is also how bindgen declares callback parameters.
+    type Shim: Copy;
+
+    /// The shim that calls a closure of this type, given a pointer to
+    /// it as the last argument.
+    fn shim() -> Self::Shim;
+}
+
+/// A closure split into a C function pointer and the user data to pass
+/// it, borrowing the closure for `'a`.
+///
+/// The pointers are only valid while the `Split` lives.
+pub struct Split<'a, S> {
+    shim: S,
+    userdata: *mut c_void,
+    _marker: PhantomData<&'a mut c_void>,
+}
+
+impl<'a, S: Copy> Split<'a, S> {
+    /// The C function pointer.
+    pub fn shim(&self) -> S {
+        self.shim
+    }
+
+    /// The user data to pass the function pointer.
+    pub fn userdata(&self) -> *mut c_void {
+        self.userdata
+    }
+}
+
+impl<'a, S> core::fmt::Debug for Split<'a, S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("Split")
+            .field("userdata", &self.userdata)
+            .finish()
+    }
+}
+
+/// Splits a closure into a monomorphized `extern "C"` shim and a user
+/// data pointer to the closure.
+///
+/// The shim is unsafe to call, since it must only be called with the
+/// user data from the same `Split`, while the `Split` lives, which C
+/// APIs taking both together do. If the closure
+/// panics, the process aborts, since the panic cannot unwind through C.
+///
+/// # Examples
+///
+/// ```
+/// use std::os::raw::c_void;
+///
+/// use libffi::high::split::split_closure;
+///
+/// // A C API in the usual shape.
+/// extern "C" fn repeat(
+///     times: u32,
+///     callback: unsafe extern "C" fn(u32, *mut c_void) -> u64,
+///     data: *mut c_void,
+/// ) -> u64 {
+///     (0..times).map(|i| unsafe { callback(i, data) }).sum()
+/// }
+///
+/// let mut calls = 0;
+/// let mut square = |i: u32| {
+///     calls += 1;
+///     u64::from(i * i)
+/// };
+///
+/// let split = split_closure(&mut square);
+/// assert_eq!(30, repeat(5, split.shim(), split.userdata()));
+/// drop(split);
+///
+/// assert_eq!(5, calls);
+/// ```
+pub fn split_closure<'a, Args, F: SplitFn<Args>>(closure: &'a mut F) -> Split<'a, F::Shim> {
+    Split {
+        shim: F::shim(),
+        userdata: closure as *mut F as *mut c_void,
+        _marker: PhantomData,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    extern "C" fn call_twice(callback: unsafe extern "C" fn(*mut c_void), data: *mut c_void) {
+        unsafe {
+            callback(data);
+            callback(data);
+        }
+    }
+
+    #[test]
+    fn no_arguments() {
+        let mut count = 0;
+        let mut bump = || count += 1;
+        {
+            let split = split_closure(&mut bump);
+            call_twice(split.shim(), split.userdata());
+        }
+        assert_eq!(2, count);
+    }
+
+    #[test]
+    fn many_arguments() {
+        let mut sum =
+            |a: u8, b: i16, c: f32, d: f64| f64::from(a) + f64::from(b) + f64::from(c) + d;
+        let split = split_closure(&mut sum);
+        assert_eq!(6.5, unsafe {
+            (split.shim())(1, -2, 3.5, 4.0, split.userdata())
+        });
+    }
+}
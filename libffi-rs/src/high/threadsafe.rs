@@ -0,0 +1,90 @@
+//! Closure wrappers that may cross thread boundaries.
+//!
+//! The closure types in the [`high`](../index.html) layer hold raw
+//! pointers, so they are neither `Send` nor `Sync` regardless of what
+//! they capture. When a callback is handed to a multi-threaded C library
+//! (a thread pool, an audio callback, *etc.*), we want the compiler to
+//! check that the captured Rust closure is safe to use from another
+//! thread. The wrappers here are only constructed by methods that
+//! require the appropriate bound on the callback, such as
+//! [`Closure1::new_sync`](../struct.Closure1.html#method.new_sync) and
+//! [`ClosureMut1::new_send`](../struct.ClosureMut1.html#method.new_send),
+//! and in exchange they implement `Send` and (for `SyncClosure`) `Sync`.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::thread;
+//! use libffi::high::ClosureOnce1;
+//!
+//! let v = vec![1, 2, 3];
+//! let closure = ClosureOnce1::new_send(move |i: usize| v[i]);
+//!
+//! let handle = thread::spawn(move || closure.code_ptr()(2));
+//! assert_eq!(3, handle.join().unwrap());
+//! ```
+
+use core::ops::Deref;
+
+/// A closure whose callback is `Send`, so the closure may be moved to
+/// another thread.
+///
+/// Construct with the `new_send` methods of the
+/// <code>Closure<span></span>Mut<em>N</em></code> and
+/// <code>Closure<span></span>Once<em>N</em></code> types.
+#[derive(Debug)]
+pub struct SendClosure<C>(C);
+
+/// A closure whose callback is `Sync`, so the closure may be moved to
+/// or shared with other threads.
+///
+/// Construct with the `new_sync` methods of the
+/// <code>Closure<em>N</em></code> types.
+#[derive(Debug)]
+pub struct SyncClosure<C>(C);
+
+// The wrappers are only constructed when the captured callback is
+// `Send` (respectively `Sync`); the closures themselves only contain
+// raw pointers to the CIF, the libffi allocation, and that callback.
+unsafe impl<C> Send for SendClosure<C> {}
+unsafe impl<C> Send for SyncClosure<C> {}
+unsafe impl<C> Sync for SyncClosure<C> {}
+
+impl<C> SendClosure<C> {
+    pub(crate) fn new(closure: C) -> Self {
+        SendClosure(closure)
+    }
+
+    /// Unwraps the underlying closure, giving up the `Send` guarantee.
+    pub fn into_inner(self) -> C {
+        self.0
+    }
+}
+
+impl<C> SyncClosure<C> {
+    pub(crate) fn new(closure: C) -> Self {
+        SyncClosure(closure)
+    }
+
+    /// Unwraps the underlying closure, giving up the `Send` and `Sync`
+    /// guarantees.
+    pub fn into_inner(self) -> C {
+        self.0
+    }
+}
+
+impl<C> Deref for SendClosure<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.0
+    }
+}
+
+impl<C> Deref for SyncClosure<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.0
+    }
+}
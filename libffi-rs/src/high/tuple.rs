@@ -0,0 +1,134 @@
+//! A closure type that is generic over its argument tuple.
+//!
+//! The <code>Closure<em>N</em></code> types each have a fixed arity, so
+//! code that creates closures generically cannot abstract over the
+//! number of arguments. [`Closure`](struct.Closure.html) instead takes
+//! its argument types as a tuple, such as `Closure<(u32, f64), u64>`,
+//! bounded by [`ArgTuple`](trait.ArgTuple.html), and its Rust callback
+//! receives the arguments as that tuple.
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::high::{ArgTuple, CType, Closure};
+//!
+//! // Works for callbacks of any arity.
+//! fn make<'a, Args: ArgTuple, R: CType>(f: &'a impl Fn(Args) -> R) -> Closure<'a, Args, R> {
+//!     Closure::new(f)
+//! }
+//!
+//! let add = |(x, y): (u32, u32)| x + y;
+//! let closure = make(&add);
+//!
+//! assert_eq!(5, closure.code_ptr()(2, 3));
+//! ```
+
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::marker::PhantomData;
+
+#[cfg(feature = "std")]
+use abort_on_panic::abort_on_panic;
+
+use super::{cast_callback, AsCFnPtr, CType};
+use crate::{low, middle};
+
+/// Tuples of argument types, of up to twelve `CType`s (or more with
+/// the `arity16` or `arity32` feature).
+///
+/// # Safety
+///
+/// The types that `reify` returns must match the values that `read`
+/// reads.
+pub unsafe trait ArgTuple: Sized {
+    /// The middle-layer types of the tuple’s elements, in order.
+    fn reify() -> Vec<middle::Type>;
+
+    /// Reads the tuple from a closure’s argument array.
+    ///
+    /// # Safety
+    ///
+    /// `args` must point to one valid argument pointer per element, as
+    /// passed to a closure whose CIF was built from `reify`.
+    unsafe fn read(args: *const *const c_void) -> Self;
+}
+
+/// An immutable, typed closure whose arguments are the tuple `Args`.
+#[derive(Debug)]
+pub struct Closure<'a, Args, R> {
+    pub(super) untyped: middle::Closure<'a>,
+    _marker: PhantomData<fn(Args) -> R>,
+}
+
+impl<'a, Args: ArgTuple, R: CType> Closure<'a, Args, R> {
+    /// Constructs a typed closure callable from C from a Rust closure
+    /// taking its arguments as a tuple.
+    pub fn new<Callback>(callback: &'a Callback) -> Self
+    where
+        Callback: Fn(Args) -> R + 'a,
+    {
+        let cif = middle::Cif::new(Args::reify(), R::reify().into_middle());
+        let callback_fn = Self::static_callback::<Callback> as extern "C" fn(_, _, _, _);
+        Closure {
+            untyped: middle::Closure::new::<_, R>(
+                cif,
+                unsafe { cast_callback(callback_fn) },
+                callback,
+            ),
+            _marker: PhantomData,
+        }
+    }
+
+    extern "C" fn static_callback<Callback>(
+        cif: &low::ffi_cif,
+        result: *mut c_void,
+        args: *const *const c_void,
+        userdata: &Callback,
+    ) where
+        Callback: Fn(Args) -> R + 'a,
+    {
+        let args = unsafe { Args::read(args) };
+        abort_on_panic!("Cannot panic inside FFI callback", {
+            unsafe {
+                middle::write_result(cif, result, userdata(args));
+            }
+        });
+    }
+}
+
+impl<'a, Args, R> Closure<'a, Args, R> {
+    /// Gets the untyped C code pointer that is used to invoke the
+    /// closure.
+    ///
+    /// It is valid only as long as the closure is alive. The typed
+    /// `code_ptr` method is available for each concrete tuple type.
+    pub fn as_code_ptr(&self) -> low::CodePtr {
+        self.untyped.as_code_ptr()
+    }
+}
+
+impl<'a, Args, R> AsCFnPtr for Closure<'a, Args, R> {
+    fn as_code_ptr(&self) -> low::CodePtr {
+        self.untyped.as_code_ptr()
+    }
+
+    fn cif(&self) -> &middle::Cif {
+        self.untyped.cif()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arities() {
+        let zero = |()| 7u8;
+        let one = |(x,): (i16,)| -x;
+        let three = |(x, y, z): (u8, f64, u64)| f64::from(x) * y + z as f64;
+
+        assert_eq!(7, Closure::new(&zero).code_ptr()());
+        assert_eq!(-4, Closure::new(&one).code_ptr()(4));
+        assert_eq!(8.0, Closure::new(&three).code_ptr()(2, 1.5, 5));
+    }
+}
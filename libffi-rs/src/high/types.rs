@@ -1,8 +1,19 @@
 //! Representations of C types for the high layer.
 
-use std::marker::PhantomData;
+use core::marker::PhantomData;
+use core::num::*;
+use core::ptr::NonNull;
+use core::str::Utf8Error;
+// `core::ffi::CStr` needs Rust 1.64, like `no_std` itself.
+#[cfg(not(feature = "std"))]
+use core::ffi::CStr;
+#[cfg(feature = "std")]
+use std::ffi::CStr;
+
+use libc::c_char;
 
 use super::super::middle;
+use super::abort::abort;
 
 /// Represents a C type statically associated with a Rust type.
 ///
@@ -24,6 +35,20 @@ impl<T> Type<T> {
         }
     }
 
+    /// Wraps a [`middle`](../../middle/index.html) layer type as the
+    /// representation of `T`.
+    ///
+    /// This is how types that are not `CType`, such as non-`Copy`
+    /// structs, can be described.
+    ///
+    /// # Safety
+    ///
+    /// `untyped` must describe the layout of `T`, or else calls through
+    /// CIFs built from the result have undefined behavior.
+    pub unsafe fn from_middle(untyped: middle::Type) -> Self {
+        Type::make(untyped)
+    }
+
     /// Gets the underlying representation as used by the
     /// [`middle`](../../middle/index.html) layer.
     pub fn into_middle(self) -> middle::Type {
@@ -38,12 +63,38 @@ impl<T> Type<T> {
 /// This trait is unsafe to implement because if the libffi type
 /// associated with a Rust type doesn’t match then we get
 /// undefined behavior.
+///
+/// There is no implementation for `half::f16`: libffi has no `ffi_type`
+/// for `_Float16`, which AArch64 passes in floating-point registers, so
+/// no type this crate can build describes it.
 pub unsafe trait CType: Copy {
     /// Creates or retrieves a `Type<T>` for any type `T: CType`.
     ///
     /// We can use the resulting object to assemble a CIF to set up
     /// a call that uses type `T`.
     fn reify() -> Type<Self>;
+
+    /// Checks an argument of this type received by a closure from C, or
+    /// a result returned by [`call`](call/fn.call.html), before it is used
+    /// as a `Self`.
+    ///
+    /// The default accepts everything. Types with invalid bit patterns,
+    /// such as `char`, override this to abort the process on invalid
+    /// values, since a C callback has no way to report an error.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to `size_of::<Self>()` readable, aligned bytes.
+    unsafe fn check_received(ptr: *const Self) {
+        let _ = ptr;
+    }
+}
+
+/// Checks and copies an argument received by a closure from C.
+#[cfg(feature = "closures")]
+pub(crate) fn receive<T: CType>(arg: &T) -> T {
+    unsafe { T::check_received(arg) };
+    *arg
 }
 
 macro_rules! impl_ffi_type {
@@ -73,16 +124,36 @@ impl_ffi_type!(usize);
 impl_ffi_type!(isize);
 impl_ffi_type!((), void);
 
-// Why is the complex stuff even here? It doesn’t work yet because
-// libffi doesn’t support it, so it should probably go away and come
-// back when it’s actually useful. Also, the definitions for c_c32 and
-// c_c64 should come from elsewhere (the num package?), but that
-// elsewhere doesn’t seem to exist yet.
+/// `bool` is passed as a `u8`, which is how C’s `_Bool` and C++’s `bool`
+/// are represented on every platform libffi supports.
+///
+/// Rust always passes `false` as 0 and `true` as 1, and results are
+/// widened like any other `u8`. In the other direction, a C `_Bool` is
+/// likewise always 0 or 1, but any other byte is not a valid `bool`:
+/// when a closure receives a `bool` argument, or a call returns a `bool`
+/// result, that is neither 0 nor 1, it prints a message and aborts the
+/// process. A C function that declares a boolean as `unsigned char` or
+/// `int` and may pass other values should be described with that
+/// integer type instead, and compared with 0.
+unsafe impl CType for bool {
+    fn reify() -> Type<Self> {
+        Type::make(middle::Type::u8())
+    }
+
+    unsafe fn check_received(ptr: *const Self) {
+        let value = *(ptr as *const u8);
+        if value > 1 {
+            abort(format_args!("Invalid bool received from C: {}", value));
+        }
+    }
+}
 
 /// Laid out the same as C11 `float complex` and C++11
-/// `std::complex<float>`.
+/// `std::complex<float>`, as the real part followed by the imaginary
+/// part.
 ///
-/// This item is enabled by `#[cfg(feature = "complex")]`.
+/// This item is enabled by `#[cfg(feature = "complex")]` on targets
+/// where libffi has complex types.
 ///
 /// # Warning
 ///
@@ -91,13 +162,17 @@ impl_ffi_type!((), void);
 /// okay. Theoretically, passing it via libffi is okay, but libffi
 /// doesn’t have complex support on most platforms yet.
 #[allow(non_camel_case_types)]
-#[cfg(feature = "complex")]
-pub type c_c32 = [f32; 2];
+#[cfg(all(feature = "complex", libffi_complex))]
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct c_c32(pub [f32; 2]);
 
 /// Laid out the same as C11 `double complex` and C++11
-/// `std::complex<double>`.
+/// `std::complex<double>`, as the real part followed by the imaginary
+/// part.
 ///
-/// This item is enabled by `#[cfg(feature = "complex")]`.
+/// This item is enabled by `#[cfg(feature = "complex")]` on targets
+/// where libffi has complex types.
 ///
 /// # Warning
 ///
@@ -106,15 +181,145 @@ pub type c_c32 = [f32; 2];
 /// okay. Theoretically, passing it via libffi is okay, but libffi
 /// doesn’t have complex support on most platforms yet.
 #[allow(non_camel_case_types)]
-#[cfg(feature = "complex")]
-pub type c_c64 = [f64; 2];
+#[cfg(all(feature = "complex", libffi_complex))]
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct c_c64(pub [f64; 2]);
 
-#[cfg(feature = "complex")]
+#[cfg(all(feature = "complex", libffi_complex))]
 impl_ffi_type!(c_c32, c32);
 
-#[cfg(feature = "complex")]
+#[cfg(all(feature = "complex", libffi_complex))]
 impl_ffi_type!(c_c64, c64);
 
+/// `num_complex::Complex<f32>` is `#[repr(C)]` with the real part first,
+/// so it is laid out the same as C11 `float complex`.
+///
+/// This impl is enabled by `#[cfg(feature = "num-complex")]` on targets
+/// where libffi has complex types.
+#[cfg(all(feature = "num-complex", libffi_complex))]
+unsafe impl CType for num_complex_crate::Complex<f32> {
+    fn reify() -> Type<Self> {
+        Type::make(middle::Type::c32())
+    }
+}
+
+/// `num_complex::Complex<f64>` is laid out the same as C11
+/// `double complex`.
+///
+/// This impl is enabled by `#[cfg(feature = "num-complex")]` on targets
+/// where libffi has complex types.
+#[cfg(all(feature = "num-complex", libffi_complex))]
+unsafe impl CType for num_complex_crate::Complex<f64> {
+    fn reify() -> Type<Self> {
+        Type::make(middle::Type::c64())
+    }
+}
+
+/// An IEEE quadruple-precision (binary128) floating point number, passed
+/// as C `long double` on targets where that is binary128.
+///
+/// Rust’s own `f128` is not stable yet, so this type only carries the
+/// bits; convert with `f128::from_bits(x.to_bits())` where `f128` is
+/// available, or with a soft-float crate.
+///
+/// This item is only available where
+/// [`middle::Type::f128`](../../middle/struct.Type.html) is.
+///
+/// # Warning
+///
+/// Rust passes this type by value as it would a struct, which is not how
+/// C passes `long double`. So a function pointer typed with an `F128`
+/// argument or result, such as the `extern "C" fn(F128)` that a
+/// closure’s `code_ptr` returns, has undefined behavior when called from
+/// Rust. Pass it only through libffi, as
+/// [`call`](../call/fn.call.html) does, or to closures called from C.
+#[cfg(libffi_f128)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(C, align(16))]
+pub struct F128(u128);
+
+#[cfg(libffi_f128)]
+impl F128 {
+    /// Creates a value from its binary128 bit pattern.
+    pub const fn from_bits(bits: u128) -> Self {
+        F128(bits)
+    }
+
+    /// Returns the binary128 bit pattern of the value.
+    pub const fn to_bits(self) -> u128 {
+        self.0
+    }
+}
+
+#[cfg(libffi_f128)]
+impl_ffi_type!(F128, f128);
+
+#[cfg(libffi_f128)]
+impl middle::ArgTarget for F128 {}
+
+macro_rules! impl_nonzero {
+    ($($nonzero:ident($type_:ident)),*) => {
+        $(
+            unsafe impl CType for $nonzero {
+                fn reify() -> Type<Self> {
+                    Type::make(middle::Type::$type_())
+                }
+
+                unsafe fn check_received(ptr: *const Self) {
+                    if *(ptr as *const $type_) == 0 {
+                        abort(format_args!("Zero {} received from C",
+                                           stringify!($nonzero)));
+                    }
+                }
+            }
+
+            unsafe impl CType for Option<$nonzero> {
+                fn reify() -> Type<Self> {
+                    Type::make(middle::Type::$type_())
+                }
+            }
+        )*
+    };
+}
+
+// The `NonZero` integers have the same layout as the corresponding
+// integers, as do `Option`s of them, with `None` as zero. Receiving a
+// zero where a `NonZero` integer is expected aborts.
+impl_nonzero!(
+    NonZeroU8(u8),
+    NonZeroU16(u16),
+    NonZeroU32(u32),
+    NonZeroU64(u64),
+    NonZeroUsize(usize)
+);
+impl_nonzero!(
+    NonZeroI8(i8),
+    NonZeroI16(i16),
+    NonZeroI32(i32),
+    NonZeroI64(i64),
+    NonZeroIsize(isize)
+);
+
+/// `char` is passed as a `u32` holding its Unicode scalar value.
+///
+/// Any `char` can be passed to C, but not every `u32` is a `char`: when
+/// a closure receives a `char` argument that is a surrogate or greater
+/// than `0x10FFFF`, it prints a message and aborts the process. Use
+/// `u32` and `char::from_u32` instead where C may pass such values.
+unsafe impl CType for char {
+    fn reify() -> Type<Self> {
+        Type::make(middle::Type::u32())
+    }
+
+    unsafe fn check_received(ptr: *const Self) {
+        let value = *(ptr as *const u32);
+        if char::from_u32(value).is_none() {
+            abort(format_args!("Invalid char received from C: {:#x}", value));
+        }
+    }
+}
+
 unsafe impl<T> CType for *const T {
     fn reify() -> Type<Self> {
         Type::make(middle::Type::pointer())
@@ -126,3 +331,345 @@ unsafe impl<T> CType for *mut T {
         Type::make(middle::Type::pointer())
     }
 }
+
+// `Option<NonNull<T>>` and `Option` of a function pointer are
+// guaranteed to have the same layout as a nullable pointer, with `None`
+// as null.
+
+unsafe impl<T> CType for Option<NonNull<T>> {
+    fn reify() -> Type<Self> {
+        Type::make(middle::Type::pointer())
+    }
+}
+
+macro_rules! impl_fn_ptr {
+    ($( $T:ident )*) => {
+        unsafe impl<$( $T, )* R> CType for extern "C" fn($( $T, )*) -> R {
+            fn reify() -> Type<Self> {
+                Type::make(middle::Type::pointer())
+            }
+
+            unsafe fn check_received(ptr: *const Self) {
+                check_fn_ptr(ptr as *const *const u8);
+            }
+        }
+
+        unsafe impl<$( $T, )* R> CType for unsafe extern "C" fn($( $T, )*) -> R {
+            fn reify() -> Type<Self> {
+                Type::make(middle::Type::pointer())
+            }
+
+            unsafe fn check_received(ptr: *const Self) {
+                check_fn_ptr(ptr as *const *const u8);
+            }
+        }
+
+        unsafe impl<$( $T, )* R> CType for Option<extern "C" fn($( $T, )*) -> R> {
+            fn reify() -> Type<Self> {
+                Type::make(middle::Type::pointer())
+            }
+        }
+
+        unsafe impl<$( $T, )* R> CType for Option<unsafe extern "C" fn($( $T, )*) -> R> {
+            fn reify() -> Type<Self> {
+                Type::make(middle::Type::pointer())
+            }
+        }
+    };
+}
+
+// Function pointers can’t be null, so receiving a null one aborts like
+// receiving an invalid `char`. Use an `Option` where C may pass null.
+unsafe fn check_fn_ptr(ptr: *const *const u8) {
+    if (*ptr).is_null() {
+        abort(format_args!("Null function pointer received from C"));
+    }
+}
+
+impl_fn_ptr!();
+impl_fn_ptr!(A);
+impl_fn_ptr!(A B);
+impl_fn_ptr!(A B C);
+impl_fn_ptr!(A B C D);
+impl_fn_ptr!(A B C D E);
+impl_fn_ptr!(A B C D E F);
+impl_fn_ptr!(A B C D E F G);
+impl_fn_ptr!(A B C D E F G H);
+impl_fn_ptr!(A B C D E F G H I);
+impl_fn_ptr!(A B C D E F G H I J);
+impl_fn_ptr!(A B C D E F G H I J K);
+impl_fn_ptr!(A B C D E F G H I J K L);
+#[cfg(feature = "arity16")]
+impl_fn_ptr!(A B C D E F G H I J K L M);
+#[cfg(feature = "arity16")]
+impl_fn_ptr!(A B C D E F G H I J K L M N);
+#[cfg(feature = "arity16")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O);
+#[cfg(feature = "arity16")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W X);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W X Y);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W X Y Z);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1 F1);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1 F1 G1);
+#[cfg(feature = "arity32")]
+impl_fn_ptr!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1 F1 G1 H1);
+
+/// A C string returned by a C function, as a `*const c_char` that may
+/// be null.
+///
+/// C functions that return strings rarely say how long the string
+/// lives: it may be static, owned by some object, or overwritten by the
+/// next call. `CStrPtr` keeps the pointer as returned and makes that
+/// assumption explicit at the point of access, through the unsafe
+/// [`as_cstr`](#method.as_cstr) and [`to_str`](#method.to_str) methods.
+///
+/// # Examples
+///
+/// ```
+/// use std::os::raw::c_char;
+/// use libffi::high::{call::*, CStrPtr};
+///
+/// extern "C" fn greeting() -> *const c_char {
+///     b"hello\0".as_ptr() as *const c_char
+/// }
+///
+/// let s = unsafe { call::<CStrPtr>(CodePtr(greeting as *mut _), &[]) };
+///
+/// assert_eq!(Some(Ok("hello")), unsafe { s.to_str() });
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct CStrPtr(*const c_char);
+
+impl CStrPtr {
+    /// Wraps a pointer to a C string.
+    pub fn new(ptr: *const c_char) -> Self {
+        CStrPtr(ptr)
+    }
+
+    /// The pointer as returned.
+    pub fn as_ptr(self) -> *const c_char {
+        self.0
+    }
+
+    /// Whether the pointer is null.
+    pub fn is_null(self) -> bool {
+        self.0.is_null()
+    }
+
+    /// Borrows the string, or returns `None` if the pointer is null.
+    ///
+    /// # Safety
+    ///
+    /// The pointer, if not null, must point to a NUL-terminated string
+    /// that remains valid and unmodified for `'a`. The caller chooses
+    /// `'a`, so it must match what the C function promises about the
+    /// string’s lifetime.
+    pub unsafe fn as_cstr<'a>(self) -> Option<&'a CStr> {
+        if self.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(self.0))
+        }
+    }
+
+    /// Borrows the string as UTF-8, or returns `None` if the pointer is
+    /// null.
+    ///
+    /// # Safety
+    ///
+    /// As for [`as_cstr`](#method.as_cstr).
+    pub unsafe fn to_str<'a>(self) -> Option<Result<&'a str, Utf8Error>> {
+        self.as_cstr().map(CStr::to_str)
+    }
+}
+
+unsafe impl CType for CStrPtr {
+    fn reify() -> Type<Self> {
+        Type::make(middle::Type::pointer())
+    }
+}
+
+impl middle::ArgTarget for CStrPtr {}
+
+#[cfg(all(test, feature = "closures"))]
+mod test {
+    use super::*;
+    use crate::high::{Closure1, Closure2};
+
+    #[test]
+    fn bool() {
+        let f = |b: bool, x: u32| if b { x } else { 0 };
+        let closure = Closure2::new(&f);
+        assert_eq!(5, closure.code_ptr()(true, 5));
+        assert_eq!(0, closure.code_ptr()(false, 5));
+
+        let g = |x: u32| x > 3;
+        let closure = Closure1::new(&g);
+        assert!(closure.code_ptr()(4));
+        assert!(!closure.code_ptr()(2));
+    }
+
+    // Receiving a byte other than 0 or 1 as a `bool` aborts, so this test
+    // runs itself again in a child process to make each bad call there.
+    #[test]
+    fn bool_invalid_byte() {
+        use crate::high::call::{arg, call, CodePtr};
+        use std::env;
+        use std::process::{Command, Stdio};
+
+        extern "C" fn two() -> u8 {
+            2
+        }
+
+        const VAR: &str = "LIBFFI_TEST_INVALID_BOOL";
+        match env::var(VAR).as_ref().map(String::as_str) {
+            Ok("argument") => {
+                let f = |b: bool| b as u8;
+                let closure = Closure1::new(&f);
+                let fun = CodePtr(*closure.code_ptr() as *mut _);
+                unsafe { call::<u8>(fun, &[arg(&2u8)]) };
+                return;
+            }
+            Ok("result") => {
+                unsafe { call::<bool>(CodePtr(two as *mut _), &[]) };
+                return;
+            }
+            _ => {}
+        }
+
+        for &case in &["argument", "result"] {
+            let status = Command::new(env::current_exe().unwrap())
+                .arg("--exact")
+                .arg("high::types::test::bool_invalid_byte")
+                .env(VAR, case)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .unwrap();
+            assert!(!status.success(), "{} 2 was accepted as a bool", case);
+        }
+    }
+
+    #[test]
+    fn nullable_pointers() {
+        extern "C" fn nop() {}
+
+        let f = |p: Option<NonNull<u32>>| -> Option<extern "C" fn()> {
+            p.map(|_| nop as extern "C" fn())
+        };
+        let closure = Closure1::new(&f);
+        let mut x = 5u32;
+
+        assert!(closure.code_ptr()(None).is_none());
+        assert!(closure.code_ptr()(NonNull::new(&mut x)).is_some());
+    }
+
+    #[test]
+    fn fn_ptr() {
+        let double = |x: u32| x * 2;
+        let double = Closure1::new(&double);
+
+        let apply = |f: extern "C" fn(u32) -> u32, x: u32| f(f(x));
+        let apply = Closure2::new(&apply);
+
+        assert_eq!(12, apply.code_ptr()(*double.code_ptr(), 3));
+    }
+
+    #[test]
+    fn nonzero() {
+        let f = |handle: NonZeroU32| NonZeroU32::new(handle.get() - 1);
+        let closure = Closure1::new(&f);
+
+        assert_eq!(
+            NonZeroU32::new(4),
+            closure.code_ptr()(NonZeroU32::new(5).unwrap())
+        );
+        assert_eq!(None, closure.code_ptr()(NonZeroU32::new(1).unwrap()));
+    }
+
+    #[test]
+    fn cstr_ptr() {
+        let null = CStrPtr::new(std::ptr::null());
+        assert!(null.is_null());
+        assert_eq!(None, unsafe { null.as_cstr() });
+
+        let invalid = CStrPtr::new(b"\xff\0".as_ptr() as *const c_char);
+        assert!(unsafe { invalid.to_str() }.unwrap().is_err());
+    }
+
+    #[test]
+    fn char() {
+        let f = |c: char| c.to_ascii_uppercase();
+        let closure = Closure1::new(&f);
+
+        assert_eq!('Q', closure.code_ptr()('q'));
+        assert_eq!('é', closure.code_ptr()('é'));
+    }
+
+    // Rust passes a struct of two floats as C passes a complex number on
+    // these targets, so the closure can be called from Rust.
+    #[cfg(all(
+        feature = "num-complex",
+        libffi_complex,
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    #[test]
+    fn num_complex() {
+        use num_complex_crate::Complex;
+
+        let f =
+            |z: Complex<f64>, w: Complex<f32>| z.conj() + Complex::new(w.re as f64, w.im as f64);
+        let closure = Closure2::new(&f);
+
+        assert_eq!(
+            Complex::new(4.0, -1.0),
+            closure.code_ptr()(Complex::new(1.0, 2.0), Complex::new(3.0, 1.0))
+        );
+        assert_eq!("complex", <Complex<f64>>::reify().into_middle().describe());
+    }
+
+    #[cfg(libffi_f128)]
+    #[test]
+    fn f128() {
+        use crate::high::call::{arg, call, CodePtr};
+
+        assert_eq!("longdouble", F128::reify().into_middle().describe());
+
+        // Negates by flipping the sign bit, which libffi passes through
+        // the floating-point registers unchanged.
+        let f = |x: F128| F128::from_bits(x.to_bits() ^ (1 << 127));
+        let closure = Closure1::new(&f);
+        let fun = CodePtr(*closure.code_ptr() as *mut _);
+
+        let one = F128::from_bits(0x3fff << 112);
+        let result: F128 = unsafe { call(fun, &[arg(&one)]) };
+        assert_eq!(0xbfff << 112, result.to_bits());
+    }
+}
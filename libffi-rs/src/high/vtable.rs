@@ -0,0 +1,331 @@
+//! Tables of C function pointers that call methods of a Rust object.
+//!
+//! C plugin interfaces commonly take a `#[repr(C)]` struct of function
+//! pointers. The [`vtable!`](../../macro.vtable.html) macro declares
+//! such a struct along with a constructor that, given a reference to any
+//! implementation of a Rust trait, makes one closure per entry calling
+//! the corresponding method, and returns the filled-in table in a
+//! [`VTable`](struct.VTable.html) that keeps the closures alive.
+//!
+//! The types here are the macro’s building blocks, and are not usually
+//! needed directly.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+#[cfg(feature = "std")]
+use abort_on_panic::abort_on_panic;
+
+use super::{cast_callback, ArgTuple, CType};
+use crate::{low, middle};
+
+/// Paths that [`vtable!`](../../macro.vtable.html) expands to, so that it
+/// does not depend on `std` being linked by the calling crate.
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::vec;
+    pub use core::ffi::c_void;
+    pub use core::mem::transmute;
+}
+
+/// A method of `T` that a vtable entry calls.
+///
+/// [`vtable!`](../../macro.vtable.html) implements this for an
+/// uninhabited marker type per entry.
+pub trait Method<T> {
+    /// The argument types of the entry.
+    type Args: ArgTuple;
+    /// The result type of the entry.
+    type Result: CType;
+
+    /// Calls the method.
+    fn call(target: &T, args: Self::Args) -> Self::Result;
+}
+
+/// A closure that calls method `M` of a borrowed `T`.
+#[derive(Debug)]
+pub struct MethodClosure<'a, T, M> {
+    untyped: middle::Closure<'a>,
+    _target: PhantomData<&'a T>,
+    _method: PhantomData<fn() -> M>,
+}
+
+impl<'a, T, M: Method<T>> MethodClosure<'a, T, M> {
+    /// Makes a closure calling `M` on `target`.
+    pub fn new(target: &'a T) -> Self {
+        let cif = middle::Cif::new(M::Args::reify(), M::Result::reify().into_middle());
+        let callback_fn = Self::static_callback as extern "C" fn(_, _, _, _);
+        MethodClosure {
+            untyped: middle::Closure::new::<_, M::Result>(
+                cif,
+                unsafe { cast_callback(callback_fn) },
+                target,
+            ),
+            _target: PhantomData,
+            _method: PhantomData,
+        }
+    }
+
+    extern "C" fn static_callback(
+        cif: &low::ffi_cif,
+        result: *mut c_void,
+        args: *const *const c_void,
+        userdata: &T,
+    ) {
+        let args = unsafe { M::Args::read(args) };
+        abort_on_panic!("Cannot panic inside FFI callback", {
+            unsafe {
+                middle::write_result(cif, result, M::call(userdata, args));
+            }
+        });
+    }
+
+    /// The C code pointer that invokes the closure.
+    pub fn as_code_ptr(&self) -> low::CodePtr {
+        self.untyped.as_code_ptr()
+    }
+
+    /// The underlying middle-layer closure.
+    pub fn into_middle(self) -> middle::Closure<'a> {
+        self.untyped
+    }
+}
+
+/// A vtable struct `V` together with the closures its entries point to.
+///
+/// The table is boxed, so its address stays the same for as long as the
+/// `VTable` lives, and C code may hold on to a pointer to it until then.
+pub struct VTable<'a, V> {
+    table: Box<V>,
+    _closures: Vec<middle::Closure<'a>>,
+}
+
+impl<'a, V> VTable<'a, V> {
+    /// Combines a table with the closures that its entries point to.
+    ///
+    /// # Safety
+    ///
+    /// Every function pointer in `table` must point to one of `closures`,
+    /// or live for `'a` on its own.
+    pub unsafe fn new(table: V, closures: Vec<middle::Closure<'a>>) -> Self {
+        VTable {
+            table: Box::new(table),
+            _closures: closures,
+        }
+    }
+
+    /// A pointer to the table, for passing to C.
+    pub fn as_ptr(&self) -> *const V {
+        &*self.table
+    }
+}
+
+impl<'a, V> Deref for VTable<'a, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.table
+    }
+}
+
+impl<'a, V> fmt::Debug for VTable<'a, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("VTable")
+            .field("table", &self.as_ptr())
+            .field("entries", &self._closures.len())
+            .finish()
+    }
+}
+
+/// Declares a `#[repr(C)]` struct of C function pointers whose entries
+/// call the methods of a Rust trait.
+///
+/// Each entry is written like a trait method without its receiver, and
+/// becomes a public `unsafe extern "C" fn` field of the struct. Calling
+/// an entry is unsafe because the closure behind it only lives as long
+/// as the `VTable`, and the struct is neither `Clone` nor `Copy`, so
+/// that it is normally only reached through the `VTable` or the pointer
+/// passed to C. The macro also
+/// generates a constructor, `new`, which takes a reference to any
+/// implementation of the trait and returns a
+/// [`VTable`](high/vtable/struct.VTable.html) that dereferences to the
+/// filled-in struct and keeps the closures behind its entries alive. The
+/// trait’s methods take `&self` and the entry’s arguments in order, so an
+/// implementation that needs to mutate state uses interior mutability,
+/// since C may call the entries in any order, and re-entrantly.
+///
+/// C interfaces usually pass a context pointer to each entry as well;
+/// list it as an ordinary argument. With closures, the implementation
+/// does not need it to find its state.
+///
+/// # Examples
+///
+/// ```
+/// use std::cell::Cell;
+/// use libffi::vtable;
+///
+/// trait Counter {
+///     fn add(&self, amount: u32);
+///     fn total(&self) -> u64;
+/// }
+///
+/// vtable! {
+///     /// The operations of a counter, as a C library expects them.
+///     pub struct CounterOps for Counter {
+///         fn add(amount: u32);
+///         fn total() -> u64;
+///     }
+/// }
+///
+/// struct Sum(Cell<u64>);
+///
+/// impl Counter for Sum {
+///     fn add(&self, amount: u32) {
+///         self.0.set(self.0.get() + u64::from(amount));
+///     }
+///
+///     fn total(&self) -> u64 {
+///         self.0.get()
+///     }
+/// }
+///
+/// // What a C library might do with the table.
+/// extern "C" fn use_counter(ops: *const CounterOps) -> u64 {
+///     unsafe {
+///         let ops = &*ops;
+///         (ops.add)(2);
+///         (ops.add)(3);
+///         (ops.total)()
+///     }
+/// }
+///
+/// let sum = Sum(Cell::new(0));
+/// let ops = CounterOps::new(&sum);
+///
+/// assert_eq!(5, use_counter(ops.as_ptr()));
+/// ```
+#[cfg(feature = "closures")]
+#[macro_export]
+macro_rules! vtable {
+    {
+        $( #[$attr:meta] )*
+        $vis:vis struct $name:ident for $trait_:path {
+            $(
+                $( #[$entry_attr:meta] )*
+                fn $entry:ident ( $( $arg:ident : $ty:ty ),* $(,)? ) $( -> $ret:ty )? ;
+            )*
+        }
+    } => {
+        $( #[$attr] )*
+        #[repr(C)]
+        #[derive(Debug)]
+        $vis struct $name {
+            $(
+                $( #[$entry_attr] )*
+                pub $entry: unsafe extern "C" fn( $( $ty ),* ) -> $crate::vtable!(@result $( $ret )?),
+            )*
+        }
+
+        impl $name {
+            /// Makes a table whose entries call the methods of `target`.
+            #[allow(dead_code)]
+            $vis fn new<'a, T: $trait_>(target: &'a T) -> $crate::high::vtable::VTable<'a, $name> {
+                $(
+                    #[allow(non_camel_case_types)]
+                    enum $entry {}
+
+                    impl<T: $trait_> $crate::high::vtable::Method<T> for $entry {
+                        type Args = ( $( $ty, )* );
+                        type Result = $crate::vtable!(@result $( $ret )?);
+
+                        #[allow(unused_parens)]
+                        fn call(target: &T, ( $( $arg, )* ): Self::Args) -> Self::Result {
+                            <T as $trait_>::$entry(target $( , $arg )*)
+                        }
+                    }
+                )*
+
+                $(
+                    let $entry = $crate::high::vtable::MethodClosure::<T, $entry>::new(target);
+                )*
+
+                unsafe {
+                    let table = $name {
+                        $(
+                            $entry: $crate::high::vtable::__private::transmute::<
+                                *mut $crate::high::vtable::__private::c_void,
+                                unsafe extern "C" fn( $( $ty ),* ) -> $crate::vtable!(@result $( $ret )?),
+                            >($entry.as_code_ptr().as_mut_ptr()),
+                        )*
+                    };
+                    $crate::high::vtable::VTable::new(
+                        table,
+                        $crate::high::vtable::__private::vec![ $( $entry.into_middle() ),* ],
+                    )
+                }
+            }
+        }
+    };
+
+    (@result) => { () };
+    (@result $ret:ty) => { $ret };
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::os::raw::c_void;
+
+    trait Plugin {
+        fn name(&self) -> u8;
+        fn feed(&self, ctx: *mut c_void, value: i16, scale: f64) -> f64;
+        fn reset(&self);
+    }
+
+    vtable! {
+        struct PluginOps for Plugin {
+            fn name() -> u8;
+            /// Feeds a value.
+            fn feed(ctx: *mut c_void, value: i16, scale: f64) -> f64;
+            fn reset();
+        }
+    }
+
+    struct Log(RefCell<Vec<i16>>);
+
+    impl Plugin for Log {
+        fn name(&self) -> u8 {
+            b'L'
+        }
+
+        fn feed(&self, ctx: *mut c_void, value: i16, scale: f64) -> f64 {
+            assert!(ctx.is_null());
+            self.0.borrow_mut().push(value);
+            f64::from(value) * scale
+        }
+
+        fn reset(&self) {
+            self.0.borrow_mut().clear();
+        }
+    }
+
+    #[test]
+    fn entries_call_methods() {
+        let log = Log(RefCell::new(vec![]));
+        let ops = PluginOps::new(&log);
+
+        unsafe {
+            assert_eq!(b'L', (ops.name)());
+            assert_eq!(-7.5, (ops.feed)(std::ptr::null_mut(), -3, 2.5));
+            assert_eq!(1.0, (ops.feed)(std::ptr::null_mut(), 4, 0.25));
+            assert_eq!(vec![-3, 4], *log.0.borrow());
+
+            (ops.reset)();
+        }
+        assert!(log.0.borrow().is_empty());
+    }
+}
@@ -38,6 +38,49 @@
 //! See [the `libffi-sys` documentation] for more information about how it
 //! finds C libffi.
 //!
+//! If you only call C functions and never create closures, you can turn off
+//! default features to drop the `closures` feature:
+//!
+//! ```toml
+//! [dependencies]
+//! libffi = { version = "1.0.1", default-features = false, features = ["std"] }
+//! ```
+//!
+//! This leaves out the closure types and functions in every layer, and
+//! libffi’s allocator for closures’ executable memory, for sandboxes that
+//! forbid such memory and for programs where size matters.
+//!
+//! Without the `std` feature, also on by default, this crate is `no_std`
+//! and needs only `alloc`. That leaves out `middle::Errno` and
+//! `Cif::call_with_errno`, the `std::error::Error` impls, the `high`
+//! layer’s panic policies and closure registry, and the messages printed
+//! before aborting, and needs Rust 1.64 or later.
+//!
+//! The `complex` feature adds libffi’s complex number types on targets
+//! where libffi has them, and the `num-complex` feature additionally lets
+//! `num_complex::Complex<f32>` and `Complex<f64>` be passed to and from C
+//! in the `high` layer.
+//!
+//! The `libloading` feature adds `middle::load`, which resolves functions
+//! in dynamic libraries with the `libloading` crate and calls them
+//! through a `Cif`, borrowing the library so it stays loaded. It needs
+//! Rust 1.56 or later, as `libloading` does.
+//!
+//! The `static-cif` feature adds `middle::StaticCif`, a CIF that can be
+//! constructed in a `static` and is prepared on first use. It needs Rust
+//! 1.51 or later, for const generics.
+//!
+//! The `arrays` feature implements `high::CType` for arrays `[T; N]`,
+//! which are passed as structs. It also needs Rust 1.51 or later.
+//!
+//! The `closure-registry` feature adds `middle::registry`, which records
+//! the signature, userdata type, and creation backtrace of every live
+//! closure. It needs Rust 1.65 or later, for `std::backtrace`.
+//!
+//! The `perf-map` feature names closure trampolines in
+//! `/tmp/perf-<pid>.map` on Unix, so that `perf` attributes samples in
+//! them to their closures. It needs Rust 1.63 or later.
+//!
 //! This crate supports Rust version 1.36 and later.
 //!
 //! # Organization
@@ -70,6 +113,7 @@
 //! `extern "C" fn(u64, u64) -> u64`.
 //!
 //! ```
+//! # #[cfg(feature = "closures")] {
 //! use libffi::high::Closure2;
 //!
 //! let x = 5u64;
@@ -79,6 +123,7 @@
 //! let fun     = closure.code_ptr();
 //!
 //! assert_eq!(18, fun(6, 7));
+//! # }
 //! ```
 //!
 //! [the `libffi-sys` crate]: https://crates.io/crates/libffi-sys/
@@ -86,8 +131,12 @@
 //! [the `libffi-sys` documentation]: https://docs.rs/libffi-sys/#usage
 //!
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![deny(missing_docs)]
 
+#[macro_use]
+extern crate alloc;
+
 /// Raw definitions imported from the C library (via bindgen).
 ///
 /// This module is generated by bindgen and undocumented. It’s intended
@@ -97,6 +146,10 @@ pub mod raw {
     pub use libffi_sys::*;
 }
 
+mod error;
+pub use error::{Error, Result};
+
+pub mod features;
 pub mod high;
 pub mod low;
 pub mod middle;
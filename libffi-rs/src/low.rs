@@ -7,8 +7,13 @@
 //! avoided drastic renaming in favor of hewing close to the libffi API.
 //! See [`middle`](../middle/index.html) for an easier-to-use approach.
 
-use std::mem;
-use std::os::raw::{c_uint, c_void};
+use core::ffi::c_void;
+use core::mem;
+#[cfg(feature = "closures")]
+use core::ptr;
+#[cfg(feature = "closures")]
+use core::sync::atomic::{AtomicPtr, Ordering};
+use libc::c_uint;
 
 use crate::raw;
 
@@ -22,7 +27,7 @@ pub enum Error {
 }
 
 /// The `Result` type specialized for libffi `Error`s.
-pub type Result<T> = ::std::result::Result<T, Error>;
+pub type Result<T> = ::core::result::Result<T, Error>;
 
 // Converts the raw status type to a `Result`.
 fn status_to_result<R>(status: raw::ffi_status, good: R) -> Result<R> {
@@ -43,12 +48,42 @@ fn status_to_result<R>(status: raw::ffi_status, good: R) -> Result<R> {
 /// simple type lint. As a `repr(C)` struct of one element, it should
 /// be safe to transmute between `CodePtr` and `*mut c_void`, or between
 /// collections thereof.
-#[derive(Clone, Copy, Debug, Hash)]
+///
+/// Code pointers compare and hash by address, so they can be used as
+/// keys, for example in a table of callbacks.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 #[repr(C)]
 pub struct CodePtr(pub *mut c_void);
 
 // How useful is this type? Does it need all the methods?
 impl CodePtr {
+    /// Initializes a code pointer from a void pointer, returning `None`
+    /// if it is null.
+    ///
+    /// This catches missing symbols, such as a null result from
+    /// `dlsym`, before they are called.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::ptr;
+    /// use libffi::low::CodePtr;
+    ///
+    /// assert_eq!(None, CodePtr::new(ptr::null_mut()));
+    /// ```
+    pub fn new(fun: *mut c_void) -> Option<Self> {
+        if fun.is_null() {
+            None
+        } else {
+            Some(CodePtr(fun))
+        }
+    }
+
+    /// Whether the code pointer is null.
+    pub fn is_null(self) -> bool {
+        self.0.is_null()
+    }
+
     /// Initializes a code pointer from a function pointer.
     ///
     /// This is useful mainly for talking to C APIs that take untyped
@@ -57,6 +92,23 @@ impl CodePtr {
         CodePtr(fun as *mut c_void)
     }
 
+    /// Initializes a code pointer from a C function pointer of any
+    /// signature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::low::CodePtr;
+    ///
+    /// extern "C" fn add(x: u32, y: u32) -> u32 { x + y }
+    ///
+    /// let code = CodePtr::from_typed_fn(add as extern "C" fn(u32, u32) -> u32);
+    /// assert!(!code.is_null());
+    /// ```
+    pub fn from_typed_fn<F: FnSig>(fun: F) -> Self {
+        CodePtr(fun.as_code_ptr())
+    }
+
     /// Initializes a code pointer from a void pointer.
     ///
     /// This is the other common type used in APIs (or at least in
@@ -120,6 +172,99 @@ impl CodePtr {
     }
 }
 
+impl From<unsafe extern "C" fn()> for CodePtr {
+    fn from(fun: unsafe extern "C" fn()) -> Self {
+        CodePtr::from_fun(fun)
+    }
+}
+
+/// C function pointer types, of any signature.
+///
+/// This is implemented for `extern "C" fn` and `unsafe extern "C" fn`
+/// types of up to twelve arguments (or more with the `arity16` or
+/// `arity32` feature), so that
+/// [`CodePtr::from_typed_fn`](struct.CodePtr.html#method.from_typed_fn)
+/// can accept them without casting through `*mut c_void`.
+///
+/// # Safety
+///
+/// Implementing types must be function pointers that can be called
+/// with the C calling convention.
+pub unsafe trait FnSig: Copy {
+    /// The address of the function.
+    fn as_code_ptr(self) -> *mut c_void;
+}
+
+macro_rules! impl_fn_sig {
+    ($( $T:ident )*) => {
+        unsafe impl<$( $T, )* R> FnSig for extern "C" fn($( $T, )*) -> R {
+            fn as_code_ptr(self) -> *mut c_void {
+                self as *mut c_void
+            }
+        }
+
+        unsafe impl<$( $T, )* R> FnSig for unsafe extern "C" fn($( $T, )*) -> R {
+            fn as_code_ptr(self) -> *mut c_void {
+                self as *mut c_void
+            }
+        }
+    };
+}
+
+impl_fn_sig!();
+impl_fn_sig!(A);
+impl_fn_sig!(A B);
+impl_fn_sig!(A B C);
+impl_fn_sig!(A B C D);
+impl_fn_sig!(A B C D E);
+impl_fn_sig!(A B C D E F);
+impl_fn_sig!(A B C D E F G);
+impl_fn_sig!(A B C D E F G H);
+impl_fn_sig!(A B C D E F G H I);
+impl_fn_sig!(A B C D E F G H I J);
+impl_fn_sig!(A B C D E F G H I J K);
+impl_fn_sig!(A B C D E F G H I J K L);
+#[cfg(feature = "arity16")]
+impl_fn_sig!(A B C D E F G H I J K L M);
+#[cfg(feature = "arity16")]
+impl_fn_sig!(A B C D E F G H I J K L M N);
+#[cfg(feature = "arity16")]
+impl_fn_sig!(A B C D E F G H I J K L M N O);
+#[cfg(feature = "arity16")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W X);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W X Y);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W X Y Z);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1 F1);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1 F1 G1);
+#[cfg(feature = "arity32")]
+impl_fn_sig!(A B C D E F G H I J K L M N O P Q S T V W X Y Z A1 B1 C1 D1 E1 F1 G1 H1);
+
 pub use raw::{ffi_abi, ffi_abi_FFI_DEFAULT_ABI, ffi_cif, ffi_closure, ffi_status, ffi_type};
 
 /// Re-exports the `ffi_type` objects used to describe the types of
@@ -128,6 +273,23 @@ pub use raw::{ffi_abi, ffi_abi_FFI_DEFAULT_ABI, ffi_cif, ffi_closure, ffi_status
 /// These are from [`raw`](../../raw/index.html), but are renamed by
 /// removing the `ffi_type_` prefix. For example, `raw::ffi_type_void`
 /// becomes `low::types::void`.
+///
+/// Like `ffi.h`, this also defines aliases for the C integer types,
+/// such as `uint` and `slong`, as the sized type of the same size.
+///
+/// # Examples
+///
+/// ```
+/// use std::mem;
+/// use std::os::raw::{c_long, c_ushort};
+///
+/// use libffi::low::types;
+///
+/// unsafe {
+///     assert_eq!(mem::size_of::<c_long>(), types::slong.size);
+///     assert_eq!(mem::size_of::<c_ushort>(), types::ushort.size);
+/// }
+/// ```
 pub mod types {
     pub use crate::raw::{
         ffi_type_double as double, ffi_type_float as float, ffi_type_pointer as pointer,
@@ -136,15 +298,30 @@ pub mod types {
         ffi_type_uint64 as uint64, ffi_type_uint8 as uint8, ffi_type_void as void,
     };
 
+    pub use crate::raw::{
+        ffi_type_sint16 as sshort, ffi_type_sint8 as schar, ffi_type_uint16 as ushort,
+        ffi_type_uint8 as uchar,
+    };
+
+    #[cfg(target_pointer_width = "16")]
+    pub use crate::raw::{ffi_type_sint16 as sint, ffi_type_uint16 as uint};
+    #[cfg(not(target_pointer_width = "16"))]
+    pub use crate::raw::{ffi_type_sint32 as sint, ffi_type_uint32 as uint};
+
+    #[cfg(not(all(target_pointer_width = "64", not(windows))))]
+    pub use crate::raw::{ffi_type_sint32 as slong, ffi_type_uint32 as ulong};
+    #[cfg(all(target_pointer_width = "64", not(windows)))]
+    pub use crate::raw::{ffi_type_sint64 as slong, ffi_type_uint64 as ulong};
+
     #[cfg(not(all(target_arch = "arm")))]
     pub use crate::raw::ffi_type_longdouble as longdouble;
 
-    #[cfg(feature = "complex")]
+    #[cfg(all(feature = "complex", libffi_complex))]
     pub use crate::raw::{
         ffi_type_complex_double as complex_double, ffi_type_complex_float as complex_float,
     };
 
-    #[cfg(feature = "complex")]
+    #[cfg(all(feature = "complex", libffi_complex))]
     #[cfg(not(all(target_arch = "arm")))]
     pub use crate::raw::ffi_type_complex_longdouble as complex_longdouble;
 }
@@ -189,15 +366,16 @@ pub mod types {
 /// ```
 pub mod type_tag {
     use crate::raw;
-    use std::os::raw::c_ushort;
+    use libc::c_ushort;
 
     /// Indicates a structure type.
     pub const STRUCT: c_ushort = raw::ffi_type_enum_STRUCT as c_ushort;
 
     /// Indicates a complex number type.
     ///
-    /// This item is enabled by `#[cfg(feature = "complex")]`.
-    #[cfg(feature = "complex")]
+    /// This item is enabled by `#[cfg(feature = "complex")]` on targets
+    /// where libffi has complex types.
+    #[cfg(all(feature = "complex", libffi_complex))]
     pub const COMPLEX: c_ushort = raw::ffi_type_enum_COMPLEX as c_ushort;
 }
 
@@ -301,6 +479,58 @@ pub unsafe fn prep_cif_var(
     status_to_result(status, ())
 }
 
+/// Computes the offsets of the fields of a struct type.
+///
+/// This wraps `ffi_get_struct_offsets`, which was added in libffi 3.3,
+/// so it is only available when the linked libffi is at least that new.
+///
+/// # Safety
+///
+/// `struct_type` must point to a valid struct type, and `offsets` must
+/// have room for one `usize` per field. The type is laid out as a side
+/// effect if it has not been already.
+///
+/// # Arguments
+///
+/// - `abi` — the calling convention whose layout rules to use
+/// - `struct_type` — the struct type
+/// - `offsets` — where to write the field offsets, or null
+///
+/// # Result
+///
+/// `Ok(())` for success or `Err(e)` for failure.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::low::*;
+///
+/// let mut fields = unsafe {
+///     [ &mut types::uint8,
+///       &mut types::uint32,
+///       std::ptr::null_mut::<ffi_type>() ]
+/// };
+/// let mut pair: ffi_type = Default::default();
+/// pair.type_ = type_tag::STRUCT;
+/// pair.elements = fields.as_mut_ptr();
+///
+/// let mut offsets = [0usize; 2];
+/// unsafe {
+///     get_struct_offsets(ffi_abi_FFI_DEFAULT_ABI, &mut pair, offsets.as_mut_ptr())
+/// }.unwrap();
+///
+/// assert_eq!([0, 4], offsets);
+/// ```
+#[cfg(libffi_3_3)]
+pub unsafe fn get_struct_offsets(
+    abi: ffi_abi,
+    struct_type: *mut ffi_type,
+    offsets: *mut usize,
+) -> Result<()> {
+    let status = raw::ffi_get_struct_offsets(abi, struct_type, offsets);
+    status_to_result(status, ())
+}
+
 /// Calls a C function as specified by a CIF.
 ///
 /// # Arguments
@@ -359,6 +589,10 @@ pub unsafe fn call<R>(cif: *mut ffi_cif, fun: CodePtr, args: *mut *mut c_void) -
 /// deallocated using [`closure_free`](fn.closure_free.html), after
 /// which point the code pointer should not be used.
 ///
+/// The memory comes from the allocator installed with
+/// [`set_closure_allocator`](fn.set_closure_allocator.html), if any, and
+/// from libffi’s `ffi_closure_alloc` otherwise.
+///
 /// # Examples
 ///
 /// ```
@@ -366,11 +600,23 @@ pub unsafe fn call<R>(cif: *mut ffi_cif, fun: CodePtr, args: *mut *mut c_void) -
 ///
 /// let (closure_handle, code_ptr) = closure_alloc();
 /// ```
+#[cfg(feature = "closures")]
 pub fn closure_alloc() -> (*mut ffi_closure, CodePtr) {
     unsafe {
+        if let Some(allocator) = closure_allocator() {
+            let size = raw::ffi_closure_size();
+            let (closure, code) = (allocator.alloc)(size);
+            // With static trampolines, `ffi_prep_closure_loc` looks for
+            // one in the closure, which must read as null when libffi did
+            // not allocate it.
+            if !closure.is_null() {
+                ptr::write_bytes(closure as *mut u8, 0, size);
+            }
+            return (closure as *mut ffi_closure, code);
+        }
+
         let mut code_pointer = mem::MaybeUninit::<*mut c_void>::uninit();
-        let closure =
-            raw::ffi_closure_alloc(mem::size_of::<ffi_closure>(), code_pointer.as_mut_ptr());
+        let closure = raw::ffi_closure_alloc(raw::ffi_closure_size(), code_pointer.as_mut_ptr());
         (
             closure as *mut ffi_closure,
             CodePtr::from_ptr(code_pointer.assume_init()),
@@ -396,8 +642,170 @@ pub fn closure_alloc() -> (*mut ffi_closure, CodePtr) {
 ///     closure_free(closure_handle);
 /// }
 /// ```
+#[cfg(feature = "closures")]
 pub unsafe fn closure_free(closure: *mut ffi_closure) {
-    raw::ffi_closure_free(closure as *mut c_void);
+    match closure_allocator() {
+        Some(allocator) => (allocator.free)(closure as *mut c_void),
+        None => raw::ffi_closure_free(closure as *mut c_void),
+    }
+}
+
+/// Hooks for allocating closures somewhere other than libffi’s own
+/// executable memory, installed with
+/// [`set_closure_allocator`](fn.set_closure_allocator.html).
+///
+/// This is for platforms where libffi cannot map executable memory
+/// itself, or where a vendor-provided JIT allocator must be used.
+/// Closures are prepared with `ffi_prep_closure_loc`, which writes the
+/// trampoline through the writable address and points it at the
+/// executable one, so the two may be different mappings of the same
+/// memory.
+#[cfg(feature = "closures")]
+#[derive(Clone, Copy, Debug)]
+pub struct ClosureAllocator {
+    /// Allocates `size` bytes for a closure, returning the writable
+    /// address of the allocation and the address at which it can be
+    /// executed, or a null pointer if allocation fails.
+    ///
+    /// The allocation must be aligned for `ffi_closure`, and the two
+    /// addresses must refer to the same memory.
+    pub alloc: unsafe fn(size: usize) -> (*mut c_void, CodePtr),
+    /// Frees an allocation returned by `alloc`, given its writable
+    /// address.
+    pub free: unsafe fn(closure: *mut c_void),
+}
+
+#[cfg(feature = "closures")]
+static CLOSURE_ALLOCATOR: AtomicPtr<ClosureAllocator> = AtomicPtr::new(ptr::null_mut());
+
+#[cfg(feature = "closures")]
+fn closure_allocator() -> Option<&'static ClosureAllocator> {
+    unsafe { CLOSURE_ALLOCATOR.load(Ordering::Acquire).as_ref() }
+}
+
+/// Installs a custom closure allocator, which
+/// [`closure_alloc`](fn.closure_alloc.html) and
+/// [`closure_free`](fn.closure_free.html), and thus the closures of the
+/// higher layers, use from then on. `None` restores libffi’s own
+/// `ffi_closure_alloc` and `ffi_closure_free`.
+///
+/// # Safety
+///
+/// Each closure must be freed by the allocator that allocated it, so no
+/// closure allocated before the call may be freed after it. It is best
+/// to install the allocator once, before creating any closures.
+///
+/// # Examples
+///
+/// An allocator that maps closures readable, writable, and executable
+/// with `mmap`, as libffi would on a platform where it cannot itself:
+///
+/// ```
+/// # #[cfg(target_os = "linux")] {
+/// use std::os::raw::c_void;
+/// use std::ptr;
+/// use libffi::low::{self, ClosureAllocator, CodePtr};
+/// use libffi::middle::{Closure, Cif, Type};
+///
+/// unsafe fn alloc(size: usize) -> (*mut c_void, CodePtr) {
+///     let memory = libc::mmap(
+///         ptr::null_mut(),
+///         size,
+///         libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+///         libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+///         -1,
+///         0,
+///     );
+///     if memory == libc::MAP_FAILED {
+///         return (ptr::null_mut(), CodePtr(ptr::null_mut()));
+///     }
+///     (memory, CodePtr(memory))
+/// }
+///
+/// unsafe fn free(closure: *mut c_void) {
+///     libc::munmap(closure, libffi::raw::ffi_closure_size());
+/// }
+///
+/// static MMAP: ClosureAllocator = ClosureAllocator { alloc, free };
+///
+/// unsafe extern "C" fn add_one(
+///     _cif: &low::ffi_cif,
+///     result: &mut u32,
+///     args: *const *const c_void,
+///     _userdata: &(),
+/// ) {
+///     *result = **(args as *const *const u32) + 1;
+/// }
+///
+/// unsafe { low::set_closure_allocator(Some(&MMAP)) };
+///
+/// {
+///     let cif = Cif::new(vec![Type::u32()], Type::u32());
+///     let closure = Closure::new(cif, add_one, &());
+///     let fun: &extern "C" fn(u32) -> u32 = unsafe { closure.instantiate_code_ptr() };
+///
+///     assert_eq!(6, fun(5));
+/// }
+///
+/// unsafe { low::set_closure_allocator(None) };
+/// # }
+/// ```
+#[cfg(feature = "closures")]
+pub unsafe fn set_closure_allocator(allocator: Option<&'static ClosureAllocator>) {
+    let allocator = allocator.map_or(ptr::null_mut(), |allocator| {
+        allocator as *const ClosureAllocator as *mut ClosureAllocator
+    });
+    CLOSURE_ALLOCATOR.store(allocator, Ordering::Release);
+}
+
+/// A closure allocator that maps each closure with `MAP_JIT`, for
+/// programs under the macOS hardened runtime that have the
+/// `com.apple.security.cs.allow-jit` entitlement but not
+/// `allow-unsigned-executable-memory`, without which libffi cannot map
+/// closures itself. Install it with
+/// [`set_closure_allocator`](fn.set_closure_allocator.html).
+///
+/// Each closure takes a page of its own. On x86-64, `MAP_JIT` memory is
+/// writable and executable at once, so no calls to
+/// `pthread_jit_write_protect_np` are needed. It is not provided on
+/// Apple Silicon, where libffi takes closures from a table of
+/// preassembled trampolines, which needs neither entitlement, and cannot
+/// prepare closures in memory that it did not allocate.
+#[cfg(all(feature = "closures", target_os = "macos", target_arch = "x86_64"))]
+pub static MAP_JIT_ALLOCATOR: ClosureAllocator = ClosureAllocator {
+    alloc: map_jit::alloc,
+    free: map_jit::free,
+};
+
+#[cfg(all(feature = "closures", target_os = "macos", target_arch = "x86_64"))]
+mod map_jit {
+    use core::ffi::c_void;
+    use core::ptr;
+
+    use super::CodePtr;
+    use crate::raw;
+
+    // Not defined by the oldest `libc` that this crate supports.
+    const MAP_JIT: libc::c_int = 0x0800;
+
+    pub unsafe fn alloc(size: usize) -> (*mut c_void, CodePtr) {
+        let memory = libc::mmap(
+            ptr::null_mut(),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+            libc::MAP_PRIVATE | libc::MAP_ANON | MAP_JIT,
+            -1,
+            0,
+        );
+        if memory == libc::MAP_FAILED {
+            return (ptr::null_mut(), CodePtr(ptr::null_mut()));
+        }
+        (memory, CodePtr(memory))
+    }
+
+    pub unsafe fn free(closure: *mut c_void) {
+        libc::munmap(closure, raw::ffi_closure_size());
+    }
 }
 
 /// The type of function called by a closure.
@@ -405,6 +813,7 @@ pub unsafe fn closure_free(closure: *mut ffi_closure) {
 /// `U` is the type of the user data captured by the closure and passed
 /// to the callback, and `R` is the type of the result. The parameters
 /// are not typed, since they are passed as a C array of `void*`.
+#[cfg(feature = "closures")]
 pub type Callback<U, R> =
     unsafe extern "C" fn(cif: &ffi_cif, result: &mut R, args: *const *const c_void, userdata: &U);
 
@@ -413,6 +822,7 @@ pub type Callback<U, R> =
 /// `U` is the type of the user data captured by the closure and passed
 /// to the callback, and `R` is the type of the result. The parameters
 /// are not typed, since they are passed as a C array of `void*`.
+#[cfg(feature = "closures")]
 pub type CallbackMut<U, R> = unsafe extern "C" fn(
     cif: &ffi_cif,
     result: &mut R,
@@ -421,6 +831,7 @@ pub type CallbackMut<U, R> = unsafe extern "C" fn(
 );
 
 /// The callback type expected by `raw::ffi_prep_closure_loc`.
+#[cfg(feature = "closures")]
 pub type RawCallback = unsafe extern "C" fn(
     cif: *mut ffi_cif,
     result: *mut c_void,
@@ -502,6 +913,7 @@ pub type RawCallback = unsafe extern "C" fn(
 ///     assert_eq!(22, twice(add5, 12));
 /// }
 /// ```
+#[cfg(feature = "closures")]
 pub unsafe fn prep_closure<U, R>(
     closure: *mut ffi_closure,
     cif: *mut ffi_cif,
@@ -595,6 +1007,7 @@ pub unsafe fn prep_closure<U, R>(
 ///     assert_eq!(19, twice(add5, 1));
 /// }
 /// ```
+#[cfg(feature = "closures")]
 pub unsafe fn prep_closure_mut<U, R>(
     closure: *mut ffi_closure,
     cif: *mut ffi_cif,
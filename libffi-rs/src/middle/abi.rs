@@ -0,0 +1,123 @@
+use crate::raw;
+
+use super::FfiAbi;
+
+/// A calling convention supported by libffi on the current target.
+///
+/// Only the conventions available on the target are defined, so that an
+/// `Abi` is always valid to pass to libffi. It converts to the raw
+/// [`FfiAbi`](type.FfiAbi.html) value that
+/// [`Cif::set_abi`](struct.Cif.html#method.set_abi),
+/// [`Builder::abi`](struct.Builder.html#method.abi), and the other
+/// methods taking a calling convention accept, with
+/// [`as_raw`](#method.as_raw) or `Into`.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::{Abi, Builder, Type};
+///
+/// let cif = Builder::new()
+///     .arg(Type::u32())
+///     .abi(Abi::Default.as_raw())
+///     .into_cif();
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Abi {
+    /// The target’s default calling convention, as used by `extern "C"`
+    /// functions.
+    Default,
+    /// The System V calling convention.
+    #[cfg(any(target_arch = "x86", target_arch = "arm", target_arch = "aarch64"))]
+    SysV,
+    /// The System V AMD64 calling convention.
+    #[cfg(all(target_arch = "x86_64", unix))]
+    Unix64,
+    /// The Microsoft x64 calling convention.
+    #[cfg(target_arch = "x86_64")]
+    Win64,
+    /// The Microsoft x64 calling convention as implemented by GCC.
+    #[cfg(target_arch = "x86_64")]
+    GnuW64,
+    /// The `stdcall` calling convention, used by the Win32 API.
+    #[cfg(target_arch = "x86")]
+    StdCall,
+    /// The `thiscall` calling convention, used by C++ member functions
+    /// with MSVC.
+    #[cfg(target_arch = "x86")]
+    ThisCall,
+    /// The `fastcall` calling convention.
+    #[cfg(target_arch = "x86")]
+    FastCall,
+    /// The Microsoft `cdecl` calling convention.
+    #[cfg(target_arch = "x86")]
+    MsCdecl,
+    /// The `pascal` calling convention.
+    #[cfg(target_arch = "x86")]
+    Pascal,
+    /// The Borland `register` calling convention.
+    #[cfg(target_arch = "x86")]
+    Register,
+    /// The ARM calling convention passing floating-point values in VFP
+    /// registers.
+    #[cfg(target_arch = "arm")]
+    Vfp,
+}
+
+impl Abi {
+    /// The calling convention of `extern "system"` functions, such as
+    /// Win32 callbacks.
+    ///
+    /// This is `stdcall` on 32-bit x86 Windows, and the default
+    /// convention everywhere else.
+    pub fn system() -> Self {
+        #[cfg(all(windows, target_arch = "x86"))]
+        return Abi::StdCall;
+
+        #[cfg(not(all(windows, target_arch = "x86")))]
+        return Abi::Default;
+    }
+
+    /// The raw libffi value of the calling convention.
+    pub fn as_raw(self) -> FfiAbi {
+        match self {
+            Abi::Default => raw::ffi_abi_FFI_DEFAULT_ABI,
+            #[cfg(any(target_arch = "x86", target_arch = "arm", target_arch = "aarch64"))]
+            Abi::SysV => raw::ffi_abi_FFI_SYSV,
+            #[cfg(all(target_arch = "x86_64", unix))]
+            Abi::Unix64 => raw::ffi_abi_FFI_UNIX64,
+            #[cfg(target_arch = "x86_64")]
+            Abi::Win64 => raw::ffi_abi_FFI_WIN64,
+            #[cfg(target_arch = "x86_64")]
+            Abi::GnuW64 => raw::ffi_abi_FFI_GNUW64,
+            #[cfg(target_arch = "x86")]
+            Abi::StdCall => raw::ffi_abi_FFI_STDCALL,
+            #[cfg(target_arch = "x86")]
+            Abi::ThisCall => raw::ffi_abi_FFI_THISCALL,
+            #[cfg(target_arch = "x86")]
+            Abi::FastCall => raw::ffi_abi_FFI_FASTCALL,
+            #[cfg(target_arch = "x86")]
+            Abi::MsCdecl => raw::ffi_abi_FFI_MS_CDECL,
+            #[cfg(target_arch = "x86")]
+            Abi::Pascal => raw::ffi_abi_FFI_PASCAL,
+            #[cfg(target_arch = "x86")]
+            Abi::Register => raw::ffi_abi_FFI_REGISTER,
+            #[cfg(target_arch = "arm")]
+            Abi::Vfp => raw::ffi_abi_FFI_VFP,
+        }
+    }
+}
+
+// Deriving this needs `#[default]`, which needs Rust 1.62.
+#[allow(clippy::derivable_impls)]
+impl Default for Abi {
+    fn default() -> Self {
+        Abi::Default
+    }
+}
+
+impl From<Abi> for FfiAbi {
+    fn from(abi: Abi) -> Self {
+        abi.as_raw()
+    }
+}
@@ -0,0 +1,184 @@
+use core::ffi::c_void;
+use core::marker::PhantomData;
+use core::mem;
+
+use super::value::{self, Value};
+use crate::low;
+
+/// Reads the arguments passed to a closure callback.
+///
+/// A [`Callback`](type.Callback.html) receives its arguments as a
+/// pointer to an array of pointers, one per argument. An `ArgsReader`
+/// wraps that array along with the CIF describing it, so that indices
+/// are checked against the CIF’s argument count. In debug builds,
+/// [`get`](#method.get) also checks the size of the requested type
+/// against the CIF’s argument type.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "closures")] {
+/// use std::os::raw::c_void;
+///
+/// use libffi::middle::*;
+/// use libffi::low;
+///
+/// unsafe extern "C" fn callback(
+///     cif: &low::ffi_cif,
+///     result: &mut u64,
+///     args: *const *const c_void,
+///     _userdata: &())
+/// {
+///     let args = ArgsReader::new(cif, args);
+///     *result = args.get::<u64>(0) * args.get::<u64>(1);
+/// }
+///
+/// let cif = Cif::new(vec![Type::u64(), Type::u64()], Type::u64());
+/// let closure = Closure::new(cif, callback, &());
+///
+/// let fun: &extern "C" fn(u64, u64) -> u64 = unsafe {
+///     closure.instantiate_code_ptr()
+/// };
+///
+/// assert_eq!(42, fun(6, 7));
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct ArgsReader<'a> {
+    cif: &'a low::ffi_cif,
+    args: *const *const c_void,
+    _marker: PhantomData<&'a c_void>,
+}
+
+impl<'a> ArgsReader<'a> {
+    /// Wraps the CIF and argument array passed to a callback.
+    ///
+    /// # Safety
+    ///
+    /// `args` must point to an array of at least `cif.nargs` valid
+    /// argument pointers, which must remain valid for `'a`. This is the
+    /// case for the arguments a callback receives from libffi.
+    pub unsafe fn new(cif: &'a low::ffi_cif, args: *const *const c_void) -> Self {
+        ArgsReader {
+            cif,
+            args,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The number of arguments.
+    pub fn len(&self) -> usize {
+        self.cif.nargs as usize
+    }
+
+    /// Whether there are no arguments.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Gets the pointer to the `i`th argument.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is out of bounds.
+    pub fn get_raw(&self, i: usize) -> *const c_void {
+        assert!(
+            i < self.len(),
+            "argument index {} out of bounds for {} arguments",
+            i,
+            self.len()
+        );
+        unsafe { *self.args.add(i) }
+    }
+
+    /// Reads the `i`th argument as a `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is out of bounds and, in debug builds, if the size
+    /// of `T` differs from that of the CIF’s argument type.
+    ///
+    /// # Safety
+    ///
+    /// The argument must actually have type `T`. Only its size is
+    /// checked, and only in debug builds.
+    pub unsafe fn get<T: Copy>(&self, i: usize) -> T {
+        let arg = self.get_raw(i);
+        debug_assert_eq!(
+            mem::size_of::<T>(),
+            (**self.cif.arg_types.add(i)).size,
+            "size of argument {} does not match the CIF",
+            i
+        );
+        *(arg as *const T)
+    }
+
+    /// Reads the `i`th argument according to the CIF’s argument type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is out of bounds or the argument type has no
+    /// [`Value`](enum.Value.html) representation.
+    pub fn value(&self, i: usize) -> Value {
+        let arg = self.get_raw(i);
+        unsafe { value::read_ffi_type(arg, *self.cif.arg_types.add(i)) }
+    }
+}
+
+#[cfg(all(test, feature = "closures"))]
+mod test {
+    use super::*;
+    use crate::middle::{Cif, Closure, Type};
+
+    unsafe extern "C" fn sum(
+        cif: &low::ffi_cif,
+        result: &mut i64,
+        args: *const *const c_void,
+        _userdata: &(),
+    ) {
+        let args = ArgsReader::new(cif, args);
+        *result = args.get::<i8>(0) as i64 + args.get::<i64>(1) + args.len() as i64;
+    }
+
+    #[test]
+    fn read_args() {
+        let cif = Cif::new(vec![Type::i8(), Type::i64()], Type::i64());
+        let closure = Closure::new(cif, sum, &());
+        let fun: &extern "C" fn(i8, i64) -> i64 = unsafe { closure.instantiate_code_ptr() };
+
+        assert_eq!(-8, fun(-20, 10));
+    }
+
+    unsafe extern "C" fn count_values(
+        cif: &low::ffi_cif,
+        result: &mut u64,
+        args: *const *const c_void,
+        _userdata: &(),
+    ) {
+        let args = ArgsReader::new(cif, args);
+        assert_eq!(Value::F64(1.5), args.value(0));
+        assert_eq!(Value::U16(7), args.value(1));
+        *result = args.len() as u64;
+    }
+
+    #[test]
+    fn read_values() {
+        let cif = Cif::new(vec![Type::f64(), Type::u16()], Type::u64());
+        let closure = Closure::new(cif, count_values, &());
+        let fun: &extern "C" fn(f64, u16) -> u64 = unsafe { closure.instantiate_code_ptr() };
+
+        assert_eq!(2, fun(1.5, 7));
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn out_of_bounds() {
+        let cif = Cif::new(vec![Type::u32()], Type::void());
+        let x = 5u32;
+        let args = [&x as *const u32 as *const c_void];
+
+        unsafe {
+            ArgsReader::new(cif.as_raw_ptr().as_ref().unwrap(), args.as_ptr()).get_raw(1);
+        }
+    }
+}
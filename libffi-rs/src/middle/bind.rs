@@ -0,0 +1,240 @@
+use alloc::vec::Vec;
+use core::ffi::c_void;
+
+use super::types::align_up;
+use super::{Arg, Cif, ClosureOnce, CodePtr, Type};
+use crate::{low, raw};
+
+// Storage for bound argument values, aligned for any libffi type.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct Chunk([u8; 16]);
+
+// What a bound closure calls: `fun` through `cif`, with copies of the
+// leading arguments at `offsets` into `storage`.
+struct Bound {
+    fun: CodePtr,
+    cif: Cif,
+    storage: Vec<Chunk>,
+    offsets: Vec<usize>,
+}
+
+unsafe extern "C" fn bound_callback(
+    cif: &low::ffi_cif,
+    result: &mut c_void,
+    args: *const *const c_void,
+    bound: &mut Option<Bound>,
+) {
+    // The userdata's type is private, so it cannot have been taken.
+    let bound = bound.as_mut().unwrap();
+    let base = bound.storage.as_mut_ptr() as *mut u8;
+
+    let mut all: Vec<*mut c_void> = bound
+        .offsets
+        .iter()
+        .map(|&offset| base.add(offset) as *mut c_void)
+        .collect();
+    all.extend((0..cif.nargs as usize).map(|i| *args.add(i) as *mut c_void));
+
+    raw::ffi_call(
+        bound.cif.as_raw_ptr(),
+        Some(*bound.fun.as_safe_fun()),
+        result,
+        all.as_mut_ptr(),
+    );
+}
+
+// Whether `cif` was prepared with `ffi_prep_cif_var`, where that is
+// recorded. `Cif`s are only ever prepared as fixed-argument, so this
+// catches CIFs re-prepared through `as_raw_ptr`.
+fn is_variadic(cif: &low::ffi_cif) -> bool {
+    #[cfg(all(
+        any(target_arch = "aarch64", target_arch = "arm64ec"),
+        target_os = "windows"
+    ))]
+    return cif.is_variadic != 0;
+
+    #[cfg(all(target_arch = "aarch64", target_vendor = "apple"))]
+    return cif.aarch64_nfixedargs != cif.nargs;
+
+    #[cfg(any(
+        all(
+            any(target_arch = "powerpc", target_arch = "powerpc64"),
+            not(target_os = "aix")
+        ),
+        target_arch = "sparc64"
+    ))]
+    return cif.nfixedargs != cif.nargs;
+
+    #[allow(unreachable_code)]
+    {
+        let _ = cif;
+        false
+    }
+}
+
+/// Binds leading arguments of a C function, producing a closure that
+/// takes the rest.
+///
+/// `cif` describes `fun`, and `prebound` gives values for its first
+/// `prebound.len()` arguments, which are copied into the closure. The
+/// closure’s signature is that of `cif` without those arguments, with
+/// the same result type and calling convention, and calling it calls
+/// `fun` with the bound arguments followed by its own.
+///
+/// The copies are shallow: a bound pointer argument must stay valid for
+/// as long as the closure may be called.
+///
+/// # Panics
+///
+/// Panics if `prebound` has more arguments than `cif` takes, or if
+/// `cif` was re-prepared as variadic through
+/// [`Cif::as_raw_ptr`](struct.Cif.html#method.as_raw_ptr), on targets
+/// whose `ffi_cif` records that. The closure would not be variadic, and
+/// a variadic CIF cannot be split into bound and free arguments.
+///
+/// # Safety
+///
+/// As for [`Cif::call`](struct.Cif.html#method.call), each time the
+/// closure is called: `cif` must match `fun`, and `prebound` must
+/// point to values of the types of its leading arguments. `cif` must
+/// not be variadic.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// extern "C" fn scale(factor: u32, offset: i64, x: i64) -> i64 {
+///     factor as i64 * x + offset
+/// }
+///
+/// let cif = Cif::new(vec![Type::u32(), Type::i64(), Type::i64()], Type::i64());
+/// let closure = unsafe {
+///     bind(CodePtr(scale as *mut _), cif, &[arg(&3u32), arg(&-1i64)])
+/// };
+///
+/// let triple: &extern "C" fn(i64) -> i64 = unsafe {
+///     closure.instantiate_code_ptr()
+/// };
+///
+/// assert_eq!(14, triple(5));
+/// ```
+pub unsafe fn bind(fun: CodePtr, cif: Cif, prebound: &[Arg]) -> ClosureOnce {
+    let nargs = cif.0.cif.nargs as usize;
+    assert!(
+        prebound.len() <= nargs,
+        "bind: {} arguments bound, but the function takes {}",
+        prebound.len(),
+        nargs
+    );
+    assert!(
+        !is_variadic(&cif.0.cif),
+        "bind: variadic CIFs are not supported"
+    );
+
+    let types: Vec<Type> = (0..nargs)
+        .map(|i| Type::clone_raw(*cif.0.cif.arg_types.add(i)))
+        .collect();
+    let (bound_types, free_types) = types.split_at(prebound.len());
+
+    let mut offsets = Vec::with_capacity(prebound.len());
+    let mut size = 0;
+    for ty in bound_types {
+        let layout = ty.layout();
+        let offset = align_up(size, layout.align());
+        offsets.push(offset);
+        size = offset + layout.size();
+    }
+
+    let mut storage = vec![Chunk([0; 16]); align_up(size, 16) / 16];
+    let base = storage.as_mut_ptr() as *mut u8;
+    for ((value, ty), &offset) in prebound.iter().zip(bound_types).zip(&offsets) {
+        core::ptr::copy_nonoverlapping(value.0 as *const u8, base.add(offset), ty.layout().size());
+    }
+
+    let closure_cif = Cif::new_with_abi(free_types.to_vec(), cif.0.result.clone(), cif.0.cif.abi);
+
+    ClosureOnce::new(
+        closure_cif,
+        bound_callback,
+        Bound {
+            fun,
+            cif,
+            storage,
+            offsets,
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::{arg, ArgTarget};
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Point {
+        x: u8,
+        y: f64,
+    }
+
+    impl ArgTarget for Point {}
+
+    extern "C" fn shift(by: Point, flag: u8, p: Point) -> Point {
+        assert_eq!(7, flag);
+        Point {
+            x: p.x + by.x,
+            y: p.y + by.y,
+        }
+    }
+
+    extern "C" fn negate(x: i32) -> i32 {
+        -x
+    }
+
+    fn point() -> Type {
+        Type::structure(vec![Type::u8(), Type::f64()])
+    }
+
+    #[test]
+    fn bind_struct() {
+        let cif = Cif::new(vec![point(), Type::u8(), point()], point());
+        let closure = unsafe {
+            bind(
+                CodePtr(shift as *mut _),
+                cif,
+                &[arg(&Point { x: 1, y: 0.5 }), arg(&7u8)],
+            )
+        };
+
+        let fun: &extern "C" fn(Point) -> Point = unsafe { closure.instantiate_code_ptr() };
+        let p = fun(Point { x: 2, y: 1.0 });
+        assert_eq!((3, 1.5), (p.x, p.y));
+    }
+
+    #[test]
+    fn bind_all() {
+        let cif = Cif::new(vec![Type::i32()], Type::i32());
+        let closure = unsafe { bind(CodePtr(negate as *mut _), cif, &[arg(&4i32)]) };
+
+        let fun: &extern "C" fn() -> i32 = unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(-4, fun());
+    }
+
+    #[test]
+    fn bind_none() {
+        let cif = Cif::new(vec![Type::i32()], Type::i32());
+        let closure = unsafe { bind(CodePtr(negate as *mut _), cif, &[]) };
+
+        let fun: &extern "C" fn(i32) -> i32 = unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(9, fun(-9));
+    }
+
+    #[test]
+    #[should_panic(expected = "2 arguments bound")]
+    fn bind_too_many() {
+        let cif = Cif::new(vec![Type::i32()], Type::i32());
+        unsafe { bind(CodePtr(negate as *mut _), cif, &[arg(&1i32), arg(&2i32)]) };
+    }
+}
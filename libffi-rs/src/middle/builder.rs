@@ -1,6 +1,11 @@
-use std::any::Any;
+use alloc::vec::Vec;
+#[cfg(feature = "closures")]
+use core::any::Any;
+use libc::c_uint;
 
 use super::types::Type;
+use crate::raw::*;
+use crate::{features, low};
 
 /// Provides a builder-style API for constructing CIFs and closures.
 ///
@@ -15,12 +20,14 @@ use super::types::Type;
 /// Once the builder is configured, construct a `Cif` with
 /// [`into_cif`](#method.into_cif) or a closure with
 /// [`into_closure`](#method.into_closure),
-/// [`into_closure_mut`](#method.into_closure_mut), or
-/// [`into_closure_once`](#method.into_closure_once).
+/// [`into_closure_mut`](#method.into_closure_mut),
+/// [`into_closure_once`](#method.into_closure_once), or
+/// [`into_closure_guarded`](#method.into_closure_guarded).
 ///
 /// # Examples
 ///
 /// ```
+/// # #[cfg(feature = "closures")] {
 /// use std::mem;
 /// use std::os::raw::c_void;
 ///
@@ -28,14 +35,14 @@ use super::types::Type;
 /// use libffi::low;
 ///
 /// unsafe extern "C" fn lambda_callback<F: Fn(u64, u64) -> u64>(
-///     _cif: &low::ffi_cif,
+///     cif: &low::ffi_cif,
 ///     result: &mut u64,
 ///     args: *const *const c_void,
 ///     userdata: &F)
 /// {
-///     let args: *const &u64 = mem::transmute(args);
-///     let arg1 = **args.offset(0);
-///     let arg2 = **args.offset(1);
+///     let args = ArgsReader::new(cif, args);
+///     let arg1 = args.get(0);
+///     let arg2 = args.get(1);
 ///
 ///     *result = userdata(arg1, arg2);
 /// }
@@ -55,6 +62,7 @@ use super::types::Type;
 ///     assert_eq!(11, fun(5, 6));
 ///     assert_eq!(12, fun(5, 7));
 /// }
+/// # }
 /// ```
 #[derive(Clone, Debug)]
 pub struct Builder {
@@ -107,10 +115,59 @@ impl Builder {
     }
 
     /// Builds a CIF.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects the types or calling convention. See
+    /// [`try_into_cif`](#method.try_into_cif) for a version that
+    /// reports why instead.
     pub fn into_cif(self) -> super::Cif {
-        let mut result = super::Cif::new(self.args, self.res);
-        result.set_abi(self.abi);
-        result
+        super::Cif::new_with_abi(self.args, self.res, self.abi)
+    }
+
+    /// Builds a CIF, or reports why the builder’s configuration is
+    /// invalid.
+    ///
+    /// Before asking libffi to prepare the CIF, this checks for `void`
+    /// argument types, complex types on targets whose libffi does not
+    /// support them, more arguments than a C `unsigned int` can count,
+    /// and calling conventions that are out of range.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::*;
+    ///
+    /// let result = Builder::new()
+    ///     .arg(Type::u32())
+    ///     .arg(Type::void())
+    ///     .try_into_cif();
+    ///
+    /// assert_eq!(Some(libffi::Error::VoidArg { index: 1 }), result.err());
+    ///
+    /// let result = Builder::new().abi(1000).try_into_cif();
+    /// assert_eq!(Some(libffi::Error::BadAbi { abi: 1000 }), result.err());
+    /// ```
+    pub fn try_into_cif(self) -> crate::Result<super::Cif> {
+        // `c_uint::MAX` needs Rust 1.43.
+        #[allow(clippy::legacy_numeric_constants)]
+        let max_args = c_uint::max_value() as usize;
+        if self.args.len() > max_args {
+            return Err(crate::Error::TooManyArgs {
+                count: self.args.len(),
+            });
+        }
+
+        if !(ffi_abi_FFI_FIRST_ABI + 1..ffi_abi_FFI_LAST_ABI).contains(&self.abi) {
+            return Err(crate::Error::BadAbi { abi: self.abi });
+        }
+
+        for (index, type_) in self.args.iter().enumerate() {
+            unsafe { check_type(type_.as_raw_ptr(), Some(index)) }?;
+        }
+        unsafe { check_type(self.res.as_raw_ptr(), None) }?;
+
+        Ok(super::Cif::prepare(self.args, self.res, self.abi)?)
     }
 
     /// Builds an immutable closure.
@@ -124,6 +181,7 @@ impl Builder {
     /// # Result
     ///
     /// The new closure.
+    #[cfg(feature = "closures")]
     pub fn into_closure<U, R>(
         self,
         callback: super::Callback<U, R>,
@@ -143,6 +201,7 @@ impl Builder {
     /// # Result
     ///
     /// The new closure.
+    #[cfg(feature = "closures")]
     pub fn into_closure_mut<U, R>(
         self,
         callback: super::CallbackMut<U, R>,
@@ -162,6 +221,7 @@ impl Builder {
     /// # Result
     ///
     /// The new closure.
+    #[cfg(feature = "closures")]
     pub fn into_closure_once<U: Any, R>(
         self,
         callback: super::CallbackOnce<U, R>,
@@ -169,4 +229,52 @@ impl Builder {
     ) -> super::ClosureOnce {
         super::ClosureOnce::new(self.into_cif(), callback, userdata)
     }
+
+    /// Builds a guarded closure, which may be dropped while calls to it
+    /// are in flight on other threads.
+    ///
+    /// # Arguments
+    ///
+    /// - `callback` — the function to call when the closure is invoked
+    /// - `userdata` — the object to pass to `callback` along with the
+    ///   arguments when the closure is called
+    ///
+    /// # Result
+    ///
+    /// The new closure.
+    #[cfg(feature = "closures")]
+    pub fn into_closure_guarded<U, R>(
+        self,
+        callback: super::Callback<U, R>,
+        userdata: U,
+    ) -> super::GuardedClosure
+    where
+        U: Send + Sync + 'static,
+    {
+        super::GuardedClosure::new(self.into_cif(), callback, userdata)
+    }
+}
+
+// Checks a type for problems that libffi would not report clearly.
+// `arg` is the argument index, or `None` for the result type, which
+// may be `void`.
+unsafe fn check_type(ty: *const low::ffi_type, arg: Option<usize>) -> crate::Result<()> {
+    match (u32::from((*ty).type_), arg) {
+        (FFI_TYPE_VOID, Some(index)) => Err(crate::Error::VoidArg { index }),
+        (FFI_TYPE_COMPLEX, _) if !features::complex_types() => {
+            Err(crate::Error::UnsupportedComplex)
+        }
+        (FFI_TYPE_STRUCT, _) => {
+            let mut element = (*ty).elements;
+            while !(*element).is_null() {
+                if u32::from((**element).type_) == FFI_TYPE_VOID {
+                    return Err(crate::Error::Ffi(low::Error::Typedef));
+                }
+                check_type(*element, arg)?;
+                element = element.offset(1);
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
 }
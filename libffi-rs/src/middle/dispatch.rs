@@ -0,0 +1,347 @@
+// Elsewhere, no signature is called directly.
+#![cfg_attr(
+    not(all(
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_pointer_width = "64"
+    )),
+    allow(dead_code)
+)]
+
+use core::ffi::c_void;
+use core::mem;
+
+use super::{Arg, Cif, CodePtr, RetSafe};
+use crate::low;
+use crate::raw::*;
+
+// The most arguments a direct call takes.
+const MAX_DIRECT_ARGS: usize = 4;
+
+/// Calls functions through a [`Cif`](struct.Cif.html), bypassing libffi
+/// for common signatures.
+///
+/// When the CIF uses the default calling convention and takes up to four
+/// arguments that are all integers and pointers, all `f32`, or all
+/// `f64`, and returns `void`, an integer, a pointer, or a float, the
+/// dispatcher transmutes the code pointer to the matching `extern "C"`
+/// function type and calls it directly, which is considerably faster
+/// than `ffi_call` in tight loops. Integer arguments are passed widened
+/// to 64 bits, so this is only done on x86-64 and AArch64, where that is
+/// how they are passed anyway. Any other call falls back to
+/// [`Cif::call`](struct.Cif.html#method.call).
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// extern "C" fn add(x: u32, y: i64) -> i64 { x as i64 + y }
+///
+/// let cif = Cif::new(vec![Type::u32(), Type::i64()], Type::i64());
+/// let dispatcher = Dispatcher::new(cif);
+///
+/// let result: i64 = unsafe {
+///     dispatcher.call(CodePtr(add as *mut _), &[arg(&5u32), arg(&-7i64)])
+/// };
+///
+/// assert_eq!(-2, result);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Dispatcher {
+    cif: Cif,
+    shape: Option<Shape>,
+}
+
+// A signature that can be called directly.
+#[derive(Clone, Copy, Debug)]
+struct Shape {
+    args: ArgClass,
+    nargs: usize,
+    words: [Word; MAX_DIRECT_ARGS],
+    result: ResultClass,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArgClass {
+    Words,
+    F32s,
+    F64s,
+}
+
+// How to widen an integer argument to 64 bits.
+#[derive(Clone, Copy, Debug)]
+enum Word {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ResultClass {
+    Void,
+    Bits8,
+    Bits16,
+    Bits32,
+    Bits64,
+    F32,
+    F64,
+}
+
+impl Word {
+    unsafe fn of(ty: *const low::ffi_type) -> Option<Self> {
+        Some(match u32::from((*ty).type_) {
+            FFI_TYPE_UINT8 => Word::U8,
+            FFI_TYPE_SINT8 => Word::I8,
+            FFI_TYPE_UINT16 => Word::U16,
+            FFI_TYPE_SINT16 => Word::I16,
+            FFI_TYPE_UINT32 => Word::U32,
+            FFI_TYPE_SINT32 | FFI_TYPE_INT => Word::I32,
+            FFI_TYPE_UINT64 | FFI_TYPE_SINT64 | FFI_TYPE_POINTER => Word::U64,
+            _ => return None,
+        })
+    }
+
+    unsafe fn read(self, arg: *const c_void) -> u64 {
+        match self {
+            Word::U8 => u64::from(*(arg as *const u8)),
+            Word::I8 => *(arg as *const i8) as i64 as u64,
+            Word::U16 => u64::from(*(arg as *const u16)),
+            Word::I16 => *(arg as *const i16) as i64 as u64,
+            Word::U32 => u64::from(*(arg as *const u32)),
+            Word::I32 => *(arg as *const i32) as i64 as u64,
+            Word::U64 => *(arg as *const u64),
+        }
+    }
+}
+
+impl ResultClass {
+    unsafe fn of(ty: *const low::ffi_type) -> Option<Self> {
+        Some(match u32::from((*ty).type_) {
+            FFI_TYPE_VOID => ResultClass::Void,
+            FFI_TYPE_UINT8 | FFI_TYPE_SINT8 => ResultClass::Bits8,
+            FFI_TYPE_UINT16 | FFI_TYPE_SINT16 => ResultClass::Bits16,
+            FFI_TYPE_UINT32 | FFI_TYPE_SINT32 | FFI_TYPE_INT => ResultClass::Bits32,
+            FFI_TYPE_UINT64 | FFI_TYPE_SINT64 | FFI_TYPE_POINTER => ResultClass::Bits64,
+            FFI_TYPE_FLOAT => ResultClass::F32,
+            FFI_TYPE_DOUBLE => ResultClass::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ResultClass::Void => 0,
+            ResultClass::Bits8 => 1,
+            ResultClass::Bits16 => 2,
+            ResultClass::Bits32 | ResultClass::F32 => 4,
+            ResultClass::Bits64 | ResultClass::F64 => 8,
+        }
+    }
+}
+
+impl Shape {
+    #[cfg(all(
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_pointer_width = "64"
+    ))]
+    unsafe fn of(cif: &low::ffi_cif) -> Option<Self> {
+        let nargs = cif.nargs as usize;
+        if cif.abi != low::ffi_abi_FFI_DEFAULT_ABI || nargs > MAX_DIRECT_ARGS {
+            return None;
+        }
+
+        let result = ResultClass::of(cif.rtype)?;
+        let mut words = [Word::U64; MAX_DIRECT_ARGS];
+        let mut args = None;
+
+        for (i, word) in words.iter_mut().enumerate().take(nargs) {
+            let ty = *cif.arg_types.add(i);
+            let class = match u32::from((*ty).type_) {
+                FFI_TYPE_FLOAT => ArgClass::F32s,
+                FFI_TYPE_DOUBLE => ArgClass::F64s,
+                _ => {
+                    *word = Word::of(ty)?;
+                    ArgClass::Words
+                }
+            };
+
+            if *args.get_or_insert(class) != class {
+                return None;
+            }
+        }
+
+        Some(Shape {
+            args: args.unwrap_or(ArgClass::Words),
+            nargs,
+            words,
+            result,
+        })
+    }
+
+    #[cfg(not(all(
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_pointer_width = "64"
+    )))]
+    unsafe fn of(_cif: &low::ffi_cif) -> Option<Self> {
+        None
+    }
+}
+
+// Calls `fun` as an `extern "C"` function taking `args` and returning `R`.
+unsafe fn invoke<T: Copy, R>(fun: CodePtr, args: &[T]) -> R {
+    let fun = fun.as_mut_ptr();
+    match *args {
+        [] => mem::transmute::<*mut c_void, extern "C" fn() -> R>(fun)(),
+        [a] => mem::transmute::<*mut c_void, extern "C" fn(T) -> R>(fun)(a),
+        [a, b] => mem::transmute::<*mut c_void, extern "C" fn(T, T) -> R>(fun)(a, b),
+        [a, b, c] => mem::transmute::<*mut c_void, extern "C" fn(T, T, T) -> R>(fun)(a, b, c),
+        [a, b, c, d] => {
+            mem::transmute::<*mut c_void, extern "C" fn(T, T, T, T) -> R>(fun)(a, b, c, d)
+        }
+        _ => unreachable!("Dispatcher: too many arguments for a direct call"),
+    }
+}
+
+// Calls `fun` directly, reading its result as the C type and returning
+// its bits as `R`, which has the same size.
+unsafe fn invoke_as<T: Copy, R>(fun: CodePtr, args: &[T], result: ResultClass) -> R {
+    match result {
+        ResultClass::Void => mem::transmute_copy(&invoke::<T, ()>(fun, args)),
+        ResultClass::Bits8 => mem::transmute_copy(&invoke::<T, u8>(fun, args)),
+        ResultClass::Bits16 => mem::transmute_copy(&invoke::<T, u16>(fun, args)),
+        ResultClass::Bits32 => mem::transmute_copy(&invoke::<T, u32>(fun, args)),
+        ResultClass::Bits64 => mem::transmute_copy(&invoke::<T, u64>(fun, args)),
+        ResultClass::F32 => mem::transmute_copy(&invoke::<T, f32>(fun, args)),
+        ResultClass::F64 => mem::transmute_copy(&invoke::<T, f64>(fun, args)),
+    }
+}
+
+impl Dispatcher {
+    /// Creates a dispatcher for calls through `cif`, deciding once
+    /// whether they can be made directly.
+    pub fn new(cif: Cif) -> Self {
+        let shape = unsafe { Shape::of(&*cif.as_raw_ptr()) };
+        Dispatcher { cif, shape }
+    }
+
+    /// Whether calls bypass libffi.
+    pub fn is_direct(&self) -> bool {
+        self.shape.is_some()
+    }
+
+    /// The CIF that calls are made through.
+    pub fn cif(&self) -> &Cif {
+        &self.cif
+    }
+
+    /// Calls a function with the given arguments.
+    ///
+    /// The call is made directly if the signature allows and `R` is
+    /// exactly the size of the result type, and through libffi
+    /// otherwise.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`](struct.Cif.html#method.call).
+    pub unsafe fn call<R: RetSafe>(&self, fun: CodePtr, args: &[Arg]) -> R {
+        let shape = match self.shape {
+            Some(shape) if shape.result.size() == mem::size_of::<R>() => shape,
+            _ => return self.cif.call(fun, args),
+        };
+
+        assert_eq!(
+            shape.nargs,
+            args.len(),
+            "Dispatcher::call: passed wrong number of arguments"
+        );
+
+        let args = &args[..shape.nargs];
+        match shape.args {
+            ArgClass::Words => {
+                let mut words = [0u64; MAX_DIRECT_ARGS];
+                for ((slot, word), arg) in words.iter_mut().zip(&shape.words).zip(args) {
+                    *slot = word.read(arg.0);
+                }
+                invoke_as(fun, &words[..shape.nargs], shape.result)
+            }
+            ArgClass::F32s => {
+                let mut floats = [0f32; MAX_DIRECT_ARGS];
+                for (slot, arg) in floats.iter_mut().zip(args) {
+                    *slot = *(arg.0 as *const f32);
+                }
+                invoke_as(fun, &floats[..shape.nargs], shape.result)
+            }
+            ArgClass::F64s => {
+                let mut floats = [0f64; MAX_DIRECT_ARGS];
+                for (slot, arg) in floats.iter_mut().zip(args) {
+                    *slot = *(arg.0 as *const f64);
+                }
+                invoke_as(fun, &floats[..shape.nargs], shape.result)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::{arg, Type};
+
+    extern "C" fn mix(a: i8, b: u16, c: i32, d: *const u8) -> i64 {
+        a as i64 + b as i64 + c as i64 + d as i64
+    }
+
+    extern "C" fn halve(x: f32) -> f32 {
+        x / 2.0
+    }
+
+    extern "C" fn scale(x: f64, factor: u32) -> f64 {
+        x * factor as f64
+    }
+
+    #[test]
+    fn direct_words() {
+        let cif = Cif::new(
+            vec![Type::i8(), Type::u16(), Type::i32(), Type::pointer()],
+            Type::i64(),
+        );
+        let dispatcher = Dispatcher::new(cif);
+        assert_eq!(
+            cfg!(all(
+                any(target_arch = "x86_64", target_arch = "aarch64"),
+                target_pointer_width = "64"
+            )),
+            dispatcher.is_direct()
+        );
+
+        let args = [
+            arg(&-1i8),
+            arg(&2u16),
+            arg(&-3i32),
+            Arg::new(&(10 as *const u8)),
+        ];
+        let result: i64 = unsafe { dispatcher.call(CodePtr(mix as *mut _), &args) };
+        assert_eq!(8, result);
+    }
+
+    #[test]
+    fn direct_float() {
+        let dispatcher = Dispatcher::new(Cif::new(vec![Type::f32()], Type::f32()));
+        let result: f32 = unsafe { dispatcher.call(CodePtr(halve as *mut _), &[arg(&3f32)]) };
+        assert_eq!(1.5, result);
+    }
+
+    #[test]
+    fn mixed_falls_back() {
+        let dispatcher = Dispatcher::new(Cif::new(vec![Type::f64(), Type::u32()], Type::f64()));
+        assert!(!dispatcher.is_direct());
+
+        let result: f64 =
+            unsafe { dispatcher.call(CodePtr(scale as *mut _), &[arg(&1.5f64), arg(&4u32)]) };
+        assert_eq!(6.0, result);
+    }
+}
@@ -0,0 +1,52 @@
+use std::io;
+use std::os::raw::c_int;
+
+#[cfg(windows)]
+extern "C" {
+    fn _errno() -> *mut c_int;
+}
+
+/// The thread’s error indicators as a foreign function left them.
+///
+/// Returned by [`Cif::call_with_errno`](struct.Cif.html#method.call_with_errno),
+/// which reads them immediately after the call, before anything on the
+/// Rust side can overwrite them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Errno {
+    /// The value of C’s `errno`.
+    pub errno: c_int,
+    /// The value of `GetLastError()`.
+    #[cfg(windows)]
+    pub last_error: u32,
+}
+
+impl Errno {
+    // Reads the current thread’s error indicators.
+    pub(super) fn snapshot() -> Self {
+        #[cfg(windows)]
+        {
+            let last_error = io::Error::last_os_error().raw_os_error().unwrap_or(0) as u32;
+            Errno {
+                errno: unsafe { *_errno() },
+                last_error,
+            }
+        }
+
+        #[cfg(not(windows))]
+        Errno {
+            errno: io::Error::last_os_error().raw_os_error().unwrap_or(0),
+        }
+    }
+
+    /// Converts `errno` to an `io::Error`.
+    ///
+    /// On Windows, this uses `last_error` instead, since that is the
+    /// code that `io::Error` describes there.
+    pub fn to_io_error(self) -> io::Error {
+        #[cfg(windows)]
+        return io::Error::from_raw_os_error(self.last_error as i32);
+
+        #[cfg(not(windows))]
+        return io::Error::from_raw_os_error(self.errno);
+    }
+}
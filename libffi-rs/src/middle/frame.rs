@@ -0,0 +1,352 @@
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::Deref;
+use core::ptr;
+
+use super::Arg;
+
+// The unit of allocation, aligned for any libffi type.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, align(16))]
+pub(super) struct Chunk(pub(super) [u8; MAX_ALIGN]);
+
+pub(super) const MAX_ALIGN: usize = 16;
+
+/// Owns copies of the arguments for a call.
+///
+/// [`Cif::call`](struct.Cif.html#method.call) takes arguments as
+/// [`Arg`](struct.Arg.html)s, which are pointers to the argument
+/// values, so those values must outlive the call. That is easy to get
+/// wrong when arguments are produced by an iterator or are temporaries.
+/// A `CallFrame` instead copies each argument into an internal arena,
+/// and [`args`](#method.args) yields `Arg`s pointing into the arena, so
+/// nothing the call references can dangle while the frame is borrowed.
+///
+/// A frame can be [`clear`](#method.clear)ed and reused for the next
+/// call, keeping its allocation.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// extern "C" fn sum3(x: u64, y: u64, z: u64) -> u64 { x + y + z }
+///
+/// let cif = Cif::new(vec![Type::u64(); 3], Type::u64());
+/// let mut frame = CallFrame::new();
+/// for i in 1..=3u64 {
+///     frame.push(i * 10);
+/// }
+///
+/// let result: u64 = unsafe {
+///     cif.call(CodePtr(sum3 as *mut _), frame.args())
+/// };
+/// assert_eq!(60, result);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CallFrame {
+    arena: Vec<Chunk>,
+    used: usize,
+    offsets: Vec<usize>,
+    // Points into `arena`; `args` only lends them out while the frame is
+    // borrowed.
+    args: Vec<Arg<'static>>,
+}
+
+impl CallFrame {
+    /// Creates an empty frame.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of arguments pushed.
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Whether no arguments have been pushed.
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Removes all arguments, keeping the allocated storage.
+    pub fn clear(&mut self) {
+        self.used = 0;
+        self.offsets.clear();
+        self.args.clear();
+    }
+
+    /// Copies a value into the frame as the next argument.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` requires alignment greater than 16.
+    pub fn push<T: Copy>(&mut self, value: T) -> &mut Self {
+        let size = mem::size_of::<T>();
+        let offset = self.reserve(size, mem::align_of::<T>());
+        unsafe {
+            ptr::write(self.at(offset) as *mut T, value);
+        }
+        self
+    }
+
+    /// Copies the bytes of a value, such as a struct whose type is only
+    /// known at run time, into the frame as the next argument.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align` is not a power of two no greater than 16.
+    pub fn push_bytes(&mut self, bytes: &[u8], align: usize) -> &mut Self {
+        let offset = self.reserve(bytes.len(), align);
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), self.at(offset) as *mut u8, bytes.len());
+        }
+        self
+    }
+
+    /// The arguments, pointing into the frame, for passing to
+    /// [`Cif::call`](struct.Cif.html#method.call).
+    pub fn args(&mut self) -> &[Arg<'_>] {
+        // The arena may have moved since the last call, so the pointers
+        // are recomputed from the offsets.
+        self.args.clear();
+        for i in 0..self.offsets.len() {
+            let arg = self.at(self.offsets[i]);
+            // `args` borrows the frame, so the arena outlives them.
+            self.args.push(unsafe { Arg::from_ptr(arg) });
+        }
+        &self.args
+    }
+
+    fn at(&mut self, offset: usize) -> *mut c_void {
+        unsafe { (self.arena.as_mut_ptr() as *mut u8).add(offset) as *mut c_void }
+    }
+
+    // Reserves space for an argument, returning its offset.
+    fn reserve(&mut self, size: usize, align: usize) -> usize {
+        assert!(
+            align.is_power_of_two() && align <= MAX_ALIGN,
+            "CallFrame: unsupported alignment {}",
+            align
+        );
+
+        let offset = (self.used + align - 1) & !(align - 1);
+        self.used = offset + size;
+
+        let chunks = ((self.used + MAX_ALIGN - 1) & !(MAX_ALIGN - 1)) / MAX_ALIGN;
+        if chunks > self.arena.len() {
+            self.arena.resize(chunks, Chunk([0; MAX_ALIGN]));
+        }
+
+        self.offsets.push(offset);
+        offset
+    }
+}
+
+/// An owned argument value.
+///
+/// [`arg`](fn.arg.html) of an `&ArgValue` points to the copy it owns,
+/// so argument values computed at run time can be kept in a
+/// `Vec<ArgValue>` that outlives the call, without a
+/// [`CallFrame`](struct.CallFrame.html).
+#[derive(Clone, Debug)]
+pub struct ArgValue(Vec<Chunk>);
+
+impl ArgValue {
+    /// Copies a value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` requires alignment greater than 16.
+    pub fn new<T: Copy>(value: T) -> Self {
+        assert!(
+            mem::align_of::<T>() <= MAX_ALIGN,
+            "ArgValue: unsupported alignment {}",
+            mem::align_of::<T>()
+        );
+
+        let mut value_ = Self::zeroed(mem::size_of::<T>());
+        unsafe {
+            ptr::write(value_.0.as_mut_ptr() as *mut T, value);
+        }
+        value_
+    }
+
+    /// Copies the bytes of a value, such as a struct whose type is only
+    /// known at run time. They are aligned for any libffi type.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut value = Self::zeroed(bytes.len());
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), value.0.as_mut_ptr() as *mut u8, bytes.len());
+        }
+        value
+    }
+
+    pub(super) fn as_ptr(&self) -> *const c_void {
+        self.0.as_ptr() as *const c_void
+    }
+
+    fn zeroed(size: usize) -> Self {
+        let chunks = ((size + MAX_ALIGN - 1) & !(MAX_ALIGN - 1)) / MAX_ALIGN;
+        ArgValue(vec![Chunk([0; MAX_ALIGN]); chunks.max(1)])
+    }
+}
+
+/// A reusable array of [`Arg`](struct.Arg.html)s.
+///
+/// Building a fresh `Vec<Arg>` for every call costs an allocation per
+/// call. An `ArgArray` keeps its capacity when
+/// [`clear`](#method.clear)ed or [`recycle`](#method.recycle)d, so that
+/// an interpreter can make any number of calls with one allocation. It
+/// dereferences to `[Arg]`, so it can be passed to
+/// [`Cif::call`](struct.Cif.html#method.call) directly, which hands the
+/// array to libffi in place rather than copying it.
+///
+/// The lifetime `'a` bounds the values the arguments point to.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// extern "C" fn add(x: i32, y: i32) -> i32 { x + y }
+///
+/// let cif = Cif::new(vec![Type::i32(), Type::i32()], Type::i32());
+/// let mut args = ArgArray::with_capacity(2);
+///
+/// for i in 0..3 {
+///     let (x, y) = (i, i * 10);
+///     let mut current = args.recycle();
+///     current.push(&x).push(&y);
+///
+///     let result: i32 = unsafe { cif.call(CodePtr(add as *mut _), &current) };
+///     assert_eq!(i * 11, result);
+///
+///     args = current.recycle();
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ArgArray<'a> {
+    // Held as `'static` so that `recycle` can change the lifetime
+    // without reallocating; `push` only admits values that live for
+    // `'a`.
+    args: Vec<Arg<'static>>,
+    _marker: PhantomData<&'a ()>,
+}
+
+impl<'a> ArgArray<'a> {
+    /// Creates an empty array.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty array with room for `capacity` arguments.
+    pub fn with_capacity(capacity: usize) -> Self {
+        ArgArray {
+            args: Vec::with_capacity(capacity),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Appends a reference to an argument value.
+    pub fn push<T>(&mut self, value: &'a T) -> &mut Self {
+        // `push` only admits values that live for `'a`.
+        self.args.push(unsafe { Arg::from_ptr(value) });
+        self
+    }
+
+    /// Removes all arguments, keeping the capacity.
+    pub fn clear(&mut self) {
+        self.args.clear();
+    }
+
+    /// Removes all arguments, keeping the capacity, and returns the
+    /// array for use with arguments of a different lifetime.
+    pub fn recycle<'b>(mut self) -> ArgArray<'b> {
+        self.args.clear();
+        ArgArray {
+            args: self.args,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a> Deref for ArgArray<'a> {
+    type Target = [Arg<'a>];
+
+    fn deref(&self) -> &[Arg<'a>] {
+        &self.args
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::{Cif, CodePtr, Type};
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Pair {
+        a: u8,
+        b: f64,
+    }
+
+    extern "C" fn weigh(x: u8, pair: Pair, y: u16) -> f64 {
+        x as f64 + pair.a as f64 * pair.b + y as f64
+    }
+
+    #[test]
+    fn call_with_frame() {
+        let pair = Type::structure(vec![Type::u8(), Type::f64()]);
+        let cif = Cif::new(vec![Type::u8(), pair, Type::u16()], Type::f64());
+        let bytes = {
+            let pair = Pair { a: 2, b: 1.5 };
+            unsafe {
+                std::slice::from_raw_parts(
+                    &pair as *const Pair as *const u8,
+                    mem::size_of::<Pair>(),
+                )
+            }
+            .to_vec()
+        };
+
+        let mut frame = CallFrame::new();
+        frame
+            .push(1u8)
+            .push_bytes(&bytes, mem::align_of::<Pair>())
+            .push(4u16);
+
+        let result: f64 = unsafe { cif.call(CodePtr(weigh as *mut _), frame.args()) };
+        assert_eq!(8.0, result);
+
+        frame.clear();
+        assert!(frame.is_empty());
+    }
+
+    #[test]
+    fn reuse_arg_array() {
+        let cif = Cif::new(vec![Type::u8(), Type::u16()], Type::f64());
+        let mut args = ArgArray::with_capacity(2);
+        let buffer = args.as_ptr();
+
+        for x in 0..4u8 {
+            let y = u16::from(x) * 2;
+            let mut current = args.recycle();
+            current.push(&x).push(&y);
+
+            let result: f64 = unsafe { cif.call(CodePtr(weigh_scalars as *mut _), &current) };
+            assert_eq!(f64::from(x) * 3.0, result);
+            assert_eq!(buffer, current.as_ptr());
+
+            args = current.recycle();
+        }
+
+        assert!(args.is_empty());
+    }
+
+    extern "C" fn weigh_scalars(x: u8, y: u16) -> f64 {
+        f64::from(x) + f64::from(y)
+    }
+}
@@ -0,0 +1,399 @@
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use core::cell::Cell;
+use core::ffi::c_void;
+use core::mem;
+#[cfg(feature = "std")]
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{util, Callback, Cif, CodePtr};
+use crate::low;
+
+// The low bit of `Guard::state` records that the `GuardedClosure` has
+// been dropped; the remaining bits count the calls in flight.
+const DROPPED: usize = 1;
+const IN_FLIGHT: usize = 2;
+
+/// Represents a closure callable from C that may be dropped while it is
+/// being called on another thread.
+///
+/// Each invocation increments an in-flight counter before entering the
+/// callback and decrements it afterward. Dropping a `GuardedClosure`
+/// while calls are in flight defers freeing the libffi closure, the
+/// CIF, and the userdata until the last of those calls returns. Because
+/// it may outlive the `GuardedClosure` handle, the userdata is owned
+/// and must be `Send + Sync`.
+///
+/// The guard only covers calls that have reached the callback: a
+/// thread that has entered the trampoline but not yet the callback
+/// when the closure is freed still races with the free. C libraries
+/// that unregister callbacks asynchronously usually guarantee no new
+/// calls start after unregistration returns, which is the situation
+/// this type is meant for.
+///
+/// When the last call in flight returns after the closure was dropped,
+/// including when the callback drops it itself, that call is still
+/// returning through libffi’s code for the closure. It drops the
+/// userdata, but leaves the trampoline and CIF to the thread making the
+/// call, which frees them once the call has returned: the next time it
+/// creates, drops, or calls a `GuardedClosure`, or when it exits.
+/// Without the `std` feature, there is no way to tell when that is, so
+/// they are leaked instead. A signal handler that creates, drops, or
+/// calls a `GuardedClosure` may interrupt such a return, and so must not
+/// run on a thread that calls guarded closures.
+///
+/// Construct with [`GuardedClosure::new`](#method.new) or
+/// [`Builder::into_closure_guarded`](struct.Builder.html#method.into_closure_guarded).
+///
+/// # Examples
+///
+/// ```
+/// use std::os::raw::c_void;
+///
+/// use libffi::middle::*;
+/// use libffi::low;
+///
+/// unsafe extern "C" fn callback(
+///     _cif: &low::ffi_cif,
+///     result: &mut u64,
+///     args: *const *const c_void,
+///     userdata: &u64)
+/// {
+///     let args = args as *const &u64;
+///     *result = **args + *userdata;
+/// }
+///
+/// let cif = Cif::new(vec![Type::u64()], Type::u64());
+/// let closure = GuardedClosure::new(cif, callback, 5u64);
+///
+/// let fun: &extern "C" fn(u64) -> u64 = unsafe {
+///     closure.instantiate_code_ptr()
+/// };
+///
+/// assert_eq!(11, fun(6));
+/// ```
+#[derive(Debug)]
+pub struct GuardedClosure {
+    guard: *mut Guard,
+    code: CodePtr,
+}
+
+struct Guard {
+    state: AtomicUsize,
+    alloc: *mut low::ffi_closure,
+    code: CodePtr,
+    callback: low::RawCallback,
+    userdata: *mut c_void,
+    drop_userdata: unsafe fn(*mut c_void),
+    cif: Cif,
+    _shim: Option<util::Shim>,
+    // The next guard waiting in `DEFERRED`.
+    #[cfg(feature = "std")]
+    next: *mut Guard,
+}
+
+// The guards whose last call on this thread freed their userdata, and
+// which wait for their trampoline and CIF to be freed, as a linked list.
+// Any Rust code that runs on the thread after such a call has pushed its
+// guard runs after that call has returned through libffi, so it may
+// free them.
+#[cfg(feature = "std")]
+struct Deferred(Cell<*mut Guard>);
+
+#[cfg(feature = "std")]
+impl Drop for Deferred {
+    fn drop(&mut self) {
+        release_list(self.0.replace(ptr::null_mut()));
+    }
+}
+
+// `const` initializers of thread locals need Rust 1.59.
+#[cfg(feature = "std")]
+thread_local! {
+    #[allow(clippy::missing_const_for_thread_local)]
+    static DEFERRED: Deferred = Deferred(Cell::new(ptr::null_mut()));
+}
+
+// The guard is only reached through the closure’s trampoline and the
+// `GuardedClosure` handle, and the userdata is required to be
+// `Send + Sync`.
+unsafe impl Send for GuardedClosure {}
+unsafe impl Sync for GuardedClosure {}
+
+unsafe fn drop_box<U>(userdata: *mut c_void) {
+    drop(Box::from_raw(userdata as *mut U));
+}
+
+// Frees the closure and everything it owns.
+unsafe fn guard_free(guard: *mut Guard) {
+    ((*guard).drop_userdata)((*guard).userdata);
+    guard_release(guard);
+}
+
+// Frees the trampoline, the CIF, and the guard itself.
+unsafe fn guard_release(guard: *mut Guard) {
+    let guard = Box::from_raw(guard);
+    util::release_closure(guard.alloc, guard.code);
+}
+
+// Frees the closure from its own last call, which is still running
+// libffi code that may use the trampoline and CIF. Those are left to
+// `release_deferred` on this thread, or leaked if this thread's list is
+// gone or, without `std`, does not exist.
+unsafe fn guard_defer(guard: *mut Guard) {
+    ((*guard).drop_userdata)((*guard).userdata);
+
+    #[cfg(feature = "std")]
+    let _ = DEFERRED.try_with(|deferred| {
+        (*guard).next = deferred.0.get();
+        deferred.0.set(guard);
+    });
+}
+
+// Frees the guards left by `guard_defer` on this thread.
+fn release_deferred() {
+    #[cfg(feature = "std")]
+    let _ = DEFERRED.try_with(|deferred| release_list(deferred.0.replace(ptr::null_mut())));
+}
+
+#[cfg(feature = "std")]
+fn release_list(mut guard: *mut Guard) {
+    while !guard.is_null() {
+        unsafe {
+            let next = (*guard).next;
+            guard_release(guard);
+            guard = next;
+        }
+    }
+}
+
+unsafe extern "C" fn guarded_callback(
+    cif: *mut low::ffi_cif,
+    result: *mut c_void,
+    args: *mut *mut c_void,
+    guard: *mut c_void,
+) {
+    let guard = guard as *mut Guard;
+    release_deferred();
+
+    // Count the call before reading the userdata, so that a concurrent
+    // drop that sees the count (with `Acquire`) leaves the userdata to
+    // the last call instead of freeing it.
+    (*guard).state.fetch_add(IN_FLIGHT, Ordering::Acquire);
+    ((*guard).callback)(cif, result, args, (*guard).userdata);
+
+    // `Release` publishes this call's use of the userdata to whichever
+    // thread frees it, and `Acquire` sees the drop's.
+    if (*guard).state.fetch_sub(IN_FLIGHT, Ordering::AcqRel) == IN_FLIGHT | DROPPED {
+        guard_defer(guard);
+    }
+}
+
+impl Drop for GuardedClosure {
+    fn drop(&mut self) {
+        unsafe {
+            if (*self.guard).state.fetch_or(DROPPED, Ordering::AcqRel) == 0 {
+                guard_free(self.guard);
+            }
+        }
+        release_deferred();
+    }
+}
+
+impl GuardedClosure {
+    /// Creates a new guarded closure with owned userdata.
+    ///
+    /// # Arguments
+    ///
+    /// - `cif` — describes the calling convention and argument and
+    ///   result types
+    /// - `callback` — the function to call when the closure is invoked
+    /// - `userdata` — the value to pass (by reference) to `callback`
+    ///   along with the arguments when the closure is called
+    ///
+    /// # Result
+    ///
+    /// The new closure.
+    pub fn new<U, R>(cif: Cif, callback: Callback<U, R>, userdata: U) -> Self
+    where
+        U: Send + Sync + 'static,
+    {
+        release_deferred();
+
+        let (alloc, code) = low::closure_alloc();
+        assert!(!alloc.is_null(), "closure_alloc: returned null");
+
+        let guard = Box::into_raw(Box::new(Guard {
+            state: AtomicUsize::new(0),
+            alloc,
+            code,
+            callback: unsafe { mem::transmute::<Callback<U, R>, low::RawCallback>(callback) },
+            userdata: Box::into_raw(Box::new(userdata)) as *mut c_void,
+            drop_userdata: drop_box::<U>,
+            cif,
+            _shim: None,
+            #[cfg(feature = "std")]
+            next: ptr::null_mut(),
+        }));
+
+        unsafe {
+            let shim = util::prep_closure(
+                alloc,
+                (*guard).cif.as_raw_ptr(),
+                guarded_callback,
+                guard as *mut c_void,
+                code,
+            )
+            .unwrap();
+            (*guard)._shim = Some(shim);
+
+            util::closure_created::<U>(code, &(*guard).cif);
+        }
+
+        GuardedClosure { guard, code }
+    }
+
+    /// Returns the number of calls that are currently executing the
+    /// closure’s callback.
+    pub fn calls_in_flight(&self) -> usize {
+        unsafe { (*self.guard).state.load(Ordering::Acquire) / IN_FLIGHT }
+    }
+
+    /// Obtains the callable code pointer for a closure.
+    ///
+    /// # Safety
+    ///
+    /// The result needs to be transmuted to the correct type before
+    /// it can be called. If the type is wrong then undefined behavior
+    /// will result.
+    pub fn code_ptr(&self) -> &unsafe extern "C" fn() {
+        self.code.as_fun()
+    }
+
+    /// Obtains the callable code pointer for a closure, by value.
+    ///
+    /// The code pointer is valid only as long as the closure is alive;
+    /// the high layer’s `FnPtrN` types enforce this with a lifetime.
+    pub fn as_code_ptr(&self) -> CodePtr {
+        self.code
+    }
+
+    /// The CIF describing the closure’s signature.
+    pub fn cif(&self) -> &Cif {
+        unsafe { &(*self.guard).cif }
+    }
+
+    /// Transmutes the callable code pointer for a closure to a reference
+    /// to any type. This is intended to be used to transmute it to its
+    /// correct function type in order to call it.
+    ///
+    /// # Safety
+    ///
+    /// This method allows transmuting to a reference to *any* sized type,
+    /// and cannot check whether the code pointer actually has that type.
+    /// If the type is wrong then undefined behavior will result.
+    pub unsafe fn instantiate_code_ptr<T>(&self) -> &T {
+        self.code.as_any_ref_()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::Type;
+    use std::ptr;
+    use std::sync::atomic::{AtomicBool, AtomicPtr};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    struct Env {
+        entered: Arc<Barrier>,
+        dropped: Arc<Barrier>,
+        freed: Arc<AtomicBool>,
+    }
+
+    impl Drop for Env {
+        fn drop(&mut self) {
+            self.freed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    unsafe extern "C" fn callback(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        _args: *const *const c_void,
+        env: &Env,
+    ) {
+        env.entered.wait();
+        env.dropped.wait();
+        *result = 7;
+    }
+
+    #[test]
+    fn drop_while_in_flight() {
+        let entered = Arc::new(Barrier::new(2));
+        let dropped = Arc::new(Barrier::new(2));
+        let freed = Arc::new(AtomicBool::new(false));
+
+        let env = Env {
+            entered: entered.clone(),
+            dropped: dropped.clone(),
+            freed: freed.clone(),
+        };
+        let closure = GuardedClosure::new(Cif::new(vec![], Type::u64()), callback, env);
+        let fun: extern "C" fn() -> u64 = unsafe { *closure.instantiate_code_ptr() };
+
+        let handle = thread::spawn(move || fun());
+
+        entered.wait();
+        assert_eq!(1, closure.calls_in_flight());
+        drop(closure);
+        assert!(!freed.load(Ordering::SeqCst));
+
+        dropped.wait();
+        assert_eq!(7, handle.join().unwrap());
+        assert!(freed.load(Ordering::SeqCst));
+    }
+
+    unsafe extern "C" fn drop_self(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        _args: *const *const c_void,
+        slot: &Arc<AtomicPtr<GuardedClosure>>,
+    ) {
+        drop(Box::from_raw(slot.swap(ptr::null_mut(), Ordering::SeqCst)));
+        *result = 3;
+    }
+
+    #[test]
+    fn drop_from_callback() {
+        let slot = Arc::new(AtomicPtr::new(ptr::null_mut()));
+        let closure = GuardedClosure::new(Cif::new(vec![], Type::u64()), drop_self, slot.clone());
+        let fun: extern "C" fn() -> u64 = unsafe { *closure.instantiate_code_ptr() };
+        slot.store(Box::into_raw(Box::new(closure)), Ordering::SeqCst);
+
+        assert_eq!(3, fun());
+        assert_eq!(1, Arc::strong_count(&slot));
+
+        // Frees the trampoline left behind by the last call.
+        drop(GuardedClosure::new(
+            Cif::new(vec![], Type::u64()),
+            drop_self,
+            slot,
+        ));
+    }
+
+    #[test]
+    fn drop_from_callback_on_exiting_thread() {
+        let slot = Arc::new(AtomicPtr::new(ptr::null_mut()));
+        let closure = GuardedClosure::new(Cif::new(vec![], Type::u64()), drop_self, slot.clone());
+        let fun: extern "C" fn() -> u64 = unsafe { *closure.instantiate_code_ptr() };
+        slot.store(Box::into_raw(Box::new(closure)), Ordering::SeqCst);
+
+        // The thread frees the trampoline left behind by its last call
+        // when it exits.
+        assert_eq!(3, thread::spawn(move || fun()).join().unwrap());
+        assert_eq!(1, Arc::strong_count(&slot));
+    }
+}
@@ -0,0 +1,284 @@
+//! Functions loaded from dynamic libraries.
+//!
+//! This module is enabled by `#[cfg(feature = "libloading")]`.
+//!
+//! A [`Function`](struct.Function.html) pairs a symbol resolved from a
+//! [`Library`](struct.Library.html) with the [`Cif`](../struct.Cif.html)
+//! describing it, and borrows the library so that it cannot be unloaded
+//! while the function may still be called. The `Cif` can be built
+//! directly, or parsed from a signature string by
+//! [`parse_signature`](fn.parse_signature.html).
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(target_os = "linux")] {
+//! use libffi::middle::load::{Function, Library};
+//! use libffi::middle::{arg, Cif, Type};
+//!
+//! let libm = unsafe { Library::new("libm.so.6") }.unwrap();
+//! let cos = unsafe {
+//!     Function::new(&libm, "cos", Cif::new(vec![Type::f64()], Type::f64()))
+//! }
+//! .unwrap();
+//!
+//! let result: f64 = unsafe { cos.call(&[arg(&0f64)]) };
+//! assert_eq!(1.0, result);
+//! # }
+//! ```
+
+use core::ffi::c_void;
+use core::marker::PhantomData;
+
+pub use libloading::{Error, Library};
+
+use super::{Arg, Cif, CodePtr, RetSafe, Type};
+
+/// A function resolved from a [`Library`](struct.Library.html), with its
+/// signature.
+#[derive(Clone, Debug)]
+pub struct Function<'lib> {
+    cif: Cif,
+    fun: CodePtr,
+    _library: PhantomData<&'lib Library>,
+}
+
+impl<'lib> Function<'lib> {
+    /// Resolves the symbol `name` in `library`, as a function described
+    /// by `cif`.
+    ///
+    /// # Safety
+    ///
+    /// As for
+    /// [`Library::get`](https://docs.rs/libloading/0.8/libloading/struct.Library.html#method.get),
+    /// and the symbol must be a function whose calling convention and
+    /// types match `cif`.
+    pub unsafe fn new(library: &'lib Library, name: &str, cif: Cif) -> Result<Self, Error> {
+        let symbol = library.get::<*mut c_void>(name.as_bytes())?;
+        Ok(Function {
+            cif,
+            fun: CodePtr(*symbol),
+            _library: PhantomData,
+        })
+    }
+
+    /// Calls the function with the given arguments.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`](../struct.Cif.html#method.call), except that
+    /// the function is known to match the CIF.
+    pub unsafe fn call<R: RetSafe>(&self, args: &[Arg]) -> R {
+        self.cif.call(self.fun, args)
+    }
+
+    /// The function’s signature.
+    pub fn cif(&self) -> &Cif {
+        &self.cif
+    }
+
+    /// The function’s address, which is only valid while the library is
+    /// loaded.
+    pub fn code_ptr(&self) -> CodePtr {
+        self.fun
+    }
+}
+
+/// Parses a function signature into a [`Cif`](../struct.Cif.html) with
+/// the default calling convention.
+///
+/// The signature is written `(arg, ...) -> result`, in the form that
+/// `Cif` describes itself in diagnostics. Each type is one of `u8`,
+/// `i8`, `u16`, `i16`, `u32`, `i32`, `u64`, `i64`, `usize`, `isize`,
+/// `f32`, `f64`, `pointer`, `longdouble`, the C names `char`, `schar`,
+/// `uchar`, `short`, `ushort`, `int`, `uint`, `long`, `ulong`,
+/// `longlong`, and `ulonglong`, or `struct { type, ... }`; the result may
+/// also be `void`. Whitespace between tokens is ignored.
+///
+/// Returns `None` if the signature is malformed.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(target_os = "linux")] {
+/// use libffi::middle::load::{parse_signature, Function, Library};
+/// use libffi::middle::arg;
+///
+/// let libm = unsafe { Library::new("libm.so.6") }.unwrap();
+/// let cif = parse_signature("(f64, int) -> f64").unwrap();
+/// let ldexp = unsafe { Function::new(&libm, "ldexp", cif) }.unwrap();
+///
+/// let result: f64 = unsafe { ldexp.call(&[arg(&1.5f64), arg(&3i32)]) };
+/// assert_eq!(12.0, result);
+/// # }
+/// ```
+pub fn parse_signature(signature: &str) -> Option<Cif> {
+    let mut tokens = Tokens::new(signature);
+
+    tokens.expect("(")?;
+    let mut args = vec![];
+    if !tokens.eat(")") {
+        loop {
+            args.push(tokens.parse_type()?);
+            if tokens.eat(")") {
+                break;
+            }
+            tokens.expect(",")?;
+        }
+    }
+    tokens.expect("->")?;
+    let result = if tokens.eat("void") {
+        Type::void()
+    } else {
+        tokens.parse_type()?
+    };
+
+    if tokens.rest.is_empty() {
+        Some(Cif::new(args, result))
+    } else {
+        None
+    }
+}
+
+// The unparsed remainder of a signature.
+struct Tokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(signature: &'a str) -> Self {
+        Tokens {
+            rest: signature.trim_start(),
+        }
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.rest = self.rest[len..].trim_start();
+    }
+
+    // Consumes `token` if it comes next.
+    fn eat(&mut self, token: &str) -> bool {
+        // A word must not run on into a longer identifier.
+        let is_word = token.chars().all(|c| c.is_ascii_alphanumeric());
+        let ends = match self.rest.get(token.len()..).and_then(|s| s.chars().next()) {
+            Some(c) => !is_word || !(c.is_ascii_alphanumeric() || c == '_'),
+            None => true,
+        };
+        let matches = self.rest.starts_with(token) && ends;
+        if matches {
+            self.advance(token.len());
+        }
+        matches
+    }
+
+    fn expect(&mut self, token: &str) -> Option<()> {
+        if self.eat(token) {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn parse_type(&mut self) -> Option<Type> {
+        if self.eat("struct") {
+            self.expect("{")?;
+            let mut fields = vec![self.parse_type()?];
+            while !self.eat("}") {
+                self.expect(",")?;
+                fields.push(self.parse_type()?);
+            }
+            return Some(Type::structure(fields));
+        }
+
+        let len = self
+            .rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(self.rest.len());
+        let type_ = match &self.rest[..len] {
+            "u8" => Type::u8(),
+            "i8" => Type::i8(),
+            "u16" => Type::u16(),
+            "i16" => Type::i16(),
+            "u32" => Type::u32(),
+            "i32" => Type::i32(),
+            "u64" => Type::u64(),
+            "i64" => Type::i64(),
+            "usize" => Type::usize(),
+            "isize" => Type::isize(),
+            "f32" => Type::f32(),
+            "f64" => Type::f64(),
+            "pointer" => Type::pointer(),
+            "longdouble" => Type::longdouble(),
+            "char" => Type::c_char(),
+            "schar" => Type::c_schar(),
+            "uchar" => Type::c_uchar(),
+            "short" => Type::c_short(),
+            "ushort" => Type::c_ushort(),
+            "int" => Type::c_int(),
+            "uint" => Type::c_uint(),
+            "long" => Type::c_long(),
+            "ulong" => Type::c_ulong(),
+            "longlong" => Type::c_longlong(),
+            "ulonglong" => Type::c_ulonglong(),
+            _ => return None,
+        };
+        self.advance(len);
+        Some(type_)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use crate::middle::{arg, Type};
+
+    fn libm() -> Library {
+        unsafe { Library::new("libm.so.6") }.unwrap()
+    }
+
+    #[test]
+    fn call_loaded() {
+        let libm = libm();
+        let pow = unsafe {
+            Function::new(
+                &libm,
+                "pow",
+                Cif::new(vec![Type::f64(), Type::f64()], Type::f64()),
+            )
+        }
+        .unwrap();
+
+        let result: f64 = unsafe { pow.call(&[arg(&2f64), arg(&10f64)]) };
+        assert_eq!(1024.0, result);
+    }
+
+    #[test]
+    fn missing_symbol() {
+        let libm = libm();
+        let missing =
+            unsafe { Function::new(&libm, "no_such_function", Cif::new(vec![], Type::void())) };
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn signatures() {
+        let point = Type::structure(vec![Type::u16(), Type::f64()]);
+        let cif = Cif::new(vec![point, Type::pointer()], Type::void());
+        let parsed = parse_signature(&cif.signature()).unwrap();
+        assert_eq!(cif.signature(), parsed.signature());
+
+        let parsed = parse_signature(" ( ) ->int").unwrap();
+        assert_eq!("() -> i32", parsed.signature());
+
+        for bad in &[
+            "",
+            "()",
+            "(void) -> void",
+            "(i32,) -> void",
+            "(int8) -> void",
+        ] {
+            assert!(parse_signature(bad).is_none(), "{:?}", bad);
+        }
+        assert!(parse_signature("() -> void extra").is_none());
+    }
+}
@@ -10,37 +10,267 @@
 //! argument types aren’t checked. See the [`high`](../high/index.html)
 //! layer for closures with type-checked arguments.
 
-use std::any::Any;
-use std::marker::PhantomData;
-use std::os::raw::c_void;
+#[cfg(feature = "closures")]
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+#[cfg(feature = "closures")]
+use core::any::Any;
+use core::ffi::c_void;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+use core::num::{
+    NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU16, NonZeroU32,
+    NonZeroU64, NonZeroU8, NonZeroUsize,
+};
+use core::ptr::NonNull;
 
 use crate::low;
-pub use crate::low::{ffi_abi as FfiAbi, ffi_abi_FFI_DEFAULT_ABI, Callback, CallbackMut, CodePtr};
+pub use crate::low::{ffi_abi as FfiAbi, ffi_abi_FFI_DEFAULT_ABI, CodePtr, FnSig};
+#[cfg(feature = "closures")]
+pub use crate::low::{Callback, CallbackMut};
 
 mod util;
 
+mod abi;
+pub use abi::Abi;
+
+mod args;
+pub use args::ArgsReader;
+
+mod result;
+#[cfg(feature = "closures")]
+pub(crate) use result::write_result;
+pub use result::{ResultValue, ResultWriter, RetSafe};
+
 mod types;
 pub use types::Type;
 
+mod value;
+pub use value::Value;
+pub(crate) use value::{is_representable, read_result, write_ffi_type};
+
 mod builder;
 pub use builder::Builder;
 
+mod dispatch;
+pub use dispatch::Dispatcher;
+
+#[cfg(feature = "std")]
+mod errno;
+#[cfg(feature = "std")]
+pub use errno::Errno;
+
+mod frame;
+pub use frame::{ArgArray, ArgValue, CallFrame};
+
+#[cfg(all(feature = "seh-guard", target_env = "msvc"))]
+mod seh;
+#[cfg(all(feature = "seh-guard", target_env = "msvc"))]
+pub use seh::CallFault;
+
+mod prepared;
+pub use prepared::PreparedCall;
+
+#[cfg(feature = "static-cif")]
+mod static_cif;
+#[cfg(feature = "static-cif")]
+pub use static_cif::{StaticCif, StaticType};
+
+#[cfg(feature = "closures")]
+mod bind;
+#[cfg(feature = "closures")]
+pub use bind::bind;
+
+#[cfg(feature = "closures")]
+mod wrap;
+#[cfg(feature = "closures")]
+pub use wrap::wrap_function;
+
+#[cfg(feature = "closures")]
+mod guarded;
+#[cfg(feature = "closures")]
+pub use guarded::GuardedClosure;
+
+#[cfg(feature = "closure-registry")]
+pub mod registry;
+
+#[cfg(feature = "libloading")]
+pub mod load;
+
+#[cfg(all(feature = "perf-map", unix))]
+mod perf_map;
+
 /// Contains an untyped pointer to a function argument.
 ///
 /// When calling a function via a [CIF](struct.Cif.html), each argument
 /// must be passed as a C `void*`. Wrapping the argument in the `Arg`
 /// struct accomplishes the necessary coercion.
-#[derive(Clone, Debug)]
+///
+/// The lifetime `'a` is that of the borrowed argument value, so that the
+/// compiler rejects calls with arguments that have been dropped:
+///
+/// ```compile_fail
+/// use libffi::middle::*;
+///
+/// extern "C" fn double(x: u32) -> u32 { 2 * x }
+///
+/// let cif = Cif::new(vec![Type::u32()], Type::u32());
+/// let args = {
+///     let x = 5u32;
+///     [arg(&x)]
+/// };
+///
+/// let _: u32 = unsafe { cif.call(CodePtr(double as *mut _), &args) };
+/// ```
+#[derive(Clone, Copy, Debug)]
 #[repr(C)]
-pub struct Arg(*mut c_void);
+pub struct Arg<'a>(*mut c_void, PhantomData<&'a c_void>);
 
-impl Arg {
+impl<'a> Arg<'a> {
     /// Coerces an argument reference into the `Arg` type.
     ///
     /// This is used to wrap each argument pointer before passing them
     /// to [`Cif::call`](struct.Cif.html#method.call).
-    pub fn new<T>(r: &T) -> Self {
-        Arg(r as *const T as *mut c_void)
+    ///
+    /// Unlike [`arg`](fn.arg.html), this accepts a reference to any type,
+    /// including a reference to a reference, as passed for a C pointer
+    /// argument described by a Rust reference.
+    pub fn new<T>(r: &'a T) -> Self {
+        Arg(r as *const T as *mut c_void, PhantomData)
+    }
+
+    /// Wraps a raw pointer to an argument value.
+    ///
+    /// # Safety
+    ///
+    /// Nothing ties `'a` to the value `ptr` points to, so the caller
+    /// must keep the value valid for as long as the `Arg` is used in
+    /// calls, since the borrow checker cannot.
+    pub unsafe fn from_ptr<T>(ptr: *const T) -> Self {
+        Arg(ptr as *mut c_void, PhantomData)
+    }
+}
+
+/// Things that borrow an argument value, and so can be made into an
+/// [`Arg`](struct.Arg.html) by [`arg`](fn.arg.html).
+///
+/// This is implemented for shared and mutable references to an
+/// [`ArgTarget`](trait.ArgTarget.html), for raw pointers, for borrowed
+/// [`ArgValue`](struct.ArgValue.html)s, and for `Arg` itself.
+///
+/// A raw pointer makes an `Arg` pointing to the value it points to, like
+/// [`Arg::from_ptr`](struct.Arg.html#method.from_ptr); the value must
+/// be valid for any call the `Arg` is passed to, as
+/// [`Cif::call`](struct.Cif.html#method.call) requires. A pointer
+/// argument is instead passed as `Arg::new(&ptr)`. `arg(&ptr)` does the
+/// same, but Clippy’s `needless_borrows_for_generic_args` lint suggests
+/// dropping its `&`, which would pass the pointee instead.
+pub trait AsArg<'a> {
+    /// Makes an `Arg` pointing to the value.
+    fn into_arg(self) -> Arg<'a>;
+}
+
+/// Types of argument values that [`arg`](fn.arg.html) takes a reference
+/// to.
+///
+/// References do not implement this, so that `arg(&&x)`, which passes
+/// a pointer to `x` rather than `x`, is rejected:
+///
+/// ```compile_fail
+/// use libffi::middle::arg;
+///
+/// let x = 5u32;
+/// let _ = arg(&&x);
+/// ```
+///
+/// It is implemented for the primitive types, raw and function pointers,
+/// and the `high` layer’s C types. Implement it for a `#[repr(C)]` struct
+/// to pass the struct with `arg`, or pass it with
+/// [`Arg::new`](struct.Arg.html#method.new). To pass a reference as a C
+/// pointer, use `Arg::new`.
+pub trait ArgTarget {}
+
+macro_rules! impl_arg_target {
+    ($($type_:ty),*) => {
+        $(impl ArgTarget for $type_ {})*
+    };
+}
+
+impl_arg_target!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128, usize, isize);
+impl_arg_target!(f32, f64, bool, char, ());
+impl_arg_target!(NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroUsize);
+impl_arg_target!(NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroIsize);
+impl_arg_target!(Option<NonZeroU8>, Option<NonZeroU16>, Option<NonZeroU32>);
+impl_arg_target!(Option<NonZeroU64>, Option<NonZeroUsize>);
+impl_arg_target!(Option<NonZeroI8>, Option<NonZeroI16>, Option<NonZeroI32>);
+impl_arg_target!(Option<NonZeroI64>, Option<NonZeroIsize>);
+
+impl<T> ArgTarget for *const T {}
+impl<T> ArgTarget for *mut T {}
+impl<T> ArgTarget for NonNull<T> {}
+impl<T> ArgTarget for Option<NonNull<T>> {}
+
+#[cfg(feature = "num-complex")]
+impl<T> ArgTarget for num_complex_crate::Complex<T> {}
+
+macro_rules! impl_arg_target_fn {
+    ($( $T:ident )*) => {
+        impl<$( $T, )* R> ArgTarget for extern "C" fn($( $T, )*) -> R {}
+        impl<$( $T, )* R> ArgTarget for unsafe extern "C" fn($( $T, )*) -> R {}
+        impl<$( $T, )* R> ArgTarget for Option<extern "C" fn($( $T, )*) -> R> {}
+        impl<$( $T, )* R> ArgTarget for Option<unsafe extern "C" fn($( $T, )*) -> R> {}
+    };
+}
+
+impl_arg_target_fn!();
+impl_arg_target_fn!(A);
+impl_arg_target_fn!(A B);
+impl_arg_target_fn!(A B C);
+impl_arg_target_fn!(A B C D);
+impl_arg_target_fn!(A B C D E);
+impl_arg_target_fn!(A B C D E F);
+impl_arg_target_fn!(A B C D E F G);
+impl_arg_target_fn!(A B C D E F G H);
+impl_arg_target_fn!(A B C D E F G H I);
+impl_arg_target_fn!(A B C D E F G H I J);
+impl_arg_target_fn!(A B C D E F G H I J K);
+impl_arg_target_fn!(A B C D E F G H I J K L);
+
+impl<'a, T: ArgTarget> AsArg<'a> for &'a T {
+    fn into_arg(self) -> Arg<'a> {
+        Arg::new(self)
+    }
+}
+
+impl<'a, T: ArgTarget> AsArg<'a> for &'a mut T {
+    fn into_arg(self) -> Arg<'a> {
+        Arg::new(self)
+    }
+}
+
+impl<'a, T> AsArg<'a> for *const T {
+    fn into_arg(self) -> Arg<'a> {
+        Arg(self as *mut c_void, PhantomData)
+    }
+}
+
+impl<'a, T> AsArg<'a> for *mut T {
+    fn into_arg(self) -> Arg<'a> {
+        Arg(self as *mut c_void, PhantomData)
+    }
+}
+
+impl<'a> AsArg<'a> for &'a ArgValue {
+    fn into_arg(self) -> Arg<'a> {
+        Arg(self.as_ptr() as *mut c_void, PhantomData)
+    }
+}
+
+impl<'a> AsArg<'a> for Arg<'a> {
+    fn into_arg(self) -> Arg<'a> {
+        self
     }
 }
 
@@ -49,9 +279,35 @@ impl Arg {
 ///
 /// This is used to wrap each argument pointer before passing them
 /// to [`Cif::call`](struct.Cif.html#method.call).
-/// (This is the same as [`Arg::new`](struct.Arg.html#method.new)).
-pub fn arg<T>(r: &T) -> Arg {
-    Arg::new(r)
+/// For a shared reference, this is the same as
+/// [`Arg::new`](struct.Arg.html#method.new).
+///
+/// Since this is generic over [`AsArg`](trait.AsArg.html) rather than
+/// over the argument type, the type of the value can no longer be given
+/// as `arg::<T>(&x)`; write `arg(&x)` or `Arg::new::<T>(&x)` instead.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// extern "C" fn add(x: u32, y: u32) -> u32 { x + y }
+///
+/// let cif = Cif::new(vec![Type::u32(), Type::u32()], Type::u32());
+/// let (x, mut y) = (4u32, 5u32);
+///
+/// let sum: u32 = unsafe {
+///     cif.call(CodePtr(add as *mut _), &[arg(&x), arg(&mut y)])
+/// };
+/// assert_eq!(9, sum);
+///
+/// let values = vec![ArgValue::new(6u32), ArgValue::new(7u32)];
+/// let args: Vec<Arg> = values.iter().map(arg).collect();
+/// let sum: u32 = unsafe { cif.call(CodePtr(add as *mut _), &args) };
+/// assert_eq!(13, sum);
+/// ```
+pub fn arg<'a, A: AsArg<'a>>(value: A) -> Arg<'a> {
+    value.into_arg()
 }
 
 /// Describes the calling convention and types for calling a function.
@@ -65,6 +321,15 @@ pub fn arg<T>(r: &T) -> Arg {
 /// Construct with [`Cif::new`](#method.new) or
 /// [`Cif::from_type_array`](#method.from_type_array).
 ///
+/// A prepared `Cif` is immutable except via `&mut self`, so it is both
+/// `Send` and `Sync` and may, for example, be kept in a static shared by
+/// worker threads.
+///
+/// Clones of a `Cif` share one prepared `ffi_cif` and its types through
+/// a reference count, so handing the same signature to many call sites
+/// or closures is cheap. [`set_abi`](#method.set_abi) on a shared `Cif`
+/// first makes it a copy of its own.
+///
 /// # Examples
 ///
 /// ```
@@ -77,30 +342,50 @@ pub fn arg<T>(r: &T) -> Arg {
 /// let args = vec![Type::f64(), Type::pointer()];
 /// let cif = Cif::new(args.into_iter(), Type::f64());
 ///
-/// let n = unsafe { cif.call(CodePtr(add as *mut _), &[arg(&5f64), arg(&&6f64)]) };
+/// let n = unsafe { cif.call(CodePtr(add as *mut _), &[arg(&5f64), Arg::new(&&6f64)]) };
 /// assert_eq!(11f64, n);
 /// ```
+#[derive(Clone)]
+pub struct Cif(Arc<CifData>);
+
 #[derive(Debug)]
-pub struct Cif {
+struct CifData {
     cif: low::ffi_cif,
-    args: types::TypeArray,
+    args: types::ArgTypes,
     result: Type,
+    // The size of the result type as prepared by libffi, for checking
+    // the result type of calls in debug builds.
+    result_size: usize,
 }
 
-// To clone a Cif we need to clone the types and then make sure the new
-// ffi_cif refers to the clones of the types.
-impl Clone for Cif {
+// A `Cif` owns everything its `ffi_cif` points to, and once prepared
+// the `ffi_cif` is only read by libffi, both when calling and from
+// closure trampolines. The only way to mutate it is through `&mut self`
+// (see `set_abi`), which first copies it if it is shared, so sharing
+// and sending it between threads is safe.
+unsafe impl Send for CifData {}
+unsafe impl Sync for CifData {}
+
+// A copy's ffi_cif still refers to the original's types, which may be
+// stored inline, until it is in place and `Cif::make_mut` repoints it.
+impl Clone for CifData {
     fn clone(&self) -> Self {
-        let mut copy = Cif {
+        CifData {
             cif: self.cif,
             args: self.args.clone(),
             result: self.result.clone(),
-        };
-
-        copy.cif.arg_types = copy.args.as_raw_ptr();
-        copy.cif.rtype = copy.result.as_raw_ptr();
+            result_size: self.result_size,
+        }
+    }
+}
 
-        copy
+impl fmt::Debug for Cif {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cif")
+            .field("cif", &self.0.cif)
+            .field("args", &self.0.args)
+            .field("result", &self.0.result)
+            .finish()
     }
 }
 
@@ -110,61 +395,480 @@ impl Cif {
     /// Takes ownership of the argument and result
     /// [`Type`](types/struct.Type.html)s, because the resulting
     /// `Cif` retains references to them.
-    /// Defaults to the platform’s default calling convention; this
-    /// can be adjusted using [`set_abi`](#method.set_abi).
+    /// Uses the platform’s default calling convention; for another, use
+    /// [`new_with_abi`](#method.new_with_abi).
     pub fn new<I>(args: I, result: Type) -> Self
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        Self::prepare(args, result, low::ffi_abi_FFI_DEFAULT_ABI).expect("low::prep_cif")
+    }
+
+    /// Creates a new CIF for the given argument and result types and
+    /// calling convention.
+    ///
+    /// Unlike setting the calling convention afterward with
+    /// [`set_abi`](#method.set_abi), this prepares the CIF for the
+    /// convention, which some targets require.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects the types or calling convention.
+    pub fn new_with_abi<I>(args: I, result: Type, abi: FfiAbi) -> Self
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        Self::prepare(args, result, abi).expect("low::prep_cif")
+    }
+
+    /// Creates a new CIF for a C++ member function using the `thiscall`
+    /// calling convention of MSVC.
+    ///
+    /// `args` are the explicit arguments; the implicit `this` pointer is
+    /// prepended as the first argument, so calls and closures using the
+    /// CIF pass or receive it first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects the types.
+    #[cfg(target_arch = "x86")]
+    pub fn thiscall<I>(args: I, result: Type) -> Self
+    where
+        I: IntoIterator<Item = Type>,
+    {
+        let args: Vec<Type> = core::iter::once(Type::pointer()).chain(args).collect();
+        Self::new_with_abi(args, result, Abi::ThisCall.as_raw())
+    }
+
+    // Prepares a CIF with the given calling convention, reporting
+    // failure from libffi.
+    pub(crate) fn prepare<I>(args: I, result: Type, abi: FfiAbi) -> low::Result<Self>
     where
         I: IntoIterator<Item = Type>,
         I::IntoIter: ExactSizeIterator<Item = Type>,
     {
         let args = args.into_iter();
         let nargs = args.len();
-        let args = types::TypeArray::new(args);
-        let mut cif: low::ffi_cif = Default::default();
 
-        unsafe {
-            low::prep_cif(
-                &mut cif,
-                low::ffi_abi_FFI_DEFAULT_ABI,
-                nargs,
-                result.as_raw_ptr(),
-                args.as_raw_ptr(),
-            )
+        // Note that cif retains references to args and result, which is
+        // why we hold onto them here. Small argument arrays are stored
+        // inline, so the CIF is prepared only once they are in place.
+        let mut data = Arc::new(CifData {
+            cif: Default::default(),
+            args: types::ArgTypes::new(args),
+            result,
+            result_size: 0,
+        });
+
+        {
+            let data = Arc::get_mut(&mut data).expect("Cif::prepare");
+            unsafe {
+                low::prep_cif(
+                    &mut data.cif,
+                    abi,
+                    nargs,
+                    data.result.as_raw_ptr(),
+                    data.args.as_raw_ptr(),
+                )
+            }?;
+            data.result_size = unsafe { (*data.cif.rtype).size };
         }
-        .expect("low::prep_cif");
 
-        // Note that cif retains references to args and result,
-        // which is why we hold onto them here.
-        Cif { cif, args, result }
+        Ok(Cif(data))
+    }
+
+    // Gets the CIF for writing, first copying it if it is shared.
+    fn make_mut(&mut self) -> &mut CifData {
+        let data = Arc::make_mut(&mut self.0);
+        data.cif.arg_types = data.args.as_raw_ptr();
+        data.cif.rtype = data.result.as_raw_ptr();
+        data
     }
 
     /// Calls a function with the given arguments.
     ///
     /// In particular, this method invokes function `fun` passing it
-    /// arguments `args`, and returns the result.
+    /// arguments `args`, and returns the result. The result type must
+    /// be [`RetSafe`](trait.RetSafe.html), which excludes types with
+    /// destructors, references, and other types that a foreign function
+    /// cannot soundly produce.
     ///
     /// # Safety
     ///
     /// There is no checking that the calling convention and types
     /// in the `Cif` match the actual calling convention and types of
     /// `fun`, nor that they match the types of `args`.
-    pub unsafe fn call<R>(&self, fun: CodePtr, args: &[Arg]) -> R {
+    ///
+    /// In debug builds, the size of `R` is checked against the CIF’s
+    /// result type.
+    ///
+    /// With the `tracing` feature enabled, each call is made inside a
+    /// trace-level `ffi_call` span recording the signature and the
+    /// address of `fun`.
+    pub unsafe fn call<R: RetSafe>(&self, fun: CodePtr, args: &[Arg]) -> R {
+        self.call_unbounded(fun, args)
+    }
+
+    // Implements `call` for any result type. The high layer uses this
+    // for types that are `CType`s, which are equally safe to return.
+    pub(crate) unsafe fn call_unbounded<R>(&self, fun: CodePtr, args: &[Arg]) -> R {
         assert_eq!(
-            self.cif.nargs as usize,
+            self.0.cif.nargs as usize,
             args.len(),
             "Cif::call: passed wrong number of arguments"
         );
+        self.check_result_type::<R>("Cif::call");
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(
+            "ffi_call",
+            signature = %self.signature(),
+            fun = ?fun.0,
+        )
+        .entered();
 
         low::call::<R>(
-            &self.cif as *const _ as *mut _,
+            &self.0.cif as *const _ as *mut _,
             fun,
             args.as_ptr() as *mut *mut c_void,
         )
     }
 
+    /// Calls a function with the given arguments, reporting detectable
+    /// misuse as an error instead of panicking.
+    ///
+    /// Before calling, this checks that the number of arguments matches
+    /// the CIF and that `fun` is not null. In debug builds, it also
+    /// checks that each argument pointer is aligned for the CIF’s
+    /// argument type.
+    ///
+    /// # Safety
+    ///
+    /// As for [`call`](#method.call): only the conditions above are
+    /// checked, not that the types actually match.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::ptr;
+    /// use libffi::middle::*;
+    ///
+    /// let cif = Cif::new(vec![Type::u32()], Type::u32());
+    /// let result = unsafe {
+    ///     cif.call_checked::<u32>(CodePtr(ptr::null_mut()), &[arg(&1u32)])
+    /// };
+    ///
+    /// assert_eq!(Err(libffi::Error::NullFunction), result);
+    /// ```
+    pub unsafe fn call_checked<R: RetSafe>(&self, fun: CodePtr, args: &[Arg]) -> crate::Result<R> {
+        let expected = self.0.cif.nargs as usize;
+        if args.len() != expected {
+            return Err(crate::Error::ArgCount {
+                expected,
+                actual: args.len(),
+            });
+        }
+
+        if fun.is_null() {
+            return Err(crate::Error::NullFunction);
+        }
+
+        if cfg!(debug_assertions) {
+            for (index, arg) in args.iter().enumerate() {
+                let align = usize::from((**self.0.cif.arg_types.add(index)).alignment);
+                if arg.0 as usize & (align - 1) != 0 {
+                    return Err(crate::Error::MisalignedArg { index, align });
+                }
+            }
+        }
+
+        Ok(self.call_unbounded(fun, args))
+    }
+
+    /// Calls a function with the given arguments, returning its result
+    /// along with the `errno` (and, on Windows, `GetLastError()`) that
+    /// it left.
+    ///
+    /// The error indicators are read as soon as the call returns, before
+    /// the caller’s runtime has a chance to overwrite them. They are not
+    /// cleared beforehand, so they are only meaningful when the callee
+    /// reports failure, as with C’s own `errno`.
+    ///
+    /// # Safety
+    ///
+    /// As for [`call`](#method.call).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(unix)] {
+    /// use std::os::raw::c_int;
+    /// use libffi::middle::*;
+    ///
+    /// extern "C" {
+    ///     fn close(fd: c_int) -> c_int;
+    /// }
+    ///
+    /// let cif = Cif::new(vec![Type::c_int()], Type::c_int());
+    /// let (result, errno) = unsafe {
+    ///     cif.call_with_errno::<c_int>(CodePtr(close as *mut _), &[arg(&-1)])
+    /// };
+    ///
+    /// assert_eq!(-1, result);
+    /// assert_ne!(0, errno.errno);
+    /// # }
+    /// ```
+    #[cfg(feature = "std")]
+    pub unsafe fn call_with_errno<R: RetSafe>(&self, fun: CodePtr, args: &[Arg]) -> (R, Errno) {
+        assert_eq!(
+            self.0.cif.nargs as usize,
+            args.len(),
+            "Cif::call_with_errno: passed wrong number of arguments"
+        );
+        self.check_result_type::<R>("Cif::call_with_errno");
+
+        // No tracing span here, since leaving it could clobber errno.
+        let result = low::call::<R>(
+            &self.0.cif as *const _ as *mut _,
+            fun,
+            args.as_ptr() as *mut *mut c_void,
+        );
+        (result, Errno::snapshot())
+    }
+
+    /// Calls a function with the given arguments inside a structured
+    /// exception handler, so that an access violation in the callee is
+    /// returned as a [`CallFault`](struct.CallFault.html) instead of
+    /// crashing the process.
+    ///
+    /// Available on MSVC targets with the `seh-guard` feature.
+    ///
+    /// # Safety
+    ///
+    /// As for [`call`](#method.call). In addition, a fault leaves any
+    /// state the callee was modifying inconsistent, and if the callee
+    /// called back into Rust, the Rust frames it skips are not unwound,
+    /// so their destructors do not run. Catching the fault keeps the
+    /// process alive; it does not make continuing safe in general.
+    #[cfg(all(feature = "seh-guard", target_env = "msvc"))]
+    pub unsafe fn call_guarded<R: RetSafe>(
+        &self,
+        fun: CodePtr,
+        args: &[Arg],
+    ) -> Result<R, CallFault> {
+        assert_eq!(
+            self.0.cif.nargs as usize,
+            args.len(),
+            "Cif::call_guarded: passed wrong number of arguments"
+        );
+        self.check_result_type::<R>("Cif::call_guarded");
+
+        let mut result = mem::MaybeUninit::<R>::uninit();
+        let mut code = 0;
+        let faulted = crate::raw::ffi_call_seh(
+            &self.0.cif as *const _ as *mut _,
+            Some(*fun.as_safe_fun()),
+            result.as_mut_ptr() as *mut c_void,
+            args.as_ptr() as *mut *mut c_void,
+            &mut code,
+        );
+
+        if faulted == 0 {
+            Ok(result.assume_init())
+        } else {
+            Err(CallFault::new(code))
+        }
+    }
+
+    /// Calls a function once for each row of arguments, writing the
+    /// results in order.
+    ///
+    /// `args` holds the rows back to back, each consisting of one `Arg`
+    /// per argument of the CIF, and `results` has room for one result
+    /// per row. The argument count is checked once for the whole batch
+    /// rather than once per call.
+    ///
+    /// # Safety
+    ///
+    /// As for [`call`](#method.call).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::*;
+    ///
+    /// extern "C" fn add(x: u32, y: u32) -> u32 { x + y }
+    ///
+    /// let cif = Cif::new(vec![Type::u32(), Type::u32()], Type::u32());
+    /// let rows = [(1u32, 2u32), (3, 4), (5, 6)];
+    /// let args: Vec<Arg> = rows.iter()
+    ///     .flat_map(|(x, y)| vec![arg(x), arg(y)])
+    ///     .collect();
+    /// let mut results = [0u32; 3];
+    ///
+    /// unsafe { cif.call_many(CodePtr(add as *mut _), &args, &mut results) };
+    /// assert_eq!([3, 7, 11], results);
+    /// ```
+    pub unsafe fn call_many<R: RetSafe>(&self, fun: CodePtr, args: &[Arg], results: &mut [R]) {
+        let nargs = self.0.cif.nargs as usize;
+        assert_eq!(
+            nargs * results.len(),
+            args.len(),
+            "Cif::call_many: passed wrong number of arguments"
+        );
+        self.check_result_type::<R>("Cif::call_many");
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(
+            "ffi_call_many",
+            signature = %self.signature(),
+            fun = ?fun.0,
+            rows = results.len(),
+        )
+        .entered();
+
+        let mut row = args.as_ptr() as *mut *mut c_void;
+        for result in results {
+            *result = low::call::<R>(&self.0.cif as *const _ as *mut _, fun, row);
+            row = row.add(nargs);
+        }
+    }
+
+    /// Calls a function with the given arguments, writing the result
+    /// into a caller-provided buffer.
+    ///
+    /// This is for results whose type is only known at run time, such
+    /// as structs built by an interpreter, which [`call`](#method.call)
+    /// cannot name. Size the buffer from the result type’s
+    /// [`Type::layout`](struct.Type.html#method.layout); since libffi
+    /// writes integer results as a whole `ffi_arg` word, the buffer must
+    /// also be at least that large.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of arguments is wrong, or if `result` is too
+    /// small or insufficiently aligned for the result type.
+    ///
+    /// # Safety
+    ///
+    /// As for [`call`](#method.call).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::*;
+    ///
+    /// #[repr(C)]
+    /// struct Pair { a: u32, b: u32 }
+    ///
+    /// extern "C" fn make_pair(x: u32) -> Pair { Pair { a: x, b: x * 2 } }
+    ///
+    /// let pair = Type::structure(vec![Type::u32(), Type::u32()]);
+    /// let cif = Cif::new(vec![Type::u32()], pair.clone());
+    ///
+    /// let mut buffer = [0u64; 1];
+    /// let bytes = unsafe {
+    ///     std::slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut u8, 8)
+    /// };
+    /// unsafe {
+    ///     cif.call_with_ret_buffer(CodePtr(make_pair as *mut _), &[arg(&21u32)], bytes)
+    /// };
+    ///
+    /// let value = unsafe { Value::read_from(bytes.as_ptr() as *const _, &pair) };
+    /// assert_eq!(Value::Struct(vec![Value::U32(21), Value::U32(42)]), value);
+    /// ```
+    pub unsafe fn call_with_ret_buffer(&self, fun: CodePtr, args: &[Arg], result: &mut [u8]) {
+        assert_eq!(
+            self.0.cif.nargs as usize,
+            args.len(),
+            "Cif::call_with_ret_buffer: passed wrong number of arguments"
+        );
+
+        let layout = self.0.result.layout();
+        let needed = if layout.size() == 0 {
+            0
+        } else {
+            layout.size().max(mem::size_of::<crate::raw::ffi_arg>())
+        };
+        assert!(
+            result.len() >= needed,
+            "Cif::call_with_ret_buffer: buffer of {} bytes is too small for {}",
+            result.len(),
+            self.0.result.describe()
+        );
+        assert_eq!(
+            result.as_ptr() as usize % layout.align(),
+            0,
+            "Cif::call_with_ret_buffer: buffer is insufficiently aligned for {}",
+            self.0.result.describe()
+        );
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(
+            "ffi_call",
+            signature = %self.signature(),
+            fun = ?fun.0,
+        )
+        .entered();
+
+        crate::raw::ffi_call(
+            &self.0.cif as *const _ as *mut _,
+            Some(*fun.as_safe_fun()),
+            result.as_mut_ptr() as *mut c_void,
+            args.as_ptr() as *mut *mut c_void,
+        );
+    }
+
+    // Checks, in debug builds, that `R` can hold the result: it must be
+    // the size of the result type, or a whole `ffi_arg` word for the
+    // small integer types that libffi widens, or empty for `void`.
+    pub(crate) fn check_result_type<R>(&self, caller: &str) {
+        if cfg!(debug_assertions) {
+            let size = mem::size_of::<R>();
+            let compatible =
+                if u32::from(unsafe { (*self.0.cif.rtype).type_ }) == crate::raw::FFI_TYPE_VOID {
+                    size == 0
+                } else {
+                    size == self.0.result_size
+                        || (self.0.result_size < mem::size_of::<crate::raw::ffi_arg>()
+                            && size == mem::size_of::<crate::raw::ffi_arg>())
+                };
+            assert!(
+                compatible,
+                "{}: result type of size {} does not match CIF result type {}",
+                caller,
+                size,
+                self.0.result.describe()
+            );
+        }
+    }
+
     /// Sets the CIF to use the given calling convention.
+    ///
+    /// This does not prepare the CIF again, and on some targets libffi
+    /// classifies arguments differently for different conventions, so
+    /// prefer [`new_with_abi`](#method.new_with_abi).
     pub fn set_abi(&mut self, abi: FfiAbi) {
-        self.cif.abi = abi;
+        self.make_mut().cif.abi = abi;
+    }
+
+    /// Describes the CIF’s signature for diagnostics, *e.g.,*
+    /// `(u64, pointer) -> void`.
+    #[allow(dead_code)]
+    pub(crate) fn signature(&self) -> String {
+        use core::fmt::Write;
+
+        let mut result = String::from("(");
+        for i in 0..self.0.cif.nargs as usize {
+            if i > 0 {
+                result.push_str(", ");
+            }
+            unsafe { types::ffi_type_describe(*self.0.cif.arg_types.add(i), &mut result) }.unwrap();
+        }
+        write!(result, ") -> {}", self.0.result.describe()).unwrap();
+        result
     }
 
     /// Gets a raw pointer to the underlying
@@ -173,7 +877,7 @@ impl Cif {
     /// This can be used for passing a `middle::Cif` to functions from the
     /// [`low`](../low/index.html) and [`raw`](../raw/index.html) modules.
     pub fn as_raw_ptr(&self) -> *mut low::ffi_cif {
-        &self.cif as *const _ as *mut _
+        &self.0.cif as *const _ as *mut _
     }
 }
 
@@ -194,26 +898,26 @@ impl Cif {
 /// when the closure is called. The callback function takes four
 /// arguments: a CIF describing its arguments, a pointer for where to
 /// store its result, a pointer to an array of pointers to its
-/// arguments, and a userdata pointer. In this ase, the Rust closure
+/// arguments, and a userdata pointer. The arguments are read with an
+/// [`ArgsReader`](struct.ArgsReader.html). In this case, the Rust closure
 /// value `lambda` is passed as userdata to `lambda_callback`, which
 /// then invokes it.
 ///
 /// ```
-/// use std::mem;
 /// use std::os::raw::c_void;
 ///
 /// use libffi::middle::*;
 /// use libffi::low;
 ///
 /// unsafe extern "C" fn lambda_callback<F: Fn(u64, u64) -> u64>(
-///     _cif: &low::ffi_cif,
+///     cif: &low::ffi_cif,
 ///     result: &mut u64,
 ///     args: *const *const c_void,
 ///     userdata: &F)
 /// {
-///     let args = args as *const &u64;
-///     let arg1 = **args.offset(0);
-///     let arg2 = **args.offset(1);
+///     let args = ArgsReader::new(cif, args);
+///     let arg1 = args.get(0);
+///     let arg2 = args.get(1);
 ///
 ///     *result = userdata(arg1, arg2);
 /// }
@@ -230,22 +934,27 @@ impl Cif {
 /// assert_eq!(11, fun(5, 6));
 /// assert_eq!(12, fun(5, 7));
 /// ```
+#[cfg(feature = "closures")]
 #[derive(Debug)]
 pub struct Closure<'a> {
-    _cif: Box<Cif>,
+    cif: Box<Cif>,
+    _shim: util::Shim,
     alloc: *mut low::ffi_closure,
     code: CodePtr,
+    userdata: *mut c_void,
     _marker: PhantomData<&'a ()>,
 }
 
+#[cfg(feature = "closures")]
 impl<'a> Drop for Closure<'a> {
     fn drop(&mut self) {
         unsafe {
-            low::closure_free(self.alloc);
+            util::release_closure(self.alloc, self.code);
         }
     }
 }
 
+#[cfg(feature = "closures")]
 impl<'a> Closure<'a> {
     /// Creates a new closure with immutable userdata.
     ///
@@ -262,23 +971,28 @@ impl<'a> Closure<'a> {
     /// The new closure.
     pub fn new<U, R>(cif: Cif, callback: Callback<U, R>, userdata: &'a U) -> Self {
         let cif = Box::new(cif);
+        let userdata = userdata as *const U as *mut c_void;
         let (alloc, code) = low::closure_alloc();
 
-        unsafe {
-            low::prep_closure(
+        let shim = unsafe {
+            util::prep_closure(
                 alloc,
                 cif.as_raw_ptr(),
-                callback,
-                userdata as *const U,
+                mem::transmute::<Callback<U, R>, low::RawCallback>(callback),
+                userdata,
                 code,
             )
-            .unwrap();
-        }
+            .unwrap()
+        };
+
+        util::closure_created::<U>(code, &cif);
 
         Closure {
-            _cif: cif,
+            cif,
+            _shim: shim,
             alloc,
             code,
+            userdata,
             _marker: PhantomData,
         }
     }
@@ -298,21 +1012,52 @@ impl<'a> Closure<'a> {
     /// The new closure.
     pub fn new_mut<U, R>(cif: Cif, callback: CallbackMut<U, R>, userdata: &'a mut U) -> Self {
         let cif = Box::new(cif);
+        let userdata = userdata as *mut U as *mut c_void;
         let (alloc, code) = low::closure_alloc();
 
-        unsafe {
-            low::prep_closure_mut(alloc, cif.as_raw_ptr(), callback, userdata as *mut U, code)
-                .unwrap();
-        }
+        let shim = unsafe {
+            util::prep_closure(
+                alloc,
+                cif.as_raw_ptr(),
+                mem::transmute::<CallbackMut<U, R>, low::RawCallback>(callback),
+                userdata,
+                code,
+            )
+            .unwrap()
+        };
+
+        util::closure_created::<U>(code, &cif);
 
         Closure {
-            _cif: cif,
+            cif,
+            _shim: shim,
             alloc,
             code,
+            userdata,
             _marker: PhantomData,
         }
     }
 
+    /// Creates a new closure with immutable userdata that can stand in
+    /// for a C++ member function using the `thiscall` calling
+    /// convention.
+    ///
+    /// The CIF is built by [`Cif::thiscall`](struct.Cif.html#method.thiscall),
+    /// so `callback` receives the `this` pointer as its first argument,
+    /// followed by `args`.
+    #[cfg(target_arch = "x86")]
+    pub fn new_thiscall<I, U, R>(
+        args: I,
+        result: Type,
+        callback: Callback<U, R>,
+        userdata: &'a U,
+    ) -> Self
+    where
+        I: IntoIterator<Item = Type>,
+    {
+        Self::new(Cif::thiscall(args, result), callback, userdata)
+    }
+
     /// Obtains the callable code pointer for a closure.
     ///
     /// # Safety
@@ -324,6 +1069,28 @@ impl<'a> Closure<'a> {
         self.code.as_fun()
     }
 
+    /// Obtains the callable code pointer for a closure, by value.
+    ///
+    /// The code pointer is valid only as long as the closure is alive;
+    /// the high layer’s `FnPtrN` types enforce this with a lifetime.
+    pub fn as_code_ptr(&self) -> CodePtr {
+        self.code
+    }
+
+    /// The CIF describing the closure’s signature.
+    pub fn cif(&self) -> &Cif {
+        &self.cif
+    }
+
+    /// The userdata pointer that the closure passes to its callback.
+    ///
+    /// This is the `userdata` the closure was created with, so state
+    /// that the callback updates can be inspected through the closure.
+    /// It must not be dereferenced while the callback is running.
+    pub fn userdata_ptr(&self) -> *mut c_void {
+        self.userdata
+    }
+
     /// Transmutes the callable code pointer for a closure to a reference
     /// to any type. This is intended to be used to transmute it to its
     /// correct function type in order to call it.
@@ -338,30 +1105,50 @@ impl<'a> Closure<'a> {
     }
 }
 
+#[cfg(feature = "closures")]
+impl Closure<'static> {
+    /// Leaks the closure, returning a code pointer that stays valid for
+    /// the rest of the program.
+    ///
+    /// This is for callbacks that are registered once and never
+    /// unregistered, such as signal handlers. Only a closure whose
+    /// userdata lives forever can be leaked.
+    pub fn leak(self) -> CodePtr {
+        let code = self.code;
+        mem::forget(self);
+        code
+    }
+}
+
 /// The type of callback invoked by a
 /// [`ClosureOnce`](struct.ClosureOnce.html).
+#[cfg(feature = "closures")]
 pub type CallbackOnce<U, R> = CallbackMut<Option<U>, R>;
 
 /// A closure that owns needs-drop data.
 ///
 /// This allows the closure’s callback to take ownership of the data, in
 /// which case the userdata will be gone if called again.
+#[cfg(feature = "closures")]
 #[derive(Debug)]
 pub struct ClosureOnce {
     alloc: *mut low::ffi_closure,
     code: CodePtr,
-    _cif: Box<Cif>,
+    cif: Box<Cif>,
+    _shim: util::Shim,
     _userdata: Box<dyn Any>,
 }
 
+#[cfg(feature = "closures")]
 impl Drop for ClosureOnce {
     fn drop(&mut self) {
         unsafe {
-            low::closure_free(self.alloc);
+            util::release_closure(self.alloc, self.code);
         }
     }
 }
 
+#[cfg(feature = "closures")]
 impl ClosureOnce {
     /// Creates a new closure with owned userdata.
     ///
@@ -377,30 +1164,33 @@ impl ClosureOnce {
     ///
     /// The new closure.
     pub fn new<U: Any, R>(cif: Cif, callback: CallbackOnce<U, R>, userdata: U) -> Self {
-        let _cif = Box::new(cif);
+        let cif = Box::new(cif);
         let _userdata = Box::new(Some(userdata)) as Box<dyn Any>;
         let (alloc, code) = low::closure_alloc();
 
         assert!(!alloc.is_null(), "closure_alloc: returned null");
 
-        {
+        let _shim = {
             let borrow = _userdata.downcast_ref::<Option<U>>().unwrap();
             unsafe {
-                low::prep_closure_mut(
+                util::prep_closure(
                     alloc,
-                    _cif.as_raw_ptr(),
-                    callback,
+                    cif.as_raw_ptr(),
+                    mem::transmute::<CallbackOnce<U, R>, low::RawCallback>(callback),
                     borrow as *const _ as *mut _,
                     code,
                 )
-                .unwrap();
+                .unwrap()
             }
-        }
+        };
+
+        util::closure_created::<U>(code, &cif);
 
         ClosureOnce {
             alloc,
             code,
-            _cif,
+            cif,
+            _shim,
             _userdata,
         }
     }
@@ -416,6 +1206,19 @@ impl ClosureOnce {
         self.code.as_fun()
     }
 
+    /// Obtains the callable code pointer for a closure, by value.
+    ///
+    /// The code pointer is valid only as long as the closure is alive;
+    /// the high layer’s `FnPtrN` types enforce this with a lifetime.
+    pub fn as_code_ptr(&self) -> CodePtr {
+        self.code
+    }
+
+    /// The CIF describing the closure’s signature.
+    pub fn cif(&self) -> &Cif {
+        &self.cif
+    }
+
     /// Transmutes the callable code pointer for a closure to a reference
     /// to any type. This is intended to be used to transmute it to its
     /// correct function type in order to call it.
@@ -428,11 +1231,56 @@ impl ClosureOnce {
     pub unsafe fn instantiate_code_ptr<T>(&self) -> &T {
         self.code.as_any_ref_()
     }
+
+    /// Leaks the closure and its userdata, returning a code pointer that
+    /// stays valid for the rest of the program.
+    ///
+    /// This is for callbacks that are registered once and never
+    /// unregistered, such as signal handlers.
+    pub fn leak(self) -> CodePtr {
+        let code = self.code;
+        mem::forget(self);
+        code
+    }
+
+    /// Borrows the closure’s userdata immutably, if it has not been
+    /// taken and is of type `U`.
+    ///
+    /// This is for inspecting state, such as counters, that the callback
+    /// updates, without keeping a copy outside the closure.
+    ///
+    /// # Safety
+    ///
+    /// Each call of the closure borrows its userdata mutably, so the
+    /// closure must not be running, on this thread or any other, or be
+    /// called while the returned borrow lives. Use
+    /// [`userdata_mut`](#method.userdata_mut) to have the borrow checker
+    /// ensure that instead.
+    pub unsafe fn downcast_userdata<U: Any>(&self) -> Option<&U> {
+        self._userdata.downcast_ref::<Option<U>>()?.as_ref()
+    }
+
+    /// Borrows the closure’s userdata, if it has not been taken and is
+    /// of type `U`.
+    pub fn userdata_mut<U: Any>(&mut self) -> Option<&mut U> {
+        self._userdata.downcast_mut::<Option<U>>()?.as_mut()
+    }
+
+    /// Takes back the closure’s userdata, if it has not been taken and is
+    /// of type `U`.
+    ///
+    /// This is useful when the closure will never be called, so that
+    /// whatever the userdata owns can be recovered. If the closure is
+    /// called afterward, the callback receives `None`.
+    pub fn take_userdata<U: Any>(&mut self) -> Option<U> {
+        self._userdata.downcast_mut::<Option<U>>()?.take()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    #[cfg(feature = "closures")]
     use crate::low;
     use std::os::raw::c_void;
 
@@ -453,10 +1301,199 @@ mod test {
     }
 
     #[test]
+    fn as_arg() {
+        extern "C" fn deref_add(p: *const i64, n: i64) -> i64 {
+            unsafe { *p + n }
+        }
+
+        let cif = Cif::new(vec![Type::i64(), Type::i64()], Type::i64());
+        let m = 5i64;
+        let owned = ArgValue::new(7i64);
+        let args = [arg(&m as *const i64), arg(&owned)];
+        let result: i64 = unsafe { cif.call(CodePtr(add_it as *mut c_void), &args) };
+        assert_eq!(12, result);
+
+        let cif = Cif::new(vec![Type::pointer(), Type::i64()], Type::i64());
+        let p = &m as *const i64;
+        let bytes = ArgValue::from_bytes(&1i64.to_ne_bytes());
+        let result: i64 = unsafe {
+            cif.call(
+                CodePtr(deref_add as *mut c_void),
+                &[Arg::new(&p), arg(&bytes)],
+            )
+        };
+        assert_eq!(6, result);
+    }
+
+    #[test]
+    #[cfg(feature = "closures")]
+    fn take_userdata() {
+        let cif = Cif::new(vec![], Type::void());
+        let mut closure = ClosureOnce::new(cif, drop_it, String::from("hello"));
+
+        assert!(closure.take_userdata::<u32>().is_none());
+        assert_eq!(
+            "hello",
+            unsafe { closure.downcast_userdata::<String>() }.unwrap()
+        );
+        assert_eq!(5, closure.userdata_mut::<String>().unwrap().len());
+        assert_eq!(Some("hello".into()), closure.take_userdata::<String>());
+        assert!(closure.take_userdata::<String>().is_none());
+    }
+
+    #[cfg(feature = "closures")]
+    unsafe extern "C" fn drop_it(
+        _cif: &low::ffi_cif,
+        _result: &mut (),
+        _args: *const *const c_void,
+        userdata: &mut Option<String>,
+    ) {
+        userdata.take();
+    }
+
+    #[test]
+    fn signature() {
+        let point = Type::structure(vec![Type::u16(), Type::f64()]);
+        let cif = Cif::new(vec![point, Type::pointer()], Type::void());
+
+        assert_eq!("(struct { u16, f64 }, pointer) -> void", cif.signature());
+    }
+
+    #[test]
+    fn call_many() {
+        let cif = Cif::new(vec![Type::i64(), Type::i64()], Type::i64());
+        let values = [1i64, 2, 3, 4];
+        let args: Vec<Arg> = values.iter().map(arg).collect();
+        let mut results = [0i64; 2];
+
+        unsafe { cif.call_many(CodePtr(add_it as *mut c_void), &args, &mut results) };
+        assert_eq!([3, 7], results);
+    }
+
+    #[test]
+    fn call_many_nullary() {
+        extern "C" fn seven() -> u64 {
+            7
+        }
+
+        let cif = Cif::new(vec![], Type::u64());
+        let mut results = [0u64; 3];
+
+        unsafe { cif.call_many(CodePtr(seven as *mut c_void), &[], &mut results) };
+        assert_eq!([7, 7, 7], results);
+    }
+
+    #[test]
+    #[should_panic(expected = "too small")]
+    fn ret_buffer_too_small() {
+        extern "C" fn seven() -> u8 {
+            7
+        }
+
+        let cif = Cif::new(vec![], Type::u8());
+        let mut buffer = [0u8; 1];
+
+        unsafe { cif.call_with_ret_buffer(CodePtr(seven as *mut c_void), &[], &mut buffer) };
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "does not match CIF result type")]
+    fn wrong_result_size() {
+        extern "C" fn seven() -> u16 {
+            7
+        }
+
+        let cif = Cif::new(vec![], Type::u16());
+        unsafe { cif.call::<f32>(CodePtr(seven as *mut c_void), &[]) };
+    }
+
+    #[test]
+    fn call_checked_errors() {
+        extern "C" fn add_it(n: i64, m: i64) -> i64 {
+            n + m
+        }
+
+        let cif = Cif::new(vec![Type::i64(), Type::i64()], Type::i64());
+        let fun = CodePtr(add_it as *mut c_void);
+
+        let result = unsafe { cif.call_checked::<i64>(fun, &[arg(&1i64)]) };
+        assert_eq!(
+            Err(crate::Error::ArgCount {
+                expected: 2,
+                actual: 1
+            }),
+            result
+        );
+
+        #[cfg(debug_assertions)]
+        {
+            let words = [0u64; 2];
+            let misaligned = arg((words.as_ptr() as *const u8).wrapping_add(1));
+            let result = unsafe { cif.call_checked::<i64>(fun, &[arg(&1i64), misaligned]) };
+            assert_eq!(
+                Err(crate::Error::MisalignedArg { index: 1, align: 8 }),
+                result
+            );
+        }
+
+        let result = unsafe { cif.call_checked::<i64>(fun, &[arg(&1i64), arg(&2i64)]) };
+        assert_eq!(Ok(3), result);
+    }
+
+    #[test]
+    fn clone_shares_until_set_abi() {
+        let cif = Cif::new(vec![Type::i64(), Type::i64()], Type::i64());
+        let mut copy = cif.clone();
+        assert_eq!(cif.as_raw_ptr(), copy.as_raw_ptr());
+
+        copy.set_abi(Abi::Default.as_raw());
+        assert_ne!(cif.as_raw_ptr(), copy.as_raw_ptr());
+
+        let result =
+            unsafe { copy.call::<i64>(CodePtr(add_it as *mut _), &[arg(&1i64), arg(&2i64)]) };
+        assert_eq!(3, result);
+    }
+
+    #[cfg(all(unix, feature = "std"))]
+    #[test]
+    fn call_with_errno() {
+        let cif = Cif::new(vec![Type::c_int()], Type::c_int());
+        let fun = CodePtr(libc::close as *mut _);
+
+        let (result, errno) = unsafe { cif.call_with_errno::<libc::c_int>(fun, &[arg(&-1)]) };
+        assert_eq!(-1, result);
+        assert_eq!(libc::EBADF, errno.errno);
+        assert_eq!(Some(libc::EBADF), errno.to_io_error().raw_os_error());
+    }
+
+    #[test]
+    fn call_from_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let cif = Arc::new(Cif::new(vec![Type::i64(), Type::i64()], Type::i64()));
+
+        let handles: Vec<_> = (0..4i64)
+            .map(|i| {
+                let cif = cif.clone();
+                thread::spawn(move || unsafe {
+                    cif.call::<i64>(CodePtr(add_it as *mut c_void), &[arg(&i), arg(&10i64)])
+                })
+            })
+            .collect();
+
+        let results: Vec<i64> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(vec![10, 11, 12, 13], results);
+    }
+
+    #[test]
+    #[cfg(feature = "closures")]
     fn closure() {
         let cif = Cif::new(vec![Type::u64()].into_iter(), Type::u64());
         let env: u64 = 5;
         let closure = Closure::new(cif, callback, &env);
+        assert_eq!(&env as *const u64 as *mut c_void, closure.userdata_ptr());
 
         let fun: &extern "C" fn(u64) -> u64 = unsafe { closure.instantiate_code_ptr() };
 
@@ -464,6 +1501,18 @@ mod test {
         assert_eq!(12, fun(7));
     }
 
+    #[test]
+    #[cfg(feature = "closures")]
+    fn leak() {
+        static ENV: u64 = 5;
+        let cif = Cif::new(vec![Type::u64()], Type::u64());
+        let code = Closure::new(cif, callback, &ENV).leak();
+
+        let fun: extern "C" fn(u64) -> u64 = unsafe { mem::transmute(code.0) };
+        assert_eq!(11, fun(6));
+    }
+
+    #[cfg(feature = "closures")]
     unsafe extern "C" fn callback(
         _cif: &low::ffi_cif,
         result: &mut u64,
@@ -475,6 +1524,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(feature = "closures")]
     fn rust_lambda() {
         let cif = Cif::new(vec![Type::u64(), Type::u64()].into_iter(), Type::u64());
         let env = |x: u64, y: u64| x + y;
@@ -485,6 +1535,7 @@ mod test {
         assert_eq!(11, fun(5, 6));
     }
 
+    #[cfg(feature = "closures")]
     unsafe extern "C" fn callback2<F: Fn(u64, u64) -> u64>(
         _cif: &low::ffi_cif,
         result: &mut u64,
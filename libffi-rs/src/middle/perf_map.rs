@@ -0,0 +1,77 @@
+//! Names closure trampolines for `perf`.
+//!
+//! This module is enabled by `#[cfg(feature = "perf-map")]` on Unix,
+//! which needs Rust 1.63 or later.
+//!
+//! Linux `perf` (and tools that read its map files) resolves samples in
+//! anonymous executable memory by consulting `/tmp/perf-<pid>.map`, a
+//! text file with one `START SIZE NAME` line per code region. With the
+//! feature enabled, each closure appends a line naming its trampoline
+//! after the closure’s userdata type and signature, so that samples in
+//! trampolines are attributed to the owning Rust closure rather than to
+//! an anonymous region.
+//!
+//! Entries are never removed, since the format has no way to express
+//! that; when a trampoline is reused by a later closure, its new entry
+//! is appended after the old one. The JITDump format is not written.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::process;
+use std::sync::Mutex;
+
+use super::{Cif, CodePtr};
+use crate::raw::FFI_TRAMPOLINE_SIZE;
+
+static PERF_MAP: Mutex<Option<File>> = Mutex::new(None);
+
+/// Returns the path of the perf map file for this process.
+fn perf_map_path() -> String {
+    format!("/tmp/perf-{}.map", process::id())
+}
+
+pub(crate) fn record<U>(code: CodePtr, cif: &Cif) {
+    let mut perf_map = PERF_MAP.lock().unwrap_or_else(|e| e.into_inner());
+
+    if perf_map.is_none() {
+        *perf_map = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(perf_map_path())
+            .ok();
+    }
+
+    // Profiling aids must never take the program down, so write errors
+    // are ignored.
+    if let Some(file) = perf_map.as_mut() {
+        let _ = writeln!(
+            file,
+            "{:x} {:x} libffi closure {} {}",
+            code.0 as usize,
+            FFI_TRAMPOLINE_SIZE,
+            std::any::type_name::<U>(),
+            cif.signature(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::high::Closure1;
+    use std::fs;
+
+    #[test]
+    fn writes_entry() {
+        let f = |x: u32| x + 1;
+        let closure = Closure1::new(&f);
+        let code = *closure.code_ptr() as usize;
+
+        let contents = fs::read_to_string(perf_map_path()).unwrap();
+        let entry = format!("{:x} {:x} libffi closure ", code, FFI_TRAMPOLINE_SIZE);
+
+        assert!(contents
+            .lines()
+            .any(|line| line.starts_with(&entry) && line.ends_with("(u32) -> u32")));
+    }
+}
@@ -0,0 +1,197 @@
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::mem;
+use core::ptr;
+
+use super::frame::{Chunk, MAX_ALIGN};
+use super::{Cif, CodePtr, RetSafe};
+use crate::low;
+
+/// A call to a particular function, prepared once and made many times.
+///
+/// A `PreparedCall` owns a [`Cif`](struct.Cif.html), the code pointer
+/// to call, and a buffer with a slot for each argument, laid out from
+/// the CIF’s argument types. Arguments are written into their slots
+/// with [`set`](#method.set) or [`set_bytes`](#method.set_bytes), which
+/// check the slot’s size, and then the call is made with
+/// [`invoke`](#method.invoke). Slots keep their values between calls,
+/// so only the arguments that change need to be set again. Nothing is
+/// allocated after construction.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// extern "C" fn scale(x: f64, factor: u32) -> f64 { x * factor as f64 }
+///
+/// let cif = Cif::new(vec![Type::f64(), Type::u32()], Type::f64());
+/// let mut call = PreparedCall::new(cif, CodePtr(scale as *mut _));
+/// call.set(1, 3u32);
+///
+/// for i in 0..4 {
+///     call.set(0, i as f64);
+///     let result: f64 = unsafe { call.invoke() };
+///     assert_eq!(3.0 * i as f64, result);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct PreparedCall {
+    cif: Cif,
+    fun: CodePtr,
+    _buffer: Vec<Chunk>,
+    // Pointers into `_buffer`, whose heap allocation never moves.
+    args: Vec<*mut c_void>,
+    sizes: Vec<usize>,
+}
+
+impl PreparedCall {
+    /// Prepares calls to `fun` as described by `cif`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an argument type requires alignment greater than 16.
+    pub fn new(cif: Cif, fun: CodePtr) -> Self {
+        let raw = unsafe { &*cif.as_raw_ptr() };
+        let nargs = raw.nargs as usize;
+
+        let mut offsets = Vec::with_capacity(nargs);
+        let mut sizes = Vec::with_capacity(nargs);
+        let mut used = 0;
+        for i in 0..nargs {
+            // The CIF is prepared, so libffi has computed the sizes and
+            // alignments of struct types.
+            let ty = unsafe { &**raw.arg_types.add(i) };
+            let align = usize::from(ty.alignment);
+            assert!(
+                align.is_power_of_two() && align <= MAX_ALIGN,
+                "PreparedCall: unsupported alignment {}",
+                align
+            );
+
+            used = (used + align - 1) & !(align - 1);
+            offsets.push(used);
+            sizes.push(ty.size);
+            used += ty.size;
+        }
+
+        let chunks = ((used + MAX_ALIGN - 1) & !(MAX_ALIGN - 1)) / MAX_ALIGN;
+        let mut buffer = vec![Chunk([0; MAX_ALIGN]); chunks];
+        let base = buffer.as_mut_ptr() as *mut u8;
+        let args = offsets
+            .into_iter()
+            .map(|offset| unsafe { base.add(offset) as *mut c_void })
+            .collect();
+
+        PreparedCall {
+            cif,
+            fun,
+            _buffer: buffer,
+            args,
+            sizes,
+        }
+    }
+
+    /// The CIF describing the call.
+    pub fn cif(&self) -> &Cif {
+        &self.cif
+    }
+
+    /// The function to call.
+    pub fn code_ptr(&self) -> CodePtr {
+        self.fun
+    }
+
+    /// Writes the `i`th argument.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is out of bounds or the size of `T` differs from
+    /// that of the CIF’s `i`th argument type.
+    pub fn set<T: Copy>(&mut self, i: usize, value: T) -> &mut Self {
+        self.check_slot(i, mem::size_of::<T>());
+        unsafe {
+            ptr::write(self.args[i] as *mut T, value);
+        }
+        self
+    }
+
+    /// Writes the bytes of the `i`th argument, such as a struct whose
+    /// type is only known at run time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is out of bounds or the length of `bytes` differs
+    /// from the size of the CIF’s `i`th argument type.
+    pub fn set_bytes(&mut self, i: usize, bytes: &[u8]) -> &mut Self {
+        self.check_slot(i, bytes.len());
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), self.args[i] as *mut u8, bytes.len());
+        }
+        self
+    }
+
+    /// Calls the function with the arguments as currently set.
+    ///
+    /// # Safety
+    ///
+    /// The arguments must have been set to values of the CIF’s
+    /// argument types, and `R` must be the CIF’s result type, or the
+    /// behavior is undefined, as for
+    /// [`Cif::call`](struct.Cif.html#method.call).
+    pub unsafe fn invoke<R: RetSafe>(&mut self) -> R {
+        self.cif.check_result_type::<R>("PreparedCall::invoke");
+        low::call::<R>(self.cif.as_raw_ptr(), self.fun, self.args.as_mut_ptr())
+    }
+
+    fn check_slot(&self, i: usize, size: usize) {
+        assert!(
+            i < self.sizes.len(),
+            "PreparedCall: argument index {} out of bounds for {} arguments",
+            i,
+            self.sizes.len()
+        );
+        assert_eq!(
+            self.sizes[i], size,
+            "PreparedCall: size of argument {} does not match the CIF",
+            i
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::Type;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Point {
+        x: i16,
+        y: i64,
+    }
+
+    extern "C" fn manhattan(tag: u8, p: Point) -> i64 {
+        tag as i64 + p.x.abs() as i64 + p.y.abs()
+    }
+
+    #[test]
+    fn invoke_with_struct() {
+        let point = Type::structure(vec![Type::i16(), Type::i64()]);
+        let cif = Cif::new(vec![Type::u8(), point], Type::i64());
+        let mut call = PreparedCall::new(cif, CodePtr(manhattan as *mut _));
+
+        call.set(0, 1u8).set(1, Point { x: -2, y: 3 });
+        assert_eq!(6, unsafe { call.invoke::<i64>() });
+
+        call.set(1, Point { x: 10, y: -10 });
+        assert_eq!(21, unsafe { call.invoke::<i64>() });
+    }
+
+    #[test]
+    #[should_panic(expected = "does not match")]
+    fn wrong_size() {
+        let cif = Cif::new(vec![Type::u32()], Type::void());
+        PreparedCall::new(cif, CodePtr(manhattan as *mut _)).set(0, 1u64);
+    }
+}
@@ -0,0 +1,154 @@
+//! A global registry of live closures, for diagnostics.
+//!
+//! This module is enabled by `#[cfg(feature = "closure-registry")]`,
+//! which needs Rust 1.65 or later.
+//!
+//! When the feature is enabled, every closure created by the
+//! [`middle`](../index.html) and [`high`](../../high/index.html) layers
+//! records its code pointer along with its CIF signature, the type name
+//! of its userdata, and a backtrace of its creation. When a C library
+//! crashes inside a callback (or holds on to one for too long), the
+//! callback’s address can be looked up here to find out which Rust
+//! closure it belongs to.
+//!
+//! The backtrace is captured with
+//! [`Backtrace::capture`](https://doc.rust-lang.org/std/backtrace/struct.Backtrace.html#method.capture),
+//! so it is only populated when `RUST_BACKTRACE` or
+//! `RUST_LIB_BACKTRACE` is set.
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::high::Closure1;
+//! use libffi::middle::{registry, CodePtr};
+//!
+//! let f = |x: u32| x + 1;
+//! let closure = Closure1::new(&f);
+//! let code = CodePtr(*closure.code_ptr() as *mut _);
+//!
+//! let info = registry::lookup(code).unwrap();
+//! assert_eq!("(u32) -> u32", info.signature());
+//! ```
+
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use super::{Cif, CodePtr};
+
+static REGISTRY: Mutex<Option<HashMap<usize, Arc<ClosureInfo>>>> = Mutex::new(None);
+
+/// Describes a live closure.
+#[derive(Debug)]
+pub struct ClosureInfo {
+    code: CodePtr,
+    signature: String,
+    userdata_type: &'static str,
+    backtrace: Backtrace,
+}
+
+impl ClosureInfo {
+    /// The closure’s code pointer.
+    pub fn code_ptr(&self) -> CodePtr {
+        self.code
+    }
+
+    /// The closure’s signature, *e.g.,* `(u64, pointer) -> void`.
+    pub fn signature(&self) -> &str {
+        &self.signature
+    }
+
+    /// The type name of the closure’s userdata. For closures from the
+    /// [`high`](../../high/index.html) layer, this is the type of the Rust
+    /// closure.
+    pub fn userdata_type(&self) -> &'static str {
+        self.userdata_type
+    }
+
+    /// The backtrace of the closure’s creation.
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+}
+
+impl fmt::Display for ClosureInfo {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "closure {:?} {} with userdata {}\n{}",
+            self.code.0, self.signature, self.userdata_type, self.backtrace
+        )
+    }
+}
+
+// The registry only hands out shared references to immutable data.
+unsafe impl Send for ClosureInfo {}
+unsafe impl Sync for ClosureInfo {}
+
+/// Looks up the closure with the given code pointer, if it is live.
+pub fn lookup(code: CodePtr) -> Option<Arc<ClosureInfo>> {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.as_ref()?.get(&(code.0 as usize)).cloned()
+}
+
+/// Returns all live closures, in no particular order.
+pub fn live_closures() -> Vec<Arc<ClosureInfo>> {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry
+        .iter()
+        .flat_map(|map| map.values().cloned())
+        .collect()
+}
+
+pub(crate) fn register<U>(code: CodePtr, cif: &Cif) {
+    let info = ClosureInfo {
+        code,
+        signature: cif.signature(),
+        userdata_type: std::any::type_name::<U>(),
+        backtrace: Backtrace::capture(),
+    };
+
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry
+        .get_or_insert_with(HashMap::new)
+        .insert(code.0 as usize, Arc::new(info));
+}
+
+pub(crate) fn unregister(code: CodePtr) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(map) = registry.as_mut() {
+        map.remove(&(code.0 as usize));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::high::{Closure2, ClosureOnce0};
+
+    #[test]
+    fn register_and_unregister() {
+        let f = |x: u64, y: u64| x + y;
+        let closure = Closure2::new(&f);
+        let code = CodePtr(*closure.code_ptr() as *mut _);
+
+        let info = lookup(code).unwrap();
+        assert_eq!("(u64, u64) -> u64", info.signature());
+        assert!(info.userdata_type().contains("closure"));
+
+        drop(closure);
+        assert!(lookup(code).is_none());
+    }
+
+    #[test]
+    fn once() {
+        let closure = ClosureOnce0::new(|| ());
+        let code = CodePtr(*closure.code_ptr() as *mut _);
+
+        assert_eq!("() -> void", lookup(code).unwrap().signature());
+        assert!(live_closures()
+            .iter()
+            .any(|info| info.code_ptr().0 == code.0));
+    }
+}
@@ -0,0 +1,264 @@
+use core::ffi::c_void;
+use core::marker::PhantomData;
+use core::mem;
+#[cfg(feature = "closures")]
+use core::ptr;
+
+#[cfg(feature = "closures")]
+use crate::low;
+use crate::raw::*;
+
+/// Types that a closure callback can return through a
+/// [`ResultWriter`](struct.ResultWriter.html).
+///
+/// libffi requires integer results smaller than a machine word to be
+/// written to the result buffer as a full `ffi_arg` (or `ffi_sarg`)
+/// word. Writing the value as-is leaves the upper bytes undefined and,
+/// on big-endian targets, puts it in the wrong bytes entirely.
+/// Implementations of this trait perform the necessary widening.
+pub trait ResultValue: Copy {
+    /// Writes `self` to a closure’s result buffer.
+    ///
+    /// # Safety
+    ///
+    /// `result` must be a result buffer provided by libffi to a closure
+    /// whose CIF returns this type.
+    unsafe fn write_result(self, result: *mut c_void);
+}
+
+macro_rules! impl_result_value_widened {
+    ($word:ty; $($T:ty),*) => {
+        $(
+            impl ResultValue for $T {
+                unsafe fn write_result(self, result: *mut c_void) {
+                    if mem::size_of::<$T>() < mem::size_of::<$word>() {
+                        *(result as *mut $word) = self as $word;
+                    } else {
+                        *(result as *mut $T) = self;
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_result_value_widened!(ffi_arg; u8, u16, u32, u64, usize);
+impl_result_value_widened!(ffi_sarg; i8, i16, i32, i64, isize);
+
+macro_rules! impl_result_value_direct {
+    ($($T:ty),*) => {
+        $(
+            impl ResultValue for $T {
+                unsafe fn write_result(self, result: *mut c_void) {
+                    *(result as *mut $T) = self;
+                }
+            }
+        )*
+    };
+}
+
+impl_result_value_direct!(f32, f64);
+
+impl<T> ResultValue for *const T {
+    unsafe fn write_result(self, result: *mut c_void) {
+        *(result as *mut *const T) = self;
+    }
+}
+
+impl<T> ResultValue for *mut T {
+    unsafe fn write_result(self, result: *mut c_void) {
+        *(result as *mut *mut T) = self;
+    }
+}
+
+impl ResultValue for () {
+    unsafe fn write_result(self, _result: *mut c_void) {}
+}
+
+/// Types that [`Cif::call`](struct.Cif.html#method.call) can return.
+///
+/// A foreign function’s result is created by libffi writing bytes into
+/// a buffer, so the result type must be valid for any bytes the
+/// function may return, and must not own anything that its destructor
+/// would free. This trait is implemented for the primitive numeric and
+/// pointer types and for `()`; implement it for `#[repr(C)]` structs of
+/// such types to return them from calls.
+///
+/// # Safety
+///
+/// Implementing types must be plain data: `Copy`, with a C layout, and
+/// with no invalid bit patterns among the values the called functions
+/// return.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// #[repr(C)]
+/// #[derive(Clone, Copy)]
+/// struct Pair { a: u32, b: u32 }
+///
+/// unsafe impl RetSafe for Pair {}
+///
+/// extern "C" fn make_pair(x: u32) -> Pair { Pair { a: x, b: x + 1 } }
+///
+/// let cif = Cif::new(vec![Type::u32()], Type::structure(vec![Type::u32(), Type::u32()]));
+/// let pair: Pair = unsafe { cif.call(CodePtr(make_pair as *mut _), &[arg(&5u32)]) };
+/// assert_eq!((5, 6), (pair.a, pair.b));
+/// ```
+pub unsafe trait RetSafe: Copy {}
+
+macro_rules! impl_ret_safe {
+    ($($T:ty),*) => {
+        $( unsafe impl RetSafe for $T {} )*
+    };
+}
+
+impl_ret_safe!(
+    u8,
+    i8,
+    u16,
+    i16,
+    u32,
+    i32,
+    u64,
+    i64,
+    usize,
+    isize,
+    f32,
+    f64,
+    ()
+);
+
+unsafe impl<T> RetSafe for *const T {}
+unsafe impl<T> RetSafe for *mut T {}
+
+/// Writes a closure result of any type, as described by the CIF.
+///
+/// Integer results smaller than `ffi_arg` are widened according to the
+/// CIF’s result type, as `ResultValue` does for known types; all other
+/// results, including structs, are written whole.
+///
+/// `result` is libffi’s result buffer, which is at least an `ffi_arg`
+/// and at least an `R`, and must be valid for writes of all of it.
+#[cfg(feature = "closures")]
+pub(crate) unsafe fn write_result<R>(cif: &low::ffi_cif, result: *mut c_void, value: R) {
+    let typed = result as *mut R;
+    let size = mem::size_of::<R>();
+    let signed = match u32::from((*cif.rtype).type_) {
+        FFI_TYPE_UINT8 | FFI_TYPE_UINT16 | FFI_TYPE_UINT32 => false,
+        FFI_TYPE_SINT8 | FFI_TYPE_SINT16 | FFI_TYPE_SINT32 | FFI_TYPE_INT => true,
+        _ => return ptr::write(typed, value),
+    };
+
+    if size >= mem::size_of::<ffi_arg>() {
+        return ptr::write(typed, value);
+    }
+
+    let word = match (size, signed) {
+        (1, false) => mem::transmute_copy::<R, u8>(&value) as ffi_arg,
+        (2, false) => mem::transmute_copy::<R, u16>(&value) as ffi_arg,
+        (4, false) => mem::transmute_copy::<R, u32>(&value) as ffi_arg,
+        (1, true) => mem::transmute_copy::<R, i8>(&value) as ffi_sarg as ffi_arg,
+        (2, true) => mem::transmute_copy::<R, i16>(&value) as ffi_sarg as ffi_arg,
+        (4, true) => mem::transmute_copy::<R, i32>(&value) as ffi_sarg as ffi_arg,
+        _ => return ptr::write(typed, value),
+    };
+    mem::forget(value);
+
+    *(result as *mut ffi_arg) = word;
+}
+
+/// Writes the result of a closure callback.
+///
+/// See [`ResultValue`](trait.ResultValue.html) for why writing small
+/// integer results directly is wrong.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "closures")] {
+/// use std::os::raw::c_void;
+///
+/// use libffi::middle::*;
+/// use libffi::{low, raw};
+///
+/// // The result buffer is at least an `ffi_arg`, so the callback
+/// // declares it as one rather than as the `u8` it returns.
+/// unsafe extern "C" fn callback(
+///     _cif: &low::ffi_cif,
+///     result: &mut raw::ffi_arg,
+///     _args: *const *const c_void,
+///     userdata: &u8)
+/// {
+///     ResultWriter::new(result).set(*userdata);
+/// }
+///
+/// let cif = Cif::new(vec![], Type::u8());
+/// let closure = Closure::new(cif, callback, &7);
+///
+/// let fun: &extern "C" fn() -> u8 = unsafe {
+///     closure.instantiate_code_ptr()
+/// };
+///
+/// assert_eq!(7, fun());
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ResultWriter<'a, R> {
+    result: *mut c_void,
+    _marker: PhantomData<&'a mut R>,
+}
+
+impl<'a, R: ResultValue> ResultWriter<'a, R> {
+    /// Wraps the result pointer passed to a callback.
+    ///
+    /// The pointer is typed as an `ffi_arg` because that is the least
+    /// that libffi’s result buffer holds, and small integers are
+    /// written as a whole `ffi_arg`.
+    ///
+    /// # Safety
+    ///
+    /// `result` must be the result buffer provided by libffi to a
+    /// closure whose CIF returns `R`, and must be valid for writes of
+    /// at least `size_of::<ffi_arg>()` bytes, and of `size_of::<R>()`
+    /// bytes if that is more. In particular, it must not be derived
+    /// from a `&mut R` when `R` is smaller than an `ffi_arg`; declare
+    /// the callback’s result parameter as `&mut ffi_arg` instead.
+    pub unsafe fn new(result: *mut ffi_arg) -> Self {
+        ResultWriter {
+            result: result as *mut c_void,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Writes the result, widening it to a full `ffi_arg` word if
+    /// necessary.
+    pub fn set(self, value: R) {
+        unsafe { value.write_result(self.result) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn widens_small_integers() {
+        let mut word: ffi_arg = !0;
+        unsafe { ResultWriter::new(&mut word).set(5u8) };
+        assert_eq!(5, word);
+
+        let mut word: ffi_sarg = 0;
+        unsafe { ResultWriter::new(&mut word as *mut ffi_sarg as *mut ffi_arg).set(-3i16) };
+        assert_eq!(-3, word);
+    }
+
+    #[test]
+    fn writes_floats_directly() {
+        let mut word: ffi_arg = 0;
+        unsafe { ResultWriter::new(&mut word).set(1.5f32) };
+        assert_eq!(1.5, unsafe { *(&word as *const ffi_arg as *const f32) });
+    }
+}
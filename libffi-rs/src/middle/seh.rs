@@ -0,0 +1,30 @@
+use core::fmt;
+use libc::c_ulong;
+#[cfg(feature = "std")]
+use std::error;
+
+/// A structured exception raised by a foreign function called with
+/// [`Cif::call_guarded`](struct.Cif.html#method.call_guarded).
+///
+/// Only access violations are caught; other exceptions propagate as
+/// usual.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CallFault {
+    /// The exception code, such as `0xC0000005` for an access violation.
+    pub code: u32,
+}
+
+impl CallFault {
+    pub(super) fn new(code: c_ulong) -> Self {
+        CallFault { code: code as u32 }
+    }
+}
+
+impl fmt::Display for CallFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "foreign function raised exception {:#010x}", self.code)
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for CallFault {}
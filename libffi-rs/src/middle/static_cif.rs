@@ -0,0 +1,410 @@
+use core::cell::UnsafeCell;
+use core::ffi::c_void;
+use core::hint;
+use core::mem::{self, MaybeUninit};
+use core::ptr;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use super::{Abi, Arg, CodePtr, RetSafe};
+use crate::low;
+
+/// A type for a [`StaticCif`](struct.StaticCif.html).
+///
+/// Unlike [`Type`](struct.Type.html), a `StaticType` owns nothing and
+/// can be constructed in a `const` context, so that a whole signature
+/// can be written down as a `static`. The primitive types stand for
+/// libffi’s own `ffi_type` statics; other types, such as structs, are
+/// given as pointers to `ffi_type`s that live as long as the program.
+#[derive(Clone, Copy, Debug)]
+pub struct StaticType(Repr);
+
+#[derive(Clone, Copy, Debug)]
+enum Repr {
+    Void,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+    Pointer,
+    Raw(*mut low::ffi_type),
+}
+
+// A `StaticType` is only a name for an `ffi_type` that is never freed,
+// and that `from_raw` requires to be safe to hand to libffi from any
+// thread.
+unsafe impl Send for StaticType {}
+unsafe impl Sync for StaticType {}
+
+impl StaticType {
+    /// Returns the representation of the C `void` type.
+    ///
+    /// This is used only for the return type of a CIF.
+    pub const fn void() -> Self {
+        StaticType(Repr::Void)
+    }
+
+    /// Returns the unsigned 8-bit numeric type.
+    pub const fn u8() -> Self {
+        StaticType(Repr::U8)
+    }
+
+    /// Returns the signed 8-bit numeric type.
+    pub const fn i8() -> Self {
+        StaticType(Repr::I8)
+    }
+
+    /// Returns the unsigned 16-bit numeric type.
+    pub const fn u16() -> Self {
+        StaticType(Repr::U16)
+    }
+
+    /// Returns the signed 16-bit numeric type.
+    pub const fn i16() -> Self {
+        StaticType(Repr::I16)
+    }
+
+    /// Returns the unsigned 32-bit numeric type.
+    pub const fn u32() -> Self {
+        StaticType(Repr::U32)
+    }
+
+    /// Returns the signed 32-bit numeric type.
+    pub const fn i32() -> Self {
+        StaticType(Repr::I32)
+    }
+
+    /// Returns the unsigned 64-bit numeric type.
+    pub const fn u64() -> Self {
+        StaticType(Repr::U64)
+    }
+
+    /// Returns the signed 64-bit numeric type.
+    pub const fn i64() -> Self {
+        StaticType(Repr::I64)
+    }
+
+    /// Returns the C equivalent of Rust `usize`.
+    pub const fn usize() -> Self {
+        Self::unsigned(mem::size_of::<usize>())
+    }
+
+    /// Returns the C equivalent of Rust `isize`.
+    pub const fn isize() -> Self {
+        Self::signed(mem::size_of::<isize>())
+    }
+
+    /// Returns the C `int` type.
+    pub const fn c_int() -> Self {
+        Self::signed(mem::size_of::<libc::c_int>())
+    }
+
+    /// Returns the C `unsigned int` type.
+    pub const fn c_uint() -> Self {
+        Self::unsigned(mem::size_of::<libc::c_uint>())
+    }
+
+    /// Returns the C `long` type.
+    pub const fn c_long() -> Self {
+        Self::signed(mem::size_of::<libc::c_long>())
+    }
+
+    /// Returns the C `unsigned long` type.
+    pub const fn c_ulong() -> Self {
+        Self::unsigned(mem::size_of::<libc::c_ulong>())
+    }
+
+    /// Returns the C `float` (32-bit floating point) type.
+    pub const fn f32() -> Self {
+        StaticType(Repr::F32)
+    }
+
+    /// Returns the C `double` (64-bit floating point) type.
+    pub const fn f64() -> Self {
+        StaticType(Repr::F64)
+    }
+
+    /// Returns the C `void*` type, for passing any kind of pointer.
+    pub const fn pointer() -> Self {
+        StaticType(Repr::Pointer)
+    }
+
+    /// Wraps a pointer to an `ffi_type`, such as a struct type built
+    /// from `static` element arrays.
+    ///
+    /// # Safety
+    ///
+    /// `ty` must point to a valid `ffi_type` that is never freed. libffi
+    /// writes the size and alignment of struct types when a CIF using
+    /// them is prepared, so nothing else may access it meanwhile.
+    pub const unsafe fn from_raw(ty: *mut low::ffi_type) -> Self {
+        StaticType(Repr::Raw(ty))
+    }
+
+    const fn signed(size: usize) -> Self {
+        match size {
+            1 => Self::i8(),
+            2 => Self::i16(),
+            4 => Self::i32(),
+            _ => Self::i64(),
+        }
+    }
+
+    const fn unsigned(size: usize) -> Self {
+        match size {
+            1 => Self::u8(),
+            2 => Self::u16(),
+            4 => Self::u32(),
+            _ => Self::u64(),
+        }
+    }
+
+    /// Gets a raw pointer to the underlying `ffi_type`.
+    // Taking the address of a `static mut` only needs `unsafe` before
+    // Rust 1.82.
+    #[allow(unused_unsafe)]
+    pub fn as_raw_ptr(self) -> *mut low::ffi_type {
+        unsafe {
+            match self.0 {
+                Repr::Void => ptr::addr_of_mut!(low::types::void),
+                Repr::U8 => ptr::addr_of_mut!(low::types::uint8),
+                Repr::I8 => ptr::addr_of_mut!(low::types::sint8),
+                Repr::U16 => ptr::addr_of_mut!(low::types::uint16),
+                Repr::I16 => ptr::addr_of_mut!(low::types::sint16),
+                Repr::U32 => ptr::addr_of_mut!(low::types::uint32),
+                Repr::I32 => ptr::addr_of_mut!(low::types::sint32),
+                Repr::U64 => ptr::addr_of_mut!(low::types::uint64),
+                Repr::I64 => ptr::addr_of_mut!(low::types::sint64),
+                Repr::F32 => ptr::addr_of_mut!(low::types::float),
+                Repr::F64 => ptr::addr_of_mut!(low::types::double),
+                Repr::Pointer => ptr::addr_of_mut!(low::types::pointer),
+                Repr::Raw(ty) => ty,
+            }
+        }
+    }
+}
+
+const UNPREPARED: u8 = 0;
+const PREPARING: u8 = 1;
+const PREPARED: u8 = 2;
+
+/// A CIF that allocates nothing, for `static`s and small heaps.
+///
+/// A [`Cif`](struct.Cif.html) boxes its argument type array and the
+/// types in it. A `StaticCif` instead holds its `N` argument types and
+/// the `ffi_cif` itself inline, and can be constructed in a `const`
+/// context. It is prepared the first time it is used, once, even when
+/// first used from several threads at once.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// extern "C" fn add(x: i32, y: i32) -> i32 { x + y }
+///
+/// static ADD: StaticCif<2> =
+///     StaticCif::new([StaticType::i32(), StaticType::i32()], StaticType::i32());
+///
+/// let result: i32 = unsafe {
+///     ADD.call(CodePtr(add as *mut _), &[arg(&5i32), arg(&6i32)])
+/// };
+///
+/// assert_eq!(11, result);
+/// ```
+pub struct StaticCif<const N: usize> {
+    args: [StaticType; N],
+    result: StaticType,
+    abi: Abi,
+    arg_types: UnsafeCell<[*mut low::ffi_type; N]>,
+    cif: UnsafeCell<MaybeUninit<low::ffi_cif>>,
+    state: AtomicU8,
+}
+
+// The `ffi_cif` and argument type array are only written while `state`
+// is `PREPARING`, which one thread at a time can claim, and are only
+// read once it is `PREPARED`.
+unsafe impl<const N: usize> Send for StaticCif<N> {}
+unsafe impl<const N: usize> Sync for StaticCif<N> {}
+
+impl<const N: usize> core::fmt::Debug for StaticCif<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("StaticCif")
+            .field("args", &self.args)
+            .field("result", &self.result)
+            .field("abi", &self.abi)
+            .finish()
+    }
+}
+
+impl<const N: usize> StaticCif<N> {
+    /// Creates a CIF for the given argument and result types, using the
+    /// platform’s default calling convention.
+    pub const fn new(args: [StaticType; N], result: StaticType) -> Self {
+        Self::new_with_abi(args, result, Abi::Default)
+    }
+
+    /// Creates a CIF for the given argument and result types and
+    /// calling convention.
+    pub const fn new_with_abi(args: [StaticType; N], result: StaticType, abi: Abi) -> Self {
+        StaticCif {
+            args,
+            result,
+            abi,
+            arg_types: UnsafeCell::new([ptr::null_mut(); N]),
+            cif: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicU8::new(UNPREPARED),
+        }
+    }
+
+    /// Prepares the CIF, unless it already has been, reporting failure
+    /// from libffi.
+    ///
+    /// This happens on first use anyway, but calling it explicitly
+    /// reports an error instead of panicking. After a failure, the next
+    /// use tries again.
+    pub fn prep(&self) -> low::Result<()> {
+        loop {
+            match self.state.compare_exchange_weak(
+                UNPREPARED,
+                PREPARING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(PREPARED) => return Ok(()),
+                Err(_) => hint::spin_loop(),
+            }
+        }
+
+        let result = unsafe {
+            let arg_types = &mut *self.arg_types.get();
+            for (slot, arg) in arg_types.iter_mut().zip(&self.args) {
+                *slot = arg.as_raw_ptr();
+            }
+
+            let cif = (*self.cif.get()).as_mut_ptr();
+            cif.write(Default::default());
+            low::prep_cif(
+                cif,
+                self.abi.as_raw(),
+                N,
+                self.result.as_raw_ptr(),
+                arg_types.as_mut_ptr(),
+            )
+        };
+
+        let state = if result.is_ok() { PREPARED } else { UNPREPARED };
+        self.state.store(state, Ordering::Release);
+        result
+    }
+
+    /// Calls a function with the given arguments, preparing the CIF
+    /// first if need be.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`](struct.Cif.html#method.call).
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects the types or calling convention, or if
+    /// `args` does not have `N` elements.
+    pub unsafe fn call<R: RetSafe>(&self, fun: CodePtr, args: &[Arg]) -> R {
+        assert_eq!(
+            N,
+            args.len(),
+            "StaticCif::call: passed wrong number of arguments"
+        );
+        low::call::<R>(self.as_raw_ptr(), fun, args.as_ptr() as *mut *mut c_void)
+    }
+
+    /// Gets a raw pointer to the underlying
+    /// [`ffi_cif`](../low/struct.ffi_cif.html), preparing it first if
+    /// need be.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects the types or calling convention.
+    pub fn as_raw_ptr(&self) -> *mut low::ffi_cif {
+        self.prep().expect("low::prep_cif");
+        self.cif.get() as *mut low::ffi_cif
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::arg;
+
+    extern "C" fn scale(x: f64, factor: u32) -> f64 {
+        x * factor as f64
+    }
+
+    static SCALE: StaticCif<2> =
+        StaticCif::new([StaticType::f64(), StaticType::u32()], StaticType::f64());
+
+    #[test]
+    fn call() {
+        let result: f64 =
+            unsafe { SCALE.call(CodePtr(scale as *mut _), &[arg(&1.5f64), arg(&4u32)]) };
+        assert_eq!(6.0, result);
+    }
+
+    #[test]
+    fn prepared_once_across_threads() {
+        static CIF: StaticCif<2> =
+            StaticCif::new([StaticType::f64(), StaticType::u32()], StaticType::f64());
+
+        let threads: Vec<_> = (0..8u32)
+            .map(|i| {
+                std::thread::spawn(move || unsafe {
+                    CIF.call::<f64>(CodePtr(scale as *mut _), &[arg(&2.0f64), arg(&i)])
+                })
+            })
+            .collect();
+
+        for (i, thread) in threads.into_iter().enumerate() {
+            assert_eq!(2.0 * i as f64, thread.join().unwrap());
+        }
+    }
+
+    #[test]
+    #[allow(unused_unsafe)]
+    fn struct_type() {
+        #[repr(C)]
+        struct Pair {
+            a: u8,
+            b: u64,
+        }
+
+        extern "C" fn sum(pair: Pair) -> u64 {
+            pair.a as u64 + pair.b
+        }
+
+        static mut ELEMENTS: [*mut low::ffi_type; 3] = [
+            unsafe { ptr::addr_of_mut!(low::types::uint8) },
+            unsafe { ptr::addr_of_mut!(low::types::uint64) },
+            ptr::null_mut(),
+        ];
+        static mut PAIR: low::ffi_type = low::ffi_type {
+            size: 0,
+            alignment: 0,
+            type_: crate::raw::FFI_TYPE_STRUCT as u16,
+            elements: unsafe { ptr::addr_of_mut!(ELEMENTS) as *mut *mut low::ffi_type },
+        };
+        static SUM: StaticCif<1> = StaticCif::new(
+            [unsafe { StaticType::from_raw(ptr::addr_of_mut!(PAIR)) }],
+            StaticType::u64(),
+        );
+
+        let pair = Pair { a: 1, b: 2 };
+        let result: u64 = unsafe { SUM.call(CodePtr(sum as *mut _), &[Arg::new(&pair)]) };
+        assert_eq!(3, result);
+    }
+}
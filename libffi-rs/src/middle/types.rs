@@ -6,10 +6,13 @@
 //! libffi uses this to figure out how to set up a call to a function
 //! with those types.
 
+use alloc::string::String;
+use core::alloc::Layout;
+use core::fmt;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{self, AtomicUsize, Ordering};
 use libc;
-use std::fmt;
-use std::mem;
-use std::ptr;
 
 use crate::low;
 
@@ -58,6 +61,37 @@ pub struct Type(Unique<low::ffi_type>);
 /// when creating a [`Cif`](struct.Cif.html).
 pub struct TypeArray(Unique<*mut low::ffi_type>);
 
+// Primitive types point at libffi’s statics, which are never mutated
+// after initialization; struct types are reference counted, and laid
+// out when created so that libffi never writes to them. Either way the
+// pointee can safely be moved between and read from multiple threads.
+unsafe impl Send for Type {}
+unsafe impl Sync for Type {}
+unsafe impl Send for TypeArray {}
+unsafe impl Sync for TypeArray {}
+
+// The number of argument types a CIF keeps inline instead of in an
+// array of their own. Few signatures have more.
+const INLINE_ARGS: usize = 6;
+
+/// The argument types of a CIF, owned like a `TypeArray` but stored
+/// inline when there are few of them.
+///
+/// The inline array moves with the value, so the pointer from
+/// `as_raw_ptr` is only valid as long as the value stays put.
+#[derive(Debug)]
+pub(crate) enum ArgTypes {
+    Inline {
+        len: usize,
+        types: [Type_; INLINE_ARGS],
+    },
+    Heap(TypeArray),
+}
+
+// As for `TypeArray`.
+unsafe impl Send for ArgTypes {}
+unsafe impl Sync for ArgTypes {}
+
 impl fmt::Debug for Type {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         formatter.write_fmt(format_args!("Type({:?})", *self.0))
@@ -108,17 +142,47 @@ where
     new
 }
 
+// A struct type along with the count of `Type`s and `TypeArray`s that
+// refer to it, so that cloning a struct type, or a CIF or array
+// containing one, only bumps the count. The `ffi_type` comes first, so
+// a pointer to it is also a pointer to the whole.
+#[repr(C)]
+struct SharedStruct {
+    ty: low::ffi_type,
+    refs: AtomicUsize,
+}
+
 /// Creates a struct type from a raw array of element types.
 unsafe fn ffi_type_struct_create_raw(elements: Owned<TypeArray_>) -> Owned<Type_> {
-    let new = libc::malloc(mem::size_of::<low::ffi_type>()) as Type_;
+    let new = libc::malloc(mem::size_of::<SharedStruct>()) as *mut SharedStruct;
     assert!(!new.is_null(), "ffi_type_struct_create_raw: out of memory");
 
-    (*new).size = 0;
-    (*new).alignment = 0;
-    (*new).type_ = low::type_tag::STRUCT;
-    (*new).elements = elements;
+    let mut ty = low::ffi_type {
+        size: 0,
+        alignment: 0,
+        type_: low::type_tag::STRUCT,
+        elements,
+    };
 
-    new
+    // libffi lays out a struct type when preparing a CIF that uses it,
+    // unless its size is already set. Since clones share the struct,
+    // lay it out now so that CIFs prepared on different threads do not
+    // write to it. An empty struct stays unset for libffi to reject.
+    let (size, align) = ffi_type_layout(&ty);
+    if size > 0 {
+        ty.size = size;
+        ty.alignment = align as u16;
+    }
+
+    ptr::write(
+        new,
+        SharedStruct {
+            ty,
+            refs: AtomicUsize::new(1),
+        },
+    );
+
+    new as Type_
 }
 
 /// Creates a struct `ffi_type` with the given elements. Takes ownership
@@ -142,13 +206,16 @@ unsafe fn ffi_type_array_clone(old: TypeArray_) -> Owned<TypeArray_> {
     new
 }
 
-/// Makes a copy of a type.
+/// Makes a copy of a type, which for a struct type is another
+/// reference to it.
 unsafe fn ffi_type_clone(old: Type_) -> Owned<Type_> {
     if (*old).type_ == low::type_tag::STRUCT {
-        ffi_type_struct_create_raw(ffi_type_array_clone((*old).elements))
-    } else {
-        old
+        (*(old as *mut SharedStruct))
+            .refs
+            .fetch_add(1, Ordering::Relaxed);
     }
+
+    old
 }
 
 /// Destroys a `TypeArray_` and all of its elements.
@@ -162,14 +229,101 @@ unsafe fn ffi_type_array_destroy(victim: Owned<TypeArray_>) {
     libc::free(victim as *mut libc::c_void);
 }
 
-/// Destroys a `Type_` if it was dynamically allocated.
+/// Releases a reference to a `Type_`, destroying it if it was
+/// dynamically allocated and this was the last reference.
 unsafe fn ffi_type_destroy(victim: Owned<Type_>) {
     if (*victim).type_ == low::type_tag::STRUCT {
+        let shared = victim as *mut SharedStruct;
+        if (*shared).refs.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+
+        atomic::fence(Ordering::Acquire);
         ffi_type_array_destroy((*victim).elements);
         libc::free(victim as *mut libc::c_void);
     }
 }
 
+pub(super) fn align_up(offset: usize, align: usize) -> usize {
+    (offset + align - 1) & !(align - 1)
+}
+
+// Computes the C layout of a type as a (size, alignment) pair. libffi
+// only fills in the size and alignment of struct types when a CIF using
+// them is prepared, so structs are laid out here instead. libffi gives
+// `void` a size of 1, but it has no value, so it is treated as empty.
+pub(super) unsafe fn ffi_type_layout(ty: *const low::ffi_type) -> (usize, usize) {
+    if u32::from((*ty).type_) == crate::raw::FFI_TYPE_VOID {
+        return (0, 1);
+    }
+
+    if (*ty).type_ != low::type_tag::STRUCT {
+        return ((*ty).size, usize::from((*ty).alignment));
+    }
+
+    let mut size = 0;
+    let mut struct_align = 1;
+    let mut element = (*ty).elements;
+    while !(*element).is_null() {
+        let (field_size, field_align) = ffi_type_layout(*element);
+        size = align_up(size, field_align) + field_size;
+        struct_align = struct_align.max(field_align);
+        element = element.offset(1);
+    }
+
+    (align_up(size, struct_align), struct_align)
+}
+
+/// Writes a short description of a type, such as `u64` or
+/// `struct { u16, u64 }`, for diagnostics.
+pub(crate) unsafe fn ffi_type_describe(
+    ty: *const low::ffi_type,
+    out: &mut dyn fmt::Write,
+) -> fmt::Result {
+    use crate::raw::*;
+
+    let name = match u32::from((*ty).type_) {
+        FFI_TYPE_VOID => "void",
+        FFI_TYPE_INT => "int",
+        FFI_TYPE_FLOAT => "f32",
+        FFI_TYPE_DOUBLE => "f64",
+        FFI_TYPE_LONGDOUBLE => "longdouble",
+        FFI_TYPE_UINT8 => "u8",
+        FFI_TYPE_SINT8 => "i8",
+        FFI_TYPE_UINT16 => "u16",
+        FFI_TYPE_SINT16 => "i16",
+        FFI_TYPE_UINT32 => "u32",
+        FFI_TYPE_SINT32 => "i32",
+        FFI_TYPE_UINT64 => "u64",
+        FFI_TYPE_SINT64 => "i64",
+        FFI_TYPE_POINTER => "pointer",
+        FFI_TYPE_COMPLEX => "complex",
+        FFI_TYPE_STRUCT => {
+            out.write_str("struct {")?;
+            let mut element = (*ty).elements;
+            while !(*element).is_null() {
+                out.write_str(if element == (*ty).elements { " " } else { ", " })?;
+                ffi_type_describe(*element, out)?;
+                element = element.offset(1);
+            }
+            return out.write_str(" }");
+        }
+        _ => "unknown",
+    };
+
+    out.write_str(name)
+}
+
+impl Drop for ArgTypes {
+    fn drop(&mut self) {
+        if let ArgTypes::Inline { len, types } = self {
+            for ty in &types[..*len] {
+                unsafe { ffi_type_destroy(*ty) }
+            }
+        }
+    }
+}
+
 impl Drop for Type {
     fn drop(&mut self) {
         unsafe { ffi_type_destroy(*self.0) }
@@ -194,6 +348,24 @@ impl Clone for TypeArray {
     }
 }
 
+impl Clone for ArgTypes {
+    fn clone(&self) -> Self {
+        match self {
+            ArgTypes::Inline { len, types } => {
+                let mut copy = [ptr::null_mut(); INLINE_ARGS];
+                for (slot, ty) in copy.iter_mut().zip(&types[..*len]) {
+                    *slot = unsafe { ffi_type_clone(*ty) };
+                }
+                ArgTypes::Inline {
+                    len: *len,
+                    types: copy,
+                }
+            }
+            ArgTypes::Heap(array) => ArgTypes::Heap(array.clone()),
+        }
+    }
+}
+
 macro_rules! match_size_signed {
     ( $name:ident ) => {
         match mem::size_of::<libc::$name>() {
@@ -218,6 +390,19 @@ macro_rules! match_size_unsigned {
     };
 }
 
+// For C types whose signedness varies between platforms. A type is
+// unsigned if all ones is positive; `MIN` would need Rust 1.43 for an
+// alias such as `libc::c_char`.
+macro_rules! match_size {
+    ( $name:ident ) => {
+        if !(0 as libc::$name) > 0 {
+            match_size_unsigned!($name)
+        } else {
+            match_size_signed!($name)
+        }
+    };
+}
+
 impl Type {
     /// Returns the representation of the C `void` type.
     ///
@@ -353,6 +538,48 @@ impl Type {
         match_size_unsigned!(c_ulonglong)
     }
 
+    /// Returns the C `char` type, which is signed or unsigned depending
+    /// on the platform.
+    pub fn c_char() -> Self {
+        match_size!(c_char)
+    }
+
+    /// Returns the C `_Bool` type.
+    pub fn c_bool() -> Self {
+        Self::u8()
+    }
+
+    /// Returns the C `size_t` type.
+    pub fn size_t() -> Self {
+        Self::usize()
+    }
+
+    /// Returns the C `ssize_t` type.
+    pub fn ssize_t() -> Self {
+        Self::isize()
+    }
+
+    /// Returns the C `ptrdiff_t` type.
+    pub fn ptrdiff_t() -> Self {
+        Self::isize()
+    }
+
+    /// Returns the C `intptr_t` type.
+    pub fn intptr_t() -> Self {
+        Self::isize()
+    }
+
+    /// Returns the C `uintptr_t` type.
+    pub fn uintptr_t() -> Self {
+        Self::usize()
+    }
+
+    /// Returns the C `wchar_t` type, whose size and signedness depend on
+    /// the platform.
+    pub fn wchar_t() -> Self {
+        match_size!(wchar_t)
+    }
+
     /// Returns the C `float` (32-bit floating point) type.
     pub fn f32() -> Self {
         Type(unsafe { Unique::new(&mut low::types::float) })
@@ -374,26 +601,40 @@ impl Type {
         Type(unsafe { Unique::new(&mut low::types::longdouble) })
     }
 
+    /// Returns the IEEE quadruple-precision (binary128) floating point
+    /// type, which is C `long double` on this target.
+    ///
+    /// This item is only available on targets where `long double` is
+    /// binary128, such as AArch64 Linux, RISC-V, and s390x, but not
+    /// x86-64, where it is the x87 80-bit format.
+    #[cfg(libffi_f128)]
+    pub fn f128() -> Self {
+        Type::longdouble()
+    }
+
     /// Returns the C `_Complex float` type.
     ///
-    /// This item is enabled by `#[cfg(feature = "complex")]`.
-    #[cfg(feature = "complex")]
+    /// This item is enabled by `#[cfg(feature = "complex")]` on targets
+    /// where libffi has complex types.
+    #[cfg(all(feature = "complex", libffi_complex))]
     pub fn c32() -> Self {
         Type(unsafe { Unique::new(&mut low::types::complex_float) })
     }
 
     /// Returns the C `_Complex double` type.
     ///
-    /// This item is enabled by `#[cfg(feature = "complex")]`.
-    #[cfg(feature = "complex")]
+    /// This item is enabled by `#[cfg(feature = "complex")]` on targets
+    /// where libffi has complex types.
+    #[cfg(all(feature = "complex", libffi_complex))]
     pub fn c64() -> Self {
         Type(unsafe { Unique::new(&mut low::types::complex_double) })
     }
 
     /// Returns the C `_Complex long double` type.
     ///
-    /// This item is enabled by `#[cfg(feature = "complex")]`.
-    #[cfg(feature = "complex")]
+    /// This item is enabled by `#[cfg(feature = "complex")]` on targets
+    /// where libffi has complex types.
+    #[cfg(all(feature = "complex", libffi_complex))]
     #[cfg(not(all(target_arch = "arm")))]
     pub fn complex_longdouble() -> Self {
         Type(unsafe { Unique::new(&mut low::types::complex_longdouble) })
@@ -408,6 +649,41 @@ impl Type {
         Type(unsafe { Unique::new(ffi_type_struct_create(fields.into_iter())) })
     }
 
+    /// The size and alignment of the type, following the C layout
+    /// rules.
+    ///
+    /// This is available before the type is used in a CIF, so it can be
+    /// used to size buffers for values whose type is only known at run
+    /// time, such as struct results for
+    /// [`Cif::call_with_ret_buffer`](struct.Cif.html#method.call_with_ret_buffer).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::Type;
+    ///
+    /// let layout = Type::structure(vec![Type::u8(), Type::u32()]).layout();
+    /// assert_eq!((8, 4), (layout.size(), layout.align()));
+    /// ```
+    pub fn layout(&self) -> Layout {
+        let (size, align) = unsafe { ffi_type_layout(*self.0) };
+        Layout::from_size_align(size, align.max(1)).expect("Type::layout")
+    }
+
+    // Makes another reference to a type that some `Type` owns, such as
+    // one of a CIF's argument types.
+    #[cfg(feature = "closures")]
+    pub(super) unsafe fn clone_raw(ty: *mut low::ffi_type) -> Self {
+        Type(Unique::new(ffi_type_clone(ty)))
+    }
+
+    /// Describes the type for diagnostics, *e.g.,* `struct { u16, u64 }`.
+    pub(crate) fn describe(&self) -> String {
+        let mut result = String::new();
+        unsafe { ffi_type_describe(*self.0, &mut result) }.unwrap();
+        result
+    }
+
     /// Gets a raw pointer to the underlying
     /// [`ffi_type`](../raw/struct._ffi_type.html).
     ///
@@ -442,6 +718,37 @@ impl TypeArray {
     }
 }
 
+impl ArgTypes {
+    /// Takes ownership of the given types.
+    pub fn new<I>(elements: I) -> Self
+    where
+        I: ExactSizeIterator<Item = Type>,
+    {
+        if elements.len() > INLINE_ARGS {
+            return ArgTypes::Heap(TypeArray::new(elements));
+        }
+
+        let mut types = [ptr::null_mut(); INLINE_ARGS];
+        let mut len = 0;
+        for (slot, element) in types.iter_mut().zip(elements) {
+            *slot = *element.0;
+            mem::forget(element);
+            len += 1;
+        }
+
+        ArgTypes::Inline { len, types }
+    }
+
+    /// Gets a raw pointer to the array of types, which is not
+    /// null-terminated when inline.
+    pub fn as_raw_ptr(&self) -> TypeArray_ {
+        match self {
+            ArgTypes::Inline { types, .. } => types.as_ptr() as TypeArray_,
+            ArgTypes::Heap(array) => array.as_raw_ptr(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -467,4 +774,64 @@ mod test {
             .clone()
             .clone();
     }
+
+    #[test]
+    fn clone_struct_shares() {
+        let inner = Type::structure(vec![Type::u8(), Type::u16()]);
+        let outer = Type::structure(vec![inner.clone(), inner.clone()]);
+        let copy = outer.clone();
+        assert_eq!(outer.as_raw_ptr(), copy.as_raw_ptr());
+
+        drop(outer);
+        drop(inner);
+        assert_eq!(
+            "struct { struct { u8, u16 }, struct { u8, u16 } }",
+            copy.describe()
+        );
+        assert_eq!(8, unsafe { (*copy.as_raw_ptr()).size });
+    }
+
+    #[test]
+    fn arg_types_inline_and_heap() {
+        for &n in &[0, INLINE_ARGS, INLINE_ARGS + 1] {
+            let args = ArgTypes::new((0..n).map(|_| Type::u32()));
+            // `matches!` needs Rust 1.42.
+            #[allow(clippy::match_like_matches_macro)]
+            let inline = if let ArgTypes::Inline { .. } = args {
+                true
+            } else {
+                false
+            };
+            assert_eq!(n <= INLINE_ARGS, inline);
+
+            let copy = args.clone();
+            for i in 0..n {
+                let ty = unsafe { *copy.as_raw_ptr().add(i) };
+                assert_eq!(Type::u32().as_raw_ptr(), ty);
+            }
+        }
+    }
+
+    #[test]
+    fn platform_types() {
+        let char_type = if !(0 as libc::c_char) > 0 {
+            Type::u8()
+        } else {
+            Type::i8()
+        };
+        assert_eq!(char_type.as_raw_ptr(), Type::c_char().as_raw_ptr());
+
+        let wchar_layout = Type::wchar_t().layout();
+        assert_eq!(mem::size_of::<libc::wchar_t>(), wchar_layout.size());
+        assert_eq!(mem::size_of::<usize>(), Type::size_t().layout().size());
+    }
+
+    #[test]
+    fn layout_nested_struct() {
+        let inner = Type::structure(vec![Type::u8(), Type::u16()]);
+        let outer = Type::structure(vec![Type::u8(), inner, Type::u64()]);
+        let layout = outer.layout();
+        assert_eq!((16, 8), (layout.size(), layout.align()));
+        assert_eq!(0, Type::void().layout().size());
+    }
 }
@@ -1,5 +1,14 @@
-use std::marker::PhantomData;
-use std::ops::Deref;
+#[cfg(any(feature = "debug-closures", all(feature = "closures", feature = "log")))]
+use alloc::boxed::Box;
+#[cfg(feature = "closures")]
+use core::ffi::c_void;
+use core::marker::PhantomData;
+#[cfg(feature = "closures")]
+use core::mem;
+use core::ops::Deref;
+
+#[cfg(feature = "closures")]
+use crate::low;
 
 pub struct Unique<T> {
     contents: *mut T,
@@ -21,3 +30,173 @@ impl<T> Unique<T> {
         }
     }
 }
+
+/// Owns whatever [`prep_closure`] interposes between a closure’s
+/// trampoline and its callback, so it must live as long as the closure.
+#[cfg(feature = "closures")]
+#[derive(Debug)]
+pub struct Shim {
+    #[cfg(feature = "log")]
+    _logging: Box<logging::Target>,
+}
+
+/// Initializes a closure to pass `userdata` to `callback`, interposing
+/// the shims enabled by Cargo features.
+///
+/// With the `log` feature, every invocation is logged at trace level
+/// before the callback is called.
+#[cfg(feature = "closures")]
+pub unsafe fn prep_closure(
+    alloc: *mut low::ffi_closure,
+    cif: *mut low::ffi_cif,
+    callback: low::RawCallback,
+    userdata: *mut c_void,
+    code: low::CodePtr,
+) -> low::Result<Shim> {
+    #[cfg(feature = "log")]
+    {
+        let target = Box::new(logging::Target {
+            callback,
+            userdata,
+            code,
+        });
+        low::prep_closure(
+            alloc,
+            cif,
+            mem::transmute::<low::RawCallback, low::Callback<logging::Target, c_void>>(
+                logging::logging_callback,
+            ),
+            &*target,
+            code,
+        )?;
+        Ok(Shim { _logging: target })
+    }
+
+    #[cfg(not(feature = "log"))]
+    {
+        low::prep_closure(
+            alloc,
+            cif,
+            mem::transmute::<low::RawCallback, low::Callback<c_void, c_void>>(callback),
+            userdata,
+            code,
+        )?;
+        Ok(Shim {})
+    }
+}
+
+/// Records a newly prepared closure with the diagnostics enabled by
+/// Cargo features.
+#[cfg(feature = "closures")]
+#[allow(unused_variables)]
+pub fn closure_created<U>(code: low::CodePtr, cif: &super::Cif) {
+    #[cfg(feature = "closure-registry")]
+    super::registry::register::<U>(code, cif);
+
+    #[cfg(all(feature = "perf-map", unix))]
+    super::perf_map::record::<U>(code, cif);
+}
+
+/// Releases a closure allocated with `low::closure_alloc`.
+///
+/// With the `debug-closures` feature the allocation is never freed;
+/// instead its trampoline is re-prepared to abort with a message, so
+/// that C code calling a callback after the Rust side dropped it fails
+/// loudly rather than jumping into freed memory.
+#[cfg(feature = "closures")]
+pub unsafe fn release_closure(alloc: *mut low::ffi_closure, code: low::CodePtr) {
+    #[cfg(feature = "closure-registry")]
+    super::registry::unregister(code);
+
+    #[cfg(feature = "debug-closures")]
+    poison::poison_closure(alloc, code);
+
+    #[cfg(not(feature = "debug-closures"))]
+    {
+        let _ = code;
+        low::closure_free(alloc);
+    }
+}
+
+#[cfg(feature = "debug-closures")]
+mod poison {
+    use core::ffi::c_void;
+    use core::ptr;
+    use std::io::{self, Write};
+    use std::process;
+    use std::sync::Once;
+
+    use crate::low;
+    use crate::middle::{Cif, Type};
+
+    static INIT: Once = Once::new();
+    static mut POISON_CIF: *mut Cif = ptr::null_mut();
+
+    unsafe extern "C" fn poisoned_callback(
+        _cif: &low::ffi_cif,
+        _result: &mut c_void,
+        _args: *const *const c_void,
+        _userdata: &c_void,
+    ) {
+        let _ = io::stderr().write_all(b"libffi: called a closure after it was dropped\n");
+        process::abort();
+    }
+
+    // Any signature works for the poisoned trampoline, since the callback
+    // neither reads its arguments nor returns.
+    fn poison_cif() -> *mut low::ffi_cif {
+        unsafe {
+            INIT.call_once(|| {
+                POISON_CIF = Box::into_raw(Box::new(Cif::new(vec![], Type::void())));
+            });
+            (*POISON_CIF).as_raw_ptr()
+        }
+    }
+
+    pub unsafe fn poison_closure(alloc: *mut low::ffi_closure, code: low::CodePtr) {
+        low::prep_closure(alloc, poison_cif(), poisoned_callback, ptr::null(), code)
+            .expect("poison_closure");
+    }
+}
+
+#[cfg(all(feature = "closures", feature = "log"))]
+mod logging {
+    use core::ffi::c_void;
+
+    use crate::low;
+
+    #[derive(Debug)]
+    pub struct Target {
+        pub callback: low::RawCallback,
+        pub userdata: *mut c_void,
+        pub code: low::CodePtr,
+    }
+
+    pub unsafe extern "C" fn logging_callback(
+        cif: *mut low::ffi_cif,
+        result: *mut c_void,
+        args: *mut *mut c_void,
+        target: *mut c_void,
+    ) {
+        let target = &*(target as *const Target);
+
+        #[cfg(feature = "std")]
+        log::trace!(
+            "closure {:?} called with {} argument(s), result type tag {}, on thread {:?}",
+            target.code.0,
+            (*cif).nargs,
+            (*(*cif).rtype).type_,
+            std::thread::current().id(),
+        );
+
+        #[cfg(not(feature = "std"))]
+        log::trace!(
+            "closure {:?} called with {} argument(s), result type tag {}",
+            target.code.0,
+            (*cif).nargs,
+            (*(*cif).rtype).type_,
+        );
+
+        (target.callback)(cif, result, args, target.userdata);
+    }
+}
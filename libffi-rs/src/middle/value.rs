@@ -0,0 +1,261 @@
+use alloc::{string::String, vec::Vec};
+use core::ffi::c_void;
+use libc::c_int;
+
+use super::types::{align_up, ffi_type_layout};
+use super::Type;
+use crate::low;
+use crate::raw::*;
+
+/// A C value whose type is only known at run time.
+///
+/// This is what closures with signatures that are only known at run
+/// time, such as those built by interpreters, receive their arguments
+/// as. Read one with [`Value::read_from`](#method.read_from) or
+/// [`ArgsReader::value`](struct.ArgsReader.html#method.value).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// No value, for type `void`.
+    Void,
+    /// A `u8`.
+    U8(u8),
+    /// An `i8`.
+    I8(i8),
+    /// A `u16`.
+    U16(u16),
+    /// An `i16`.
+    I16(i16),
+    /// A `u32`.
+    U32(u32),
+    /// An `i32`.
+    I32(i32),
+    /// A `u64`.
+    U64(u64),
+    /// An `i64`.
+    I64(i64),
+    /// An `f32`.
+    F32(f32),
+    /// An `f64`.
+    F64(f64),
+    /// A pointer.
+    Pointer(*mut c_void),
+    /// A struct, as the values of its fields.
+    Struct(Vec<Value>),
+}
+
+impl Value {
+    /// Reads a value of the given type from memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ty` is, or contains, a `longdouble` or complex type,
+    /// which have no `Value` representation.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a valid value of type `ty`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::os::raw::c_void;
+    /// use libffi::middle::{Type, Value};
+    ///
+    /// #[repr(C)]
+    /// struct Pair { tag: u8, value: f64 }
+    ///
+    /// let pair = Pair { tag: 3, value: 0.5 };
+    /// let ty = Type::structure(vec![Type::u8(), Type::f64()]);
+    /// let value = unsafe {
+    ///     Value::read_from(&pair as *const Pair as *const c_void, &ty)
+    /// };
+    ///
+    /// assert_eq!(Value::Struct(vec![Value::U8(3), Value::F64(0.5)]), value);
+    /// ```
+    pub unsafe fn read_from(ptr: *const c_void, ty: &Type) -> Self {
+        read_ffi_type(ptr, ty.as_raw_ptr())
+    }
+}
+
+pub(crate) unsafe fn read_ffi_type(ptr: *const c_void, ty: *const low::ffi_type) -> Value {
+    match u32::from((*ty).type_) {
+        FFI_TYPE_VOID => Value::Void,
+        FFI_TYPE_INT => Value::I32(*(ptr as *const c_int)),
+        FFI_TYPE_FLOAT => Value::F32(*(ptr as *const f32)),
+        FFI_TYPE_DOUBLE => Value::F64(*(ptr as *const f64)),
+        FFI_TYPE_UINT8 => Value::U8(*(ptr as *const u8)),
+        FFI_TYPE_SINT8 => Value::I8(*(ptr as *const i8)),
+        FFI_TYPE_UINT16 => Value::U16(*(ptr as *const u16)),
+        FFI_TYPE_SINT16 => Value::I16(*(ptr as *const i16)),
+        FFI_TYPE_UINT32 => Value::U32(*(ptr as *const u32)),
+        FFI_TYPE_SINT32 => Value::I32(*(ptr as *const i32)),
+        FFI_TYPE_UINT64 => Value::U64(*(ptr as *const u64)),
+        FFI_TYPE_SINT64 => Value::I64(*(ptr as *const i64)),
+        FFI_TYPE_POINTER => Value::Pointer(*(ptr as *const *mut c_void)),
+        FFI_TYPE_STRUCT => {
+            let mut fields = Vec::new();
+            let mut offset = 0;
+            let mut element = (*ty).elements;
+            while !(*element).is_null() {
+                let (size, align) = ffi_type_layout(*element);
+                offset = align_up(offset, align);
+                fields.push(read_ffi_type(
+                    (ptr as *const u8).add(offset) as *const c_void,
+                    *element,
+                ));
+                offset += size;
+                element = element.offset(1);
+            }
+            Value::Struct(fields)
+        }
+        _ => panic!("Value::read_from: unsupported type {}", describe(ty)),
+    }
+}
+
+// Writes a value of the given type, returning false without writing
+// anything further if the value does not match the type.
+pub(crate) unsafe fn write_ffi_type(
+    ptr: *mut c_void,
+    ty: *const low::ffi_type,
+    value: &Value,
+) -> bool {
+    match (u32::from((*ty).type_), value) {
+        (FFI_TYPE_VOID, Value::Void) => {}
+        (FFI_TYPE_INT, &Value::I32(v)) => *(ptr as *mut c_int) = v,
+        (FFI_TYPE_FLOAT, &Value::F32(v)) => *(ptr as *mut f32) = v,
+        (FFI_TYPE_DOUBLE, &Value::F64(v)) => *(ptr as *mut f64) = v,
+        (FFI_TYPE_UINT8, &Value::U8(v)) => *(ptr as *mut u8) = v,
+        (FFI_TYPE_SINT8, &Value::I8(v)) => *(ptr as *mut i8) = v,
+        (FFI_TYPE_UINT16, &Value::U16(v)) => *(ptr as *mut u16) = v,
+        (FFI_TYPE_SINT16, &Value::I16(v)) => *(ptr as *mut i16) = v,
+        (FFI_TYPE_UINT32, &Value::U32(v)) => *(ptr as *mut u32) = v,
+        (FFI_TYPE_SINT32, &Value::I32(v)) => *(ptr as *mut i32) = v,
+        (FFI_TYPE_UINT64, &Value::U64(v)) => *(ptr as *mut u64) = v,
+        (FFI_TYPE_SINT64, &Value::I64(v)) => *(ptr as *mut i64) = v,
+        (FFI_TYPE_POINTER, &Value::Pointer(v)) => *(ptr as *mut *mut c_void) = v,
+        (FFI_TYPE_STRUCT, Value::Struct(fields)) => {
+            let mut offset = 0;
+            let mut element = (*ty).elements;
+            for field in fields {
+                if (*element).is_null() {
+                    return false;
+                }
+                let (size, align) = ffi_type_layout(*element);
+                offset = align_up(offset, align);
+                let field_ptr = (ptr as *mut u8).add(offset) as *mut c_void;
+                if !write_ffi_type(field_ptr, *element, field) {
+                    return false;
+                }
+                offset += size;
+                element = element.offset(1);
+            }
+            return (*element).is_null();
+        }
+        _ => return false,
+    }
+    true
+}
+
+// Whether values of the given type have a `Value` representation.
+pub(crate) unsafe fn is_representable(ty: *const low::ffi_type) -> bool {
+    match u32::from((*ty).type_) {
+        FFI_TYPE_LONGDOUBLE | FFI_TYPE_COMPLEX => false,
+        FFI_TYPE_STRUCT => {
+            let mut element = (*ty).elements;
+            while !(*element).is_null() {
+                if !is_representable(*element) {
+                    return false;
+                }
+                element = element.offset(1);
+            }
+            true
+        }
+        _ => true,
+    }
+}
+
+// Reads a call result of the given type. libffi returns integers
+// smaller than `ffi_arg` as a whole word, so on big-endian targets
+// they are found at the end of the word.
+pub(crate) unsafe fn read_result(ptr: *const c_void, ty: *const low::ffi_type) -> Value {
+    let size = (*ty).size;
+    let word = core::mem::size_of::<ffi_arg>();
+    // `matches!` needs Rust 1.42.
+    #[allow(clippy::match_like_matches_macro)]
+    let integer = match u32::from((*ty).type_) {
+        FFI_TYPE_UINT8 | FFI_TYPE_SINT8 | FFI_TYPE_UINT16 | FFI_TYPE_SINT16 | FFI_TYPE_UINT32
+        | FFI_TYPE_SINT32 | FFI_TYPE_INT => true,
+        _ => false,
+    };
+
+    if cfg!(target_endian = "big") && integer && size < word {
+        read_ffi_type((ptr as *const u8).add(word - size) as *const c_void, ty)
+    } else {
+        read_ffi_type(ptr, ty)
+    }
+}
+
+unsafe fn describe(ty: *const low::ffi_type) -> String {
+    let mut result = String::new();
+    let _ = super::types::ffi_type_describe(ty, &mut result);
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[repr(C)]
+    struct Outer {
+        a: u8,
+        inner: Inner,
+        b: i16,
+    }
+
+    #[repr(C)]
+    struct Inner {
+        x: u32,
+        p: *mut c_void,
+    }
+
+    #[test]
+    fn read_nested_struct() {
+        let outer = Outer {
+            a: 1,
+            inner: Inner {
+                x: 2,
+                p: 0x10 as *mut c_void,
+            },
+            b: -3,
+        };
+        let inner = Type::structure(vec![Type::u32(), Type::pointer()]);
+        let ty = Type::structure(vec![Type::u8(), inner, Type::i16()]);
+
+        let value = unsafe { Value::read_from(&outer as *const Outer as *const c_void, &ty) };
+
+        assert_eq!(
+            Value::Struct(vec![
+                Value::U8(1),
+                Value::Struct(vec![Value::U32(2), Value::Pointer(0x10 as *mut c_void)]),
+                Value::I16(-3),
+            ]),
+            value
+        );
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let ty = Type::structure(vec![Type::u8(), Type::i64()]);
+        let value = Value::Struct(vec![Value::U8(9), Value::I64(-1)]);
+        let mut buffer = [0u64; 2];
+        let ptr = buffer.as_mut_ptr() as *mut c_void;
+
+        unsafe {
+            assert!(write_ffi_type(ptr, ty.as_raw_ptr(), &value));
+            assert_eq!(value, Value::read_from(ptr, &ty));
+
+            let short = Value::Struct(vec![Value::U8(9)]);
+            assert!(!write_ffi_type(ptr, ty.as_raw_ptr(), &short));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use core::any::Any;
+use core::ffi::c_void;
+
+use super::{ArgsReader, Cif, ClosureOnce, CodePtr};
+use crate::{low, raw};
+
+// What a wrapping closure calls.
+struct Wrapper<B, A> {
+    target: CodePtr,
+    before: B,
+    after: A,
+}
+
+unsafe extern "C" fn wrapper_callback<B, A>(
+    cif: &low::ffi_cif,
+    result: &mut c_void,
+    args: *const *const c_void,
+    wrapper: &mut Option<Wrapper<B, A>>,
+) where
+    B: FnMut(ArgsReader),
+    A: FnMut(ArgsReader, *mut c_void),
+{
+    // The userdata's type is private, so it cannot have been taken.
+    let wrapper = wrapper.as_mut().unwrap();
+
+    (wrapper.before)(ArgsReader::new(cif, args));
+    raw::ffi_call(
+        cif as *const _ as *mut _,
+        Some(*wrapper.target.as_safe_fun()),
+        result,
+        args as *mut *mut c_void,
+    );
+    (wrapper.after)(ArgsReader::new(cif, args), result);
+}
+
+/// Wraps a C function in a closure of the same signature that calls
+/// hooks before and after forwarding each call.
+///
+/// Calling the closure calls `before` with its arguments, then `target`
+/// with the same arguments, then `after` with the arguments and a
+/// pointer to the result, which `after` may read or overwrite before it
+/// is returned. This is useful for tracing and interposing on calls,
+/// and for observing calls in tests.
+///
+/// The result pointer is to a buffer that is at least an `ffi_arg`
+/// wide, holding integer results smaller than that widened, as a
+/// [`ResultWriter`](struct.ResultWriter.html) writes them.
+///
+/// # Safety
+///
+/// As for [`Cif::call`](struct.Cif.html#method.call), each time the
+/// closure is called: `cif` must match `target`. Additionally, neither
+/// `target` nor the hooks may call the closure re-entrantly, since the
+/// hooks are borrowed mutably for the duration of each call.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::atomic::{AtomicU32, Ordering};
+/// use std::sync::Arc;
+///
+/// use libffi::middle::*;
+///
+/// extern "C" fn add(x: u32, y: u32) -> u32 { x + y }
+///
+/// let calls = Arc::new(AtomicU32::new(0));
+/// let counter = calls.clone();
+///
+/// let cif = Cif::new(vec![Type::u32(), Type::u32()], Type::u32());
+/// let closure = unsafe {
+///     wrap_function(
+///         cif,
+///         CodePtr(add as *mut _),
+///         move |_args| {
+///             counter.fetch_add(1, Ordering::Relaxed);
+///         },
+///         |_args, _result| {},
+///     )
+/// };
+///
+/// let fun: &extern "C" fn(u32, u32) -> u32 = unsafe {
+///     closure.instantiate_code_ptr()
+/// };
+///
+/// assert_eq!(5, fun(2, 3));
+/// assert_eq!(9, fun(4, 5));
+/// assert_eq!(2, calls.load(Ordering::Relaxed));
+/// ```
+pub unsafe fn wrap_function<B, A>(cif: Cif, target: CodePtr, before: B, after: A) -> ClosureOnce
+where
+    B: FnMut(ArgsReader) + Any,
+    A: FnMut(ArgsReader, *mut c_void) + Any,
+{
+    ClosureOnce::new(
+        cif,
+        wrapper_callback::<B, A>,
+        Wrapper {
+            target,
+            before,
+            after,
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::Type;
+    use alloc::rc::Rc;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    extern "C" fn sub(x: i64, y: f64) -> f64 {
+        x as f64 - y
+    }
+
+    #[test]
+    fn hooks_see_arguments_and_result() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let (before_log, after_log) = (log.clone(), log.clone());
+
+        let cif = Cif::new(vec![Type::i64(), Type::f64()], Type::f64());
+        let closure = unsafe {
+            wrap_function(
+                cif,
+                CodePtr(sub as *mut _),
+                move |args| {
+                    let x: i64 = args.get(0);
+                    before_log.borrow_mut().push(x as f64);
+                },
+                move |args, result| {
+                    let y: f64 = args.get(1);
+                    let result = &mut *(result as *mut f64);
+                    after_log.borrow_mut().extend(&[y, *result]);
+                    // Mock the result.
+                    *result *= 10.0;
+                },
+            )
+        };
+
+        let fun: &extern "C" fn(i64, f64) -> f64 = unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(55.0, fun(7, 1.5));
+        assert_eq!(vec![7.0, 1.5, 5.5], *log.borrow());
+    }
+}
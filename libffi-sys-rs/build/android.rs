@@ -0,0 +1,135 @@
+use crate::common::*;
+
+// The API level to build for when none is given, the oldest that current
+// NDKs support.
+const DEFAULT_API_LEVEL: &str = "21";
+
+// Points the configure script at an Android NDK toolchain when building
+// for Android, so that it is not necessary to set `CC` and friends by
+// hand. A C compiler given through the variables the `cc` crate reads
+// (`CC_<target>`, `TARGET_CC`, `CC`) takes precedence over the NDK's.
+pub fn configure_toolchain(command: &mut Command, target: &str) {
+    for var in &[
+        "ANDROID_NDK_HOME",
+        "ANDROID_NDK_ROOT",
+        "ANDROID_NDK",
+        "NDK_HOME",
+        "ANDROID_PLATFORM",
+        "ANDROID_API_LEVEL",
+        "CARGO_NDK_ANDROID_PLATFORM",
+    ] {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+
+    if let Some(cc) = target_var("CC", target) {
+        command.env("CC", cc);
+        return;
+    }
+
+    let toolchain = match ndk_toolchain() {
+        Some(toolchain) => toolchain,
+        None => {
+            println!(
+                "cargo:warning=Android NDK not found; set ANDROID_NDK_HOME or CC_{}",
+                target
+            );
+            return;
+        }
+    };
+
+    let bin = toolchain.join("bin");
+    // On Windows hosts, the compiler wrappers are batch files.
+    let suffix = if cfg!(windows) { ".cmd" } else { "" };
+    let clang = bin.join(format!(
+        "{}{}-clang{}",
+        clang_target(target),
+        api_level(),
+        suffix
+    ));
+    let sysroot = toolchain.join("sysroot");
+
+    command
+        .env("CC", clang)
+        .env("AR", bin.join("llvm-ar"))
+        .env("RANLIB", bin.join("llvm-ranlib"))
+        .env("CFLAGS", format!("--sysroot={}", sysroot.display()))
+        .arg(format!("--with-sysroot={}", sysroot.display()));
+}
+
+// The configure host triple for an Android Rust target. config.sub does
+// not know Rust's `armv7` and `thumbv7neon` variants, but libffi only
+// needs to know that it is ARM.
+pub fn host_triple(target: &str) -> String {
+    match target.split('-').next() {
+        Some("armv7") | Some("thumbv7neon") => "arm-linux-androideabi".to_owned(),
+        _ => target.to_owned(),
+    }
+}
+
+// The triple prefixing the NDK's compiler wrappers, such as
+// `armv7a-linux-androideabi21-clang`.
+fn clang_target(target: &str) -> String {
+    match target.split('-').next() {
+        Some("armv7") | Some("thumbv7neon") => "armv7a-linux-androideabi".to_owned(),
+        _ => target.to_owned(),
+    }
+}
+
+// Reads the API level from `ANDROID_PLATFORM`, which cargo-ndk and the
+// NDK's CMake toolchain accept as either `21` or `android-21`.
+fn api_level() -> String {
+    [
+        "CARGO_NDK_ANDROID_PLATFORM",
+        "ANDROID_PLATFORM",
+        "ANDROID_API_LEVEL",
+    ]
+    .iter()
+    .find_map(|var| env::var(var).ok())
+    .map(|level| level.trim_start_matches("android-").to_owned())
+    .unwrap_or_else(|| DEFAULT_API_LEVEL.to_owned())
+}
+
+// Looks up a tool the way the `cc` crate does: `CC_aarch64-linux-android`,
+// then `CC_aarch64_linux_android`, `TARGET_CC`, and `CC`.
+fn target_var(tool: &str, target: &str) -> Option<String> {
+    let names = [
+        format!("{}_{}", tool, target),
+        format!("{}_{}", tool, target.replace('-', "_")),
+        format!("TARGET_{}", tool),
+        tool.to_owned(),
+    ];
+
+    names.iter().find_map(|name| {
+        println!("cargo:rerun-if-env-changed={}", name);
+        env::var(name).ok()
+    })
+}
+
+// Finds the LLVM toolchain of the NDK named by the usual environment
+// variables, which cargo-ndk and Android Studio set.
+fn ndk_toolchain() -> Option<PathBuf> {
+    let ndk = [
+        "ANDROID_NDK_HOME",
+        "ANDROID_NDK_ROOT",
+        "ANDROID_NDK",
+        "NDK_HOME",
+    ]
+    .iter()
+    .find_map(env::var_os)?;
+
+    let host_tag = match env::consts::OS {
+        "linux" => "linux-x86_64",
+        "macos" => "darwin-x86_64",
+        "windows" => "windows-x86_64",
+        _ => return None,
+    };
+
+    let toolchain = Path::new(&ndk)
+        .join("toolchains/llvm/prebuilt")
+        .join(host_tag);
+    if toolchain.is_dir() {
+        Some(toolchain)
+    } else {
+        None
+    }
+}
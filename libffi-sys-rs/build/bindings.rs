@@ -0,0 +1,32 @@
+use crate::common::*;
+
+// Generates bindings from the headers of the libffi being linked, for
+// `src/lib.rs` to include in place of the hand-maintained ones, which
+// can drift from the system headers.
+pub fn generate_bindings(include_dirs: &[String]) {
+    let mut builder = bindgen_crate::Builder::default()
+        .header_contents("wrapper.h", "#include <ffi.h>")
+        .allowlist_type("ffi_.*")
+        .allowlist_function("ffi_.*")
+        .allowlist_var("ffi_.*")
+        .derive_default(true)
+        .impl_debug(true)
+        .layout_tests(false)
+        .use_core();
+
+    // Without `std`, the C types come from `core::ffi`.
+    if !cfg!(feature = "std") {
+        builder = builder.ctypes_prefix("core::ffi");
+    }
+
+    for dir in include_dirs {
+        builder = builder.clang_arg(format!("-I{}", dir));
+    }
+
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("generated.rs");
+    builder
+        .generate()
+        .expect("Generating libffi bindings")
+        .write_to_file(out_path)
+        .expect("Writing libffi bindings");
+}
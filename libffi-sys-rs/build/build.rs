@@ -1,18 +1,67 @@
+mod android;
+#[cfg(feature = "bindgen")]
+mod bindings;
+mod cc_build;
 mod common;
-#[cfg(target_env = "msvc")]
 mod msvc;
-#[cfg(not(target_env = "msvc"))]
 mod not_msvc;
 
-#[cfg(target_env = "msvc")]
-use msvc::*;
-#[cfg(not(target_env = "msvc"))]
-use not_msvc::*;
+use std::env;
+use std::path::Path;
 
 fn main() {
+    println!("cargo:rerun-if-env-changed=DOCS_RS");
+    println!("cargo:rustc-check-cfg=cfg(libffi_docs_only)");
+    // Rust has no ARC targets yet, but `arch.rs` is ready for them.
+    println!("cargo:rustc-check-cfg=cfg(target_arch, values(\"arc\"))");
+    common::export_complex();
+
+    // On docs.rs, rustdoc only needs the bindings, so skip compiling and
+    // linking the C library, which is slow and can fail there.
+    if env::var_os("DOCS_RS").is_some() {
+        println!("cargo:rustc-cfg=libffi_docs_only");
+        common::export_version(&common::vendored_version());
+        return;
+    }
+
+    // libffi has no port for wasm32 outside Emscripten, or for Hexagon;
+    // skip the C build so that the crate's `compile_error!` explains that
+    // instead.
+    let target_arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    if (target_arch == "wasm32" && env::var("CARGO_CFG_TARGET_OS").unwrap() != "emscripten")
+        || target_arch == "hexagon"
+    {
+        common::export_version(&common::vendored_version());
+        return;
+    }
+
+    println!("cargo:rerun-if-env-changed=LIBFFI_PREBUILT_DIR");
+
     if cfg!(feature = "system") {
-        probe_and_link();
+        // Go by the target rather than by the host that this script was
+        // compiled for, so that cross builds look for the right library.
+        if common::target_is_msvc() {
+            msvc::probe_and_link();
+            common::export_version(&msvc::system_version());
+
+            #[cfg(feature = "bindgen")]
+            bindings::generate_bindings(&msvc::system_include_dirs());
+        } else {
+            not_msvc::probe_and_link();
+            common::export_version(&not_msvc::system_version());
+
+            #[cfg(feature = "bindgen")]
+            bindings::generate_bindings(&not_msvc::system_include_dirs());
+        }
+    } else if let Some(prefix) = env::var_os("LIBFFI_PREBUILT_DIR") {
+        common::export_version(&common::link_prebuilt(Path::new(&prefix)));
     } else {
-        build_and_link();
+        // MinGW targets take `not_msvc`'s `cc` build even from an MSVC host.
+        if common::target_is_msvc() {
+            msvc::build_and_link();
+        } else {
+            not_msvc::build_and_link();
+        }
+        common::export_version(&common::vendored_version());
     }
 }
@@ -0,0 +1,256 @@
+use crate::common::*;
+
+// Builds the vendored libffi with `cc`, as `msvc.rs` does, for the
+// Unix and MinGW targets listed in `supported`, so that neither autotools
+// nor, on Windows, an MSYS2 shell is needed. The headers that `configure`
+// would generate are written to `OUT_DIR`.
+
+const BUILD_FILES: &[&str] = &["prep_cif.c", "types.c", "raw_api.c", "java_raw_api.c"];
+
+// The allocator for closures' executable memory and the static
+// trampolines, left out without the `closures` feature.
+const BUILD_FILES_CLOSURES: &[&str] = &["closures.c", "tramp.c"];
+
+const BUILD_FILES_X86_64: &[&str] = &["x86/ffi64.c", "x86/unix64.S", "x86/ffiw64.c", "x86/win64.S"];
+
+// The GAS-syntax sources, unlike the `_intel` ones that MSVC assembles.
+const BUILD_FILES_X86_WIN64: &[&str] = &["x86/ffiw64.c", "x86/win64.S"];
+
+const BUILD_FILES_X86_WIN32: &[&str] = &["x86/ffi.c", "x86/sysv.S"];
+
+const BUILD_FILES_AARCH64: &[&str] = &["aarch64/ffi.c", "aarch64/sysv.S"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Os {
+    Linux,
+    Apple,
+    MinGw,
+}
+
+struct Target {
+    // The directory under `libffi/src` holding the port.
+    port: &'static str,
+    // The value of `@TARGET@` in `ffi.h.in`.
+    name: &'static str,
+    files: &'static [&'static str],
+    os: Os,
+    // The sizes of `size_t` and `long double`.
+    size_t: usize,
+    long_double: usize,
+}
+
+// The targets that this build supports, by their Rust target triple.
+// Apple targets include iOS, tvOS, watchOS, and Mac Catalyst, for whose
+// SDKs `cc` finds the compiler flags.
+fn supported(target: &str) -> Option<Target> {
+    let os = if target.ends_with("-linux-gnu") || target.ends_with("-linux-musl") {
+        Os::Linux
+    } else if target.contains("-apple-") {
+        Os::Apple
+    } else if target.ends_with("-windows-gnu") {
+        Os::MinGw
+    } else {
+        return None;
+    };
+
+    let arch = target.split('-').next().unwrap();
+    let (port, name, files, size_t, long_double) = match (arch, os) {
+        ("x86_64", Os::MinGw) => ("x86", "X86_WIN64", BUILD_FILES_X86_WIN64, 8, 16),
+        ("i686", Os::MinGw) => ("x86", "X86_WIN32", BUILD_FILES_X86_WIN32, 4, 12),
+        ("x86_64", _) => ("x86", "X86_64", BUILD_FILES_X86_64, 8, 16),
+        ("aarch64", Os::Apple) => ("aarch64", "AARCH64", BUILD_FILES_AARCH64, 8, 8),
+        // watchOS's ILP32 ABI, which libffi's AArch64 port supports.
+        ("arm64_32", Os::Apple) => ("aarch64", "AARCH64", BUILD_FILES_AARCH64, 4, 8),
+        ("aarch64", Os::Linux) => ("aarch64", "AARCH64", BUILD_FILES_AARCH64, 8, 16),
+        _ => return None,
+    };
+
+    Some(Target {
+        port,
+        name,
+        files,
+        os,
+        size_t,
+        long_double,
+    })
+}
+
+// Whether to build with `cc` rather than autotools: if
+// `LIBFFI_SYS_BUILD` is `cc` or `autotools`, as it says, and otherwise
+// when the target is supported and either `autoreconf` is not installed
+// or the target is an Apple platform other than macOS, which `configure`
+// cannot find the SDK for.
+pub fn use_cc_build() -> bool {
+    println!("cargo:rerun-if-env-changed=LIBFFI_SYS_BUILD");
+
+    let target = env::var("TARGET").unwrap();
+    match env::var("LIBFFI_SYS_BUILD").as_ref().map(String::as_str) {
+        Ok("cc") => {
+            assert!(
+                supported(&target).is_some(),
+                "LIBFFI_SYS_BUILD=cc does not support target {}",
+                target
+            );
+            true
+        }
+        Ok("autotools") => false,
+        _ => {
+            supported(&target).is_some()
+                && (is_apple_mobile(&target)
+                    || Command::new("autoreconf")
+                        .arg("--version")
+                        .output()
+                        .is_err())
+        }
+    }
+}
+
+fn is_apple_mobile(target: &str) -> bool {
+    target.contains("-apple-") && !target.ends_with("-apple-darwin")
+}
+
+pub fn build_and_link() {
+    let target_triple = env::var("TARGET").unwrap();
+    let target = supported(&target_triple).unwrap();
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    // Lay out the headers and library as `make install` would.
+    let root = out_dir.join("libffi-root");
+    let include_dir = root.join("include");
+
+    fs::create_dir_all(&include_dir).expect("Creating the libffi include directory");
+    write_ffi_h(&include_dir, &target);
+    fs::write(include_dir.join("fficonfig.h"), fficonfig_h(&target)).expect("Writing fficonfig.h");
+    fs::copy(
+        format!("libffi/src/{}/ffitarget.h", target.port),
+        include_dir.join("ffitarget.h"),
+    )
+    .expect("Copying ffitarget.h");
+
+    let mut build = cc::Build::new();
+    build
+        .out_dir(root.join("lib"))
+        .include(&include_dir)
+        .include("libffi/include")
+        .include(format!("libffi/src/{}", target.port))
+        .warnings(false);
+
+    let closure_files: &[&str] = if cfg!(feature = "closures") {
+        BUILD_FILES_CLOSURES
+    } else {
+        &[]
+    };
+
+    for file in BUILD_FILES.iter().chain(closure_files).chain(target.files) {
+        let path = format!("libffi/src/{}", file);
+        // Some files, such as `tramp.c`, only exist in newer versions.
+        if Path::new(&path).exists() {
+            build.file(path);
+        }
+    }
+
+    build.compile("ffi");
+    export_root(&root);
+}
+
+// Generates `ffi.h` from `ffi.h.in` as `configure` would.
+fn write_ffi_h(include_dir: &Path, target: &Target) {
+    let template = fs::read_to_string("libffi/include/ffi.h.in").expect("Reading ffi.h.in");
+    let header = template
+        .replace("@VERSION@", &vendored_version())
+        .replace("@TARGET@", target.name)
+        .replace(
+            "@HAVE_LONG_DOUBLE@",
+            if target.long_double > 8 { "1" } else { "0" },
+        )
+        .replace("@HAVE_LONG_DOUBLE_VARIANT@", "0")
+        .replace(
+            "@FFI_EXEC_TRAMPOLINE_TABLE@",
+            if target.os == Os::Apple && target.port == "aarch64" {
+                "1"
+            } else {
+                "0"
+            },
+        );
+    fs::write(include_dir.join("ffi.h"), header).expect("Writing ffi.h");
+}
+
+// Generates the `fficonfig.h` that `configure` would for the target.
+fn fficonfig_h(target: &Target) -> String {
+    let mut config = String::from(
+        "#define STDC_HEADERS 1\n\
+         #define HAVE_INTTYPES_H 1\n\
+         #define HAVE_MEMCPY 1\n\
+         #define HAVE_STDINT_H 1\n\
+         #define HAVE_STDLIB_H 1\n\
+         #define HAVE_STRING_H 1\n\
+         #define HAVE_UNISTD_H 1\n\
+         #define HAVE_AS_CFI_PSEUDO_OP 1\n\
+         #define SIZEOF_DOUBLE 8\n\
+         #define EH_FRAME_FLAGS \"a\"\n",
+    );
+
+    config.push_str(&format!("#define SIZEOF_SIZE_T {}\n", target.size_t));
+    config.push_str(&format!(
+        "#define SIZEOF_LONG_DOUBLE {}\n",
+        target.long_double
+    ));
+    if target.long_double > 8 {
+        config.push_str("#define HAVE_LONG_DOUBLE 1\n");
+    }
+
+    if target.port == "x86" {
+        config.push_str("#define HAVE_AS_X86_PCREL 1\n");
+    }
+
+    match target.os {
+        Os::Linux => {
+            config.push_str(
+                "#define HAVE_ALLOCA_H 1\n\
+                 #define HAVE_MMAP 1\n\
+                 #define HAVE_MMAP_ANON 1\n\
+                 #define HAVE_SYS_MMAN_H 1\n\
+                 #define HAVE_HIDDEN_VISIBILITY_ATTRIBUTE 1\n\
+                 #define HAVE_RO_EH_FRAME 1\n",
+            );
+            if target.port == "x86" {
+                config.push_str("#define HAVE_AS_X86_64_UNWIND_SECTION_TYPE 1\n");
+            }
+        }
+        Os::Apple => config.push_str(
+            "#define HAVE_MMAP 1\n\
+             #define HAVE_MMAP_ANON 1\n\
+             #define HAVE_SYS_MMAN_H 1\n\
+             #define HAVE_HIDDEN_VISIBILITY_ATTRIBUTE 1\n\
+             #define SYMBOL_UNDERSCORE 1\n\
+             #define FFI_MMAP_EXEC_WRIT 1\n",
+        ),
+        // PE has no hidden visibility, and closures use `VirtualAlloc`.
+        Os::MinGw => {
+            if target.size_t == 4 {
+                config.push_str("#define SYMBOL_UNDERSCORE 1\n");
+            }
+        }
+    }
+
+    config.push_str(
+        "\n#ifdef HAVE_HIDDEN_VISIBILITY_ATTRIBUTE\n\
+         #ifdef LIBFFI_ASM\n\
+         #ifdef __APPLE__\n\
+         #define FFI_HIDDEN(name) .private_extern name\n\
+         #else\n\
+         #define FFI_HIDDEN(name) .hidden name\n\
+         #endif\n\
+         #else\n\
+         #define FFI_HIDDEN __attribute__ ((visibility (\"hidden\")))\n\
+         #endif\n\
+         #else\n\
+         #ifdef LIBFFI_ASM\n\
+         #define FFI_HIDDEN(name)\n\
+         #else\n\
+         #define FFI_HIDDEN\n\
+         #endif\n\
+         #endif\n",
+    );
+
+    config
+}
@@ -7,3 +7,170 @@ pub use std::{
 pub fn run_command(which: &'static str, cmd: &mut Command) {
     assert!(cmd.status().expect(which).success(), "{}", which);
 }
+
+// Reads the version of the vendored libffi from its `configure.ac`.
+pub fn vendored_version() -> String {
+    let configure = fs::read_to_string("libffi/configure.ac").unwrap_or_default();
+    configure
+        .lines()
+        .find(|line| line.starts_with("AC_INIT("))
+        .and_then(|line| line.split(['[', ']'].as_ref()).nth(3))
+        .unwrap_or("unknown")
+        .to_owned()
+}
+
+// Reads the version of libffi from the banner of `ffi.h` in `include_dir`.
+pub fn header_version(include_dir: &Path) -> String {
+    fs::read_to_string(include_dir.join("ffi.h"))
+        .ok()
+        .and_then(|header| {
+            header
+                .lines()
+                .find_map(|line| {
+                    let mut words = line.split_whitespace();
+                    if words.next() == Some("libffi") {
+                        words.next()
+                    } else {
+                        None
+                    }
+                })
+                .map(str::to_owned)
+        })
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+// Links a prebuilt copy of the vendored libffi, installed under
+// `prefix`, instead of building it, and returns its version.
+pub fn link_prebuilt(prefix: &Path) -> String {
+    let version = header_version(&prefix.join("include"));
+    let vendored = vendored_version();
+    if vendored != "unknown" && version != vendored {
+        println!(
+            "cargo:warning=prebuilt libffi in {} is version {}, but the vendored \
+             bindings are for {}",
+            prefix.display(),
+            version,
+            vendored
+        );
+    }
+
+    // `cc` names the library built for MSVC `libffi.lib`.
+    let name = if target_is_msvc() { "libffi" } else { "ffi" };
+    println!("cargo:rustc-link-lib=static={}", name);
+    println!(
+        "cargo:rustc-link-search=native={}",
+        prefix.join("lib").display()
+    );
+    println!(
+        "cargo:rustc-link-search=native={}",
+        prefix.join("lib64").display()
+    );
+    export_root(prefix);
+    build_seh_guard(&[prefix.join("include")]);
+
+    version
+}
+
+// Whether the target, rather than the host the build script runs on, uses
+// the MSVC toolchain.
+pub fn target_is_msvc() -> bool {
+    env::var("CARGO_CFG_TARGET_ENV").unwrap_or_default() == "msvc"
+}
+
+// Tells the build scripts of dependents where the linked libffi is
+// installed, as `DEP_FFI_ROOT`, and where its headers are, as
+// `DEP_FFI_INCLUDE`, so that they can compile C code against its `ffi.h`.
+pub fn export_root(root: &Path) {
+    println!("cargo:root={}", root.display());
+    println!("cargo:include={}", root.join("include").display());
+}
+
+// Compiles `ffi_call_seh` for the `seh-guard` feature on MSVC targets,
+// against the `ffi.h` in `include_dirs` or else on the compiler's include
+// path. It is built as a library of its own, rather than as part of the
+// vendored libffi, so that it wraps whichever libffi the crate links.
+pub fn build_seh_guard(include_dirs: &[PathBuf]) {
+    if env::var_os("CARGO_FEATURE_SEH_GUARD").is_none() || !target_is_msvc() {
+        return;
+    }
+
+    println!("cargo:rerun-if-changed=src/seh_guard.c");
+    let mut build = cc::Build::new();
+    for dir in include_dirs {
+        build.include(dir);
+    }
+    build.file("src/seh_guard.c").compile("ffi_seh_guard");
+}
+
+// The libffi versions that introduced entry points that are only
+// declared when the linked libffi has them, as `libffi_X_Y` cfg flags.
+const VERSION_CFGS: &[(u32, u32)] = &[(3, 3), (3, 4)];
+
+// Makes the libffi version available as `LIBFFI_VERSION` to the crate
+// and as `DEP_FFI_VERSION` to the build scripts of dependents, and
+// enables the `libffi_X_Y` flag for each version it is at least.
+//
+// The flags are also passed on to dependents, so that libffi-rs need not
+// parse the version again: `DEP_FFI_VERSION_CFGS` lists all of them and
+// `DEP_FFI_ENABLED_VERSION_CFGS` those that are set, comma-separated.
+//
+// A version that cannot be parsed sets no flags, so that only the entry
+// points of the oldest supported libffi are declared.
+pub fn export_version(version: &str) {
+    println!("cargo:rustc-env=LIBFFI_VERSION={}", version);
+    println!("cargo:version={}", version);
+
+    let mut parts = version.split('.').map(|part| part.parse::<u32>());
+    let parsed = match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => Some((major, minor)),
+        _ => {
+            println!(
+                "cargo:warning=could not parse the libffi version `{}`; \
+                 assuming the oldest supported libffi",
+                version
+            );
+            None
+        }
+    };
+
+    let mut all = Vec::new();
+    let mut enabled = Vec::new();
+    for &(major, minor) in VERSION_CFGS {
+        let cfg = format!("libffi_{}_{}", major, minor);
+        println!("cargo:rustc-check-cfg=cfg({})", cfg);
+        if let Some(found) = parsed {
+            if found >= (major, minor) {
+                println!("cargo:rustc-cfg={}", cfg);
+                enabled.push(cfg.clone());
+            }
+        }
+        all.push(cfg);
+    }
+
+    println!("cargo:version_cfgs={}", all.join(","));
+    println!("cargo:enabled_version_cfgs={}", enabled.join(","));
+}
+
+// Enables the `libffi_complex` flag, and tells dependents as
+// `DEP_FFI_COMPLEX`, when libffi has complex types on the target. This
+// follows `FFI_TARGET_HAS_COMPLEX_TYPE` in libffi's `ffitarget.h`, which
+// MSVC builds for x86 and Windows builds for ARM leave undefined.
+pub fn export_complex() {
+    println!("cargo:rustc-check-cfg=cfg(libffi_complex)");
+
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    let target_env = env::var("CARGO_CFG_TARGET_ENV").unwrap_or_default();
+
+    let complex = match arch.as_str() {
+        "x86" | "x86_64" => target_env != "msvc",
+        "arm" | "aarch64" => os != "windows",
+        "mips" | "mips64" | "mips32r6" | "mips64r6" | "s390x" | "sparc" | "sparc64" => true,
+        _ => false,
+    };
+
+    if complex {
+        println!("cargo:rustc-cfg=libffi_complex");
+        println!("cargo:complex=1");
+    }
+}
@@ -1,16 +1,64 @@
 use crate::common::*;
 
-const INCLUDE_DIRS: &[&str] = &["libffi", "libffi/include", "include/msvc", "libffi/src/x86"];
+const INCLUDE_DIRS: &[&str] = &["libffi", "libffi/include", "include/msvc"];
 
-const BUILD_FILES: &[&str] = &[
-    "closures.c",
-    "prep_cif.c",
-    "raw_api.c",
-    "types.c",
-    "x86/ffi.c",
-];
+const BUILD_FILES: &[&str] = &["prep_cif.c", "raw_api.c", "types.c"];
 
-const BUILD_FILES_X64: &[&str] = &["x86/ffiw64.c"];
+const BUILD_FILES_X86: &[&str] = &["x86/ffi.c"];
+
+const BUILD_FILES_X64: &[&str] = &["x86/ffi.c", "x86/ffiw64.c"];
+
+const BUILD_FILES_AARCH64: &[&str] = &["aarch64/ffi.c"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Arch {
+    X86,
+    X64,
+    Aarch64,
+    // ARM64EC code uses the AArch64 calling convention, interoperating
+    // with x86-64 code through thunks that the linker provides.
+    Arm64Ec,
+}
+
+impl Arch {
+    fn from_target(target: &str) -> Arch {
+        if target.starts_with("arm64ec") {
+            Arch::Arm64Ec
+        } else if target.starts_with("aarch64") {
+            Arch::Aarch64
+        } else if target.contains("x86_64") {
+            Arch::X64
+        } else {
+            Arch::X86
+        }
+    }
+
+    // The directory under `libffi/src` holding the port.
+    fn port(self) -> &'static str {
+        match self {
+            Arch::X86 | Arch::X64 => "libffi/src/x86",
+            Arch::Aarch64 | Arch::Arm64Ec => "libffi/src/aarch64",
+        }
+    }
+
+    fn files(self) -> &'static [&'static str] {
+        match self {
+            Arch::X86 => BUILD_FILES_X86,
+            Arch::X64 => BUILD_FILES_X64,
+            Arch::Aarch64 | Arch::Arm64Ec => BUILD_FILES_AARCH64,
+        }
+    }
+
+    // The assembly source, in the syntax of MASM or armasm64, without its
+    // `.S` extension.
+    fn asm_file(self) -> &'static str {
+        match self {
+            Arch::X86 => "sysv_intel",
+            Arch::X64 => "win64_intel",
+            Arch::Aarch64 | Arch::Arm64Ec => "win64_armasm",
+        }
+    }
+}
 
 fn add_file(build: &mut cc::Build, file: &str) {
     build.file(format!("libffi/src/{}", file));
@@ -18,31 +66,57 @@ fn add_file(build: &mut cc::Build, file: &str) {
 
 pub fn build_and_link() {
     let target = env::var("TARGET").unwrap();
-    let is_x64 = target.contains("x86_64");
-    let asm_path = pre_process_asm(INCLUDE_DIRS, &target, is_x64);
+    let arch = Arch::from_target(&target);
+    let include_dirs = include_dirs(arch);
+    let asm_path = pre_process_asm(&include_dirs, &target, arch);
     let mut build = cc::Build::new();
 
-    for inc in INCLUDE_DIRS {
+    for inc in &include_dirs {
         build.include(inc);
     }
 
-    for file in BUILD_FILES {
+    for file in BUILD_FILES.iter().chain(arch.files()) {
         add_file(&mut build, file);
     }
 
-    if is_x64 {
-        for file in BUILD_FILES_X64 {
-            add_file(&mut build, file);
-        }
+    // MSVC defines `_M_ARM64EC` instead of `_M_ARM64` for ARM64EC, so
+    // tell libffi's sources that they are building for AArch64.
+    if arch == Arch::Arm64Ec {
+        build.define("__aarch64__", None);
+    }
+
+    if env::var_os("CARGO_FEATURE_CLOSURES").is_some() {
+        add_file(&mut build, "closures.c");
     }
 
+    // Lay out the headers and library as `make install` would.
+    let root = PathBuf::from(env::var("OUT_DIR").unwrap()).join("libffi-root");
+    let include_dir = root.join("include");
+    fs::create_dir_all(&include_dir).expect("Creating the libffi include directory");
+    fs::copy("include/msvc/ffi.h", include_dir.join("ffi.h")).expect("Copying ffi.h");
+    fs::copy(
+        format!("{}/ffitarget.h", arch.port()),
+        include_dir.join("ffitarget.h"),
+    )
+    .expect("Copying ffitarget.h");
+
     build
+        .out_dir(root.join("lib"))
         .file(asm_path)
         .define("WIN32", None)
         .define("_LIB", None)
         .define("FFI_BUILDING", None)
         .warnings(false)
         .compile("libffi");
+
+    export_root(&root);
+    build_seh_guard(&[include_dir]);
+}
+
+fn include_dirs(arch: Arch) -> Vec<&'static str> {
+    let mut dirs = INCLUDE_DIRS.to_vec();
+    dirs.push(arch.port());
+    dirs
 }
 
 pub fn probe_and_link() {
@@ -53,16 +127,54 @@ pub fn probe_and_link() {
     build_and_link();
 }
 
-pub fn pre_process_asm(include_dirs: &[&str], target: &str, is_x64: bool) -> String {
-    let file_name = if is_x64 { "win64_intel" } else { "sysv_intel" };
+#[cfg(feature = "bindgen")]
+pub fn system_include_dirs() -> Vec<String> {
+    // `probe_and_link` builds the vendored libffi.
+    let arch = Arch::from_target(&env::var("TARGET").unwrap());
+    include_dirs(arch)
+        .iter()
+        .map(|dir| dir.to_string())
+        .collect()
+}
+
+pub fn system_version() -> String {
+    // `probe_and_link` builds the vendored libffi.
+    vendored_version()
+}
 
-    let mut cmd = cc::windows_registry::find(&target, "cl.exe").expect("Could not locate cl.exe");
-    cmd.env("INCLUDE", include_dirs.join(";"));
+fn pre_process_asm(include_dirs: &[&str], target: &str, arch: Arch) -> String {
+    let file_name = arch.asm_file();
+
+    let cl = cc::windows_registry::find_tool(target, "cl.exe").expect("Could not locate cl.exe");
+    let mut cmd = cl.to_command();
+
+    // The AArch64 assembly includes `ksarm64.h` from the Windows SDK, so
+    // keep the SDK's include path after libffi's.
+    let mut include = include_dirs.join(";");
+    // `Tool::get_envs` needs a newer `cc` than this crate requires, and
+    // `Command::get_envs` needs Rust 1.57.
+    #[allow(deprecated)]
+    let sdk_include = cl
+        .env()
+        .iter()
+        .find(|(key, _)| key == "INCLUDE")
+        .map(|(_, value)| value.to_string_lossy().into_owned());
+    if let Some(sdk_include) = sdk_include {
+        include.push(';');
+        include.push_str(&sdk_include);
+    }
+    cmd.env("INCLUDE", include);
+
+    // Predefine `_M_ARM64EC`, which `ksarm64.h` needs to decorate the
+    // names of ARM64EC functions.
+    if arch == Arch::Arm64Ec {
+        cmd.arg("/arm64EC");
+    }
 
     cmd.arg("/EP");
-    cmd.arg(format!("libffi/src/x86/{}.S", file_name));
+    cmd.arg(format!("{}/{}.S", arch.port(), file_name));
 
-    let out_path = format!("libffi/src/x86/{}.asm", file_name);
+    let out_path = format!("{}/{}.asm", arch.port(), file_name);
     let asm_file = fs::File::create(&out_path).expect("Could not create output file");
 
     cmd.stdout(asm_file);
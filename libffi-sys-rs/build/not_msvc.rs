@@ -1,64 +1,313 @@
+use crate::android;
+use crate::cc_build;
 use crate::common::*;
 
 pub fn build_and_link() {
+    if cc_build::use_cc_build() {
+        return cc_build::build_and_link();
+    }
+
     let out_dir = env::var("OUT_DIR").unwrap();
     let build_dir = Path::new(&out_dir).join("libffi-build");
     let prefix = Path::new(&out_dir).join("libffi-root");
     let libdir = Path::new(&prefix).join("lib");
     let libdir64 = Path::new(&prefix).join("lib64");
 
-    // Copy LIBFFI_DIR into build_dir to avoid an unnecessary build
-    if let Err(e) = fs::remove_dir_all(&build_dir) {
-        assert_eq!(
-            e.kind(),
-            std::io::ErrorKind::NotFound,
-            "can't remove the build directory: {}",
-            e
-        );
+    // A git checkout of the submodule has no `configure`. It is then
+    // generated in a copy of the source tree in `OUT_DIR`, so that the
+    // source tree is left untouched and can be read-only.
+    let vendored_dir = env::current_dir().unwrap().join("libffi");
+    let generate = !vendored_dir.join("configure").exists();
+    let src_dir = if generate {
+        Path::new(&out_dir).join("libffi-src")
+    } else {
+        vendored_dir.clone()
+    };
+
+    // Configure out of tree, and again only when the target or the
+    // configuration changes, so that rebuilds are incremental.
+    let mut configure = configure_libffi(&prefix, &src_dir, &build_dir);
+    let key = configure_key(&configure);
+    let key_file = build_dir.join("configure-key");
+    if fs::read_to_string(&key_file).ok().as_ref() != Some(&key) {
+        if generate {
+            autogen(&vendored_dir, &src_dir);
+        }
+
+        let _ = fs::remove_dir_all(&build_dir);
+        fs::create_dir_all(&build_dir).expect("Creating the libffi build directory");
+        run_command("Configuring libffi", &mut configure);
+        fs::write(&key_file, key).expect("Writing the libffi configure key");
     }
-    run_command(
-        "Copying libffi into the build directory",
-        Command::new("cp").arg("-R").arg("libffi").arg(&build_dir),
-    );
 
-    // Generate configure, run configure, make, make install
-    autogen(&build_dir);
+    let mut make = make_cmd::make();
+    make.env_remove("DESTDIR")
+        .arg("install")
+        .current_dir(&build_dir);
+
+    // AIX's `ar` and `as` read the object mode from the environment.
+    if env::var("TARGET").unwrap().starts_with("powerpc64-ibm-aix") {
+        make.env("OBJECT_MODE", "64");
+    }
+
+    // Build in parallel: preferably by joining Cargo's jobserver, which
+    // keeps the total number of jobs within Cargo's limit, or else with
+    // as many jobs as Cargo allots this build script.
+    if let Some(makeflags) = env::var_os("CARGO_MAKEFLAGS") {
+        make.env("MAKEFLAGS", makeflags);
+    } else if let Ok(jobs) = env::var("NUM_JOBS") {
+        make.arg(format!("-j{}", jobs));
+    }
 
-    configure_libffi(prefix, &build_dir);
+    run_command("Building libffi", &mut make);
 
-    run_command(
-        "Building libffi",
-        make_cmd::make()
-            .env_remove("DESTDIR")
-            .arg("install")
-            .current_dir(&build_dir),
-    );
+    if !cfg!(feature = "closures") {
+        remove_closure_objects(&build_dir, &[&libdir, &libdir64]);
+    }
 
     // Cargo linking directives
     println!("cargo:rustc-link-lib=static=ffi");
     println!("cargo:rustc-link-search={}", libdir.display());
     println!("cargo:rustc-link-search={}", libdir64.display());
+    export_root(&prefix);
+}
+
+// Removes the allocator for closures' executable memory and the static
+// trampolines from the installed `libffi.a`. They cannot be left out of
+// the build instead, because libffi's recursive `make` discards
+// command-line overrides of its object lists.
+fn remove_closure_objects(build_dir: &Path, libdirs: &[&Path]) {
+    // Use the `ar` that `configure` chose, which may be a cross `ar`.
+    let makefile =
+        fs::read_to_string(build_dir.join("Makefile")).expect("Reading the libffi Makefile");
+    let ar = makefile
+        .lines()
+        .find(|line| line.starts_with("AR = "))
+        .map_or("ar", |line| line["AR = ".len()..].trim());
+    let mut ar = ar.split_whitespace();
+    let program = ar.next().unwrap_or("ar");
+
+    for libdir in libdirs {
+        let lib = libdir.join("libffi.a");
+        if lib.exists() {
+            let mut command = Command::new(program);
+            command
+                .args(ar.clone())
+                .arg("ds")
+                .arg(&lib)
+                .arg("closures.o")
+                .arg("tramp.o");
+            run_command("Removing closures from libffi", &mut command);
+        }
+    }
 }
 
 pub fn probe_and_link() {
-    println!("cargo:rustc-link-lib=dylib=ffi");
+    println!("cargo:rerun-if-env-changed=LIBFFI_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=LIBFFI_INCLUDE_DIR");
+    println!("cargo:rerun-if-env-changed=LIBFFI_STATIC");
+
+    if let Some(lib_dir) = env::var_os("LIBFFI_LIB_DIR") {
+        println!(
+            "cargo:rustc-link-search=native={}",
+            Path::new(&lib_dir).display()
+        );
+    }
+
+    if let Some(include_dir) = env::var_os("LIBFFI_INCLUDE_DIR") {
+        println!("cargo:include={}", Path::new(&include_dir).display());
+    }
+
+    let include_dirs: Vec<PathBuf> = env::var_os("LIBFFI_INCLUDE_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .collect();
+    build_seh_guard(&include_dirs);
+
+    let link_static = match env::var("LIBFFI_STATIC") {
+        Ok(value) => value != "0",
+        Err(_) => cfg!(feature = "system-static"),
+    };
+
+    if link_static {
+        match find_static_lib() {
+            Some(dir) => println!("cargo:rustc-link-search=native={}", dir.display()),
+            None => {
+                println!("cargo:warning=libffi.a not found; set LIBFFI_LIB_DIR to its directory")
+            }
+        }
+        println!("cargo:rustc-link-lib=static=ffi");
+    } else {
+        println!("cargo:rustc-link-lib=dylib=ffi");
+    }
+}
+
+// Looks for `libffi.a` in `LIBFFI_LIB_DIR`, in the directory that
+// pkg-config reports for libffi, and then in the usual system library
+// directories, returning the directory it is in. rustc needs the
+// directory even where the linker would find the library by itself.
+fn find_static_lib() -> Option<PathBuf> {
+    let pkg_config_dir = || {
+        Command::new("pkg-config")
+            .arg("--variable=libdir")
+            .arg("libffi")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|dir| PathBuf::from(dir.trim()))
+    };
+
+    // Debian-style multiarch directories drop the vendor from the target.
+    let target = env::var("TARGET").unwrap();
+    let parts: Vec<&str> = target.split('-').collect();
+    let multiarch = match parts.as_slice() {
+        [arch, _vendor, os, env] => format!("/usr/lib/{}-{}-{}", arch, os, env),
+        _ => format!("/usr/lib/{}", target),
+    };
+    let system_dirs = vec![
+        PathBuf::from("/usr/local/lib"),
+        PathBuf::from(multiarch),
+        PathBuf::from("/usr/lib64"),
+        PathBuf::from("/usr/lib"),
+    ];
+
+    env::var_os("LIBFFI_LIB_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(pkg_config_dir())
+        .chain(system_dirs)
+        .find(|dir| dir.join("libffi.a").is_file())
 }
 
-pub fn configure_libffi(prefix: PathBuf, build_dir: &Path) {
+// Asks pkg-config for the directories holding the system libffi’s
+// headers, beyond the compiler’s defaults.
+#[cfg(feature = "bindgen")]
+pub fn system_include_dirs() -> Vec<String> {
+    if let Ok(include_dir) = env::var("LIBFFI_INCLUDE_DIR") {
+        return vec![include_dir];
+    }
+
+    Command::new("pkg-config")
+        .arg("--cflags-only-I")
+        .arg("libffi")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|flags| {
+            flags
+                .split_whitespace()
+                .filter(|flag| flag.starts_with("-I"))
+                .map(|flag| flag[2..].to_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Finds the version of the system libffi: from the banner of `ffi.h` in
+// `LIBFFI_INCLUDE_DIR` if that is set, or else from pkg-config.
+pub fn system_version() -> String {
+    if let Some(include_dir) = env::var_os("LIBFFI_INCLUDE_DIR") {
+        return header_version(Path::new(&include_dir));
+    }
+
+    Command::new("pkg-config")
+        .arg("--modversion")
+        .arg("libffi")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+// Builds the command that configures libffi in `build_dir`.
+pub fn configure_libffi(prefix: &Path, src_dir: &Path, build_dir: &Path) -> Command {
     let mut command = Command::new("sh");
 
     command
-        .arg("configure")
+        .arg(shell_path(&src_dir.join("configure")))
+        .arg(format!("--srcdir={}", shell_path(src_dir)))
+        .arg(format!("--prefix={}", shell_path(prefix)))
         .arg("--with-pic")
         .arg("--disable-docs");
 
+    // Without closures, `build_and_link` removes `closures.c` and
+    // `tramp.c` from the library, which the static trampolines would
+    // refer to.
+    if !cfg!(feature = "closures") {
+        command.arg("--disable-exec-static-tramp");
+    }
+
     let target = std::env::var("TARGET").unwrap();
-    if target != std::env::var("HOST").unwrap() {
-        command.arg(format!("--host={}", target.to_string()));
+    if target.contains("android") {
+        command.arg(format!("--host={}", android::host_triple(&target)));
+        android::configure_toolchain(&mut command, &target);
+    } else if target.ends_with("-aix") {
+        configure_aix(&mut command, &target);
+    } else if target != std::env::var("HOST").unwrap() {
+        command.arg(format!("--host={}", host_triple(&target)));
     }
 
     command.current_dir(&build_dir);
+    command
+}
+
+// What a configured build directory depends on: the libffi version, the
+// target, the configure command, and the variables that configure reads
+// from the environment.
+fn configure_key(configure: &Command) -> String {
+    let mut key = format!(
+        "{}\n{}\n{:?}\n",
+        vendored_version(),
+        env::var("TARGET").unwrap(),
+        configure
+    );
+    for var in &[
+        "CC", "CFLAGS", "CPPFLAGS", "LDFLAGS", "LIBS", "AR", "RANLIB",
+    ] {
+        println!("cargo:rerun-if-env-changed={}", var);
+        key.push_str(&format!("{}={:?}\n", var, env::var_os(var)));
+    }
+    key
+}
 
+// Converts a Rust target triple to one that libffi's `config.sub` and
+// `configure.host` understand. Its `config.sub` predates illumos, which
+// autotools treats as Solaris, and Rust's Solaris triples omit the
+// release.
+fn host_triple(target: &str) -> String {
+    let arch = target.split('-').next().unwrap();
+    if target.ends_with("-illumos") || target.ends_with("-solaris") {
+        let vendor = if arch.starts_with("sparc") {
+            "sun"
+        } else {
+            "pc"
+        };
+        format!("{}-{}-solaris2.11", arch, vendor)
+    } else {
+        target.to_owned()
+    }
+}
+
+// libffi's configure only knows AIX as `powerpc-*-aix*`, building for
+// 64 bits when the tools default to it, as they do with `OBJECT_MODE=64`.
+fn configure_aix(command: &mut Command, target: &str) {
+    if target.starts_with("powerpc64") {
+        command.env("OBJECT_MODE", "64");
+        let cflags = env::var("CFLAGS").unwrap_or_default();
+        command.env("CFLAGS", format!("{} -maix64", cflags).trim_start());
+    }
+
+    if target != env::var("HOST").unwrap() {
+        command.arg("--host=powerpc-ibm-aix");
+    }
+}
+
+// Converts a path for use by the configure script.
+fn shell_path(path: &Path) -> String {
     if cfg!(windows) {
         // When using MSYS2, OUT_DIR will be a Windows like path such as
         // C:\foo\bar. Unfortunately, the various scripts used for building
@@ -68,25 +317,23 @@ pub fn configure_libffi(prefix: PathBuf, build_dir: &Path) {
         // This code assumes the path only uses : for the drive letter, and only
         // uses \ as a component separator. It will likely break for file paths
         // that include a :.
-        let mut msys_prefix = prefix
+        let mut msys_path = path
             .to_str()
             .unwrap()
             .replace(":\\", "/")
             .replace("\\", "/");
 
-        msys_prefix.insert(0, '/');
-
-        command.arg("--prefix").arg(msys_prefix);
+        msys_path.insert(0, '/');
+        msys_path
     } else {
-        command.arg("--prefix").arg(prefix);
+        path.to_str().unwrap().to_owned()
     }
-
-    run_command("Configuring libffi", &mut command);
 }
 
-pub fn autogen(build_dir: &Path) {
+// Generates `configure` in a copy of the source tree at `src_dir`.
+pub fn autogen(vendored_dir: &Path, src_dir: &Path) {
     assert!(
-        build_dir.join("autogen.sh").exists(),
+        vendored_dir.join("autogen.sh").exists(),
         "
         **********
         build.rs could not find autogen.sh when attempting to build C
@@ -96,9 +343,12 @@ pub fn autogen(build_dir: &Path) {
         "
     );
 
+    let _ = fs::remove_dir_all(src_dir);
+    copy_dir(vendored_dir, src_dir);
+
     let mut command = Command::new("sh");
 
-    command.arg("autogen.sh").current_dir(&build_dir);
+    command.arg("autogen.sh").current_dir(&src_dir);
 
     if cfg!(windows) {
         // When building in MSYS2, not clearing the environment variables first
@@ -110,3 +360,21 @@ pub fn autogen(build_dir: &Path) {
 
     run_command("Generating configure", &mut command);
 }
+
+// Copies a directory tree, leaving out git metadata.
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).expect("Creating a copy of the libffi source");
+    for entry in fs::read_dir(from).expect("Reading the libffi source") {
+        let entry = entry.expect("Reading the libffi source");
+        if entry.file_name() == ".git" {
+            continue;
+        }
+
+        let dest = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &dest);
+        } else {
+            fs::copy(entry.path(), &dest).expect("Copying the libffi source");
+        }
+    }
+}
@@ -49,7 +49,11 @@ extern "C" {
 #endif
 
 /* Specify which architecture libffi is configured for. */
-#ifdef _WIN64
+#if defined(_M_ARM64) || defined(_M_ARM64EC)
+#ifndef AARCH64
+#define AARCH64
+#endif
+#elif defined(_WIN64)
 #ifndef X86_WIN64
 #define X86_WIN64
 #endif
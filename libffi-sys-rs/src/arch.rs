@@ -30,7 +30,13 @@ mod x86 {
         #[cfg(target_env = "msvc")]
         pub use msvc::*;
 
+        // libffi 3.4 made room for an ENDBR64 instruction.
+        // See: https://github.com/libffi/libffi/blob/v3.4.4/src/x86/ffitarget.h
+        #[cfg(libffi_3_4)]
+        pub const FFI_TRAMPOLINE_SIZE: usize = 32;
+
         // See: https://github.com/libffi/libffi/blob/369ef49f71186fc9d6ab15614488ad466fac3fc1/src/x86/ffitarget.h#L137
+        #[cfg(not(libffi_3_4))]
         pub const FFI_TRAMPOLINE_SIZE: usize = 24;
         pub const FFI_NATIVE_RAW_API: u32 = 0;
     }
@@ -46,7 +52,13 @@ mod x86 {
         pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 5;
         pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_UNIX64;
 
+        // libffi 3.4 made room for an ENDBR64 instruction.
+        // See: https://github.com/libffi/libffi/blob/v3.4.4/src/x86/ffitarget.h
+        #[cfg(libffi_3_4)]
+        pub const FFI_TRAMPOLINE_SIZE: usize = 32;
+
         // See: https://github.com/libffi/libffi/blob/369ef49f71186fc9d6ab15614488ad466fac3fc1/src/x86/ffitarget.h#L137
+        #[cfg(not(libffi_3_4))]
         pub const FFI_TRAMPOLINE_SIZE: usize = 24;
         pub const FFI_NATIVE_RAW_API: u32 = 0;
     }
@@ -65,7 +77,13 @@ mod x86 {
         pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 8;
         pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_MS_CDECL;
 
+        // libffi 3.4 made room for an ENDBR32 instruction.
+        // See: https://github.com/libffi/libffi/blob/v3.4.4/src/x86/ffitarget.h
+        #[cfg(libffi_3_4)]
+        pub const FFI_TRAMPOLINE_SIZE: usize = 16;
+
         // See: https://github.com/libffi/libffi/blob/369ef49f71186fc9d6ab15614488ad466fac3fc1/src/x86/ffitarget.h#L137
+        #[cfg(not(libffi_3_4))]
         pub const FFI_TRAMPOLINE_SIZE: usize = 12;
         pub const FFI_NATIVE_RAW_API: u32 = 1;
     }
@@ -84,7 +102,13 @@ mod x86 {
         pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 9;
         pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_SYSV;
 
+        // libffi 3.4 made room for an ENDBR32 instruction.
+        // See: https://github.com/libffi/libffi/blob/v3.4.4/src/x86/ffitarget.h
+        #[cfg(libffi_3_4)]
+        pub const FFI_TRAMPOLINE_SIZE: usize = 16;
+
         // See: https://github.com/libffi/libffi/blob/369ef49f71186fc9d6ab15614488ad466fac3fc1/src/x86/ffitarget.h#L137
+        #[cfg(not(libffi_3_4))]
         pub const FFI_TRAMPOLINE_SIZE: usize = 12;
         pub const FFI_NATIVE_RAW_API: u32 = 1;
     }
@@ -131,9 +155,20 @@ mod aarch64 {
 
     pub const ffi_abi_FFI_FIRST_ABI: ffi_abi = 0;
     pub const ffi_abi_FFI_SYSV: ffi_abi = 1;
+
+    #[cfg(not(target_os = "windows"))]
     pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 2;
+    #[cfg(not(target_os = "windows"))]
     pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_SYSV;
 
+    // See: https://github.com/libffi/libffi/blob/v3.4.4/src/aarch64/ffitarget.h#L44
+    #[cfg(target_os = "windows")]
+    pub const ffi_abi_FFI_WIN64: ffi_abi = 2;
+    #[cfg(target_os = "windows")]
+    pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 3;
+    #[cfg(target_os = "windows")]
+    pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_WIN64;
+
     pub const FFI_NATIVE_RAW_API: u32 = 0;
 
     #[cfg(target_vendor = "apple")]
@@ -147,7 +182,8 @@ mod aarch64 {
     pub const FFI_GO_CLOSURES: u32 = 1;
 }
 
-#[cfg(target_arch = "aarch64")]
+// ARM64EC uses the AArch64 port.
+#[cfg(any(target_arch = "aarch64", target_arch = "arm64ec"))]
 pub use aarch64::*;
 
 /// From libffi:src/powerpc/ffitarget.h
@@ -293,10 +329,162 @@ mod powerpc {
         pub const FFI_NATIVE_RAW_API: u32 = 0;
         pub const FFI_GO_CLOSURES: u32 = 1;
     }
+
+    // AIX uses its own ABI rather than any of the ELF ones.
+    // See: https://github.com/libffi/libffi/blob/v3.4.4/src/powerpc/ffitarget.h
+    pub mod aix {
+        use crate::ffi_abi;
+
+        pub const ffi_abi_FFI_FIRST_ABI: ffi_abi = 0;
+        pub const ffi_abi_FFI_AIX: ffi_abi = 1;
+        pub const ffi_abi_FFI_DARWIN: ffi_abi = 2;
+        pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 3;
+        pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_AIX;
+
+        pub const FFI_TRAMPOLINE_SIZE: usize = 24;
+
+        pub const FFI_NATIVE_RAW_API: u32 = 0;
+        pub const FFI_GO_CLOSURES: u32 = 1;
+    }
 }
 
-#[cfg(target_arch = "powerpc")]
+#[cfg(all(target_arch = "powerpc", not(target_os = "aix")))]
 pub use powerpc::powerpc::*;
 
-#[cfg(target_arch = "powerpc64")]
+#[cfg(all(target_arch = "powerpc64", not(target_os = "aix")))]
 pub use powerpc::powerpc64::*;
+
+#[cfg(all(
+    any(target_arch = "powerpc", target_arch = "powerpc64"),
+    target_os = "aix"
+))]
+pub use powerpc::aix::*;
+
+/// From libffi:src/sparc/ffitarget.h
+/// See: https://github.com/libffi/libffi/blob/v3.4.4/src/sparc/ffitarget.h
+mod sparc {
+    pub mod sparc32 {
+        use crate::ffi_abi;
+
+        pub const ffi_abi_FFI_FIRST_ABI: ffi_abi = 0;
+        pub const ffi_abi_FFI_V8: ffi_abi = 1;
+        pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 2;
+        pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_V8;
+
+        pub const FFI_TRAMPOLINE_SIZE: usize = 16;
+        pub const FFI_NATIVE_RAW_API: u32 = 0;
+        pub const FFI_GO_CLOSURES: u32 = 1;
+    }
+
+    pub mod sparc64 {
+        use crate::ffi_abi;
+
+        pub const ffi_abi_FFI_FIRST_ABI: ffi_abi = 0;
+        pub const ffi_abi_FFI_V9: ffi_abi = 1;
+        pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 2;
+        pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_V9;
+
+        pub const FFI_TRAMPOLINE_SIZE: usize = 24;
+        pub const FFI_NATIVE_RAW_API: u32 = 0;
+        pub const FFI_GO_CLOSURES: u32 = 1;
+    }
+}
+
+#[cfg(target_arch = "sparc")]
+pub use sparc::sparc32::*;
+
+#[cfg(target_arch = "sparc64")]
+pub use sparc::sparc64::*;
+
+/// From libffi:src/m68k/ffitarget.h
+/// See: https://github.com/libffi/libffi/blob/v3.4.4/src/m68k/ffitarget.h
+mod m68k {
+    use crate::ffi_abi;
+
+    pub const ffi_abi_FFI_FIRST_ABI: ffi_abi = 0;
+    pub const ffi_abi_FFI_SYSV: ffi_abi = 1;
+    pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 2;
+    pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_SYSV;
+
+    pub const FFI_TRAMPOLINE_SIZE: usize = 16;
+    pub const FFI_NATIVE_RAW_API: u32 = 0;
+}
+
+#[cfg(target_arch = "m68k")]
+pub use m68k::*;
+
+/// From libffi:src/csky/ffitarget.h
+/// See: https://github.com/libffi/libffi/blob/v3.4.4/src/csky/ffitarget.h
+mod csky {
+    use crate::ffi_abi;
+
+    pub const ffi_abi_FFI_FIRST_ABI: ffi_abi = 0;
+    pub const ffi_abi_FFI_SYSV: ffi_abi = 1;
+    pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 2;
+    pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_SYSV;
+
+    // Rust only targets C-SKY ABIv2; ABIv1 trampolines are 28 bytes.
+    pub const FFI_TRAMPOLINE_SIZE: usize = 24;
+    pub const FFI_NATIVE_RAW_API: u32 = 0;
+}
+
+#[cfg(target_arch = "csky")]
+pub use csky::*;
+
+/// From libffi:src/xtensa/ffitarget.h
+/// See: https://github.com/libffi/libffi/blob/v3.4.4/src/xtensa/ffitarget.h
+mod xtensa {
+    use crate::ffi_abi;
+
+    pub const ffi_abi_FFI_FIRST_ABI: ffi_abi = 0;
+    pub const ffi_abi_FFI_SYSV: ffi_abi = 1;
+    pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 2;
+    pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_SYSV;
+
+    pub const FFI_TRAMPOLINE_SIZE: usize = 24;
+    pub const FFI_NATIVE_RAW_API: u32 = 0;
+}
+
+#[cfg(target_arch = "xtensa")]
+pub use xtensa::*;
+
+/// From libffi:src/arc/ffitarget.h
+/// See: https://github.com/libffi/libffi/blob/v3.4.4/src/arc/ffitarget.h
+mod arc {
+    use crate::ffi_abi;
+
+    pub const ffi_abi_FFI_FIRST_ABI: ffi_abi = 0;
+    pub const ffi_abi_FFI_ARCOMPACT: ffi_abi = 1;
+    pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 2;
+    pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_ARCOMPACT;
+
+    pub const FFI_TRAMPOLINE_SIZE: usize = 12;
+    pub const FFI_NATIVE_RAW_API: u32 = 0;
+}
+
+#[cfg(target_arch = "arc")]
+pub use arc::*;
+
+/// From libffi:src/wasm32/ffitarget.h, which is newer than the vendored
+/// libffi, so these are only useful with a `system` or prebuilt libffi.
+/// See: https://github.com/libffi/libffi/blob/v3.4.6/src/wasm32/ffitarget.h
+mod wasm32 {
+    use crate::ffi_abi;
+
+    pub const ffi_abi_FFI_FIRST_ABI: ffi_abi = 0;
+    pub const ffi_abi_FFI_WASM32: ffi_abi = 1;
+    pub const ffi_abi_FFI_WASM32_EMSCRIPTEN: ffi_abi = 2;
+    pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 3;
+
+    #[cfg(target_os = "emscripten")]
+    pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_WASM32_EMSCRIPTEN;
+
+    #[cfg(not(target_os = "emscripten"))]
+    pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_WASM32;
+
+    pub const FFI_TRAMPOLINE_SIZE: usize = 4;
+    pub const FFI_NATIVE_RAW_API: u32 = 0;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use wasm32::*;
@@ -0,0 +1,629 @@
+//! The hand-maintained bindings.
+//!
+//! With the `bindgen` feature, the items that bindgen generates from the
+//! system headers take precedence over these, since `lib.rs` defines them
+//! directly and re-exports these by glob.
+
+#[cfg(not(feature = "std"))]
+use core::ffi::{c_char, c_int, c_long, c_schar, c_uint, c_ulong, c_ushort, c_void};
+use core::fmt::{self, Debug, Formatter};
+use core::mem::{size_of, zeroed};
+#[cfg(feature = "std")]
+use std::os::raw::{c_char, c_int, c_long, c_schar, c_uint, c_ulong, c_ushort, c_void};
+
+use crate::arch::FFI_TRAMPOLINE_SIZE;
+
+// The size of the `tramp` fields of the closure types. On AArch64 Apple
+// platforms, where libffi uses `FFI_EXEC_TRAMPOLINE_TABLE`, they are two
+// pointers into its trampoline table instead, which is less than
+// `FFI_TRAMPOLINE_SIZE` on `arm64_32`.
+#[cfg(all(target_arch = "aarch64", target_vendor = "apple"))]
+const CLOSURE_TRAMP_SIZE: usize = 2 * size_of::<*mut c_void>();
+#[cfg(not(all(target_arch = "aarch64", target_vendor = "apple")))]
+const CLOSURE_TRAMP_SIZE: usize = FFI_TRAMPOLINE_SIZE;
+
+// `ffi_arg` is `unsigned long` except where that is narrower than a
+// register: on 64-bit Windows, where `long` is 32 bits, and on the ILP32
+// ABIs of 64-bit architectures (x32, AArch64 ILP32, and MIPS n32), where
+// Java raw arguments stay 4 bytes.
+#[cfg(any(
+    all(windows, target_pointer_width = "64"),
+    all(
+        any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "mips64"
+        ),
+        target_pointer_width = "32"
+    )
+))]
+mod arg {
+    pub type ffi_arg = u64;
+    pub type ffi_sarg = i64;
+
+    #[cfg(target_pointer_width = "32")]
+    pub const FFI_SIZEOF_JAVA_RAW: usize = 4;
+    #[cfg(target_pointer_width = "64")]
+    pub const FFI_SIZEOF_JAVA_RAW: usize = super::FFI_SIZEOF_ARG;
+}
+
+#[cfg(not(any(
+    all(windows, target_pointer_width = "64"),
+    all(
+        any(
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            target_arch = "mips64"
+        ),
+        target_pointer_width = "32"
+    )
+)))]
+mod arg {
+    pub type ffi_arg = super::c_ulong;
+    pub type ffi_sarg = super::c_long;
+
+    pub const FFI_SIZEOF_JAVA_RAW: usize = super::FFI_SIZEOF_ARG;
+}
+
+pub use arg::{ffi_arg, ffi_sarg, FFI_SIZEOF_JAVA_RAW};
+pub type ffi_abi = u32;
+pub type ffi_status = u32;
+pub type ffi_type_enum = u32;
+
+pub const FFI_64_BIT_MAX: u64 = 9223372036854775807;
+pub const FFI_CLOSURES: u32 = 1;
+pub const FFI_SIZEOF_ARG: usize = size_of::<ffi_arg>();
+
+pub const FFI_TYPE_VOID: u32 = 0;
+pub const FFI_TYPE_INT: u32 = 1;
+pub const FFI_TYPE_FLOAT: u32 = 2;
+pub const FFI_TYPE_DOUBLE: u32 = 3;
+pub const FFI_TYPE_LONGDOUBLE: u32 = 4;
+pub const FFI_TYPE_UINT8: u32 = 5;
+pub const FFI_TYPE_SINT8: u32 = 6;
+pub const FFI_TYPE_UINT16: u32 = 7;
+pub const FFI_TYPE_SINT16: u32 = 8;
+pub const FFI_TYPE_UINT32: u32 = 9;
+pub const FFI_TYPE_SINT32: u32 = 10;
+pub const FFI_TYPE_UINT64: u32 = 11;
+pub const FFI_TYPE_SINT64: u32 = 12;
+pub const FFI_TYPE_STRUCT: u32 = 13;
+pub const FFI_TYPE_POINTER: u32 = 14;
+pub const FFI_TYPE_COMPLEX: u32 = 15;
+pub const FFI_TYPE_LAST: u32 = 15;
+
+pub const ffi_status_FFI_OK: ffi_status = 0;
+pub const ffi_status_FFI_BAD_TYPEDEF: ffi_status = 1;
+pub const ffi_status_FFI_BAD_ABI: ffi_status = 2;
+
+pub const ffi_type_enum_STRUCT: ffi_type_enum = 13;
+pub const ffi_type_enum_COMPLEX: ffi_type_enum = 15;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ffi_type {
+    pub size: usize,
+    pub alignment: c_ushort,
+    pub type_: c_ushort,
+    pub elements: *mut *mut ffi_type,
+}
+
+impl Default for ffi_type {
+    fn default() -> Self {
+        unsafe { zeroed() }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ffi_cif {
+    pub abi: ffi_abi,
+    pub nargs: c_uint,
+    pub arg_types: *mut *mut ffi_type,
+    pub rtype: *mut ffi_type,
+    pub bytes: c_uint,
+    pub flags: c_uint,
+    #[cfg(all(
+        any(target_arch = "aarch64", target_arch = "arm64ec"),
+        target_os = "windows"
+    ))]
+    pub is_variadic: c_uint,
+    #[cfg(all(target_arch = "aarch64", target_vendor = "apple"))]
+    pub aarch64_nfixedargs: c_uint,
+    #[cfg(all(target_arch = "arm"))]
+    pub vfp_used: c_int,
+    #[cfg(all(target_arch = "arm"))]
+    pub vfp_reg_free: c_ushort,
+    #[cfg(all(target_arch = "arm"))]
+    pub vfp_nargs: c_ushort,
+    #[cfg(all(target_arch = "arm"))]
+    pub vfp_args: [c_schar; 16],
+    #[cfg(any(
+        all(
+            any(target_arch = "powerpc", target_arch = "powerpc64"),
+            not(target_os = "aix")
+        ),
+        target_arch = "sparc64"
+    ))]
+    pub nfixedargs: c_uint,
+}
+
+impl Default for ffi_cif {
+    fn default() -> Self {
+        unsafe { zeroed() }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union ffi_raw {
+    pub sint: ffi_sarg,
+    pub uint: ffi_arg,
+    pub flt: f32,
+    pub data: [c_char; FFI_SIZEOF_ARG],
+    pub ptr: *mut c_void,
+}
+
+impl Default for ffi_raw {
+    fn default() -> Self {
+        unsafe { zeroed() }
+    }
+}
+
+// Where Java raw arguments are narrower than `ffi_arg`, they get their
+// own union.
+#[cfg(not(all(
+    target_pointer_width = "32",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "mips64"
+    )
+)))]
+pub type ffi_java_raw = ffi_raw;
+
+#[cfg(all(
+    target_pointer_width = "32",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "mips64"
+    )
+))]
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union ffi_java_raw {
+    pub sint: i32,
+    pub uint: u32,
+    pub flt: f32,
+    pub data: [c_char; FFI_SIZEOF_JAVA_RAW],
+    pub ptr: *mut c_void,
+}
+
+#[cfg(all(
+    target_pointer_width = "32",
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "mips64"
+    )
+))]
+impl Default for ffi_java_raw {
+    fn default() -> Self {
+        unsafe { zeroed() }
+    }
+}
+
+// libffi declares `ffi_closure` with `__attribute__((aligned (8)))`
+// under GCC and Clang, which rounds its size up to a multiple of 8 on
+// 32-bit targets. On 32-bit MSVC, an explicit padding field does that
+// instead.
+#[repr(C, align(8))]
+#[derive(Copy, Clone)]
+pub struct ffi_closure {
+    pub tramp: [c_char; CLOSURE_TRAMP_SIZE],
+    pub cif: *mut ffi_cif,
+    pub fun: Option<
+        unsafe extern "C" fn(
+            arg1: *mut ffi_cif,
+            arg2: *mut c_void,
+            arg3: *mut *mut c_void,
+            arg4: *mut c_void,
+        ),
+    >,
+    pub user_data: *mut c_void,
+    #[cfg(all(target_env = "msvc", target_arch = "x86"))]
+    pub padding: *mut c_void,
+}
+
+// The size of `ffi_closure` in C: the trampoline and three pointers, or
+// four on 32-bit MSVC, rounded up to a multiple of 8.
+#[cfg(not(all(target_env = "msvc", target_arch = "x86")))]
+const C_CLOSURE_POINTERS: usize = 3;
+#[cfg(all(target_env = "msvc", target_arch = "x86"))]
+const C_CLOSURE_POINTERS: usize = 4;
+
+const C_CLOSURE_SIZE: usize =
+    (CLOSURE_TRAMP_SIZE + C_CLOSURE_POINTERS * size_of::<*mut c_void>() + 7) & !7;
+
+// Fails to compile, with an overflow in the array length, unless the
+// sizes agree.
+#[allow(dead_code)]
+const CLOSURE_SIZE_CHECK: [(); 0] = [(); (size_of::<ffi_closure>() == C_CLOSURE_SIZE) as usize - 1];
+
+/// Implements Debug manually since sometimes FFI_TRAMPOLINE_SIZE is too large
+impl Debug for ffi_closure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ffi_closure")
+            .field("tramp", &&self.tramp[..])
+            .field("cif", &self.cif)
+            .field("fun", &self.fun)
+            .field("user_data", &self.user_data)
+            .finish()
+    }
+}
+
+impl Default for ffi_closure {
+    fn default() -> Self {
+        unsafe { zeroed() }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct ffi_raw_closure {
+    pub tramp: [c_char; CLOSURE_TRAMP_SIZE],
+    pub cif: *mut ffi_cif,
+    // See: https://github.com/libffi/libffi/blob/3a7580da73b7f16f275277316d00e3497cbb5a8c/include/ffi.h.in#L364
+    #[cfg(not(target_arch = "x86"))]
+    pub translate_args: Option<
+        unsafe extern "C" fn(
+            arg1: *mut ffi_cif,
+            arg2: *mut c_void,
+            arg3: *mut *mut c_void,
+            arg4: *mut c_void,
+        ),
+    >,
+    #[cfg(not(target_arch = "x86"))]
+    pub this_closure: *mut c_void,
+    pub fun: Option<
+        unsafe extern "C" fn(
+            arg1: *mut ffi_cif,
+            arg2: *mut c_void,
+            arg3: *mut ffi_raw,
+            arg4: *mut c_void,
+        ),
+    >,
+    pub user_data: *mut c_void,
+}
+
+/// Implements Debug manually since sometimes FFI_TRAMPOLINE_SIZE is too large
+impl Debug for ffi_raw_closure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut debug_struct = f.debug_struct("ffi_raw_closure");
+        debug_struct
+            .field("tramp", &&self.tramp[..])
+            .field("cif", &self.cif);
+
+        #[cfg(not(target_arch = "x86"))]
+        debug_struct.field("translate_args", &self.translate_args);
+        #[cfg(not(target_arch = "x86"))]
+        debug_struct.field("this_closure", &self.this_closure);
+
+        debug_struct
+            .field("fun", &self.fun)
+            .field("user_data", &self.user_data)
+            .finish()
+    }
+}
+
+impl Default for ffi_raw_closure {
+    fn default() -> Self {
+        unsafe { zeroed() }
+    }
+}
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct ffi_java_raw_closure {
+    pub tramp: [c_char; CLOSURE_TRAMP_SIZE],
+    pub cif: *mut ffi_cif,
+    // See: https://github.com/libffi/libffi/blob/3a7580da73b7f16f275277316d00e3497cbb5a8c/include/ffi.h.in#L390
+    #[cfg(not(target_arch = "x86"))]
+    pub translate_args: Option<
+        unsafe extern "C" fn(
+            arg1: *mut ffi_cif,
+            arg2: *mut c_void,
+            arg3: *mut *mut c_void,
+            arg4: *mut c_void,
+        ),
+    >,
+    #[cfg(not(target_arch = "x86"))]
+    pub this_closure: *mut c_void,
+    pub fun: Option<
+        unsafe extern "C" fn(
+            arg1: *mut ffi_cif,
+            arg2: *mut c_void,
+            arg3: *mut ffi_java_raw,
+            arg4: *mut c_void,
+        ),
+    >,
+    pub user_data: *mut c_void,
+}
+
+/// Implements Debug manually since sometimes FFI_TRAMPOLINE_SIZE is too large
+impl Debug for ffi_java_raw_closure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut debug_struct = f.debug_struct("ffi_java_raw_closure");
+        debug_struct
+            .field("tramp", &&self.tramp[..])
+            .field("cif", &self.cif);
+
+        #[cfg(not(target_arch = "x86"))]
+        debug_struct.field("translate_args", &self.translate_args);
+        #[cfg(not(target_arch = "x86"))]
+        debug_struct.field("this_closure", &self.this_closure);
+
+        debug_struct
+            .field("fun", &self.fun)
+            .field("user_data", &self.user_data)
+            .finish()
+    }
+}
+
+impl Default for ffi_java_raw_closure {
+    fn default() -> Self {
+        unsafe { zeroed() }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ffi_go_closure {
+    pub tramp: *mut c_void,
+    pub cif: *mut ffi_cif,
+    pub fun: Option<
+        unsafe extern "C" fn(
+            arg1: *mut ffi_cif,
+            arg2: *mut c_void,
+            arg3: *mut *mut c_void,
+            arg4: *mut c_void,
+        ),
+    >,
+}
+impl Default for ffi_go_closure {
+    fn default() -> Self {
+        unsafe { zeroed() }
+    }
+}
+
+extern "C" {
+    pub static mut ffi_type_void: ffi_type;
+    pub static mut ffi_type_uint8: ffi_type;
+    pub static mut ffi_type_sint8: ffi_type;
+    pub static mut ffi_type_uint16: ffi_type;
+    pub static mut ffi_type_sint16: ffi_type;
+    pub static mut ffi_type_uint32: ffi_type;
+    pub static mut ffi_type_sint32: ffi_type;
+    pub static mut ffi_type_uint64: ffi_type;
+    pub static mut ffi_type_sint64: ffi_type;
+    pub static mut ffi_type_float: ffi_type;
+    pub static mut ffi_type_double: ffi_type;
+    pub static mut ffi_type_pointer: ffi_type;
+
+    #[cfg(not(all(target_arch = "arm", target_os = "linux", target_env = "gnu")))]
+    pub static mut ffi_type_longdouble: ffi_type;
+
+    #[cfg(all(feature = "complex", libffi_complex))]
+    pub static mut ffi_type_complex_float: ffi_type;
+
+    #[cfg(all(feature = "complex", libffi_complex))]
+    pub static mut ffi_type_complex_double: ffi_type;
+
+    #[cfg(all(feature = "complex", libffi_complex))]
+    #[cfg(not(all(target_arch = "arm", target_os = "linux", target_env = "gnu")))]
+    pub static mut ffi_type_complex_longdouble: ffi_type;
+
+    pub fn ffi_raw_call(
+        cif: *mut ffi_cif,
+        fn_: Option<unsafe extern "C" fn()>,
+        rvalue: *mut c_void,
+        avalue: *mut ffi_raw,
+    );
+
+    pub fn ffi_ptrarray_to_raw(cif: *mut ffi_cif, args: *mut *mut c_void, raw: *mut ffi_raw);
+
+    pub fn ffi_raw_to_ptrarray(cif: *mut ffi_cif, raw: *mut ffi_raw, args: *mut *mut c_void);
+
+    pub fn ffi_raw_size(cif: *mut ffi_cif) -> usize;
+
+    // See: https://github.com/libffi/libffi/blob/3a7580da73b7f16f275277316d00e3497cbb5a8c/include/ffi.h.in#L286
+    #[cfg(not(target_arch = "x86"))]
+    pub fn ffi_java_raw_call(
+        cif: *mut ffi_cif,
+        fn_: Option<unsafe extern "C" fn()>,
+        rvalue: *mut c_void,
+        avalue: *mut ffi_java_raw,
+    );
+
+    pub fn ffi_java_ptrarray_to_raw(
+        cif: *mut ffi_cif,
+        args: *mut *mut c_void,
+        raw: *mut ffi_java_raw,
+    );
+
+    pub fn ffi_java_raw_to_ptrarray(
+        cif: *mut ffi_cif,
+        raw: *mut ffi_java_raw,
+        args: *mut *mut c_void,
+    );
+
+    pub fn ffi_java_raw_size(cif: *mut ffi_cif) -> usize;
+
+    #[cfg(feature = "closures")]
+    pub fn ffi_closure_alloc(size: usize, code: *mut *mut c_void) -> *mut c_void;
+
+    #[cfg(feature = "closures")]
+    pub fn ffi_closure_free(arg1: *mut c_void);
+
+    #[cfg(feature = "closures")]
+    pub fn ffi_prep_closure(
+        arg1: *mut ffi_closure,
+        arg2: *mut ffi_cif,
+        fun: Option<
+            unsafe extern "C" fn(
+                arg1: *mut ffi_cif,
+                arg2: *mut c_void,
+                arg3: *mut *mut c_void,
+                arg4: *mut c_void,
+            ),
+        >,
+        user_data: *mut c_void,
+    ) -> ffi_status;
+
+    #[cfg(feature = "closures")]
+    pub fn ffi_prep_closure_loc(
+        arg1: *mut ffi_closure,
+        arg2: *mut ffi_cif,
+        fun: Option<
+            unsafe extern "C" fn(
+                arg1: *mut ffi_cif,
+                arg2: *mut c_void,
+                arg3: *mut *mut c_void,
+                arg4: *mut c_void,
+            ),
+        >,
+        user_data: *mut c_void,
+        codeloc: *mut c_void,
+    ) -> ffi_status;
+
+    #[cfg(feature = "closures")]
+    pub fn ffi_prep_raw_closure(
+        arg1: *mut ffi_raw_closure,
+        cif: *mut ffi_cif,
+        fun: Option<
+            unsafe extern "C" fn(
+                arg1: *mut ffi_cif,
+                arg2: *mut c_void,
+                arg3: *mut ffi_raw,
+                arg4: *mut c_void,
+            ),
+        >,
+        user_data: *mut c_void,
+    ) -> ffi_status;
+
+    #[cfg(feature = "closures")]
+    pub fn ffi_prep_raw_closure_loc(
+        arg1: *mut ffi_raw_closure,
+        cif: *mut ffi_cif,
+        fun: Option<
+            unsafe extern "C" fn(
+                arg1: *mut ffi_cif,
+                arg2: *mut c_void,
+                arg3: *mut ffi_raw,
+                arg4: *mut c_void,
+            ),
+        >,
+        user_data: *mut c_void,
+        codeloc: *mut c_void,
+    ) -> ffi_status;
+
+    // See: https://github.com/libffi/libffi/blob/3a7580da73b7f16f275277316d00e3497cbb5a8c/include/ffi.h.in#L419
+    #[cfg(all(feature = "closures", not(target_arch = "x86")))]
+    pub fn ffi_prep_java_raw_closure(
+        arg1: *mut ffi_java_raw_closure,
+        cif: *mut ffi_cif,
+        fun: Option<
+            unsafe extern "C" fn(
+                arg1: *mut ffi_cif,
+                arg2: *mut c_void,
+                arg3: *mut ffi_java_raw,
+                arg4: *mut c_void,
+            ),
+        >,
+        user_data: *mut c_void,
+    ) -> ffi_status;
+
+    // See: https://github.com/libffi/libffi/blob/3a7580da73b7f16f275277316d00e3497cbb5a8c/include/ffi.h.in#L419
+    #[cfg(all(feature = "closures", not(target_arch = "x86")))]
+    pub fn ffi_prep_java_raw_closure_loc(
+        arg1: *mut ffi_java_raw_closure,
+        cif: *mut ffi_cif,
+        fun: Option<
+            unsafe extern "C" fn(
+                arg1: *mut ffi_cif,
+                arg2: *mut c_void,
+                arg3: *mut ffi_java_raw,
+                arg4: *mut c_void,
+            ),
+        >,
+        user_data: *mut c_void,
+        codeloc: *mut c_void,
+    ) -> ffi_status;
+
+    pub fn ffi_prep_go_closure(
+        arg1: *mut ffi_go_closure,
+        arg2: *mut ffi_cif,
+        fun: Option<
+            unsafe extern "C" fn(
+                arg1: *mut ffi_cif,
+                arg2: *mut c_void,
+                arg3: *mut *mut c_void,
+                arg4: *mut c_void,
+            ),
+        >,
+    ) -> ffi_status;
+
+    pub fn ffi_call_go(
+        cif: *mut ffi_cif,
+        fn_: Option<unsafe extern "C" fn()>,
+        rvalue: *mut c_void,
+        avalue: *mut *mut c_void,
+        closure: *mut c_void,
+    );
+
+    pub fn ffi_prep_cif(
+        cif: *mut ffi_cif,
+        abi: ffi_abi,
+        nargs: c_uint,
+        rtype: *mut ffi_type,
+        atypes: *mut *mut ffi_type,
+    ) -> ffi_status;
+
+    pub fn ffi_prep_cif_var(
+        cif: *mut ffi_cif,
+        abi: ffi_abi,
+        nfixedargs: c_uint,
+        ntotalargs: c_uint,
+        rtype: *mut ffi_type,
+        atypes: *mut *mut ffi_type,
+    ) -> ffi_status;
+
+    pub fn ffi_call(
+        cif: *mut ffi_cif,
+        fn_: Option<unsafe extern "C" fn()>,
+        rvalue: *mut c_void,
+        avalue: *mut *mut c_void,
+    );
+
+    #[cfg(libffi_3_3)]
+    pub fn ffi_get_struct_offsets(
+        abi: ffi_abi,
+        struct_type: *mut ffi_type,
+        offsets: *mut usize,
+    ) -> ffi_status;
+}
+
+#[cfg(all(feature = "seh-guard", target_env = "msvc"))]
+extern "C" {
+    /// Like `ffi_call`, but catches an access violation in the callee
+    /// with structured exception handling. Returns 0 if the call
+    /// returned normally, or 1 after storing the exception code in
+    /// `code` if it faulted.
+    pub fn ffi_call_seh(
+        cif: *mut ffi_cif,
+        fn_: Option<unsafe extern "C" fn()>,
+        rvalue: *mut c_void,
+        avalue: *mut *mut c_void,
+        code: *mut c_ulong,
+    ) -> c_int;
+}
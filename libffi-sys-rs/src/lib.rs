@@ -39,498 +39,134 @@
 //!
 //! to your `Cargo.toml` instead.
 //!
+//! With the `system` feature, `libffi-sys` links `-lffi` from the linker’s
+//! default search path. To use a libffi installed elsewhere, or without
+//! pkg-config, set these environment variables when building:
+//!
+//! - `LIBFFI_LIB_DIR` — a directory to search for the library
+//! - `LIBFFI_INCLUDE_DIR` — the directory holding `ffi.h`, from which the
+//!   libffi version is read instead of asking pkg-config
+//! - `LIBFFI_STATIC` — if set to anything but `0`, link the library
+//!   statically, and if set to `0`, dynamically
+//!
+//! The `system-static` feature, which implies `system`, links the system
+//! libffi statically unless `LIBFFI_STATIC` is `0`, looking for
+//! `libffi.a` in `LIBFFI_LIB_DIR`, in the directory that pkg-config
+//! reports, and in the usual system library directories.
+//!
+//! With the `system` feature, the `bindgen` feature additionally
+//! regenerates the bindings at build time from the headers in
+//! `LIBFFI_INCLUDE_DIR` or found by `pkg-config`, in case they differ
+//! from the bundled bindings, *e.g.,* in struct layout or calling
+//! conventions.
+//! This requires libclang.
+//!
+//! On x86-64 and AArch64 Linux (glibc or musl) and macOS, and on x86 and
+//! x86-64 MinGW (`windows-gnu`), if `autoreconf` is not installed, the
+//! vendored libffi is instead compiled directly with the C compiler, as it
+//! is for MSVC, so neither autotools nor an MSYS2 shell are needed. Set
+//! `LIBFFI_SYS_BUILD` to `cc` or `autotools` to choose explicitly. For
+//! iOS, tvOS, watchOS, and Mac Catalyst on AArch64 and x86-64 (the
+//! simulators), and for watchOS on `arm64_32`, the vendored libffi is
+//! always built this way, since `configure` cannot find their SDKs.
+//!
+//! With MSVC, the vendored libffi builds for x86, x86-64, AArch64, and
+//! ARM64EC (`arm64ec-pc-windows-msvc`), whose code uses the AArch64 port.
+//!
+//! Building the vendored libffi runs autotools and `make`, which can
+//! dominate build times. If you have already built it, for instance in a
+//! CI cache, set `LIBFFI_PREBUILT_DIR` to its installation prefix, holding
+//! `lib/libffi.a` (or `lib/libffi.lib` for MSVC) and `include/ffi.h`, and
+//! `libffi-sys` links that instead of building it again. It should be the
+//! same version as the vendored copy, since the bindings are written for
+//! that.
+//!
+//! The build script tells the build scripts of crates that depend on
+//! `libffi-sys` directly where the libffi it links is, as `DEP_FFI_ROOT`,
+//! its installation prefix, and `DEP_FFI_INCLUDE`, the directory holding
+//! its `ffi.h`, so that they can compile C code against the same header.
+//! With the `system` feature, only `DEP_FFI_INCLUDE` is set, and only if
+//! `LIBFFI_INCLUDE_DIR` is.
+//!
+//! The `closures` feature, on by default, builds libffi's allocator for
+//! closures' executable memory and its static trampolines, and declares the
+//! closure functions. Programs that only call functions can disable
+//! default features to leave them out, for sandboxes that forbid
+//! executable memory or to save space. Closure support compiled into a
+//! `system` libffi is unaffected, but is then not declared.
+//!
+//! Without the `std` feature, also on by default, `libffi-sys` is
+//! `no_std`, taking its C types from `core::ffi`, which needs Rust 1.64 or
+//! later.
+//!
+//! # Android
+//!
+//! When building the vendored libffi for an Android target, the build
+//! script finds the NDK through `ANDROID_NDK_HOME` (or `ANDROID_NDK_ROOT`,
+//! `ANDROID_NDK`, or `NDK_HOME`), as set by `cargo-ndk` and Android Studio,
+//! and configures libffi with the NDK's clang for the API level in
+//! `ANDROID_PLATFORM` (default 21) and its sysroot. A compiler set in
+//! `CC_<target>`, `TARGET_CC`, or `CC` overrides the NDK's.
+//!
+//! # WebAssembly
+//!
+//! libffi has no WASI port: calling a function pointer with a signature
+//! chosen at run time needs either generated code or an interpreter of
+//! the host's calling convention, and WASI offers neither. Building for a
+//! `wasm32` target other than Emscripten therefore fails with a
+//! compile-time error rather than a failed C build. The Emscripten port
+//! needs libffi 3.4.5 or later, newer than the vendored copy, so use it
+//! with `system` or `LIBFFI_PREBUILT_DIR`.
+//!
 //! This crate supports Rust version 1.32 and later.
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 #![allow(non_upper_case_globals)]
 #![allow(improper_ctypes)]
 #![allow(unused_imports)]
 
-use std::fmt::{self, Debug};
-use std::mem::zeroed;
-use std::os::raw::{c_char, c_int, c_long, c_schar, c_uint, c_ulong, c_ushort, c_void};
-
-mod arch;
-pub use arch::*;
-use fmt::Formatter;
-
-pub type ffi_arg = c_ulong;
-pub type ffi_sarg = c_long;
-pub type ffi_abi = u32;
-pub type ffi_status = u32;
-pub type ffi_type_enum = u32;
-
-pub const FFI_64_BIT_MAX: u64 = 9223372036854775807;
-pub const FFI_CLOSURES: u32 = 1;
-pub const FFI_SIZEOF_ARG: usize = std::mem::size_of::<c_long>();
-// NOTE: This only differs from FFI_SIZEOF_ARG on ILP platforms, which Rust does not support
-pub const FFI_SIZEOF_JAVA_RAW: usize = FFI_SIZEOF_ARG;
-
-pub const FFI_TYPE_VOID: u32 = 0;
-pub const FFI_TYPE_INT: u32 = 1;
-pub const FFI_TYPE_FLOAT: u32 = 2;
-pub const FFI_TYPE_DOUBLE: u32 = 3;
-pub const FFI_TYPE_LONGDOUBLE: u32 = 4;
-pub const FFI_TYPE_UINT8: u32 = 5;
-pub const FFI_TYPE_SINT8: u32 = 6;
-pub const FFI_TYPE_UINT16: u32 = 7;
-pub const FFI_TYPE_SINT16: u32 = 8;
-pub const FFI_TYPE_UINT32: u32 = 9;
-pub const FFI_TYPE_SINT32: u32 = 10;
-pub const FFI_TYPE_UINT64: u32 = 11;
-pub const FFI_TYPE_SINT64: u32 = 12;
-pub const FFI_TYPE_STRUCT: u32 = 13;
-pub const FFI_TYPE_POINTER: u32 = 14;
-pub const FFI_TYPE_COMPLEX: u32 = 15;
-pub const FFI_TYPE_LAST: u32 = 15;
-
-pub const ffi_status_FFI_OK: ffi_status = 0;
-pub const ffi_status_FFI_BAD_TYPEDEF: ffi_status = 1;
-pub const ffi_status_FFI_BAD_ABI: ffi_status = 2;
-
-pub const ffi_type_enum_STRUCT: ffi_type_enum = 13;
-pub const ffi_type_enum_COMPLEX: ffi_type_enum = 15;
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-pub struct ffi_type {
-    pub size: usize,
-    pub alignment: c_ushort,
-    pub type_: c_ushort,
-    pub elements: *mut *mut ffi_type,
-}
-
-impl Default for ffi_type {
-    fn default() -> Self {
-        unsafe { zeroed() }
-    }
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-pub struct ffi_cif {
-    pub abi: ffi_abi,
-    pub nargs: c_uint,
-    pub arg_types: *mut *mut ffi_type,
-    pub rtype: *mut ffi_type,
-    pub bytes: c_uint,
-    pub flags: c_uint,
-    #[cfg(all(target_arch = "aarch64", target_os = "windows"))]
-    pub is_variadic: c_uint,
-    #[cfg(all(target_arch = "aarch64", target_vendor = "apple"))]
-    pub aarch64_nfixedargs: c_uint,
-    #[cfg(all(target_arch = "arm"))]
-    pub vfp_used: c_int,
-    #[cfg(all(target_arch = "arm"))]
-    pub vfp_reg_free: c_ushort,
-    #[cfg(all(target_arch = "arm"))]
-    pub vfp_nargs: c_ushort,
-    #[cfg(all(target_arch = "arm"))]
-    pub vfp_args: [c_schar; 16],
-    #[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
-    pub nfixedargs: c_uint,
-}
-
-impl Default for ffi_cif {
-    fn default() -> Self {
-        unsafe { zeroed() }
-    }
-}
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub union ffi_raw {
-    pub sint: ffi_sarg,
-    pub uint: ffi_arg,
-    pub flt: f32,
-    pub data: [c_char; FFI_SIZEOF_ARG],
-    pub ptr: *mut c_void,
-    _bindgen_union_align: u64,
-}
-
-impl Default for ffi_raw {
-    fn default() -> Self {
-        unsafe { zeroed() }
-    }
-}
-
-pub type ffi_java_raw = ffi_raw;
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct ffi_closure {
-    pub tramp: [c_char; FFI_TRAMPOLINE_SIZE],
-    pub cif: *mut ffi_cif,
-    pub fun: Option<
-        unsafe extern "C" fn(
-            arg1: *mut ffi_cif,
-            arg2: *mut c_void,
-            arg3: *mut *mut c_void,
-            arg4: *mut c_void,
-        ),
-    >,
-    pub user_data: *mut c_void,
-}
-
-/// Implements Debug manually since sometimes FFI_TRAMPOLINE_SIZE is too large
-impl Debug for ffi_closure {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.debug_struct("ffi_closure")
-            .field("tramp", &&self.tramp[..])
-            .field("cif", &self.cif)
-            .field("fun", &self.fun)
-            .field("user_data", &self.user_data)
-            .finish()
-    }
-}
-
-impl Default for ffi_closure {
-    fn default() -> Self {
-        unsafe { zeroed() }
-    }
-}
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct ffi_raw_closure {
-    pub tramp: [c_char; FFI_TRAMPOLINE_SIZE],
-    pub cif: *mut ffi_cif,
-    // See: https://github.com/libffi/libffi/blob/3a7580da73b7f16f275277316d00e3497cbb5a8c/include/ffi.h.in#L364
-    #[cfg(not(target_arch = "i686"))]
-    pub translate_args: Option<
-        unsafe extern "C" fn(
-            arg1: *mut ffi_cif,
-            arg2: *mut c_void,
-            arg3: *mut *mut c_void,
-            arg4: *mut c_void,
-        ),
-    >,
-    #[cfg(not(target_arch = "i686"))]
-    pub this_closure: *mut c_void,
-    pub fun: Option<
-        unsafe extern "C" fn(
-            arg1: *mut ffi_cif,
-            arg2: *mut c_void,
-            arg3: *mut ffi_raw,
-            arg4: *mut c_void,
-        ),
-    >,
-    pub user_data: *mut c_void,
-}
-
-/// Implements Debug manually since sometimes FFI_TRAMPOLINE_SIZE is too large
-impl Debug for ffi_raw_closure {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let mut debug_struct = f.debug_struct("ffi_raw_closure");
-        debug_struct
-            .field("tramp", &&self.tramp[..])
-            .field("cif", &self.cif);
-
-        #[cfg(not(target_arch = "i686"))]
-        debug_struct.field("translate_args", &self.translate_args);
-        #[cfg(not(target_arch = "i686"))]
-        debug_struct.field("this_closure", &self.this_closure);
-
-        debug_struct
-            .field("fun", &self.fun)
-            .field("user_data", &self.user_data)
-            .finish()
-    }
-}
-
-impl Default for ffi_raw_closure {
-    fn default() -> Self {
-        unsafe { zeroed() }
-    }
-}
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct ffi_java_raw_closure {
-    pub tramp: [c_char; FFI_TRAMPOLINE_SIZE],
-    pub cif: *mut ffi_cif,
-    // See: https://github.com/libffi/libffi/blob/3a7580da73b7f16f275277316d00e3497cbb5a8c/include/ffi.h.in#L390
-    #[cfg(not(target_arch = "i686"))]
-    pub translate_args: Option<
-        unsafe extern "C" fn(
-            arg1: *mut ffi_cif,
-            arg2: *mut c_void,
-            arg3: *mut *mut c_void,
-            arg4: *mut c_void,
-        ),
-    >,
-    #[cfg(not(target_arch = "i686"))]
-    pub this_closure: *mut c_void,
-    pub fun: Option<
-        unsafe extern "C" fn(
-            arg1: *mut ffi_cif,
-            arg2: *mut c_void,
-            arg3: *mut ffi_java_raw,
-            arg4: *mut c_void,
-        ),
-    >,
-    pub user_data: *mut c_void,
-}
-
-/// Implements Debug manually since sometimes FFI_TRAMPOLINE_SIZE is too large
-impl Debug for ffi_java_raw_closure {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        let mut debug_struct = f.debug_struct("ffi_java_raw_closure");
-        debug_struct
-            .field("tramp", &&self.tramp[..])
-            .field("cif", &self.cif);
-
-        #[cfg(not(target_arch = "i686"))]
-        debug_struct.field("translate_args", &self.translate_args);
-        #[cfg(not(target_arch = "i686"))]
-        debug_struct.field("this_closure", &self.this_closure);
-
-        debug_struct
-            .field("fun", &self.fun)
-            .field("user_data", &self.user_data)
-            .finish()
-    }
-}
-
-impl Default for ffi_java_raw_closure {
-    fn default() -> Self {
-        unsafe { zeroed() }
-    }
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
-pub struct ffi_go_closure {
-    pub tramp: *mut c_void,
-    pub cif: *mut ffi_cif,
-    pub fun: Option<
-        unsafe extern "C" fn(
-            arg1: *mut ffi_cif,
-            arg2: *mut c_void,
-            arg3: *mut *mut c_void,
-            arg4: *mut c_void,
-        ),
-    >,
-}
-impl Default for ffi_go_closure {
-    fn default() -> Self {
-        unsafe { zeroed() }
-    }
-}
-
-extern "C" {
-    pub static mut ffi_type_void: ffi_type;
-    pub static mut ffi_type_uint8: ffi_type;
-    pub static mut ffi_type_sint8: ffi_type;
-    pub static mut ffi_type_uint16: ffi_type;
-    pub static mut ffi_type_sint16: ffi_type;
-    pub static mut ffi_type_uint32: ffi_type;
-    pub static mut ffi_type_sint32: ffi_type;
-    pub static mut ffi_type_uint64: ffi_type;
-    pub static mut ffi_type_sint64: ffi_type;
-    pub static mut ffi_type_float: ffi_type;
-    pub static mut ffi_type_double: ffi_type;
-    pub static mut ffi_type_pointer: ffi_type;
-
-    #[cfg(not(all(target_arch = "arm", target_os = "linux", target_env = "gnu")))]
-    pub static mut ffi_type_longdouble: ffi_type;
-
-    #[cfg(feature = "complex")]
-    pub static mut ffi_type_complex_float: ffi_type;
-
-    #[cfg(feature = "complex")]
-    pub static mut ffi_type_complex_double: ffi_type;
-
-    #[cfg(feature = "complex")]
-    #[cfg(not(all(target_arch = "arm", target_os = "linux", target_env = "gnu")))]
-    pub static mut ffi_type_complex_longdouble: ffi_type;
-
-    pub fn ffi_raw_call(
-        cif: *mut ffi_cif,
-        fn_: Option<unsafe extern "C" fn()>,
-        rvalue: *mut c_void,
-        avalue: *mut ffi_raw,
-    );
-
-    pub fn ffi_ptrarray_to_raw(cif: *mut ffi_cif, args: *mut *mut c_void, raw: *mut ffi_raw);
-
-    pub fn ffi_raw_to_ptrarray(cif: *mut ffi_cif, raw: *mut ffi_raw, args: *mut *mut c_void);
-
-    pub fn ffi_raw_size(cif: *mut ffi_cif) -> usize;
-
-    // See: https://github.com/libffi/libffi/blob/3a7580da73b7f16f275277316d00e3497cbb5a8c/include/ffi.h.in#L286
-    #[cfg(not(target_arch = "i686"))]
-    pub fn ffi_java_raw_call(
-        cif: *mut ffi_cif,
-        fn_: Option<unsafe extern "C" fn()>,
-        rvalue: *mut c_void,
-        avalue: *mut ffi_java_raw,
-    );
-
-    pub fn ffi_java_ptrarray_to_raw(
-        cif: *mut ffi_cif,
-        args: *mut *mut c_void,
-        raw: *mut ffi_java_raw,
-    );
-
-    pub fn ffi_java_raw_to_ptrarray(
-        cif: *mut ffi_cif,
-        raw: *mut ffi_java_raw,
-        args: *mut *mut c_void,
-    );
-
-    pub fn ffi_java_raw_size(cif: *mut ffi_cif) -> usize;
-
-    pub fn ffi_closure_alloc(size: usize, code: *mut *mut c_void) -> *mut c_void;
-
-    pub fn ffi_closure_free(arg1: *mut c_void);
-
-    pub fn ffi_prep_closure(
-        arg1: *mut ffi_closure,
-        arg2: *mut ffi_cif,
-        fun: Option<
-            unsafe extern "C" fn(
-                arg1: *mut ffi_cif,
-                arg2: *mut c_void,
-                arg3: *mut *mut c_void,
-                arg4: *mut c_void,
-            ),
-        >,
-        user_data: *mut c_void,
-    ) -> ffi_status;
-
-    pub fn ffi_prep_closure_loc(
-        arg1: *mut ffi_closure,
-        arg2: *mut ffi_cif,
-        fun: Option<
-            unsafe extern "C" fn(
-                arg1: *mut ffi_cif,
-                arg2: *mut c_void,
-                arg3: *mut *mut c_void,
-                arg4: *mut c_void,
-            ),
-        >,
-        user_data: *mut c_void,
-        codeloc: *mut c_void,
-    ) -> ffi_status;
-
-    pub fn ffi_prep_raw_closure(
-        arg1: *mut ffi_raw_closure,
-        cif: *mut ffi_cif,
-        fun: Option<
-            unsafe extern "C" fn(
-                arg1: *mut ffi_cif,
-                arg2: *mut c_void,
-                arg3: *mut ffi_raw,
-                arg4: *mut c_void,
-            ),
-        >,
-        user_data: *mut c_void,
-    ) -> ffi_status;
-
-    pub fn ffi_prep_raw_closure_loc(
-        arg1: *mut ffi_raw_closure,
-        cif: *mut ffi_cif,
-        fun: Option<
-            unsafe extern "C" fn(
-                arg1: *mut ffi_cif,
-                arg2: *mut c_void,
-                arg3: *mut ffi_raw,
-                arg4: *mut c_void,
-            ),
-        >,
-        user_data: *mut c_void,
-        codeloc: *mut c_void,
-    ) -> ffi_status;
-
-    // See: https://github.com/libffi/libffi/blob/3a7580da73b7f16f275277316d00e3497cbb5a8c/include/ffi.h.in#L419
-    #[cfg(not(target_arch = "i686"))]
-    pub fn ffi_prep_java_raw_closure(
-        arg1: *mut ffi_java_raw_closure,
-        cif: *mut ffi_cif,
-        fun: Option<
-            unsafe extern "C" fn(
-                arg1: *mut ffi_cif,
-                arg2: *mut c_void,
-                arg3: *mut ffi_java_raw,
-                arg4: *mut c_void,
-            ),
-        >,
-        user_data: *mut c_void,
-    ) -> ffi_status;
-
-    // See: https://github.com/libffi/libffi/blob/3a7580da73b7f16f275277316d00e3497cbb5a8c/include/ffi.h.in#L419
-    #[cfg(not(target_arch = "i686"))]
-    pub fn ffi_prep_java_raw_closure_loc(
-        arg1: *mut ffi_java_raw_closure,
-        cif: *mut ffi_cif,
-        fun: Option<
-            unsafe extern "C" fn(
-                arg1: *mut ffi_cif,
-                arg2: *mut c_void,
-                arg3: *mut ffi_java_raw,
-                arg4: *mut c_void,
-            ),
-        >,
-        user_data: *mut c_void,
-        codeloc: *mut c_void,
-    ) -> ffi_status;
-
-    pub fn ffi_prep_go_closure(
-        arg1: *mut ffi_go_closure,
-        arg2: *mut ffi_cif,
-        fun: Option<
-            unsafe extern "C" fn(
-                arg1: *mut ffi_cif,
-                arg2: *mut c_void,
-                arg3: *mut *mut c_void,
-                arg4: *mut c_void,
-            ),
-        >,
-    ) -> ffi_status;
-
-    pub fn ffi_call_go(
-        cif: *mut ffi_cif,
-        fn_: Option<unsafe extern "C" fn()>,
-        rvalue: *mut c_void,
-        avalue: *mut *mut c_void,
-        closure: *mut c_void,
-    );
+#[cfg(all(target_arch = "wasm32", not(target_os = "emscripten")))]
+compile_error!(
+    "libffi-sys does not support this wasm32 target: libffi has no WASI or \
+     bare wasm32 port. Only wasm32-unknown-emscripten is supported, with a \
+     libffi 3.4.5 or later provided via the `system` feature or \
+     LIBFFI_PREBUILT_DIR."
+);
 
-    pub fn ffi_prep_cif(
-        cif: *mut ffi_cif,
-        abi: ffi_abi,
-        nargs: c_uint,
-        rtype: *mut ffi_type,
-        atypes: *mut *mut ffi_type,
-    ) -> ffi_status;
+#[cfg(target_arch = "hexagon")]
+compile_error!("libffi-sys does not support Hexagon, which libffi has not been ported to.");
 
-    pub fn ffi_prep_cif_var(
-        cif: *mut ffi_cif,
-        abi: ffi_abi,
-        nfixedargs: c_uint,
-        ntotalargs: c_uint,
-        rtype: *mut ffi_type,
-        atypes: *mut *mut ffi_type,
-    ) -> ffi_status;
+/// The version of the C libffi, such as `"3.4.4"`: that of the vendored
+/// copy, or for the `system` feature, as reported by `pkg-config` at
+/// build time. It is `"unknown"` if the version could not be found.
+pub const LIBFFI_VERSION: &str = env!("LIBFFI_VERSION");
 
-    pub fn ffi_call(
-        cif: *mut ffi_cif,
-        fn_: Option<unsafe extern "C" fn()>,
-        rvalue: *mut c_void,
-        avalue: *mut *mut c_void,
-    );
+mod arch;
+pub use arch::*;
 
-    pub fn ffi_get_struct_offsets(
-        abi: ffi_abi,
-        struct_type: *mut ffi_type,
-        offsets: *mut usize,
-    ) -> ffi_status;
+mod bindings;
+pub use bindings::*;
+
+#[cfg(all(feature = "system", feature = "bindgen", not(libffi_docs_only)))]
+include!(concat!(env!("OUT_DIR"), "/generated.rs"));
+
+/// The size of an `ffi_closure`, to pass to `ffi_closure_alloc`.
+///
+/// This is `size_of::<ffi_closure>()`, whose trampoline size comes from
+/// this crate’s `FFI_TRAMPOLINE_SIZE` for the target, and which is
+/// checked at compile time against the size of the C struct. It is not
+/// queried from the linked libffi.
+#[cfg(feature = "closures")]
+pub fn ffi_closure_size() -> usize {
+    core::mem::size_of::<ffi_closure>()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::os::raw::c_void;
 
     extern "C" fn add(x: u64, y: u64) -> u64 {
         x + y
@@ -571,4 +207,54 @@ mod test {
             assert_eq!(rval, 9);
         }
     }
+
+    #[test]
+    fn raw_layout() {
+        use std::mem::{align_of, size_of};
+
+        assert_eq!(size_of::<ffi_raw>(), FFI_SIZEOF_ARG);
+        assert_eq!(size_of::<ffi_arg>(), FFI_SIZEOF_ARG);
+        assert_eq!(align_of::<ffi_raw>(), align_of::<ffi_arg>());
+        assert_eq!(size_of::<ffi_java_raw>(), FFI_SIZEOF_JAVA_RAW);
+        assert!(FFI_SIZEOF_ARG >= size_of::<*mut c_void>());
+    }
+
+    #[test]
+    fn raw_call() {
+        unsafe {
+            let mut cif: ffi_cif = Default::default();
+            let uint64 = &mut ffi_type_uint64 as *mut _;
+            let mut arg_types = [uint64, uint64];
+
+            let prep_status = ffi_prep_cif(
+                &mut cif,
+                ffi_abi_FFI_DEFAULT_ABI,
+                2,
+                uint64,
+                arg_types.as_mut_ptr(),
+            );
+            assert_eq!(prep_status, ffi_status_FFI_OK);
+            assert_eq!(ffi_raw_size(&mut cif), 16);
+
+            let mut args = [4u64, 5u64];
+            let mut raw = vec![ffi_raw::default(); ffi_raw_size(&mut cif) / FFI_SIZEOF_ARG];
+            let mut arg_ptrs: Vec<*mut c_void> = args
+                .iter_mut()
+                .map(|arg| arg as *mut u64 as *mut c_void)
+                .collect();
+            ffi_ptrarray_to_raw(&mut cif, arg_ptrs.as_mut_ptr(), raw.as_mut_ptr());
+
+            let mut rval = 0u64;
+            let func = &*(&(add as *mut extern "C" fn(u64, u64) -> u64) as *const _
+                as *const extern "C" fn());
+            ffi_raw_call(
+                &mut cif,
+                Some(*func),
+                &mut rval as *mut _ as *mut c_void,
+                raw.as_mut_ptr(),
+            );
+
+            assert_eq!(rval, 9);
+        }
+    }
 }
@@ -0,0 +1,24 @@
+/* Calls ffi_call inside a structured-exception handler, so that an
+ * access violation in the callee is reported instead of terminating the
+ * process. Compiled only for MSVC targets with the seh-guard feature. */
+
+#include <windows.h>
+#include <ffi.h>
+
+static int fault_filter(DWORD code)
+{
+    return code == EXCEPTION_ACCESS_VIOLATION
+        ? EXCEPTION_EXECUTE_HANDLER
+        : EXCEPTION_CONTINUE_SEARCH;
+}
+
+int ffi_call_seh(ffi_cif *cif, void (*fn)(void), void *rvalue,
+                 void **avalue, unsigned long *code)
+{
+    __try {
+        ffi_call(cif, fn, rvalue, avalue);
+        return 0;
+    } __except (fault_filter(*code = GetExceptionCode())) {
+        return 1;
+    }
+}